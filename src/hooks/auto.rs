@@ -0,0 +1,354 @@
+//! Hooks that detect the graphics API at runtime.
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+
+use imgui::{Context, Io, Ui};
+use parking_lot::Mutex;
+use tracing::{debug, error, info};
+use windows::core::w;
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+
+use super::{HookStrategy, SwapChainInfo};
+use crate::mh::{MH_ApplyQueued, MH_QueueDisableHook, MhHook};
+use crate::renderer::{RenderLoopFactory, RenderLoops};
+use crate::{Hooks, ImguiRenderLoop, MessageFilter, PerWindowHooks, RenderContext};
+
+/// A graphics API that [`ImguiAutoHooks`] can lock onto.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsApi {
+    /// DirectX 9.
+    Dx9 = 1,
+    /// DirectX 11.
+    Dx11 = 2,
+    /// DirectX 12.
+    Dx12 = 3,
+    /// OpenGL 3.
+    OpenGl3 = 4,
+}
+
+impl GraphicsApi {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(GraphicsApi::Dx9),
+            2 => Some(GraphicsApi::Dx11),
+            3 => Some(GraphicsApi::Dx12),
+            4 => Some(GraphicsApi::OpenGl3),
+            _ => None,
+        }
+    }
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// No API has presented yet.
+const NO_API: u8 = 0;
+
+static AUTO_MODE: AtomicBool = AtomicBool::new(false);
+static ACTIVE_API: AtomicU8 = AtomicU8::new(NO_API);
+static ROUTES_DX11: AtomicBool = AtomicBool::new(false);
+
+// Target addresses of the installed hooks, with the mask of the APIs each of
+// them serves. The DXGI `Present` hook, for instance, serves both DirectX 11
+// and DirectX 12.
+static HOOK_TARGETS: Mutex<Vec<(u8, usize)>> = Mutex::new(Vec::new());
+
+/// Decide whether the hooks for `api` should render the current frame.
+///
+/// Outside of [`ImguiAutoHooks`] this always succeeds. Otherwise, the first API
+/// that presents a frame is locked in and the hooks of all the other APIs are
+/// disabled.
+pub(super) fn claim(api: GraphicsApi) -> bool {
+    if !AUTO_MODE.load(Ordering::SeqCst) {
+        return true;
+    }
+
+    match ACTIVE_API.compare_exchange(NO_API, api as u8, Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(_) => {
+            info!("Locked onto {api:?}");
+            disable_other_hooks(api);
+            true
+        },
+        Err(active_api) => active_api == api as u8,
+    }
+}
+
+/// Whether the DirectX 12 hooks should hand DirectX 11 swap chains over to the
+/// DirectX 11 renderer.
+pub(super) fn routes_dx11() -> bool {
+    ROUTES_DX11.load(Ordering::SeqCst)
+}
+
+fn disable_other_hooks(api: GraphicsApi) {
+    let targets = HOOK_TARGETS.lock();
+
+    for &(mask, addr) in targets.iter().filter(|(mask, _)| mask & api.mask() == 0) {
+        debug!("Disabling hook at {addr:#x} (API mask {mask:#b})");
+        if let Err(e) = unsafe { MH_QueueDisableHook(addr as *mut c_void) }.ok() {
+            error!("Couldn't queue hook for disabling: {e:?}");
+        }
+    }
+
+    if let Err(e) = unsafe { MH_ApplyQueued() }.ok() {
+        error!("Couldn't disable unused hooks: {e:?}");
+    }
+}
+
+fn is_module_loaded(name: windows::core::PCWSTR) -> bool {
+    unsafe { GetModuleHandleW(name) }.is_ok()
+}
+
+type SharedRenderLoopInner = Arc<Mutex<Box<dyn ImguiRenderLoop + Send + Sync>>>;
+
+// A render loop that can be handed out to several hook implementations.
+//
+// Only the hooks of the API that gets locked in ever build a pipeline, so the
+// lock is uncontended in practice.
+struct SharedRenderLoop(SharedRenderLoopInner);
+
+impl ImguiRenderLoop for SharedRenderLoop {
+    fn initialize<'a>(&'a mut self, ctx: &mut Context, render_context: &'a mut dyn RenderContext) {
+        self.0.lock().initialize(ctx, render_context);
+    }
+
+    fn before_render<'a>(
+        &'a mut self,
+        ctx: &mut Context,
+        render_context: &'a mut dyn RenderContext,
+    ) {
+        self.0.lock().before_render(ctx, render_context);
+    }
+
+    fn render(&mut self, ui: &mut Ui) {
+        self.0.lock().render(ui);
+    }
+
+    fn on_wnd_proc(&self, hwnd: HWND, umsg: u32, wparam: WPARAM, lparam: LPARAM) {
+        self.0.lock().on_wnd_proc(hwnd, umsg, wparam, lparam);
+    }
+
+    fn message_filter(&self, io: &Io) -> MessageFilter {
+        self.0.lock().message_filter(io)
+    }
+
+    fn ui_scale(&self, io: &Io) -> f32 {
+        self.0.lock().ui_scale(io)
+    }
+
+    fn frame_rate_limit(&self, io: &Io) -> Option<f32> {
+        self.0.lock().frame_rate_limit(io)
+    }
+}
+
+// Where the hooks of every detected API get their render loops from.
+enum RenderLoopSource {
+    Shared(SharedRenderLoopInner),
+    PerWindow(Arc<RenderLoopFactory>),
+}
+
+impl RenderLoopSource {
+    fn render_loops(&self) -> RenderLoops {
+        match self {
+            RenderLoopSource::Shared(render_loop) => {
+                RenderLoops::single(SharedRenderLoop(Arc::clone(render_loop)))
+            },
+            RenderLoopSource::PerWindow(factory) => RenderLoops::PerWindow(Arc::clone(factory)),
+        }
+    }
+}
+
+/// Hooks that detect the graphics API in use at runtime.
+///
+/// On construction, the loaded modules (`d3d9.dll`, `d3d11.dll`, `d3d12.dll`
+/// and `opengl32.dll`) are inspected and the hooks for every API whose module
+/// is found are installed, sharing the same render loop. The first API that
+/// presents a frame is locked in, and the hooks of all the other APIs are
+/// disabled.
+///
+/// As detection happens when the hooks are created, the graphics modules must
+/// already be loaded in the process at that point.
+///
+/// Example usage:
+/// ```no_run
+/// use hudhook::hooks::auto::ImguiAutoHooks;
+/// use hudhook::*;
+///
+/// pub struct MyRenderLoop;
+///
+/// impl ImguiRenderLoop for MyRenderLoop {
+///     fn render(&mut self, ui: &mut imgui::Ui) {
+///         // ...
+///     }
+/// }
+///
+/// hudhook!(ImguiAutoHooks, MyRenderLoop);
+/// ```
+pub struct ImguiAutoHooks {
+    hooks: Vec<MhHook>,
+    apis: Vec<Box<dyn Hooks>>,
+}
+
+impl ImguiAutoHooks {
+    /// Construct the hooks for all the graphics APIs whose modules are loaded
+    /// in the process, rendering UI via the provided [`ImguiRenderLoop`].
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn new<T>(t: T) -> Self
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Self::with_source(RenderLoopSource::Shared(Arc::new(Mutex::new(Box::new(t)))))
+    }
+
+    unsafe fn with_source(source: RenderLoopSource) -> Self {
+        let mut apis: Vec<(u8, Box<dyn Hooks>)> = Vec::new();
+
+        #[cfg(feature = "dx9")]
+        if is_module_loaded(w!("d3d9.dll")) {
+            info!("d3d9.dll found, installing DirectX 9 hooks");
+            apis.push((
+                GraphicsApi::Dx9.mask(),
+                Box::new(super::dx9::ImguiDx9Hooks::with_render_loops(
+                    source.render_loops(),
+                    &HookStrategy::Inline,
+                )),
+            ));
+        }
+
+        // DirectX 11 and DirectX 12 swap chains share the same `Present`
+        // implementation in DXGI, which can only be hooked once. When both
+        // modules are loaded, the DirectX 12 hooks hand DirectX 11 swap chains
+        // over to the DirectX 11 renderer.
+        #[allow(unused_variables)]
+        let d3d11_loaded = cfg!(feature = "dx11") && is_module_loaded(w!("d3d11.dll"));
+        #[allow(unused_variables)]
+        let d3d12_loaded = cfg!(feature = "dx12") && is_module_loaded(w!("d3d12.dll"));
+
+        #[cfg(feature = "dx12")]
+        if d3d12_loaded {
+            info!("d3d12.dll found, installing DirectX 12 hooks");
+            #[allow(unused_mut)]
+            let mut mask = GraphicsApi::Dx12.mask();
+
+            #[cfg(feature = "dx11")]
+            if d3d11_loaded {
+                info!("d3d11.dll found, routing DirectX 11 swap chains through DirectX 12 hooks");
+                super::dx11::set_render_loops(source.render_loops());
+                ROUTES_DX11.store(true, Ordering::SeqCst);
+                mask |= GraphicsApi::Dx11.mask();
+            }
+
+            let hooks: Box<dyn Hooks> = Box::new(super::dx12::ImguiDx12Hooks::with_render_loops(
+                source.render_loops(),
+                &HookStrategy::Inline,
+            ));
+
+            // Only the present hooks are shared with DirectX 11: the others can
+            // be disabled if DirectX 11 wins.
+            for (i, hook) in hooks.hooks().iter().enumerate() {
+                let hook_mask = if i < super::dx12::DXGI_SHARED_HOOKS {
+                    mask
+                } else {
+                    GraphicsApi::Dx12.mask()
+                };
+                HOOK_TARGETS.lock().push((hook_mask, hook.target() as usize));
+            }
+            apis.push((0, hooks));
+        }
+
+        #[cfg(feature = "dx11")]
+        if d3d11_loaded && !d3d12_loaded {
+            info!("d3d11.dll found, installing DirectX 11 hooks");
+            apis.push((
+                GraphicsApi::Dx11.mask(),
+                Box::new(super::dx11::ImguiDx11Hooks::with_render_loops(
+                    source.render_loops(),
+                    &HookStrategy::Inline,
+                )),
+            ));
+        }
+
+        #[cfg(feature = "opengl3")]
+        if is_module_loaded(w!("opengl32.dll")) {
+            info!("opengl32.dll found, installing OpenGL 3 hooks");
+            apis.push((
+                GraphicsApi::OpenGl3.mask(),
+                Box::new(super::opengl3::ImguiOpenGl3Hooks::with_render_loops(
+                    source.render_loops(),
+                )),
+            ));
+        }
+
+        if apis.is_empty() {
+            error!("No supported graphics module is loaded in the process");
+        }
+
+        let mut hook_targets = HOOK_TARGETS.lock();
+        for (mask, api) in apis.iter().filter(|(mask, _)| *mask != 0) {
+            hook_targets.extend(api.hooks().iter().map(|hook| (*mask, hook.target() as usize)));
+        }
+        drop(hook_targets);
+
+        let hooks = apis.iter().flat_map(|(_, api)| api.hooks().iter().cloned()).collect();
+        let apis = apis.into_iter().map(|(_, api)| api).collect();
+
+        ACTIVE_API.store(NO_API, Ordering::SeqCst);
+        AUTO_MODE.store(true, Ordering::SeqCst);
+
+        Self { hooks, apis }
+    }
+
+    /// Return the graphics API that has been locked in, if any has presented a
+    /// frame yet.
+    pub fn active_api() -> Option<GraphicsApi> {
+        GraphicsApi::from_u8(ACTIVE_API.load(Ordering::SeqCst))
+    }
+}
+
+impl Hooks for ImguiAutoHooks {
+    fn from_render_loop<T>(t: T) -> Box<Self>
+    where
+        Self: Sized,
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Box::new(unsafe { Self::new(t) })
+    }
+
+    fn hooks(&self) -> &[MhHook] {
+        &self.hooks
+    }
+
+    unsafe fn unhook(&mut self) {
+        for api in &mut self.apis {
+            api.unhook();
+        }
+
+        #[cfg(feature = "dx11")]
+        super::dx11::cleanup();
+
+        HOOK_TARGETS.lock().clear();
+        ROUTES_DX11.store(false, Ordering::SeqCst);
+        AUTO_MODE.store(false, Ordering::SeqCst);
+        ACTIVE_API.store(NO_API, Ordering::SeqCst);
+    }
+}
+
+impl PerWindowHooks for ImguiAutoHooks {
+    fn from_render_loop_factory<F>(factory: F) -> Box<Self>
+    where
+        Self: Sized,
+        F: Fn(&SwapChainInfo) -> Option<Box<dyn ImguiRenderLoop + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Box::new(unsafe { Self::with_source(RenderLoopSource::PerWindow(Arc::new(factory))) })
+    }
+}
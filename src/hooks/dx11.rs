@@ -2,13 +2,11 @@
 
 use std::ffi::c_void;
 use std::mem;
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
 
-use imgui::Context;
-use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use tracing::{error, trace};
-use windows::core::{Error, Interface, Result, HRESULT};
+use windows::core::{Error, Interface, Result, GUID, HRESULT};
 use windows::Win32::Foundation::BOOL;
 use windows::Win32::Graphics::Direct3D::{
     D3D_DRIVER_TYPE_NULL, D3D_FEATURE_LEVEL_10_0, D3D_FEATURE_LEVEL_11_0,
@@ -22,57 +20,78 @@ use windows::Win32::Graphics::Dxgi::Common::{
     DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_SAMPLE_DESC,
 };
 use windows::Win32::Graphics::Dxgi::{
-    IDXGISwapChain, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_EFFECT_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
+    IDXGISwapChain, IDXGISwapChain1, DXGI_PRESENT_PARAMETERS, DXGI_SWAP_CHAIN_DESC,
+    DXGI_SWAP_EFFECT_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
 };
 
-use super::DummyHwnd;
+use super::auto::GraphicsApi;
+use super::{DummyHwnd, HookStrategy, PresentGuard, SwapChainDesc, SwapChainInfo, Tracked};
 use crate::mh::MhHook;
-use crate::renderer::{D3D11RenderEngine, Pipeline};
-use crate::{util, Hooks, ImguiRenderLoop};
+use crate::renderer::{color, D3D11RenderEngine, Pipelines, RenderLoops};
+use crate::{util, Hooks, ImguiRenderLoop, PerWindowHooks};
 
 type DXGISwapChainPresentType =
     unsafe extern "system" fn(this: IDXGISwapChain, sync_interval: u32, flags: u32) -> HRESULT;
 
+type DXGISwapChain1Present1Type = unsafe extern "system" fn(
+    this: IDXGISwapChain1,
+    sync_interval: u32,
+    flags: u32,
+    present_parameters: *const DXGI_PRESENT_PARAMETERS,
+) -> HRESULT;
+
 struct Trampolines {
     dxgi_swap_chain_present: DXGISwapChainPresentType,
+    dxgi_swap_chain1_present1: DXGISwapChain1Present1Type,
 }
 
 static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
-static mut PIPELINE: OnceCell<Mutex<Pipeline<D3D11RenderEngine>>> = OnceCell::new();
-static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
-
-unsafe fn init_pipeline(swap_chain: &IDXGISwapChain) -> Result<Mutex<Pipeline<D3D11RenderEngine>>> {
-    let hwnd = util::try_out_param(|v| swap_chain.GetDesc(v)).map(|desc| desc.OutputWindow)?;
-
-    let mut ctx = Context::create();
-    let engine = D3D11RenderEngine::new(&swap_chain.GetDevice()?, &mut ctx)?;
+// Pipelines, keyed by the address of their swap chain.
+static mut PIPELINES: Mutex<Pipelines<usize, D3D11RenderEngine>> = Mutex::new(Pipelines::new());
+static SWAP_CHAINS: Mutex<Tracked> = Mutex::new(Tracked::new());
+// Private data of the swap chains, notifying their destruction.
+const DESTRUCTION_NOTIFIER: GUID = GUID::from_u128(0x06e57f22_f354_4acc_9570_41dc888a80f0);
 
-    let Some(render_loop) = RENDER_LOOP.take() else {
-        error!("Render loop not yet initialized");
-        return Err(Error::from_hresult(HRESULT(-1)));
-    };
-
-    let pipeline = Pipeline::new(hwnd, ctx, engine, render_loop).map_err(|(e, render_loop)| {
-        RENDER_LOOP.get_or_init(move || render_loop);
-        e
-    })?;
-
-    Ok(Mutex::new(pipeline))
-}
-
-fn render(swap_chain: &IDXGISwapChain) -> Result<()> {
+pub(super) fn render(swap_chain: &IDXGISwapChain) -> Result<()> {
     unsafe {
-        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline(swap_chain))?;
-
-        let Some(mut pipeline) = pipeline.try_lock() else {
-            error!("Could not lock pipeline");
+        let Some(mut pipelines) = PIPELINES.try_lock() else {
+            error!("Could not lock pipelines");
             return Err(Error::from_hresult(HRESULT(-1)));
         };
 
-        pipeline.prepare_render()?;
+        // Tear down the pipelines of the swap chains the application has released,
+        // so they can be rebuilt on new ones.
+        let (inserted, released) = {
+            let mut swap_chains = SWAP_CHAINS.lock();
+            (swap_chains.insert(swap_chain.as_raw() as usize), swap_chains.take_released())
+        };
+        pipelines.retain(|addr| !released.contains(addr));
+
+        if inserted {
+            super::notify_destruction(swap_chain, &DESTRUCTION_NOTIFIER, |addr| {
+                SWAP_CHAINS.lock().release(addr)
+            })?;
+        }
+
+        let Some(pipeline) = pipelines.get_or_try_init(
+            &(swap_chain.as_raw() as usize),
+            || {
+                let desc = util::try_out_param(|v| swap_chain.GetDesc(v))?;
+                Ok(SwapChainInfo::new(SwapChainDesc::Dxgi(desc), desc.OutputWindow))
+            },
+            |ctx| {
+                let mut engine = D3D11RenderEngine::new(&swap_chain.GetDevice()?, ctx)?;
+                engine.set_hdr10_output(color::is_hdr10_output(swap_chain));
+                Ok(engine)
+            },
+        )?
+        else {
+            return Ok(());
+        };
 
         let target: ID3D11Texture2D = swap_chain.GetBuffer(0)?;
 
+        pipeline.prepare_render(&target)?;
         pipeline.render(target)?;
     }
     Ok(())
@@ -83,18 +102,83 @@ unsafe extern "system" fn dxgi_swap_chain_present_impl(
     sync_interval: u32,
     flags: u32,
 ) -> HRESULT {
-    let Trampolines { dxgi_swap_chain_present } =
+    let Trampolines { dxgi_swap_chain_present, .. } =
         TRAMPOLINES.get().expect("DirectX 11 trampolines uninitialized");
 
-    if let Err(e) = render(&swap_chain) {
-        error!("Render error: {e:?}");
-    }
+    let _present_guard = present(&swap_chain);
 
     trace!("Call IDXGISwapChain::Present trampoline");
     dxgi_swap_chain_present(swap_chain, sync_interval, flags)
 }
 
-fn get_target_addrs() -> DXGISwapChainPresentType {
+unsafe extern "system" fn dxgi_swap_chain1_present1_impl(
+    swap_chain: IDXGISwapChain1,
+    sync_interval: u32,
+    flags: u32,
+    present_parameters: *const DXGI_PRESENT_PARAMETERS,
+) -> HRESULT {
+    let Trampolines { dxgi_swap_chain1_present1, .. } =
+        TRAMPOLINES.get().expect("DirectX 11 trampolines uninitialized");
+
+    let _present_guard = present(&swap_chain);
+
+    trace!("Call IDXGISwapChain1::Present1 trampoline");
+    dxgi_swap_chain1_present1(swap_chain, sync_interval, flags, present_parameters)
+}
+
+// Render the frame, unless a present call is already in progress on this
+// thread. The returned guard must be held until the trampoline returns.
+fn present(swap_chain: &IDXGISwapChain) -> Option<PresentGuard> {
+    let present_guard = PresentGuard::enter()?;
+
+    if super::auto::claim(GraphicsApi::Dx11) {
+        if let Err(e) = render(swap_chain) {
+            error!("Render error: {e:?}");
+        }
+    }
+
+    Some(present_guard)
+}
+
+// Hook the functions of a swap chain, or of a dummy swap chain unless the
+// strategy targets an instance.
+unsafe fn create_hooks(strategy: &HookStrategy) -> [MhHook; 2] {
+    let swap_chain = match strategy {
+        HookStrategy::Instance(object) => {
+            let swap_chain: IDXGISwapChain =
+                object.cast().expect("the hooked object should be a DXGI swap chain");
+            // DXGI swap chains implement all their interfaces with one vtable.
+            super::copy_vtable(&swap_chain, super::dxgi_swap_chain_vtable_len(&swap_chain));
+            swap_chain
+        },
+        HookStrategy::Inline | HookStrategy::SharedVTable => {
+            return with_dummy_swap_chain(|swap_chain| hook_swap_chain(swap_chain, strategy));
+        },
+    };
+
+    hook_swap_chain(&swap_chain, strategy)
+}
+
+unsafe fn hook_swap_chain(swap_chain: &IDXGISwapChain, strategy: &HookStrategy) -> [MhHook; 2] {
+    let swap_chain1: IDXGISwapChain1 =
+        swap_chain.cast().expect("IDXGISwapChain1 should be supported");
+
+    let present = &swap_chain.vtable().Present;
+    trace!("IDXGISwapChain::Present = {:p}", *present as *const c_void);
+    let hook_present = strategy
+        .hook(present, dxgi_swap_chain_present_impl as *mut _)
+        .expect("couldn't create IDXGISwapChain::Present hook");
+
+    let present1 = &swap_chain1.vtable().Present1;
+    trace!("IDXGISwapChain1::Present1 = {:p}", *present1 as *const c_void);
+    let hook_present1 = strategy
+        .hook(present1, dxgi_swap_chain1_present1_impl as *mut _)
+        .expect("couldn't create IDXGISwapChain1::Present1 hook");
+
+    [hook_present, hook_present1]
+}
+
+fn with_dummy_swap_chain<R>(f: impl FnOnce(&IDXGISwapChain) -> R) -> R {
     let mut p_device: Option<ID3D11Device> = None;
     let mut p_context: Option<ID3D11DeviceContext> = None;
     let mut p_swap_chain: Option<IDXGISwapChain> = None;
@@ -131,20 +215,24 @@ fn get_target_addrs() -> DXGISwapChainPresentType {
         .expect("D3D11CreateDeviceAndSwapChain failed");
     }
 
-    let swap_chain = p_swap_chain.unwrap();
+    f(&p_swap_chain.unwrap())
+}
 
-    let present_ptr: DXGISwapChainPresentType = unsafe {
-        mem::transmute::<
-            unsafe extern "system" fn(*mut c_void, u32, u32) -> HRESULT,
-            DXGISwapChainPresentType,
-        >(swap_chain.vtable().Present)
-    };
+// Provide the render loops for DirectX 11 swap chains presented through the
+// DirectX 12 hooks, without creating DirectX 11 hooks.
+#[cfg(feature = "dx12")]
+pub(super) fn set_render_loops(render_loops: RenderLoops) {
+    unsafe { PIPELINES.lock().set_render_loops(render_loops) };
+}
 
-    present_ptr
+pub(super) unsafe fn cleanup() {
+    PIPELINES.lock().clear();
+    let live = SWAP_CHAINS.lock().take_live();
+    super::forget_destruction(&live, &DESTRUCTION_NOTIFIER);
 }
 
 /// Hooks for DirectX 11.
-pub struct ImguiDx11Hooks([MhHook; 1]);
+pub struct ImguiDx11Hooks([MhHook; 2]);
 
 impl ImguiDx11Hooks {
     /// Construct a set of [`MhHook`]s that will render UI via the
@@ -152,6 +240,7 @@ impl ImguiDx11Hooks {
     ///
     /// The following functions are hooked:
     /// - `IDXGISwapChain::Present`
+    /// - `IDXGISwapChain1::Present1`
     ///
     /// # Safety
     ///
@@ -160,23 +249,39 @@ impl ImguiDx11Hooks {
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        let dxgi_swap_chain_present_addr = get_target_addrs();
+        Self::with_render_loops(RenderLoops::single(t), &HookStrategy::Inline)
+    }
 
-        trace!("IDXGISwapChain::Present = {:p}", dxgi_swap_chain_present_addr as *const c_void);
-        let hook_present = MhHook::new(
-            dxgi_swap_chain_present_addr as *mut _,
-            dxgi_swap_chain_present_impl as *mut _,
-        )
-        .expect("couldn't create IDXGISwapChain::Present hook");
+    /// Construct a set of hooks like [`ImguiDx11Hooks::new`], patching the
+    /// hooked functions with the given strategy.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn with_strategy<T>(t: T, strategy: HookStrategy) -> Self
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Self::with_render_loops(RenderLoops::single(t), &strategy)
+    }
 
-        RENDER_LOOP.get_or_init(|| Box::new(t));
+    pub(super) unsafe fn with_render_loops(
+        render_loops: RenderLoops,
+        strategy: &HookStrategy,
+    ) -> Self {
+        let [hook_present, hook_present1] = create_hooks(strategy);
+
+        PIPELINES.lock().set_render_loops(render_loops);
         TRAMPOLINES.get_or_init(|| Trampolines {
             dxgi_swap_chain_present: mem::transmute::<*mut c_void, DXGISwapChainPresentType>(
                 hook_present.trampoline(),
             ),
+            dxgi_swap_chain1_present1: mem::transmute::<*mut c_void, DXGISwapChain1Present1Type>(
+                hook_present1.trampoline(),
+            ),
         });
 
-        Self([hook_present])
+        Self([hook_present, hook_present1])
     }
 }
 
@@ -189,13 +294,38 @@ impl Hooks for ImguiDx11Hooks {
         Box::new(unsafe { Self::new(t) })
     }
 
+    fn from_render_loop_with_strategy<T>(t: T, strategy: HookStrategy) -> Box<Self>
+    where
+        Self: Sized,
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Box::new(unsafe { Self::with_strategy(t, strategy) })
+    }
+
     fn hooks(&self) -> &[MhHook] {
         &self.0
     }
 
     unsafe fn unhook(&mut self) {
         TRAMPOLINES.take();
-        PIPELINE.take().map(|p| p.into_inner().take());
-        RENDER_LOOP.take(); // should already be null
+        cleanup();
+    }
+}
+
+impl PerWindowHooks for ImguiDx11Hooks {
+    fn from_render_loop_factory<F>(factory: F) -> Box<Self>
+    where
+        Self: Sized,
+        F: Fn(&SwapChainInfo) -> Option<Box<dyn ImguiRenderLoop + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Box::new(unsafe {
+            Self::with_render_loops(
+                RenderLoops::PerWindow(Arc::new(factory)),
+                &HookStrategy::Inline,
+            )
+        })
     }
 }
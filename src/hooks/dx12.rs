@@ -3,13 +3,11 @@
 use std::ffi::c_void;
 use std::mem;
 use std::sync::atomic::Ordering;
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
 
-use imgui::Context;
-use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use tracing::{debug, error, trace, warn};
-use windows::core::{Error, Interface, Result, HRESULT};
+use windows::core::{Error, Interface, Result, GUID, HRESULT};
 use windows::Win32::Foundation::BOOL;
 use windows::Win32::Graphics::Direct3D::D3D_FEATURE_LEVEL_11_0;
 use windows::Win32::Graphics::Direct3D12::{
@@ -21,19 +19,30 @@ use windows::Win32::Graphics::Dxgi::Common::{
     DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_RATIONAL, DXGI_SAMPLE_DESC,
 };
 use windows::Win32::Graphics::Dxgi::{
-    CreateDXGIFactory2, IDXGIFactory2, IDXGISwapChain, IDXGISwapChain3, DXGI_SWAP_CHAIN_DESC,
-    DXGI_SWAP_CHAIN_FLAG_ALLOW_MODE_SWITCH, DXGI_SWAP_EFFECT_FLIP_DISCARD,
-    DXGI_USAGE_RENDER_TARGET_OUTPUT,
+    CreateDXGIFactory2, IDXGIFactory2, IDXGISwapChain, IDXGISwapChain1, IDXGISwapChain3,
+    DXGI_PRESENT_PARAMETERS, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_CHAIN_FLAG_ALLOW_MODE_SWITCH,
+    DXGI_SWAP_EFFECT_FLIP_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
 };
 
-use super::DummyHwnd;
+use super::auto::GraphicsApi;
+use super::{DummyHwnd, HookStrategy, PresentGuard, SwapChainDesc, SwapChainInfo, Tracked};
 use crate::mh::MhHook;
-use crate::renderer::{D3D12RenderEngine, Pipeline};
-use crate::{perform_eject, util, Hooks, ImguiRenderLoop, EJECT_REQUESTED, HOOK_EJECTION_BARRIER};
+use crate::renderer::{color, D3D12RenderEngine, Pipelines, RenderLoops};
+use crate::{
+    perform_eject, util, Hooks, ImguiRenderLoop, PerWindowHooks, EJECT_REQUESTED,
+    HOOK_EJECTION_BARRIER,
+};
 
 type DXGISwapChainPresentType =
     unsafe extern "system" fn(this: IDXGISwapChain3, sync_interval: u32, flags: u32) -> HRESULT;
 
+type DXGISwapChain1Present1Type = unsafe extern "system" fn(
+    this: IDXGISwapChain3,
+    sync_interval: u32,
+    flags: u32,
+    present_parameters: *const DXGI_PRESENT_PARAMETERS,
+) -> HRESULT;
+
 type DXGISwapChainResizeBuffersType = unsafe extern "system" fn(
     this: IDXGISwapChain3,
     buffer_count: u32,
@@ -51,70 +60,65 @@ type D3D12CommandQueueExecuteCommandListsType = unsafe extern "system" fn(
 
 struct Trampolines {
     dxgi_swap_chain_present: DXGISwapChainPresentType,
+    dxgi_swap_chain1_present1: DXGISwapChain1Present1Type,
     dxgi_swap_chain_resize_buffers: DXGISwapChainResizeBuffersType,
     d3d12_command_queue_execute_command_lists: D3D12CommandQueueExecuteCommandListsType,
 }
 
 static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
 
-enum InitializationContext {
-    Empty,
-    WithSwapChain(IDXGISwapChain3),
-    Complete(IDXGISwapChain3, ID3D12CommandQueue),
-    Done,
+// Swap chains waiting for the command queue they present on to be found, and
+// those whose command queue was found but whose pipeline is not built yet. Swap
+// chains are identified by their address, so as not to keep them alive.
+struct InitializationContext {
+    pending: Vec<usize>,
+    complete: Vec<(usize, ID3D12CommandQueue)>,
 }
 
 impl InitializationContext {
-    // Transition to a state where the swap chain is set. Ignore other mutations.
-    fn insert_swap_chain(&mut self, swap_chain: &IDXGISwapChain3) {
-        *self = match mem::replace(self, InitializationContext::Empty) {
-            InitializationContext::Empty => {
-                InitializationContext::WithSwapChain(swap_chain.clone())
-            },
-            s => s,
+    const fn new() -> Self {
+        Self { pending: Vec::new(), complete: Vec::new() }
+    }
+
+    // Start looking for the command queue of the swap chain.
+    fn insert_swap_chain(&mut self, swap_chain: usize) {
+        if !self.holds(swap_chain) {
+            self.pending.push(swap_chain);
         }
     }
 
-    // Transition to a complete state if the swap chain is set and the command queue
-    // is associated with it.
+    // Complete the pending swap chains the command queue is associated with.
     fn insert_command_queue(&mut self, command_queue: &ID3D12CommandQueue) {
-        *self = match mem::replace(self, InitializationContext::Empty) {
-            InitializationContext::WithSwapChain(swap_chain) => {
-                if unsafe { Self::check_command_queue(&swap_chain, command_queue) } {
-                    trace!(
-                        "Found command queue matching swap chain {swap_chain:?} at \
-                         {command_queue:?}"
-                    );
-                    InitializationContext::Complete(swap_chain, command_queue.clone())
-                } else {
-                    InitializationContext::WithSwapChain(swap_chain)
-                }
-            },
-            s => s,
+        let (complete, pending) =
+            mem::take(&mut self.pending).into_iter().partition(|&swap_chain| unsafe {
+                Self::check_command_queue(swap_chain, command_queue)
+            });
+        self.pending = pending;
+
+        for swap_chain in complete {
+            trace!("Found command queue matching swap chain {swap_chain:#x} at {command_queue:?}");
+            self.complete.push((swap_chain, command_queue.clone()));
         }
     }
 
-    // Retrieve the values if the context is complete.
-    fn get(&self) -> Option<(IDXGISwapChain3, ID3D12CommandQueue)> {
-        if let InitializationContext::Complete(swap_chain, command_queue) = self {
-            Some((swap_chain.clone(), command_queue.clone()))
-        } else {
-            None
-        }
+    // Retrieve the command queue of the swap chain, if it was found.
+    fn get(&self, swap_chain: usize) -> Option<ID3D12CommandQueue> {
+        self.complete.iter().find(|&&(s, _)| s == swap_chain).map(|(_, c)| c.clone())
     }
 
-    // Mark the context as done so no further operations are executed on it.
-    fn done(&mut self) {
-        if let InitializationContext::Complete(..) = self {
-            *self = InitializationContext::Done;
-        }
+    // Whether the context holds the swap chain.
+    fn holds(&self, swap_chain: usize) -> bool {
+        self.pending.iter().chain(self.complete.iter().map(|(s, _)| s)).any(|&s| s == swap_chain)
     }
 
-    unsafe fn check_command_queue(
-        swap_chain: &IDXGISwapChain3,
-        command_queue: &ID3D12CommandQueue,
-    ) -> bool {
-        let swap_chain_ptr = swap_chain.as_raw() as *mut *mut c_void;
+    // Forget the swap chain, once its pipeline is built or it has been released.
+    fn remove(&mut self, swap_chain: usize) {
+        self.pending.retain(|&s| s != swap_chain);
+        self.complete.retain(|&(s, _)| s != swap_chain);
+    }
+
+    unsafe fn check_command_queue(swap_chain: usize, command_queue: &ID3D12CommandQueue) -> bool {
+        let swap_chain_ptr = swap_chain as *mut *mut c_void;
         let readable_ptrs = util::readable_region(swap_chain_ptr, 512);
 
         match readable_ptrs.iter().position(|&ptr| std::ptr::eq(ptr, command_queue.as_raw())) {
@@ -138,52 +142,67 @@ impl InitializationContext {
 }
 
 static INITIALIZATION_CONTEXT: Mutex<InitializationContext> =
-    Mutex::new(InitializationContext::Empty);
-static mut PIPELINE: OnceCell<Mutex<Pipeline<D3D12RenderEngine>>> = OnceCell::new();
-static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
-
-unsafe fn init_pipeline() -> Result<Mutex<Pipeline<D3D12RenderEngine>>> {
-    let Some((swap_chain, command_queue)) = ({ INITIALIZATION_CONTEXT.lock().get() }) else {
-        error!("Initialization context incomplete");
-        return Err(Error::from_hresult(HRESULT(-1)));
-    };
-
-    let hwnd = util::try_out_param(|v| swap_chain.GetDesc(v)).map(|desc| desc.OutputWindow)?;
-
-    let mut ctx = Context::create();
-    let engine = D3D12RenderEngine::new(&command_queue, &mut ctx)?;
-
-    let Some(render_loop) = RENDER_LOOP.take() else {
-        error!("Render loop not yet initialized");
-        return Err(Error::from_hresult(HRESULT(-1)));
-    };
-
-    let pipeline = Pipeline::new(hwnd, ctx, engine, render_loop).map_err(|(e, render_loop)| {
-        RENDER_LOOP.get_or_init(move || render_loop);
-        e
-    })?;
-
-    {
-        INITIALIZATION_CONTEXT.lock().done();
-    }
-
-    Ok(Mutex::new(pipeline))
-}
+    Mutex::new(InitializationContext::new());
+// Pipelines, keyed by the address of their swap chain.
+static mut PIPELINES: Mutex<Pipelines<usize, D3D12RenderEngine>> = Mutex::new(Pipelines::new());
+static SWAP_CHAINS: Mutex<Tracked> = Mutex::new(Tracked::new());
+// Private data of the swap chains, notifying their destruction.
+const DESTRUCTION_NOTIFIER: GUID = GUID::from_u128(0x2f8575cb_3900_4ea3_8e89_31df05072b9a);
 
 fn render(swap_chain: &IDXGISwapChain3) -> Result<()> {
     unsafe {
-        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline())?;
-
-        let Some(mut pipeline) = pipeline.try_lock() else {
-            error!("Could not lock pipeline");
+        let Some(mut pipelines) = PIPELINES.try_lock() else {
+            error!("Could not lock pipelines");
             return Err(Error::from_hresult(HRESULT(-1)));
         };
 
-        pipeline.prepare_render()?;
+        let addr = swap_chain.as_raw() as usize;
+
+        // Tear down the pipelines of the swap chains the application has released,
+        // so they can be rebuilt on new ones.
+        let (inserted, released) = {
+            let mut swap_chains = SWAP_CHAINS.lock();
+            (swap_chains.insert(addr), swap_chains.take_released())
+        };
+        pipelines.retain(|addr| !released.contains(addr));
+
+        if inserted {
+            super::notify_destruction(swap_chain, &DESTRUCTION_NOTIFIER, released_swap_chain)?;
+        }
+
+        let Some(pipeline) = pipelines.get_or_try_init(
+            &addr,
+            || {
+                let desc = util::try_out_param(|v| swap_chain.GetDesc(v))?;
+                Ok(SwapChainInfo::new(SwapChainDesc::Dxgi(desc), desc.OutputWindow))
+            },
+            |ctx| {
+                let command_queue = {
+                    let mut initialization_context = INITIALIZATION_CONTEXT.lock();
+                    initialization_context.insert_swap_chain(addr);
+                    initialization_context.get(addr)
+                };
+
+                let Some(command_queue) = command_queue else {
+                    error!("Initialization context incomplete");
+                    return Err(Error::from_hresult(HRESULT(-1)));
+                };
+
+                let mut engine = D3D12RenderEngine::new(&command_queue, ctx)?;
+                engine.set_hdr10_output(color::is_hdr10_output(swap_chain));
+                INITIALIZATION_CONTEXT.lock().remove(addr);
+
+                Ok(engine)
+            },
+        )?
+        else {
+            return Ok(());
+        };
 
         let target: ID3D12Resource =
             swap_chain.GetBuffer(swap_chain.GetCurrentBackBufferIndex())?;
 
+        pipeline.prepare_render(&target)?;
         pipeline.render(target)?;
     }
 
@@ -196,28 +215,76 @@ unsafe extern "system" fn dxgi_swap_chain_present_impl(
     flags: u32,
 ) -> HRESULT {
     let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
-    {
-        INITIALIZATION_CONTEXT.lock().insert_swap_chain(&swap_chain);
-    }
 
     let Trampolines { dxgi_swap_chain_present, .. } =
         TRAMPOLINES.get().expect("DirectX 12 trampolines uninitialized");
 
-    if let Err(e) = render(&swap_chain) {
-        util::print_dxgi_debug_messages();
-        error!("Render error: {e:?}");
-    }
+    let present_guard = present(&swap_chain);
 
     trace!("Call IDXGISwapChain::Present trampoline");
     let result = dxgi_swap_chain_present(swap_chain, sync_interval, flags);
 
-    if EJECT_REQUESTED.load(Ordering::SeqCst) {
+    if present_guard.is_some() && EJECT_REQUESTED.load(Ordering::SeqCst) {
         perform_eject();
     }
 
     result
 }
 
+unsafe extern "system" fn dxgi_swap_chain1_present1_impl(
+    swap_chain: IDXGISwapChain3,
+    sync_interval: u32,
+    flags: u32,
+    present_parameters: *const DXGI_PRESENT_PARAMETERS,
+) -> HRESULT {
+    let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
+
+    let Trampolines { dxgi_swap_chain1_present1, .. } =
+        TRAMPOLINES.get().expect("DirectX 12 trampolines uninitialized");
+
+    let present_guard = present(&swap_chain);
+
+    trace!("Call IDXGISwapChain1::Present1 trampoline");
+    let result = dxgi_swap_chain1_present1(swap_chain, sync_interval, flags, present_parameters);
+
+    if present_guard.is_some() && EJECT_REQUESTED.load(Ordering::SeqCst) {
+        perform_eject();
+    }
+
+    result
+}
+
+// Render the frame, unless a present call is already in progress on this
+// thread. The returned guard must be held until the trampoline returns.
+unsafe fn present(swap_chain: &IDXGISwapChain3) -> Option<PresentGuard> {
+    let present_guard = PresentGuard::enter()?;
+
+    // DXGI shares the `Present` implementation between DirectX 11 and DirectX 12
+    // swap chains. When running under `ImguiAutoHooks` with both APIs loaded,
+    // DirectX 11 swap chains are handed over to the DirectX 11 renderer.
+    #[cfg(feature = "dx11")]
+    let is_dx11 =
+        super::auto::routes_dx11() && swap_chain.GetDevice::<ID3D12CommandQueue>().is_err();
+    #[cfg(not(feature = "dx11"))]
+    let is_dx11 = false;
+
+    if is_dx11 {
+        #[cfg(feature = "dx11")]
+        if super::auto::claim(GraphicsApi::Dx11) {
+            if let Err(e) = super::dx11::render(swap_chain) {
+                error!("Render error: {e:?}");
+            }
+        }
+    } else if super::auto::claim(GraphicsApi::Dx12) {
+        if let Err(e) = render(swap_chain) {
+            util::print_dxgi_debug_messages();
+            error!("Render error: {e:?}");
+        }
+    }
+
+    Some(present_guard)
+}
+
 unsafe extern "system" fn dxgi_swap_chain_resize_buffers_impl(
     p_this: IDXGISwapChain3,
     buffer_count: u32,
@@ -234,6 +301,12 @@ unsafe extern "system" fn dxgi_swap_chain_resize_buffers_impl(
     dxgi_swap_chain_resize_buffers(p_this, buffer_count, width, height, new_format, flags)
 }
 
+// Forget the destroyed swap chain at `addr`, so that its pipeline is torn down.
+fn released_swap_chain(addr: usize) {
+    INITIALIZATION_CONTEXT.lock().remove(addr);
+    SWAP_CHAINS.lock().release(addr);
+}
+
 unsafe extern "system" fn d3d12_command_queue_execute_command_lists_impl(
     command_queue: ID3D12CommandQueue,
     num_command_lists: u32,
@@ -255,11 +328,67 @@ unsafe extern "system" fn d3d12_command_queue_execute_command_lists_impl(
     d3d12_command_queue_execute_command_lists(command_queue, num_command_lists, command_lists);
 }
 
-fn get_target_addrs() -> (
-    DXGISwapChainPresentType,
-    DXGISwapChainResizeBuffersType,
-    D3D12CommandQueueExecuteCommandListsType,
-) {
+// Hook the functions of a swap chain and of its command queue, or of dummy
+// ones unless the strategy targets an instance.
+unsafe fn create_hooks(strategy: &HookStrategy) -> [MhHook; 4] {
+    let (swap_chain, command_queue) = match strategy {
+        HookStrategy::Instance(object) => {
+            let swap_chain: IDXGISwapChain =
+                object.cast().expect("the hooked object should be a DXGI swap chain");
+            let command_queue: ID3D12CommandQueue = swap_chain
+                .GetDevice()
+                .expect("the hooked swap chain should present on a DirectX 12 command queue");
+
+            // DXGI swap chains implement all their interfaces with one vtable.
+            super::copy_vtable(&swap_chain, super::dxgi_swap_chain_vtable_len(&swap_chain));
+            super::copy_vtable(&command_queue, super::vtable_len::<ID3D12CommandQueue>());
+            (swap_chain, command_queue)
+        },
+        HookStrategy::Inline | HookStrategy::SharedVTable => {
+            return with_dummy_swap_chain(|swap_chain, command_queue| {
+                hook_swap_chain(swap_chain, command_queue, strategy)
+            });
+        },
+    };
+
+    hook_swap_chain(&swap_chain, &command_queue, strategy)
+}
+
+unsafe fn hook_swap_chain(
+    swap_chain: &IDXGISwapChain,
+    command_queue: &ID3D12CommandQueue,
+    strategy: &HookStrategy,
+) -> [MhHook; 4] {
+    let swap_chain1: IDXGISwapChain1 =
+        swap_chain.cast().expect("IDXGISwapChain1 should be supported");
+
+    let present = &swap_chain.vtable().Present;
+    trace!("IDXGISwapChain::Present = {:p}", *present as *const c_void);
+    let hook_present = strategy
+        .hook(present, dxgi_swap_chain_present_impl as *mut _)
+        .expect("couldn't create IDXGISwapChain::Present hook");
+
+    let present1 = &swap_chain1.vtable().Present1;
+    trace!("IDXGISwapChain1::Present1 = {:p}", *present1 as *const c_void);
+    let hook_present1 = strategy
+        .hook(present1, dxgi_swap_chain1_present1_impl as *mut _)
+        .expect("couldn't create IDXGISwapChain1::Present1 hook");
+
+    let hook_resize_buffers = strategy
+        .hook(&swap_chain.vtable().ResizeBuffers, dxgi_swap_chain_resize_buffers_impl as *mut _)
+        .expect("couldn't create IDXGISwapChain::ResizeBuffers hook");
+
+    let hook_cqecl = strategy
+        .hook(
+            &command_queue.vtable().ExecuteCommandLists,
+            d3d12_command_queue_execute_command_lists_impl as *mut _,
+        )
+        .expect("couldn't create ID3D12CommandQueue::ExecuteCommandLists hook");
+
+    [hook_present, hook_present1, hook_resize_buffers, hook_cqecl]
+}
+
+fn with_dummy_swap_chain<R>(f: impl FnOnce(&IDXGISwapChain, &ID3D12CommandQueue) -> R) -> R {
     let dummy_hwnd = DummyHwnd::new();
 
     let factory: IDXGIFactory2 = unsafe { CreateDXGIFactory2(0) }.unwrap();
@@ -311,18 +440,15 @@ fn get_target_addrs() -> (
         },
     };
 
-    let present_ptr: DXGISwapChainPresentType =
-        unsafe { mem::transmute(swap_chain.vtable().Present) };
-    let resize_buffers_ptr: DXGISwapChainResizeBuffersType =
-        unsafe { mem::transmute(swap_chain.vtable().ResizeBuffers) };
-    let cqecl_ptr: D3D12CommandQueueExecuteCommandListsType =
-        unsafe { mem::transmute(command_queue.vtable().ExecuteCommandLists) };
-
-    (present_ptr, resize_buffers_ptr, cqecl_ptr)
+    f(&swap_chain, &command_queue)
 }
 
+// Number of leading hooks, in the order returned by `hooks()`, that target DXGI
+// functions shared with DirectX 11 swap chains.
+pub(super) const DXGI_SHARED_HOOKS: usize = 2;
+
 /// Hooks for DirectX 12.
-pub struct ImguiDx12Hooks([MhHook; 3]);
+pub struct ImguiDx12Hooks([MhHook; 4]);
 
 impl ImguiDx12Hooks {
     /// Construct a set of [`MhHook`]s that will render UI via the
@@ -330,6 +456,7 @@ impl ImguiDx12Hooks {
     ///
     /// The following functions are hooked:
     /// - `IDXGISwapChain3::Present`
+    /// - `IDXGISwapChain3::Present1`
     /// - `IDXGISwapChain3::ResizeBuffers`
     /// - `ID3D12CommandQueue::ExecuteCommandLists`
     ///
@@ -340,35 +467,37 @@ impl ImguiDx12Hooks {
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        let (
-            dxgi_swap_chain_present_addr,
-            dxgi_swap_chain_resize_buffers_addr,
-            d3d12_command_queue_execute_command_lists_addr,
-        ) = get_target_addrs();
-
-        trace!("IDXGISwapChain::Present = {:p}", dxgi_swap_chain_present_addr as *const c_void);
-        let hook_present = MhHook::new(
-            dxgi_swap_chain_present_addr as *mut _,
-            dxgi_swap_chain_present_impl as *mut _,
-        )
-        .expect("couldn't create IDXGISwapChain::Present hook");
-        let hook_resize_buffers = MhHook::new(
-            dxgi_swap_chain_resize_buffers_addr as *mut _,
-            dxgi_swap_chain_resize_buffers_impl as *mut _,
-        )
-        .expect("couldn't create IDXGISwapChain::ResizeBuffers hook");
-        let hook_cqecl = MhHook::new(
-            d3d12_command_queue_execute_command_lists_addr as *mut _,
-            d3d12_command_queue_execute_command_lists_impl as *mut _,
-        )
-        .expect("couldn't create ID3D12CommandQueue::ExecuteCommandLists hook");
+        Self::with_render_loops(RenderLoops::single(t), &HookStrategy::Inline)
+    }
+
+    /// Construct a set of hooks like [`ImguiDx12Hooks::new`], patching the
+    /// hooked functions with the given strategy.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn with_strategy<T>(t: T, strategy: HookStrategy) -> Self
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Self::with_render_loops(RenderLoops::single(t), &strategy)
+    }
+
+    pub(super) unsafe fn with_render_loops(
+        render_loops: RenderLoops,
+        strategy: &HookStrategy,
+    ) -> Self {
+        let [hook_present, hook_present1, hook_resize_buffers, hook_cqecl] = create_hooks(strategy);
 
-        RENDER_LOOP.get_or_init(|| Box::new(t));
+        PIPELINES.lock().set_render_loops(render_loops);
 
         TRAMPOLINES.get_or_init(|| Trampolines {
             dxgi_swap_chain_present: mem::transmute::<*mut c_void, DXGISwapChainPresentType>(
                 hook_present.trampoline(),
             ),
+            dxgi_swap_chain1_present1: mem::transmute::<*mut c_void, DXGISwapChain1Present1Type>(
+                hook_present1.trampoline(),
+            ),
             dxgi_swap_chain_resize_buffers: mem::transmute::<
                 *mut c_void,
                 DXGISwapChainResizeBuffersType,
@@ -379,7 +508,7 @@ impl ImguiDx12Hooks {
             >(hook_cqecl.trampoline()),
         });
 
-        Self([hook_present, hook_resize_buffers, hook_cqecl])
+        Self([hook_present, hook_present1, hook_resize_buffers, hook_cqecl])
     }
 }
 
@@ -392,15 +521,42 @@ impl Hooks for ImguiDx12Hooks {
         Box::new(unsafe { Self::new(t) })
     }
 
+    fn from_render_loop_with_strategy<T>(t: T, strategy: HookStrategy) -> Box<Self>
+    where
+        Self: Sized,
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Box::new(unsafe { Self::with_strategy(t, strategy) })
+    }
+
     fn hooks(&self) -> &[MhHook] {
         &self.0
     }
 
     unsafe fn unhook(&mut self) {
         TRAMPOLINES.take();
-        PIPELINE.take().map(|p| p.into_inner().take());
-        RENDER_LOOP.take(); // should already be null
+        PIPELINES.lock().clear();
+
+        *INITIALIZATION_CONTEXT.lock() = InitializationContext::new();
+        let live = SWAP_CHAINS.lock().take_live();
+        super::forget_destruction(&live, &DESTRUCTION_NOTIFIER);
+    }
+}
 
-        *INITIALIZATION_CONTEXT.lock() = InitializationContext::Empty;
+impl PerWindowHooks for ImguiDx12Hooks {
+    fn from_render_loop_factory<F>(factory: F) -> Box<Self>
+    where
+        Self: Sized,
+        F: Fn(&SwapChainInfo) -> Option<Box<dyn ImguiRenderLoop + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Box::new(unsafe {
+            Self::with_render_loops(
+                RenderLoops::PerWindow(Arc::new(factory)),
+                &HookStrategy::Inline,
+            )
+        })
     }
 }
@@ -2,25 +2,25 @@
 
 use std::ffi::c_void;
 use std::mem;
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
 
-use imgui::Context;
-use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
-use tracing::{error, trace};
+use tracing::{error, trace, warn};
 use windows::core::{Error, Interface, Result, HRESULT};
 use windows::Win32::Foundation::{BOOL, HWND, RECT};
 use windows::Win32::Graphics::Direct3D9::{
-    Direct3DCreate9, IDirect3DDevice9, D3DADAPTER_DEFAULT, D3DBACKBUFFER_TYPE_MONO,
+    Direct3DCreate9, Direct3DCreate9Ex, IDirect3DDevice9, IDirect3DDevice9Ex, IDirect3DSwapChain9,
+    IDirect3DSwapChain9Ex, D3DADAPTER_DEFAULT, D3DBACKBUFFER_TYPE_MONO,
     D3DCREATE_SOFTWARE_VERTEXPROCESSING, D3DDEVTYPE_NULLREF, D3DDISPLAYMODE, D3DFORMAT,
     D3DPRESENT_PARAMETERS, D3DSWAPEFFECT_DISCARD, D3D_SDK_VERSION,
 };
 use windows::Win32::Graphics::Gdi::RGNDATA;
 
-use super::DummyHwnd;
+use super::auto::GraphicsApi;
+use super::{DummyHwnd, HookStrategy, PresentGuard, SwapChainDesc, SwapChainInfo};
 use crate::mh::MhHook;
-use crate::renderer::{D3D9RenderEngine, Pipeline};
-use crate::{util, Hooks, ImguiRenderLoop};
+use crate::renderer::{D3D9RenderEngine, Pipelines, RenderLoops};
+use crate::{util, Hooks, ImguiRenderLoop, PerWindowHooks};
 
 type Dx9PresentType = unsafe extern "system" fn(
     this: IDirect3DDevice9,
@@ -30,59 +30,136 @@ type Dx9PresentType = unsafe extern "system" fn(
     pdirtyregion: *const RGNDATA,
 ) -> HRESULT;
 
+type Dx9PresentExType = unsafe extern "system" fn(
+    this: IDirect3DDevice9Ex,
+    psourcerect: *const RECT,
+    pdestrect: *const RECT,
+    hdestwindowoverride: HWND,
+    pdirtyregion: *const RGNDATA,
+    dwflags: u32,
+) -> HRESULT;
+
+type Dx9SwapChainPresentType = unsafe extern "system" fn(
+    this: IDirect3DSwapChain9,
+    psourcerect: *const RECT,
+    pdestrect: *const RECT,
+    hdestwindowoverride: HWND,
+    pdirtyregion: *const RGNDATA,
+    dwflags: u32,
+) -> HRESULT;
+
 type Dx9ResetType =
     unsafe extern "system" fn(this: IDirect3DDevice9, *const D3DPRESENT_PARAMETERS) -> HRESULT;
 
+type Dx9ReleaseType = unsafe extern "system" fn(this: *mut c_void) -> u32;
+
 struct Trampolines {
     dx9_present: Dx9PresentType,
+    dx9_present_ex: Option<Dx9PresentExType>,
+    dx9_swap_chain_present: Dx9SwapChainPresentType,
     dx9_reset: Dx9ResetType,
+    dx9_release: Dx9ReleaseType,
 }
 
 static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
-static mut PIPELINE: OnceCell<Mutex<Pipeline<D3D9RenderEngine>>> = OnceCell::new();
-static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
-
-unsafe fn init_pipeline(device: &IDirect3DDevice9) -> Result<Mutex<Pipeline<D3D9RenderEngine>>> {
-    trace!("initializing pipeline");
-    let mut creation_parameters = Default::default();
-    device.GetCreationParameters(&mut creation_parameters)?;
+// Pipelines, keyed by the address of their device.
+static mut PIPELINES: Mutex<Pipelines<usize, D3D9RenderEngine>> = Mutex::new(Pipelines::new());
+static DEVICES: Mutex<Devices> = Mutex::new(Devices::new());
+
+// The references held on each device by its pipeline, through the render engine
+// and its resources, and the devices released by the application while their
+// pipelines were busy.
+//
+// Unlike swap chains, devices are kept alive by their pipeline: the application
+// is done with a device once only the pipeline holds references to it.
+struct Devices {
+    refs: Vec<(usize, u32)>,
+    released: Vec<usize>,
+}
 
-    let hwnd = creation_parameters.hFocusWindow;
+impl Devices {
+    const fn new() -> Self {
+        Self { refs: Vec::new(), released: Vec::new() }
+    }
 
-    let mut ctx = Context::create();
-    trace!("creating engine");
-    let engine = D3D9RenderEngine::new(device, &mut ctx)?;
+    // Account for the references acquired or released by the pipeline.
+    fn add_refs(&mut self, device: usize, delta: i64) {
+        match self.refs.iter_mut().find(|(d, _)| *d == device) {
+            Some((_, refs)) => *refs = (*refs as i64 + delta).max(0) as u32,
+            None if delta > 0 => self.refs.push((device, delta as u32)),
+            None => {},
+        }
+    }
 
-    let Some(render_loop) = RENDER_LOOP.take() else {
-        error!("Render loop not yet initialized");
-        return Err(Error::from_hresult(HRESULT(-1)));
-    };
+    // Forget the references held on the device, once its pipeline is torn down.
+    fn remove(&mut self, device: usize) {
+        self.refs.retain(|&(d, _)| d != device);
+    }
 
-    trace!("creating pipeline");
-    let pipeline = Pipeline::new(hwnd, ctx, engine, render_loop).map_err(|(e, render_loop)| {
-        RENDER_LOOP.get_or_init(move || render_loop);
-        e
-    })?;
-    Ok(Mutex::new(pipeline))
+    // Whether the remaining references to the device all belong to the pipeline,
+    // in which case the device is forgotten.
+    fn is_released(&mut self, device: usize, count: u32) -> bool {
+        match self.refs.iter().position(|&(d, refs)| d == device && refs == count) {
+            Some(idx) => {
+                self.refs.swap_remove(idx);
+                true
+            },
+            None => false,
+        }
+    }
 }
 
 fn render(device: &IDirect3DDevice9) -> Result<()> {
-    let pipeline = unsafe { PIPELINE.get_or_try_init(|| init_pipeline(device)) }?;
-
-    let Some(mut pipeline) = pipeline.try_lock() else {
-        error!("Could not lock pipeline");
+    let Some(mut pipelines) = unsafe { PIPELINES.try_lock() } else {
+        error!("Could not lock pipelines");
         return Err(Error::from_hresult(HRESULT(-1)));
     };
 
-    pipeline.prepare_render()?;
+    // Tear down the pipelines of the devices released while they were busy.
+    let released = mem::take(&mut DEVICES.lock().released);
+    pipelines.retain(|addr| !released.contains(addr));
 
-    let surface = unsafe { device.GetBackBuffer(0, 0, D3DBACKBUFFER_TYPE_MONO)? };
+    // Count the references the pipeline acquires or releases on the device, so
+    // that the release hook can tell when the application is done with it.
+    let addr = device.as_raw() as usize;
+    let refs = util::ref_count(device);
+    let result = render_pipeline(&mut pipelines, device);
+    let delta = util::ref_count(device) as i64 - refs as i64;
+    DEVICES.lock().add_refs(addr, delta);
 
-    unsafe { device.BeginScene() }?;
-    let render_result = pipeline.render(surface);
-    unsafe { device.EndScene() }?;
+    result
+}
 
-    render_result
+fn render_pipeline(
+    pipelines: &mut Pipelines<usize, D3D9RenderEngine>,
+    device: &IDirect3DDevice9,
+) -> Result<()> {
+    let Some(pipeline) = pipelines.get_or_try_init(
+        &(device.as_raw() as usize),
+        || unsafe {
+            let mut creation_parameters = Default::default();
+            device.GetCreationParameters(&mut creation_parameters)?;
+            let mut present_params = Default::default();
+            device.GetSwapChain(0)?.GetPresentParameters(&mut present_params)?;
+
+            Ok(SwapChainInfo::new(
+                SwapChainDesc::D3D9(present_params),
+                creation_parameters.hFocusWindow,
+            ))
+        },
+        |ctx| {
+            trace!("creating engine");
+            unsafe { D3D9RenderEngine::new(device, ctx) }
+        },
+    )?
+    else {
+        return Ok(());
+    };
+
+    let surface = unsafe { device.GetBackBuffer(0, 0, D3DBACKBUFFER_TYPE_MONO)? };
+
+    pipeline.prepare_render(&surface)?;
+    pipeline.render(surface)
 }
 
 unsafe extern "system" fn dx9_present_impl(
@@ -95,13 +172,73 @@ unsafe extern "system" fn dx9_present_impl(
     let Trampolines { dx9_present, .. } =
         TRAMPOLINES.get().expect("DirectX 9 trampolines uninitialized");
 
-    if let Err(e) = render(&device) {
-        error!("Render error: {e:?}");
-    }
+    let _present_guard = present(&device);
 
     trace!("Call IDirect3DDevice9::Present trampoline");
     dx9_present(device, psourcerect, pdestrect, hdestwindowoverride, pdirtyregion)
 }
+
+unsafe extern "system" fn dx9_present_ex_impl(
+    device: IDirect3DDevice9Ex,
+    psourcerect: *const RECT,
+    pdestrect: *const RECT,
+    hdestwindowoverride: HWND,
+    pdirtyregion: *const RGNDATA,
+    dwflags: u32,
+) -> HRESULT {
+    let Trampolines { dx9_present_ex, .. } =
+        TRAMPOLINES.get().expect("DirectX 9 trampolines uninitialized");
+    let dx9_present_ex = dx9_present_ex.expect("IDirect3DDevice9Ex::PresentEx is not hooked");
+
+    let _present_guard = present(&device);
+
+    trace!("Call IDirect3DDevice9Ex::PresentEx trampoline");
+    dx9_present_ex(device, psourcerect, pdestrect, hdestwindowoverride, pdirtyregion, dwflags)
+}
+
+unsafe extern "system" fn dx9_swap_chain_present_impl(
+    swap_chain: IDirect3DSwapChain9,
+    psourcerect: *const RECT,
+    pdestrect: *const RECT,
+    hdestwindowoverride: HWND,
+    pdirtyregion: *const RGNDATA,
+    dwflags: u32,
+) -> HRESULT {
+    let Trampolines { dx9_swap_chain_present, .. } =
+        TRAMPOLINES.get().expect("DirectX 9 trampolines uninitialized");
+
+    let _present_guard = match swap_chain.GetDevice() {
+        Ok(device) => present(&device),
+        Err(e) => {
+            error!("Couldn't get swap chain device: {e:?}");
+            None
+        },
+    };
+
+    trace!("Call IDirect3DSwapChain9::Present trampoline");
+    dx9_swap_chain_present(
+        swap_chain,
+        psourcerect,
+        pdestrect,
+        hdestwindowoverride,
+        pdirtyregion,
+        dwflags,
+    )
+}
+
+// Render the frame, unless a present call is already in progress on this
+// thread. The returned guard must be held until the trampoline returns.
+fn present(device: &IDirect3DDevice9) -> Option<PresentGuard> {
+    let present_guard = PresentGuard::enter()?;
+
+    if super::auto::claim(GraphicsApi::Dx9) {
+        if let Err(e) = render(device) {
+            error!("Render error: {e:?}");
+        }
+    }
+
+    Some(present_guard)
+}
 unsafe extern "system" fn dx9_reset_impl(
     this: IDirect3DDevice9,
     present_params: *const D3DPRESENT_PARAMETERS,
@@ -110,16 +247,113 @@ unsafe extern "system" fn dx9_reset_impl(
         TRAMPOLINES.get().expect("DirectX 9 trampolines uninitialized");
 
     trace!("Resetting pipeline");
-    if let Some(pipeline) = PIPELINE.take() {
-        let render_loop = pipeline.into_inner().take();
+    let device_addr = this.as_raw() as usize;
+    PIPELINES.lock().retain(|&addr| addr != device_addr);
+    DEVICES.lock().remove(device_addr);
 
-        RENDER_LOOP.set(render_loop).map_err(|_| ()).expect("Render loop cell should be empty");
+    dx9_reset(this, present_params)
+}
+
+unsafe extern "system" fn dx9_release_impl(device: *mut c_void) -> u32 {
+    let Trampolines { dx9_release, .. } =
+        TRAMPOLINES.get().expect("DirectX 9 trampolines uninitialized");
+
+    let count = dx9_release(device);
+
+    // Tear down the pipeline of a device the application has released, which
+    // destroys the device, so that it can be rebuilt on a new one. If the
+    // pipelines are busy, this is done on the next frame.
+    let device = device as usize;
+    if DEVICES.lock().is_released(device, count) {
+        trace!("Device {device:#x} released");
+        match PIPELINES.try_lock() {
+            Some(mut pipelines) => pipelines.retain(|&addr| addr != device),
+            None => DEVICES.lock().released.push(device),
+        }
     }
 
-    dx9_reset(this, present_params)
+    count
+}
+
+struct DeviceHooks {
+    present: MhHook,
+    present_ex: Option<MhHook>,
+    swap_chain_present: MhHook,
+    reset: MhHook,
+    release: MhHook,
+}
+
+// Hook the functions of a device and of its swap chain, or of dummy ones
+// unless the strategy targets an instance.
+unsafe fn create_hooks(strategy: &HookStrategy) -> DeviceHooks {
+    match strategy {
+        HookStrategy::Instance(object) => {
+            let device: IDirect3DDevice9 =
+                object.cast().expect("the hooked object should be a DirectX 9 device");
+            let device_ex = device.cast::<IDirect3DDevice9Ex>().ok();
+            let swap_chain = device
+                .GetSwapChain(0)
+                .expect("IDirect3DDevice9::GetSwapChain: failed to get swap chain");
+
+            let device_len = match device_ex {
+                Some(_) => super::vtable_len::<IDirect3DDevice9Ex>(),
+                None => super::vtable_len::<IDirect3DDevice9>(),
+            };
+            let swap_chain_len = match swap_chain.cast::<IDirect3DSwapChain9Ex>() {
+                Ok(_) => super::vtable_len::<IDirect3DSwapChain9Ex>(),
+                Err(_) => super::vtable_len::<IDirect3DSwapChain9>(),
+            };
+            super::copy_vtable(&device, device_len);
+            super::copy_vtable(&swap_chain, swap_chain_len);
+
+            hook_device(&device, device_ex.as_ref(), strategy)
+        },
+        HookStrategy::Inline | HookStrategy::SharedVTable => {
+            with_dummy_devices(|device, device_ex| hook_device(device, device_ex, strategy))
+        },
+    }
 }
 
-fn get_target_addrs() -> (Dx9PresentType, Dx9ResetType) {
+unsafe fn hook_device(
+    device: &IDirect3DDevice9,
+    device_ex: Option<&IDirect3DDevice9Ex>,
+    strategy: &HookStrategy,
+) -> DeviceHooks {
+    let swap_chain =
+        device.GetSwapChain(0).expect("IDirect3DDevice9::GetSwapChain: failed to get swap chain");
+
+    let present = &device.vtable().Present;
+    trace!("IDirect3DDevice9::Present = {:p}", *present as *const c_void);
+    let present = strategy
+        .hook(present, dx9_present_impl as *mut c_void)
+        .expect("couldn't create IDirect3DDevice9::Present hook");
+
+    let present_ex = device_ex.map(|device_ex| {
+        let present_ex = &device_ex.vtable().PresentEx;
+        trace!("IDirect3DDevice9Ex::PresentEx = {:p}", *present_ex as *const c_void);
+        strategy
+            .hook(present_ex, dx9_present_ex_impl as *mut c_void)
+            .expect("couldn't create IDirect3DDevice9Ex::PresentEx hook")
+    });
+
+    let swap_chain_present = &swap_chain.vtable().Present;
+    trace!("IDirect3DSwapChain9::Present = {:p}", *swap_chain_present as *const c_void);
+    let swap_chain_present = strategy
+        .hook(swap_chain_present, dx9_swap_chain_present_impl as *mut c_void)
+        .expect("couldn't create IDirect3DSwapChain9::Present hook");
+
+    let reset = strategy
+        .hook(&device.vtable().Reset, dx9_reset_impl as *mut c_void)
+        .expect("couldn't create IDirect3DDevice9::Reset hook");
+
+    let release = strategy
+        .hook(&device.vtable().base__.Release, dx9_release_impl as *mut c_void)
+        .expect("couldn't create IDirect3DDevice9::Release hook");
+
+    DeviceHooks { present, present_ex, swap_chain_present, reset, release }
+}
+
+fn with_dummy_devices<R>(f: impl FnOnce(&IDirect3DDevice9, Option<&IDirect3DDevice9Ex>) -> R) -> R {
     let d9 = unsafe { Direct3DCreate9(D3D_SDK_VERSION).unwrap() };
 
     let mut d3d_display_mode =
@@ -148,31 +382,35 @@ fn get_target_addrs() -> (Dx9PresentType, Dx9ResetType) {
     })
     .expect("IDirect3DDevice9::CreateDevice: failed to create device");
 
-    let present_ptr = device.vtable().Present;
-    let reset_ptr = device.vtable().Reset;
-
-    unsafe {
-        (
-            mem::transmute::<
-                unsafe extern "system" fn(
-                    *mut c_void,
-                    *const RECT,
-                    *const RECT,
-                    HWND,
-                    *const RGNDATA,
-                ) -> HRESULT,
-                Dx9PresentType,
-            >(present_ptr),
-            mem::transmute::<
-                unsafe extern "system" fn(*mut c_void, *mut D3DPRESENT_PARAMETERS) -> HRESULT,
-                Dx9ResetType,
-            >(reset_ptr),
-        )
-    }
+    // `IDirect3DDevice9Ex` is not available everywhere: if it can't be created,
+    // `PresentEx` is simply not hooked.
+    let device_ex = unsafe { Direct3DCreate9Ex(D3D_SDK_VERSION) }.and_then(|d9ex| {
+        util::try_out_ptr(|v| unsafe {
+            d9ex.CreateDeviceEx(
+                D3DADAPTER_DEFAULT,
+                D3DDEVTYPE_NULLREF,
+                dummy_hwnd.hwnd(),
+                D3DCREATE_SOFTWARE_VERTEXPROCESSING as u32,
+                &mut present_params,
+                std::ptr::null_mut(),
+                v,
+            )
+        })
+    });
+
+    let device_ex: Option<IDirect3DDevice9Ex> = match device_ex {
+        Ok(device_ex) => Some(device_ex),
+        Err(e) => {
+            warn!("IDirect3D9Ex::CreateDeviceEx: failed to create device: {e:?}");
+            None
+        },
+    };
+
+    f(&device, device_ex.as_ref())
 }
 
 /// Hooks for DirectX 9.
-pub struct ImguiDx9Hooks([MhHook; 2]);
+pub struct ImguiDx9Hooks(Vec<MhHook>);
 
 impl ImguiDx9Hooks {
     /// Construct a set of [`MhHook`]s that will render UI via the
@@ -180,6 +418,10 @@ impl ImguiDx9Hooks {
     ///
     /// The following functions are hooked:
     /// - `IDirect3DDevice9::Present`
+    /// - `IDirect3DDevice9Ex::PresentEx`, if available
+    /// - `IDirect3DSwapChain9::Present`
+    /// - `IDirect3DDevice9::Reset`
+    /// - `IDirect3DDevice9::Release`
     ///
     /// # Safety
     ///
@@ -188,22 +430,59 @@ impl ImguiDx9Hooks {
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        let (dx9_present_addr, dx9_reset_addr) = get_target_addrs();
+        Self::with_render_loops(RenderLoops::single(t), &HookStrategy::Inline)
+    }
 
-        trace!("IDirect3DDevice9::Present = {:p}", dx9_present_addr as *const c_void);
-        let hook_present =
-            MhHook::new(dx9_present_addr as *mut c_void, dx9_present_impl as *mut c_void)
-                .expect("couldn't create IDirect3DDevice9::Present hook");
-        let hook_reset = MhHook::new(dx9_reset_addr as *mut c_void, dx9_reset_impl as *mut c_void)
-            .expect("couldn't create IDirect3DDevice9::Reset hook");
+    /// Construct a set of hooks like [`ImguiDx9Hooks::new`], patching the
+    /// hooked functions with the given strategy.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn with_strategy<T>(t: T, strategy: HookStrategy) -> Self
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Self::with_render_loops(RenderLoops::single(t), &strategy)
+    }
 
-        RENDER_LOOP.get_or_init(|| Box::new(t));
+    pub(super) unsafe fn with_render_loops(
+        render_loops: RenderLoops,
+        strategy: &HookStrategy,
+    ) -> Self {
+        let DeviceHooks {
+            present: hook_present,
+            present_ex: hook_present_ex,
+            swap_chain_present: hook_swap_chain_present,
+            reset: hook_reset,
+            release: hook_release,
+        } = create_hooks(strategy);
+
+        PIPELINES.lock().set_render_loops(render_loops);
         TRAMPOLINES.get_or_init(|| Trampolines {
             dx9_present: mem::transmute::<*mut c_void, Dx9PresentType>(hook_present.trampoline()),
+            dx9_present_ex: hook_present_ex.as_ref().map(|hook_present_ex| {
+                mem::transmute::<*mut c_void, Dx9PresentExType>(hook_present_ex.trampoline())
+            }),
+            dx9_swap_chain_present: mem::transmute::<*mut c_void, Dx9SwapChainPresentType>(
+                hook_swap_chain_present.trampoline(),
+            ),
             dx9_reset: mem::transmute::<*mut c_void, Dx9ResetType>(hook_reset.trampoline()),
+            dx9_release: mem::transmute::<*mut c_void, Dx9ReleaseType>(hook_release.trampoline()),
         });
 
-        Self([hook_present, hook_reset])
+        Self(
+            [
+                Some(hook_present),
+                hook_present_ex,
+                Some(hook_swap_chain_present),
+                Some(hook_reset),
+                Some(hook_release),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        )
     }
 }
 
@@ -216,13 +495,39 @@ impl Hooks for ImguiDx9Hooks {
         Box::new(unsafe { Self::new(t) })
     }
 
+    fn from_render_loop_with_strategy<T>(t: T, strategy: HookStrategy) -> Box<Self>
+    where
+        Self: Sized,
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Box::new(unsafe { Self::with_strategy(t, strategy) })
+    }
+
     fn hooks(&self) -> &[MhHook] {
         &self.0
     }
 
     unsafe fn unhook(&mut self) {
         TRAMPOLINES.take();
-        PIPELINE.take().map(|p| p.into_inner().take());
-        RENDER_LOOP.take();
+        PIPELINES.lock().clear();
+        *DEVICES.lock() = Devices::new();
+    }
+}
+
+impl PerWindowHooks for ImguiDx9Hooks {
+    fn from_render_loop_factory<F>(factory: F) -> Box<Self>
+    where
+        Self: Sized,
+        F: Fn(&SwapChainInfo) -> Option<Box<dyn ImguiRenderLoop + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Box::new(unsafe {
+            Self::with_render_loops(
+                RenderLoops::PerWindow(Arc::new(factory)),
+                &HookStrategy::Inline,
+            )
+        })
     }
 }
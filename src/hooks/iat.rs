@@ -0,0 +1,293 @@
+//! Hooks patching the import and export address tables of loaded modules.
+//!
+//! Instead of patching code, an import hook replaces the address a module
+//! calls an imported function through, and an export hook replaces the
+//! address other modules resolve an exported function to. They suit targets
+//! which can't be patched inline, like functions which are too short or
+//! already patched by other tools.
+//!
+//! Import hooks only affect calls made by the hooked module, and export hooks
+//! only affect modules which resolve the function after the hook is enabled,
+//! e.g. with `GetProcAddress` or by being loaded later.
+//!
+//! The [`MhHook`]s returned by [`hook_import`] and [`hook_export`] can be
+//! listed by [`crate::Hooks::hooks`], so that [`crate::Hudhook::unapply`]
+//! restores the tables. An [`IatHook`] restores its table when dropped.
+
+use std::ffi::c_void;
+use std::marker::PhantomData;
+
+use tracing::error;
+use windows::core::HSTRING;
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+
+use crate::mh::{HookFn, MhHook, MH_STATUS};
+use crate::util::pe::{ExportTarget, ImportName, PeImage};
+
+/// A function imported or exported by a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbol<'a> {
+    /// A function imported or exported by name.
+    Name(&'a str),
+    /// A function imported or exported by ordinal.
+    Ordinal(u16),
+}
+
+/// The base address of the loaded module `module`, or of the executable.
+fn module_base(module: Option<&str>) -> Result<*mut u8, MH_STATUS> {
+    let handle = match module {
+        Some(module) => unsafe { GetModuleHandleW(&HSTRING::from(module)) },
+        None => unsafe { GetModuleHandleW(None) },
+    };
+
+    handle.map(|handle| handle.0 as *mut u8).map_err(|e| {
+        error!("Could not find the module {module:?}: {e:?}");
+        MH_STATUS::MH_ERROR_MODULE_NOT_FOUND
+    })
+}
+
+/// Find the import address table slot through which the module loaded at
+/// `base` calls the function `import` of `dll`.
+///
+/// # Safety
+///
+/// `base` must be the base address of a loaded module.
+pub unsafe fn find_import(
+    base: *mut c_void,
+    dll: &str,
+    import: Symbol,
+) -> Option<*mut *mut c_void> {
+    let image = PeImage::from_base(base).ok()?;
+    let function = image
+        .imports()
+        .ok()?
+        .into_iter()
+        .filter(|imports| imports.dll.eq_ignore_ascii_case(dll))
+        .flat_map(|imports| imports.functions)
+        .find(|function| match (function.name, import) {
+            (ImportName::Name { name, .. }, Symbol::Name(import)) => name == import,
+            (ImportName::Ordinal(ordinal), Symbol::Ordinal(import)) => ordinal == import,
+            _ => false,
+        })?;
+
+    Some(base.byte_add(function.slot as usize) as _)
+}
+
+/// Find the export address table slot holding the address of the function
+/// `export` of the module loaded at `base`, relative to `base`. Forwarded
+/// exports aren't found.
+///
+/// # Safety
+///
+/// `base` must be the base address of a loaded module.
+pub unsafe fn find_export(base: *mut c_void, export: Symbol) -> Option<*mut u32> {
+    let image = PeImage::from_base(base).ok()?;
+    let export = image.exports().ok()?.into_iter().find(|function| match export {
+        Symbol::Name(name) => function.name == Some(name),
+        Symbol::Ordinal(ordinal) => function.ordinal == ordinal,
+    })?;
+
+    match export.target {
+        ExportTarget::Rva(_) => Some(base.byte_add(export.slot as usize) as _),
+        ExportTarget::Forwarder(_) => None,
+    }
+}
+
+/// Create a hook replacing the function `import` of `dll` in the import
+/// address table of the loaded module `module`, or of the executable, with
+/// `hook_impl`.
+///
+/// # Safety
+///
+/// `hook_impl` must be of the type of the imported function.
+pub unsafe fn hook_import(
+    module: Option<&str>,
+    dll: &str,
+    import: Symbol,
+    hook_impl: *mut c_void,
+) -> Result<MhHook, MH_STATUS> {
+    let base = module_base(module)?;
+    let slot = find_import(base as _, dll, import).ok_or_else(|| {
+        error!("{module:?} doesn't import {import:?} from {dll}");
+        MH_STATUS::MH_ERROR_FUNCTION_NOT_FOUND
+    })?;
+
+    Ok(MhHook::vtable(slot, hook_impl))
+}
+
+/// Create a hook replacing the function `export` in the export address table
+/// of the loaded module `module` with `hook_impl`.
+///
+/// # Safety
+///
+/// `hook_impl` must be of the type of the exported function.
+pub unsafe fn hook_export(
+    module: &str,
+    export: Symbol,
+    hook_impl: *mut c_void,
+) -> Result<MhHook, MH_STATUS> {
+    let base = module_base(Some(module))?;
+    let slot = find_export(base as _, export).ok_or_else(|| {
+        error!("{module} doesn't export {export:?}");
+        MH_STATUS::MH_ERROR_FUNCTION_NOT_FOUND
+    })?;
+
+    let hook_impl = relay(base, hook_impl)?;
+    Ok(MhHook::export(base as _, slot, hook_impl))
+}
+
+/// A function jumping to `function`, placed after the module at `base` so that
+/// its address relative to `base` fits in an export address table slot.
+#[cfg(target_pointer_width = "64")]
+unsafe fn relay(base: *mut u8, function: *mut c_void) -> Result<*mut c_void, MH_STATUS> {
+    use windows::Win32::System::Diagnostics::Debug::FlushInstructionCache;
+    use windows::Win32::System::Memory::{
+        VirtualAlloc, VirtualProtect, MEM_COMMIT, MEM_RESERVE, PAGE_EXECUTE_READ,
+        PAGE_PROTECTION_FLAGS, PAGE_READWRITE,
+    };
+    use windows::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+    use windows::Win32::System::Threading::GetCurrentProcess;
+
+    let offset = (function as usize).wrapping_sub(base as usize);
+    if offset <= u32::MAX as usize {
+        return Ok(function);
+    }
+
+    let mut system_info = SYSTEM_INFO::default();
+    GetSystemInfo(&mut system_info);
+    let granularity = system_info.dwAllocationGranularity as usize;
+
+    // jmp [rip], followed by the address.
+    let mut code = vec![0xFF, 0x25, 0x00, 0x00, 0x00, 0x00];
+    code.extend_from_slice(&(function as usize).to_le_bytes());
+
+    // The relay lives as long as the process, as modules may have resolved the
+    // export to it.
+    let mut address = (base as usize).next_multiple_of(granularity);
+    while address - (base as usize) < u32::MAX as usize - granularity {
+        let relay =
+            VirtualAlloc(Some(address as _), code.len(), MEM_COMMIT | MEM_RESERVE, PAGE_READWRITE);
+        if relay.is_null() {
+            address += granularity;
+            continue;
+        }
+
+        (relay as *mut u8).copy_from_nonoverlapping(code.as_ptr(), code.len());
+        let mut old_protect = PAGE_PROTECTION_FLAGS::default();
+        VirtualProtect(relay, code.len(), PAGE_EXECUTE_READ, &mut old_protect).map_err(|e| {
+            error!("Could not make the relay executable: {e:?}");
+            MH_STATUS::MH_ERROR_MEMORY_PROTECT
+        })?;
+        if let Err(e) = FlushInstructionCache(GetCurrentProcess(), Some(relay), code.len()) {
+            error!("Could not flush the instruction cache: {e:?}");
+        }
+
+        return Ok(relay);
+    }
+
+    error!("Could not allocate a relay within 4 GiB of the module at {base:p}");
+    Err(MH_STATUS::MH_ERROR_MEMORY_ALLOC)
+}
+
+/// Relative addresses wrap around the 32-bit address space.
+#[cfg(target_pointer_width = "32")]
+unsafe fn relay(_base: *mut u8, function: *mut c_void) -> Result<*mut c_void, MH_STATUS> {
+    Ok(function)
+}
+
+/// A typed import or export address table hook, which restores the table
+/// when dropped.
+///
+/// ```no_run
+/// # use hudhook::hooks::iat::{IatHook, Symbol};
+/// # use std::sync::OnceLock;
+/// type Sleep = extern "system" fn(u32);
+///
+/// static SLEEP_HOOK: OnceLock<IatHook<Sleep>> = OnceLock::new();
+///
+/// extern "system" fn sleep_impl(milliseconds: u32) {
+///     SLEEP_HOOK.get().unwrap().original()(milliseconds / 2)
+/// }
+///
+/// # unsafe fn hook() {
+/// let hook =
+///     IatHook::<Sleep>::import(None, "kernel32.dll", Symbol::Name("Sleep"), sleep_impl).unwrap();
+/// let hook = SLEEP_HOOK.get_or_init(|| hook);
+/// hook.enable().unwrap();
+/// # }
+/// ```
+pub struct IatHook<F: HookFn> {
+    hook: MhHook,
+    _marker: PhantomData<F>,
+}
+
+unsafe impl<F: HookFn> Send for IatHook<F> {}
+unsafe impl<F: HookFn> Sync for IatHook<F> {}
+
+impl<F: HookFn> IatHook<F> {
+    /// Create a hook of the function `import` of `dll` in the import address
+    /// table of the loaded module `module`, or of the executable, which calls
+    /// `detour` instead once enabled.
+    ///
+    /// # Safety
+    ///
+    /// The imported function must be of type `F`.
+    pub unsafe fn import(
+        module: Option<&str>,
+        dll: &str,
+        import: Symbol,
+        detour: F,
+    ) -> Result<Self, MH_STATUS> {
+        let hook = hook_import(module, dll, import, detour.to_ptr())?;
+        Ok(Self { hook, _marker: PhantomData })
+    }
+
+    /// Create a hook of the function `export` in the export address table of
+    /// the loaded module `module`, which calls `detour` instead once enabled.
+    ///
+    /// # Safety
+    ///
+    /// The exported function must be of type `F`.
+    pub unsafe fn export(module: &str, export: Symbol, detour: F) -> Result<Self, MH_STATUS> {
+        let hook = hook_export(module, export, detour.to_ptr())?;
+        Ok(Self { hook, _marker: PhantomData })
+    }
+
+    /// The function the table pointed to before the hook.
+    pub fn original(&self) -> F {
+        unsafe { F::from_ptr(self.hook.trampoline()) }
+    }
+
+    /// The address of the hooked function.
+    pub fn target(&self) -> *mut c_void {
+        self.hook.target()
+    }
+
+    /// Enable the hook.
+    ///
+    /// # Safety
+    ///
+    /// The table is patched while other threads may be reading it.
+    pub unsafe fn enable(&self) -> Result<(), MH_STATUS> {
+        self.hook.queue_enable()
+    }
+
+    /// Disable the hook.
+    ///
+    /// # Safety
+    ///
+    /// The table is patched while other threads may be reading it.
+    pub unsafe fn disable(&self) -> Result<(), MH_STATUS> {
+        self.hook.queue_disable()
+    }
+}
+
+impl<F: HookFn> Drop for IatHook<F> {
+    fn drop(&mut self) {
+        // Table hooks are applied right away, and disabling them again is
+        // harmless.
+        if let Err(status) = unsafe { self.hook.queue_disable() } {
+            error!("Could not restore the table: {status:?}");
+        }
+    }
+}
@@ -1,28 +1,406 @@
 //! Implementations of render engine hooks.
 
+use std::cell::Cell;
+use std::ffi::c_void;
 use std::mem;
+use std::slice;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use std::ptr;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::{AtomicPtr, Ordering};
 use std::sync::OnceLock;
 
-use tracing::{debug, error};
-use windows::core::w;
-use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, WPARAM};
+use parking_lot::{Mutex, RwLock};
+use tracing::{debug, error, warn};
+use windows::core::{w, IUnknown, Interface};
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::core::{IUnknown_Vtbl, GUID, HRESULT};
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::Win32::Foundation::{E_NOINTERFACE, S_OK};
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Direct3D9::D3DPRESENT_PARAMETERS;
+use windows::Win32::Graphics::Dxgi::DXGI_SWAP_CHAIN_DESC;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Dxgi::{
+    IDXGISwapChain, IDXGISwapChain1, IDXGISwapChain2, IDXGISwapChain3, IDXGISwapChain4,
+};
 use windows::Win32::System::LibraryLoader::GetModuleHandleW;
 use windows::Win32::System::Threading::GetCurrentProcessId;
 use windows::Win32::UI::WindowsAndMessaging::{
-    CreateWindowExW, DefWindowProcW, DestroyWindow, EnumWindows, GetWindowThreadProcessId,
-    RegisterClassExW, UnregisterClassW, CS_HREDRAW, CS_VREDRAW, WNDCLASSEXW,
-    WS_EX_OVERLAPPEDWINDOW, WS_OVERLAPPEDWINDOW,
+    CreateWindowExW, DefWindowProcW, DestroyWindow, EnumWindows, GetClassNameW, GetClientRect,
+    GetWindowThreadProcessId, RegisterClassExW, UnregisterClassW, CS_HREDRAW, CS_VREDRAW,
+    WNDCLASSEXW, WS_EX_OVERLAPPEDWINDOW, WS_OVERLAPPEDWINDOW,
 };
 
+use crate::mh::{MhHook, MH_STATUS};
+
+pub mod auto;
 #[cfg(feature = "dx11")]
 pub mod dx11;
 #[cfg(feature = "dx12")]
 pub mod dx12;
 #[cfg(feature = "dx9")]
 pub mod dx9;
+pub mod iat;
 #[cfg(feature = "opengl3")]
 pub mod opengl3;
 
+type SwapChainFilter = dyn Fn(&SwapChainInfo) -> bool + Send + Sync;
+
+static SWAP_CHAIN_FILTER: RwLock<Option<Box<SwapChainFilter>>> = RwLock::new(None);
+
+/// API-specific description of a swap chain.
+#[derive(Debug, Clone, Copy)]
+pub enum SwapChainDesc {
+    /// Description of a DXGI swap chain (DirectX 11 and DirectX 12).
+    Dxgi(DXGI_SWAP_CHAIN_DESC),
+    /// Presentation parameters of a DirectX 9 device's implicit swap chain.
+    D3D9(D3DPRESENT_PARAMETERS),
+    /// OpenGL has no swap chain object: only the window is known.
+    OpenGl,
+}
+
+/// Information about a swap chain that is a candidate for rendering the
+/// overlay.
+///
+/// See [`HudhookBuilder::with_swap_chain_filter`](crate::HudhookBuilder::with_swap_chain_filter).
+#[derive(Debug, Clone)]
+pub struct SwapChainInfo {
+    /// API-specific description of the swap chain.
+    pub desc: SwapChainDesc,
+    /// Window the swap chain presents to.
+    pub hwnd: HWND,
+    /// Width of the back buffer, or of the window's client area if the back
+    /// buffer size is unknown.
+    pub width: u32,
+    /// Height of the back buffer, or of the window's client area if the back
+    /// buffer size is unknown.
+    pub height: u32,
+    /// Class name of the window.
+    pub class_name: String,
+}
+
+impl SwapChainInfo {
+    pub(crate) fn new(desc: SwapChainDesc, hwnd: HWND) -> Self {
+        let (width, height) = match desc {
+            SwapChainDesc::Dxgi(desc) => (desc.BufferDesc.Width, desc.BufferDesc.Height),
+            SwapChainDesc::D3D9(params) => (params.BackBufferWidth, params.BackBufferHeight),
+            SwapChainDesc::OpenGl => (0, 0),
+        };
+
+        let (width, height) = if width == 0 || height == 0 {
+            let mut rect = RECT::default();
+            match unsafe { GetClientRect(hwnd, &mut rect) } {
+                Ok(()) => ((rect.right - rect.left) as u32, (rect.bottom - rect.top) as u32),
+                Err(e) => {
+                    warn!("GetClientRect: {e:?}");
+                    (0, 0)
+                },
+            }
+        } else {
+            (width, height)
+        };
+
+        let mut class_name = [0u16; 256];
+        let len = unsafe { GetClassNameW(hwnd, &mut class_name) };
+        let class_name = String::from_utf16_lossy(&class_name[..len.max(0) as usize]);
+
+        Self { desc, hwnd, width, height, class_name }
+    }
+}
+
+/// How the hooks of a [`Hooks`](crate::Hooks) implementation patch the
+/// functions they hook.
+///
+/// See [`HudhookBuilder::with_strategy`](crate::HudhookBuilder::with_strategy).
+#[derive(Debug, Clone, Default)]
+pub enum HookStrategy {
+    /// Patch the code of the hooked functions with minhook. It affects every
+    /// object, and collides with other overlays patching the same code.
+    #[default]
+    Inline,
+    /// Replace the hooked functions in the vtables shared by the objects of
+    /// their class, without patching code.
+    ///
+    /// The vtables are read from dummy objects created by the hooks, e.g. a
+    /// bitblt model swap chain for DirectX 11. Objects of another class, e.g.
+    /// flip model swap chains if DXGI implements them separately, or swap
+    /// chains wrapped by another overlay, have their own vtable and aren't
+    /// hooked: use [`HookStrategy::Instance`] for those.
+    SharedVTable,
+    /// Give a single object a private copy of its vtable, and replace the
+    /// hooked functions in the copy. Other objects are unaffected.
+    ///
+    /// The object is the swap chain for DirectX 11 and DirectX 12, and the
+    /// device for DirectX 9. It is kept alive until the hooks are removed,
+    /// which restores its vtable.
+    Instance(IUnknown),
+}
+
+impl HookStrategy {
+    /// Hook the function in a vtable `entry`: inline hooks patch its code,
+    /// while vtable hooks replace the entry.
+    pub(crate) unsafe fn hook<T>(
+        &self,
+        entry: &T,
+        hook_impl: *mut c_void,
+    ) -> Result<MhHook, MH_STATUS> {
+        let slot = entry as *const T as *mut *mut c_void;
+        match self {
+            HookStrategy::Inline => MhHook::new(*slot, hook_impl),
+            HookStrategy::SharedVTable | HookStrategy::Instance(_) => {
+                Ok(MhHook::vtable(slot, hook_impl))
+            },
+        }
+    }
+}
+
+/// The number of entries in the vtable of interface `I`.
+pub(crate) fn vtable_len<I: Interface>() -> usize {
+    mem::size_of::<I::Vtable>() / mem::size_of::<*const c_void>()
+}
+
+// An object using a private copy of its vtable. The object is kept alive so
+// that its own vtable can be restored before the copy is freed.
+struct VTableCopy {
+    object: IUnknown,
+    vtable: *mut *mut c_void,
+    copy: Box<[*mut c_void]>,
+}
+
+unsafe impl Send for VTableCopy {}
+
+static VTABLE_COPIES: Mutex<Vec<VTableCopy>> = Mutex::new(Vec::new());
+
+/// Make `object` use a private copy of the first `len` entries of its vtable,
+/// so that they can be replaced without affecting other objects. The object is
+/// kept alive until [`restore_vtables`].
+pub(crate) unsafe fn copy_vtable(object: &impl Interface, len: usize) {
+    let raw = object.as_raw();
+    let vptr = raw as *mut *mut *mut c_void;
+    let vtable = *vptr;
+    let mut copy: Box<[_]> = slice::from_raw_parts(vtable, len).into();
+    AtomicPtr::from_ptr(vptr).store(copy.as_mut_ptr(), Ordering::SeqCst);
+
+    // Keep the interface pointer itself, which holds the vtable pointer.
+    let object = IUnknown::from_raw_borrowed(&raw).expect("the object should not be null").clone();
+    VTABLE_COPIES.lock().push(VTableCopy { object, vtable, copy });
+}
+
+/// Make the objects given a copy of their vtable by [`copy_vtable`] use their
+/// own vtable again, and free the copies.
+pub(crate) unsafe fn restore_vtables() {
+    for VTableCopy { object, vtable, copy } in VTABLE_COPIES.lock().drain(..) {
+        let vptr = object.as_raw() as *mut *mut *mut c_void;
+        AtomicPtr::from_ptr(vptr).store(vtable, Ordering::SeqCst);
+        drop(copy);
+    }
+}
+
+/// The number of entries in the vtable of a DXGI swap chain, according to the
+/// most recent interface it implements.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) fn dxgi_swap_chain_vtable_len(swap_chain: &IDXGISwapChain) -> usize {
+    if swap_chain.cast::<IDXGISwapChain4>().is_ok() {
+        vtable_len::<IDXGISwapChain4>()
+    } else if swap_chain.cast::<IDXGISwapChain3>().is_ok() {
+        vtable_len::<IDXGISwapChain3>()
+    } else if swap_chain.cast::<IDXGISwapChain2>().is_ok() {
+        vtable_len::<IDXGISwapChain2>()
+    } else if swap_chain.cast::<IDXGISwapChain1>().is_ok() {
+        vtable_len::<IDXGISwapChain1>()
+    } else {
+        vtable_len::<IDXGISwapChain>()
+    }
+}
+
+// A COM object stored in the private data of a swap chain, which DXGI releases
+// when it destroys the swap chain.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+#[repr(C)]
+struct DestructionNotifier {
+    vtable: *const IUnknown_Vtbl,
+    refs: AtomicU32,
+    addr: usize,
+    on_destroy: fn(usize),
+}
+
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+static DESTRUCTION_NOTIFIER_VTABLE: IUnknown_Vtbl = IUnknown_Vtbl {
+    QueryInterface: DestructionNotifier::query_interface,
+    AddRef: DestructionNotifier::add_ref,
+    Release: DestructionNotifier::release,
+};
+
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+impl DestructionNotifier {
+    unsafe extern "system" fn query_interface(
+        this: *mut c_void,
+        iid: *const GUID,
+        interface: *mut *mut c_void,
+    ) -> HRESULT {
+        if *iid == IUnknown::IID {
+            Self::add_ref(this);
+            *interface = this;
+            S_OK
+        } else {
+            *interface = ptr::null_mut();
+            E_NOINTERFACE
+        }
+    }
+
+    unsafe extern "system" fn add_ref(this: *mut c_void) -> u32 {
+        (*(this as *const Self)).refs.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    unsafe extern "system" fn release(this: *mut c_void) -> u32 {
+        let _hook_ejection_guard = crate::HOOK_EJECTION_BARRIER.acquire_ejection_guard();
+
+        let refs = (*(this as *const Self)).refs.fetch_sub(1, Ordering::SeqCst) - 1;
+        if refs == 0 {
+            let notifier = Box::from_raw(this as *mut Self);
+            (notifier.on_destroy)(notifier.addr);
+        }
+        refs
+    }
+}
+
+/// Call `on_destroy` with the address of `swap_chain` when DXGI destroys it,
+/// whichever of its interfaces is released last. An object is stored in the
+/// private data `guid` of the swap chain, which releases it on destruction.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) unsafe fn notify_destruction(
+    swap_chain: &IDXGISwapChain,
+    guid: &GUID,
+    on_destroy: fn(usize),
+) -> windows::core::Result<()> {
+    let notifier = Box::new(DestructionNotifier {
+        vtable: &DESTRUCTION_NOTIFIER_VTABLE,
+        refs: AtomicU32::new(1),
+        addr: swap_chain.as_raw() as usize,
+        on_destroy,
+    });
+    let notifier = IUnknown::from_raw(Box::into_raw(notifier) as *mut c_void);
+    swap_chain.SetPrivateDataInterface(guid, &notifier)
+}
+
+/// Remove the objects stored by [`notify_destruction`] from the swap chains
+/// at `addrs`, which are still alive, so that DXGI doesn't call into the
+/// library after it is ejected. Their `on_destroy` functions are called.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) unsafe fn forget_destruction(addrs: &[usize], guid: &GUID) {
+    for &addr in addrs {
+        let raw = addr as *mut c_void;
+        if let Some(swap_chain) = IDXGISwapChain::from_raw_borrowed(&raw) {
+            if let Err(e) = swap_chain.SetPrivateDataInterface(guid, None::<&IUnknown>) {
+                error!("Could not remove the destruction notifier of {raw:p}: {e:?}");
+            }
+        }
+    }
+}
+
+/// Set the swap chain filter, replacing the previous one if any.
+pub(crate) fn set_swap_chain_filter<F>(filter: F)
+where
+    F: Fn(&SwapChainInfo) -> bool + Send + Sync + 'static,
+{
+    if SWAP_CHAIN_FILTER.write().replace(Box::new(filter)).is_some() {
+        debug!("Replacing the swap chain filter");
+    }
+}
+
+/// Remove the swap chain filter, so that all swap chains are accepted.
+pub(crate) fn clear_swap_chain_filter() {
+    SWAP_CHAIN_FILTER.write().take();
+}
+
+/// Decide whether the overlay should be rendered on a swap chain, according to
+/// the filter set in the builder. All swap chains are accepted if no filter is
+/// set.
+pub(crate) fn filter_swap_chain(info: &SwapChainInfo) -> bool {
+    let accepted = SWAP_CHAIN_FILTER.read().as_ref().map_or(true, |filter| filter(info));
+    debug!("Swap chain {info:?} {}", if accepted { "accepted" } else { "rejected" });
+    accepted
+}
+
+thread_local! {
+    static IN_PRESENT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A guard marking the current thread as being inside a hooked present call.
+///
+/// Some present variants are implemented by calling into another one (e.g.
+/// `IDirect3DDevice9::Present` into `IDirect3DSwapChain9::Present`). Only the
+/// outermost call acquires the guard, so that each frame is rendered once.
+pub(crate) struct PresentGuard(());
+
+impl PresentGuard {
+    /// Acquire the guard, or return `None` if the current thread is already
+    /// inside a hooked present call.
+    pub(crate) fn enter() -> Option<Self> {
+        if IN_PRESENT.with(|in_present| in_present.replace(true)) {
+            None
+        } else {
+            Some(Self(()))
+        }
+    }
+}
+
+impl Drop for PresentGuard {
+    fn drop(&mut self) {
+        IN_PRESENT.with(|in_present| in_present.set(false));
+    }
+}
+
+/// The objects a hook module renders to, identified by their address, and
+/// those of them that were destroyed since their pipelines were last pruned.
+///
+/// Pipelines don't hold strong references to the objects, which would keep
+/// them alive after the application releases them. Their destruction is
+/// reported instead, and the pipelines are torn down on the next frame, as the
+/// lock on the pipelines can't be taken from within the reports.
+pub(crate) struct Tracked {
+    live: Vec<usize>,
+    released: Vec<usize>,
+}
+
+impl Tracked {
+    pub(crate) const fn new() -> Self {
+        Self { live: Vec::new(), released: Vec::new() }
+    }
+
+    /// Start tracking the object at `addr`, and return whether it was not
+    /// tracked yet.
+    pub(crate) fn insert(&mut self, addr: usize) -> bool {
+        if self.live.contains(&addr) {
+            return false;
+        }
+        self.live.push(addr);
+        true
+    }
+
+    /// Mark the object at `addr` as destroyed, if it is tracked.
+    pub(crate) fn release(&mut self, addr: usize) {
+        if let Some(idx) = self.live.iter().position(|&a| a == addr) {
+            self.live.swap_remove(idx);
+            self.released.push(addr);
+        }
+    }
+
+    /// Take the objects destroyed since the last call.
+    pub(crate) fn take_released(&mut self) -> Vec<usize> {
+        mem::take(&mut self.released)
+    }
+
+    /// Stop tracking all the objects, and return those still alive.
+    #[cfg(any(feature = "dx11", feature = "dx12"))]
+    pub(crate) fn take_live(&mut self) -> Vec<usize> {
+        self.released.clear();
+        mem::take(&mut self.live)
+    }
+}
+
 /// A utility function to retrieve the top level [`HWND`] belonging to this
 /// process.
 pub fn find_process_hwnd() -> Option<HWND> {
@@ -2,59 +2,66 @@
 
 use std::ffi::{c_void, CString};
 use std::mem;
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
 
-use imgui::Context;
-use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use tracing::{error, trace};
 use windows::core::{Error, Result, HRESULT, PCSTR};
+use windows::Win32::Foundation::BOOL;
 use windows::Win32::Graphics::Gdi::{WindowFromDC, HDC};
+use windows::Win32::Graphics::OpenGL::{wglGetCurrentContext, HGLRC};
 use windows::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
 
+use super::auto::GraphicsApi;
+use super::{PresentGuard, SwapChainDesc, SwapChainInfo, Tracked};
 use crate::mh::MhHook;
-use crate::renderer::{OpenGl3RenderEngine, Pipeline};
-use crate::{Hooks, ImguiRenderLoop};
+use crate::renderer::{OpenGl3RenderEngine, Pipelines, RenderLoops};
+use crate::{Hooks, ImguiRenderLoop, PerWindowHooks};
 
 type OpenGl32wglSwapBuffersType = unsafe extern "system" fn(HDC) -> ();
+type OpenGl32wglDeleteContextType = unsafe extern "system" fn(HGLRC) -> BOOL;
 
 struct Trampolines {
     opengl32_wgl_swap_buffers: OpenGl32wglSwapBuffersType,
+    opengl32_wgl_delete_context: OpenGl32wglDeleteContextType,
 }
 
 static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
-static mut PIPELINE: OnceCell<Mutex<Pipeline<OpenGl3RenderEngine>>> = OnceCell::new();
-static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
-
-unsafe fn init_pipeline(dc: HDC) -> Result<Mutex<Pipeline<OpenGl3RenderEngine>>> {
-    let hwnd = WindowFromDC(dc);
-
-    let mut ctx = Context::create();
-    let engine = OpenGl3RenderEngine::new(&mut ctx)?;
-
-    let Some(render_loop) = RENDER_LOOP.take() else {
-        error!("Render loop not yet initialized");
-        return Err(Error::from_hresult(HRESULT(-1)));
-    };
-
-    let pipeline = Pipeline::new(hwnd, ctx, engine, render_loop).map_err(|(e, render_loop)| {
-        RENDER_LOOP.get_or_init(move || render_loop);
-        e
-    })?;
-
-    Ok(Mutex::new(pipeline))
-}
+// Pipelines, keyed by their window handle and their OpenGL context.
+static mut PIPELINES: Mutex<Pipelines<(isize, isize), OpenGl3RenderEngine>> =
+    Mutex::new(Pipelines::new());
+static CONTEXTS: Mutex<Tracked> = Mutex::new(Tracked::new());
 
 fn render(dc: HDC) -> Result<()> {
     unsafe {
-        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline(dc))?;
-
-        let Some(mut pipeline) = pipeline.try_lock() else {
-            error!("Could not lock pipeline");
+        let hwnd = WindowFromDC(dc);
+        let context = wglGetCurrentContext();
+        let Some(mut pipelines) = PIPELINES.try_lock() else {
+            error!("Could not lock pipelines");
             return Err(Error::from_hresult(HRESULT(-1)));
         };
 
-        pipeline.prepare_render()?;
+        // Tear down the pipelines of the contexts the application has deleted, and
+        // that of the window if it is now rendered with another context, so they
+        // can be rebuilt.
+        let released = {
+            let mut contexts = CONTEXTS.lock();
+            contexts.insert(context.0 as usize);
+            contexts.take_released()
+        };
+        pipelines
+            .retain(|&(w, c)| !released.contains(&(c as usize)) && (w != hwnd.0 || c == context.0));
+
+        let Some(pipeline) = pipelines.get_or_try_init(
+            &(hwnd.0, context.0),
+            || Ok(SwapChainInfo::new(SwapChainDesc::OpenGl, hwnd)),
+            |ctx| OpenGl3RenderEngine::new(ctx),
+        )?
+        else {
+            return Ok(());
+        };
+
+        pipeline.prepare_render(&())?;
 
         pipeline.render(())?;
     }
@@ -66,33 +73,47 @@ unsafe extern "system" fn opengl32_wgl_swap_buffers_impl(dc: HDC) {
     let Trampolines { opengl32_wgl_swap_buffers } =
         TRAMPOLINES.get().expect("OpenGL3 trampolines uninitialized");
 
-    if let Err(e) = render(dc) {
-        error!("Render error: {e:?}");
+    // Viewport windows are presented from within the render call.
+    if let Some(_present_guard) = PresentGuard::enter() {
+        if super::auto::claim(GraphicsApi::OpenGl3) {
+            if let Err(e) = render(dc) {
+                error!("Render error: {e:?}");
+            }
+        }
     }
 
     trace!("Call OpenGL3 wglSwapBuffers trampoline");
     opengl32_wgl_swap_buffers(dc);
 }
 
-// Get the address of wglSwapBuffers in opengl32.dll
-unsafe fn get_opengl_wglswapbuffers_addr() -> OpenGl32wglSwapBuffersType {
+unsafe extern "system" fn opengl32_wgl_delete_context_impl(context: HGLRC) -> BOOL {
+    let Trampolines { opengl32_wgl_delete_context, .. } =
+        TRAMPOLINES.get().expect("OpenGL3 trampolines uninitialized");
+
+    // The handle may be reused by the next context, so the pipeline must not
+    // outlive it.
+    CONTEXTS.lock().release(context.0 as usize);
+
+    trace!("Call OpenGL3 wglDeleteContext trampoline");
+    opengl32_wgl_delete_context(context)
+}
+
+// Get the address of a function in opengl32.dll
+unsafe fn get_opengl32_proc_addr(name: &str) -> *mut c_void {
     // Grab a handle to opengl32.dll
     let opengl32dll = CString::new("opengl32.dll").unwrap();
     let opengl32module = GetModuleHandleA(PCSTR(opengl32dll.as_ptr() as *mut _))
         .expect("failed finding opengl32.dll");
 
-    // Grab the address of wglSwapBuffers
-    let wglswapbuffers = CString::new("wglSwapBuffers").unwrap();
-    let wglswapbuffers_func =
-        GetProcAddress(opengl32module, PCSTR(wglswapbuffers.as_ptr() as *mut _)).unwrap();
+    // Grab the address of the function
+    let name = CString::new(name).unwrap();
+    let func = GetProcAddress(opengl32module, PCSTR(name.as_ptr() as *mut _)).unwrap();
 
-    mem::transmute::<unsafe extern "system" fn() -> isize, OpenGl32wglSwapBuffersType>(
-        wglswapbuffers_func,
-    )
+    func as *mut c_void
 }
 
 /// Hooks for OpenGL 3.
-pub struct ImguiOpenGl3Hooks([MhHook; 1]);
+pub struct ImguiOpenGl3Hooks([MhHook; 2]);
 
 impl ImguiOpenGl3Hooks {
     /// Construct a set of [`MhHook`]s that will render UI via the
@@ -100,6 +121,7 @@ impl ImguiOpenGl3Hooks {
     ///
     /// The following functions are hooked:
     /// - `opengl32::wglSwapBuffers`
+    /// - `opengl32::wglDeleteContext`
     ///
     /// # Safety
     ///
@@ -108,25 +130,37 @@ impl ImguiOpenGl3Hooks {
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
+        Self::with_render_loops(RenderLoops::single(t))
+    }
+
+    pub(super) unsafe fn with_render_loops(render_loops: RenderLoops) -> Self {
         // Grab the addresses
-        let hook_opengl_swap_buffers_address = get_opengl_wglswapbuffers_addr();
+        let hook_opengl_swap_buffers_address = get_opengl32_proc_addr("wglSwapBuffers");
+        let hook_opengl_delete_context_address = get_opengl32_proc_addr("wglDeleteContext");
 
         // Create detours
-        let hook_opengl_wgl_swap_buffers = MhHook::new(
-            hook_opengl_swap_buffers_address as *mut _,
-            opengl32_wgl_swap_buffers_impl as *mut _,
+        let hook_opengl_wgl_swap_buffers =
+            MhHook::new(hook_opengl_swap_buffers_address, opengl32_wgl_swap_buffers_impl as *mut _)
+                .expect("couldn't create opengl32.wglSwapBuffers hook");
+        let hook_opengl_wgl_delete_context = MhHook::new(
+            hook_opengl_delete_context_address,
+            opengl32_wgl_delete_context_impl as *mut _,
         )
-        .expect("couldn't create opengl32.wglSwapBuffers hook");
+        .expect("couldn't create opengl32.wglDeleteContext hook");
 
         // Initialize the render loop and store detours
-        RENDER_LOOP.get_or_init(move || Box::new(t));
+        PIPELINES.lock().set_render_loops(render_loops);
         TRAMPOLINES.get_or_init(|| Trampolines {
             opengl32_wgl_swap_buffers: mem::transmute::<*mut c_void, OpenGl32wglSwapBuffersType>(
                 hook_opengl_wgl_swap_buffers.trampoline(),
             ),
+            opengl32_wgl_delete_context: mem::transmute::<
+                *mut c_void,
+                OpenGl32wglDeleteContextType,
+            >(hook_opengl_wgl_delete_context.trampoline()),
         });
 
-        Self([hook_opengl_wgl_swap_buffers])
+        Self([hook_opengl_wgl_swap_buffers, hook_opengl_wgl_delete_context])
     }
 }
 
@@ -145,7 +179,20 @@ impl Hooks for ImguiOpenGl3Hooks {
 
     unsafe fn unhook(&mut self) {
         TRAMPOLINES.take();
-        PIPELINE.take().map(|p| p.into_inner().take());
-        RENDER_LOOP.take();
+        PIPELINES.lock().clear();
+        *CONTEXTS.lock() = Tracked::new();
+    }
+}
+
+impl PerWindowHooks for ImguiOpenGl3Hooks {
+    fn from_render_loop_factory<F>(factory: F) -> Box<Self>
+    where
+        Self: Sized,
+        F: Fn(&SwapChainInfo) -> Option<Box<dyn ImguiRenderLoop + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Box::new(unsafe { Self::with_render_loops(RenderLoops::PerWindow(Arc::new(factory))) })
     }
 }
@@ -1,10 +1,11 @@
 //! Facilities for injecting compiled DLLs into target processes.
 
 use std::ffi::c_void;
+use std::fs;
 use std::mem::{self, size_of};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use tracing::debug;
+use tracing::{debug, error, warn};
 use windows::core::{s, w, Error, Result, HRESULT, HSTRING, PCSTR, PCWSTR};
 use windows::Win32::Foundation::{CloseHandle, BOOL, HANDLE, MAX_PATH};
 use windows::Win32::System::Diagnostics::Debug::WriteProcessMemory;
@@ -22,6 +23,8 @@ use windows::Win32::System::Threading::{
 };
 use windows::Win32::UI::WindowsAndMessaging::{FindWindowA, FindWindowW, GetWindowThreadProcessId};
 
+use crate::util::pe::{Machine, PeImage};
+
 /// A process, open with the permissions appropriate for injection.
 pub struct Process(HANDLE);
 
@@ -39,7 +42,12 @@ impl Process {
     }
 
     /// Inject the DLL in the process.
+    ///
+    /// The DLL is loaded with the `LoadLibraryW` of this process, so it must
+    /// be built for the same architecture.
     pub fn inject(&self, dll_path: PathBuf) -> Result<()> {
+        check_machine(&dll_path)?;
+
         let proc_addr =
             unsafe { GetProcAddress(GetModuleHandleW(w!("Kernel32"))?, s!("LoadLibraryW")) };
 
@@ -107,6 +115,26 @@ impl Drop for Process {
     }
 }
 
+// Check that the DLL is built for the architecture of this process. The check
+// is skipped if the DLL can't be read, leaving `LoadLibraryW` to report it.
+fn check_machine(dll_path: &Path) -> Result<()> {
+    let data = match fs::read(dll_path) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Could not read {dll_path:?}, skipping the architecture check: {e}");
+            return Ok(());
+        },
+    };
+
+    let machine = PeImage::parse(&data)?.machine();
+    if machine != Machine::host() {
+        error!("{dll_path:?} is built for {machine:?} rather than {:?}", Machine::host());
+        return Err(Error::from_hresult(HRESULT(-1)));
+    }
+
+    Ok(())
+}
+
 // Find process given the title of one of its windows.
 fn get_process_by_title(title: &str) -> Result<HANDLE> {
     if cfg!(target_arch = "x86") {
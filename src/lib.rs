@@ -89,6 +89,12 @@
 //!     use hudhook::hooks::opengl3::ImguiOpenGl3Hooks;
 //!     hudhook!(ImguiOpenGl3Hooks, MyRenderLoop);
 //! }
+//!
+//! {
+//!     // Use this if the graphics API should be detected at runtime.
+//!     use hudhook::hooks::auto::ImguiAutoHooks;
+//!     hudhook!(ImguiAutoHooks, MyRenderLoop);
+//! }
 //! ```
 //!
 //! #### Injecting the DLL
@@ -117,7 +123,7 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 
-use imgui::{Context, Io, TextureId, Ui};
+use imgui::{Context, DrawData, Io, TextureId, Ui};
 use once_cell::sync::OnceCell;
 use tracing::{error, trace, warn};
 use windows::core::Error;
@@ -129,6 +135,7 @@ use windows::Win32::System::Console::{
 use windows::Win32::System::LibraryLoader::FreeLibraryAndExitThread;
 pub use {imgui, tracing, windows};
 
+use crate::hooks::{HookStrategy, SwapChainInfo};
 use crate::mh::{MH_ApplyQueued, MH_Initialize, MH_Uninitialize, MhHook, MH_STATUS};
 use crate::util::HookEjectionBarrier;
 
@@ -138,7 +145,17 @@ pub mod inject;
 pub mod mh;
 pub(crate) mod renderer;
 
+pub use renderer::animation::{AnimatedTexture, Animation, AnimationFrame, FrameSchedule};
+pub use renderer::callback::{
+    add_draw_callback, add_reset_render_state, DrawCallbackBackend, DrawCallbackContext,
+};
+pub use renderer::capture::CapturedImage;
+#[cfg(feature = "opengl3")]
+pub use renderer::gl;
 pub use renderer::msg_filter::MessageFilter;
+pub use renderer::shader::{set_pixel_shader, ShaderId};
+pub use renderer::stats::{FrameStats, FrameStatsWindow};
+pub use renderer::texture::{TextureDesc, TextureFormat};
 
 pub mod util;
 
@@ -153,11 +170,25 @@ static HOOK_EJECTION_BARRIER: HookEjectionBarrier = HookEjectionBarrier::new();
 pub trait RenderContext {
     /// Load texture and return TextureId to use. Invoke it in your
     /// [`crate::ImguiRenderLoop::initialize`] method for setting up textures.
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId, Error>;
+    ///
+    /// Pixels are tightly packed RGBA8 values. See
+    /// [`RenderContext::load_texture_with_desc`] for other layouts.
+    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId, Error> {
+        self.load_texture_with_desc(data, &TextureDesc::new(width, height, TextureFormat::Rgba8))
+    }
+
+    /// Load a texture laid out as described by `desc`, e.g. in another format
+    /// or with mip levels, and return TextureId to use.
+    fn load_texture_with_desc(
+        &mut self,
+        data: &[u8],
+        desc: &TextureDesc,
+    ) -> Result<TextureId, Error>;
 
     /// Upload an image to an existing texture, replacing its content. Invoke it
     /// in your [`crate::ImguiRenderLoop::before_render`] method for
-    /// updating textures.
+    /// updating textures. Only RGBA8 textures without mip levels can be
+    /// replaced.
     fn replace_texture(
         &mut self,
         texture_id: TextureId,
@@ -165,6 +196,54 @@ pub trait RenderContext {
         width: u32,
         height: u32,
     ) -> Result<(), Error>;
+
+    /// Compile a custom pixel shader. See [`ShaderId`] for the language and
+    /// the inputs expected by each render engine.
+    fn create_pixel_shader(&mut self, source: &str) -> Result<ShaderId, Error>;
+
+    /// Replace the constants of a pixel shader, laid out as `float4` values.
+    fn set_shader_constants(&mut self, shader: ShaderId, data: &[u8]) -> Result<(), Error>;
+
+    /// Draw a texture with a pixel shader whenever it is referenced by imgui,
+    /// e.g. by [`imgui::Image`]. Pass `None` to use the default shader again.
+    fn bind_texture_shader(
+        &mut self,
+        texture_id: TextureId,
+        shader: Option<ShaderId>,
+    ) -> Result<(), Error>;
+
+    /// Set the brightness of imgui's white on HDR render targets, in nits.
+    /// The default is 203 nits. It has no effect on SDR render targets, and
+    /// on custom pixel shaders, which output their colors as they are.
+    fn set_hdr_paper_white(&mut self, nits: f32);
+
+    /// Copy the render target, as drawn by the application, to a texture to
+    /// use with [`imgui::Image`]. Later captures reuse the same texture. Invoke
+    /// it in your [`crate::ImguiRenderLoop::before_render`] method.
+    fn capture_backbuffer(&mut self) -> Result<TextureId, Error>;
+
+    /// Read the render target back to memory, e.g. to save a screenshot.
+    /// Invoke it in your [`crate::ImguiRenderLoop::before_render`] method.
+    fn capture_backbuffer_rgba(&mut self) -> Result<CapturedImage, Error>;
+
+    /// Create an RGBA8 texture that imgui can be rendered to with
+    /// [`RenderContext::render_to_target`], and sampled like any other
+    /// texture afterwards.
+    fn create_render_target(&mut self, width: u32, height: u32) -> Result<TextureId, Error>;
+
+    /// Clear a texture created with [`RenderContext::create_render_target`]
+    /// and render `draw_data` to it, e.g. the frame of a separate
+    /// [`imgui::Context`]. Its textures are created as needed, as for the
+    /// overlay's frames. Invoke it in your
+    /// [`crate::ImguiRenderLoop::before_render`] method.
+    fn render_to_target(
+        &mut self,
+        texture_id: TextureId,
+        draw_data: &DrawData,
+    ) -> Result<(), Error>;
+
+    /// The cost of the last frame rendered by the overlay.
+    fn frame_stats(&self) -> FrameStats;
 }
 
 /// Allocate a Windows console.
@@ -235,6 +314,9 @@ unsafe fn perform_eject() {
         }
     }
 
+    // Leave the patched code as it was found.
+    util::patch::revert_all();
+
     thread::spawn(|| unsafe {
         // Wait for all hook ejection guards to complete. As we have
         // already called `hudhook.unapply()` above any future invocations
@@ -256,6 +338,10 @@ pub trait ImguiRenderLoop {
     /// initialize your data.
     /// `ctx` is the imgui context, and `render_context` is meant to access
     /// hudhook renderers' extensions such as texture management.
+    ///
+    /// With the `imgui-docking` feature, imgui windows can be dragged out of
+    /// the host window by setting `ConfigFlags::VIEWPORTS_ENABLE` in
+    /// `ctx.io_mut().config_flags`.
     fn initialize<'a>(
         &'a mut self,
         _ctx: &mut Context,
@@ -285,6 +371,23 @@ pub trait ImguiRenderLoop {
     fn message_filter(&self, _io: &Io) -> MessageFilter {
         MessageFilter::empty()
     }
+
+    /// Returns the number of window pixels in an imgui unit, e.g. `2.0` to
+    /// draw the UI twice as large. The UI is rendered at the resolution of
+    /// the back buffer regardless, and the mouse position is scaled to match.
+    /// The scale is ignored while multiple viewports are enabled.
+    fn ui_scale(&self, _io: &Io) -> f32 {
+        1.0
+    }
+
+    /// Returns the maximum number of frames built per second, or `None` to
+    /// build one on every present. In between, the last frame's draw data is
+    /// rendered again, and [`ImguiRenderLoop::before_render`] and
+    /// [`ImguiRenderLoop::render`] are not called. Input always builds a new
+    /// frame right away.
+    fn frame_rate_limit(&self, _io: &Io) -> Option<f32> {
+        None
+    }
 }
 
 /// Generic trait for platform-specific hooks.
@@ -298,6 +401,7 @@ pub trait ImguiRenderLoop {
 /// - [`ImguiDx11Hooks`](crate::hooks::dx11::ImguiDx11Hooks)
 /// - [`ImguiDx12Hooks`](crate::hooks::dx12::ImguiDx12Hooks)
 /// - [`ImguiOpenGl3Hooks`](crate::hooks::opengl3::ImguiOpenGl3Hooks)
+/// - [`ImguiAutoHooks`](crate::hooks::auto::ImguiAutoHooks)
 pub trait Hooks {
     /// Construct a boxed instance of the implementor, storing the provided
     /// render loop where appropriate.
@@ -306,6 +410,22 @@ pub trait Hooks {
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static;
 
+    /// Construct a boxed instance of the implementor like
+    /// [`Hooks::from_render_loop`], patching the hooked functions with the
+    /// given strategy.
+    ///
+    /// Implementors that only support inline hooks ignore the strategy.
+    fn from_render_loop_with_strategy<T>(t: T, strategy: HookStrategy) -> Box<Self>
+    where
+        Self: Sized,
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        if !matches!(strategy, HookStrategy::Inline) {
+            warn!("Only inline hooks are supported, ignoring {strategy:?}");
+        }
+        Self::from_render_loop(t)
+    }
+
     /// Return the list of hooks to be enabled, in order.
     fn hooks(&self) -> &[MhHook];
 
@@ -317,6 +437,26 @@ pub trait Hooks {
     unsafe fn unhook(&mut self);
 }
 
+/// Hooks that can render a separate UI into each window.
+///
+/// Each swap chain accepted by the
+/// [swap chain filter](HudhookBuilder::with_swap_chain_filter) gets its own
+/// pipeline, with its own imgui [`Context`] and a render loop created for it by
+/// the factory. The factory receives the [`SwapChainInfo`] of the swap chain,
+/// so that it can tell windows apart; returning `None` leaves the swap chain
+/// without an overlay.
+pub trait PerWindowHooks: Hooks {
+    /// Construct a boxed instance of the implementor, creating render loops via
+    /// the provided factory.
+    fn from_render_loop_factory<F>(factory: F) -> Box<Self>
+    where
+        Self: Sized,
+        F: Fn(&SwapChainInfo) -> Option<Box<dyn ImguiRenderLoop + Send + Sync>>
+            + Send
+            + Sync
+            + 'static;
+}
+
 /// Holds all the activated hooks and manages their lifetime.
 pub struct Hudhook(Vec<Box<dyn Hooks>>);
 unsafe impl Send for Hudhook {}
@@ -380,6 +520,8 @@ impl Hudhook {
         for hook in &mut self.0 {
             unsafe { hook.unhook() };
         }
+        hooks::clear_swap_chain_filter();
+        unsafe { hooks::restore_vtables() };
         trace!("Finished removing hook");
 
         Ok(())
@@ -430,12 +572,100 @@ impl HudhookBuilder {
         self
     }
 
+    /// Add a hook object, patching the hooked functions with the given
+    /// strategy instead of inline hooks.
+    ///
+    /// Vtable hooks don't patch code, so they don't collide with other
+    /// overlays hooking the same functions, and can be restricted to a single
+    /// object with [`HookStrategy::Instance`].
+    ///
+    /// ```no_run
+    /// # use hudhook::*;
+    /// # use hudhook::hooks::HookStrategy;
+    /// # use hudhook::hooks::dx11::ImguiDx11Hooks;
+    /// # struct MyRenderLoop;
+    /// # impl ImguiRenderLoop for MyRenderLoop {
+    /// #     fn render(&mut self, ui: &mut imgui::Ui) {}
+    /// # }
+    /// let hudhook = Hudhook::builder()
+    ///     .with_strategy::<ImguiDx11Hooks>(MyRenderLoop, HookStrategy::SharedVTable)
+    ///     .build();
+    /// ```
+    pub fn with_strategy<T: Hooks + 'static>(
+        mut self,
+        render_loop: impl ImguiRenderLoop + Send + Sync + 'static,
+        strategy: HookStrategy,
+    ) -> Self {
+        self.0 .0.push(T::from_render_loop_with_strategy(render_loop, strategy));
+        self
+    }
+
+    /// Add a hook object rendering a separate UI into each window, with a
+    /// render loop created by `factory` for each swap chain.
+    ///
+    /// ```no_run
+    /// # use hudhook::*;
+    /// # use hudhook::hooks::dx11::ImguiDx11Hooks;
+    /// # struct MyRenderLoop(String);
+    /// # impl ImguiRenderLoop for MyRenderLoop {
+    /// #     fn render(&mut self, ui: &mut imgui::Ui) {}
+    /// # }
+    /// let hudhook = Hudhook::builder()
+    ///     .with_per_window::<ImguiDx11Hooks>(|info| {
+    ///         Some(Box::new(MyRenderLoop(info.class_name.clone())))
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn with_per_window<T: PerWindowHooks + 'static>(
+        mut self,
+        factory: impl Fn(&SwapChainInfo) -> Option<Box<dyn ImguiRenderLoop + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.0 .0.push(T::from_render_loop_factory(factory));
+        self
+    }
+
     /// Save the DLL instance (for the [`eject`] method).
     pub fn with_hmodule(self, module: HINSTANCE) -> Self {
         unsafe { MODULE.set(module).unwrap() };
         self
     }
 
+    /// Choose which swap chain the overlay is rendered into.
+    ///
+    /// The predicate is evaluated on each swap chain that presents a frame,
+    /// until one is accepted. If the chosen swap chain, DirectX 9 device or
+    /// OpenGL context is destroyed by the application, the pipeline is rebuilt
+    /// on the next accepted swap chain. If no filter is set, the first swap
+    /// chain that presents is chosen.
+    ///
+    /// The filter is shared by all the hooks: calling this again replaces it.
+    ///
+    /// With [`with_per_window`](Self::with_per_window), every accepted swap
+    /// chain gets its own pipeline instead.
+    ///
+    /// ```no_run
+    /// # use hudhook::*;
+    /// # use hudhook::hooks::dx11::ImguiDx11Hooks;
+    /// # struct MyRenderLoop;
+    /// # impl ImguiRenderLoop for MyRenderLoop {
+    /// #     fn render(&mut self, ui: &mut imgui::Ui) {}
+    /// # }
+    /// let hudhook = Hudhook::builder()
+    ///     .with::<ImguiDx11Hooks>(MyRenderLoop)
+    ///     .with_swap_chain_filter(|info| info.class_name != "SplashScreen" && info.width >= 640)
+    ///     .build();
+    /// ```
+    pub fn with_swap_chain_filter<F>(self, filter: F) -> Self
+    where
+        F: Fn(&SwapChainInfo) -> bool + Send + Sync + 'static,
+    {
+        hooks::set_swap_chain_filter(filter);
+        self
+    }
+
     /// Build the [`Hudhook`] object.
     pub fn build(self) -> Hudhook {
         self.0
@@ -1,10 +1,27 @@
 //! Thin FFI wrapper around [`minhook`](https://github.com/TsudaKageyu/minhook).
 #![allow(dead_code, non_snake_case, non_camel_case_types, missing_docs)]
 
-use std::ffi::c_void;
-use std::ptr::null_mut;
+mod mid;
+mod thunk;
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
+use std::ptr::{null_mut, NonNull};
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
 
 use tracing::error;
+use windows::core::{Error, HSTRING, PCSTR, PCWSTR};
+use windows::Win32::Foundation::{
+    ERROR_ALREADY_EXISTS, ERROR_ALREADY_INITIALIZED, ERROR_MOD_NOT_FOUND, ERROR_NOT_FOUND,
+    ERROR_PROC_NOT_FOUND, E_ACCESSDENIED, E_FAIL, E_INVALIDARG, E_OUTOFMEMORY, E_UNEXPECTED,
+};
+use windows::Win32::System::Memory::{VirtualProtect, PAGE_PROTECTION_FLAGS, PAGE_READWRITE};
+
+pub use self::mid::{mid_hook, Context, MidHook, Xmm};
+use self::thunk::Thunk;
+use crate::HOOK_EJECTION_BARRIER;
 
 #[allow(non_camel_case_types)]
 #[must_use]
@@ -43,6 +60,9 @@ pub enum MH_STATUS {
     MH_ERROR_FUNCTION_NOT_FOUND,
 }
 
+/// Pass it to the functions enabling or disabling hooks to act on all of them.
+pub const MH_ALL_HOOKS: *mut c_void = null_mut();
+
 extern "system" {
     pub fn MH_Initialize() -> MH_STATUS;
     pub fn MH_Uninitialize() -> MH_STATUS;
@@ -51,11 +71,26 @@ extern "system" {
         pDetour: *mut c_void,
         ppOriginal: *mut *mut c_void,
     ) -> MH_STATUS;
+    pub fn MH_CreateHookApi(
+        pszModule: PCWSTR,
+        pszProcName: PCSTR,
+        pDetour: *mut c_void,
+        ppOriginal: *mut *mut c_void,
+    ) -> MH_STATUS;
+    pub fn MH_CreateHookApiEx(
+        pszModule: PCWSTR,
+        pszProcName: PCSTR,
+        pDetour: *mut c_void,
+        ppOriginal: *mut *mut c_void,
+        ppTarget: *mut *mut c_void,
+    ) -> MH_STATUS;
+    pub fn MH_RemoveHook(pTarget: *mut c_void) -> MH_STATUS;
     pub fn MH_EnableHook(pTarget: *mut c_void) -> MH_STATUS;
     pub fn MH_QueueEnableHook(pTarget: *mut c_void) -> MH_STATUS;
     pub fn MH_DisableHook(pTarget: *mut c_void) -> MH_STATUS;
     pub fn MH_QueueDisableHook(pTarget: *mut c_void) -> MH_STATUS;
     pub fn MH_ApplyQueued() -> MH_STATUS;
+    pub fn MH_StatusToString(status: MH_STATUS) -> *const c_char;
 }
 
 impl MH_STATUS {
@@ -63,7 +98,7 @@ impl MH_STATUS {
         if self == MH_STATUS::MH_OK {
             Ok(())
         } else {
-            error!("{context}: {self:?}");
+            error!("{context}: {self}");
             Err(self)
         }
     }
@@ -75,14 +110,91 @@ impl MH_STATUS {
             Err(self)
         }
     }
+
+    /// The name of the status, e.g. `MH_ERROR_NOT_EXECUTABLE`.
+    pub fn name(self) -> &'static str {
+        unsafe { CStr::from_ptr(MH_StatusToString(self)) }.to_str().unwrap_or("(unknown)")
+    }
+
+    /// What the status means.
+    pub fn description(self) -> &'static str {
+        match self {
+            MH_STATUS::MH_UNKNOWN => "unknown error",
+            MH_STATUS::MH_OK => "successful",
+            MH_STATUS::MH_ERROR_ALREADY_INITIALIZED => "minhook is already initialized",
+            MH_STATUS::MH_ERROR_NOT_INITIALIZED => "minhook is not initialized",
+            MH_STATUS::MH_ERROR_ALREADY_CREATED => "the hook is already created",
+            MH_STATUS::MH_ERROR_NOT_CREATED => "the hook is not created",
+            MH_STATUS::MH_ERROR_ENABLED => "the hook is already enabled",
+            MH_STATUS::MH_ERROR_DISABLED => "the hook is not enabled",
+            MH_STATUS::MH_ERROR_NOT_EXECUTABLE => "the target is not executable memory",
+            MH_STATUS::MH_ERROR_UNSUPPORTED_FUNCTION => "the target cannot be hooked",
+            MH_STATUS::MH_ERROR_MEMORY_ALLOC => "could not allocate memory",
+            MH_STATUS::MH_ERROR_MEMORY_PROTECT => "could not change the memory protection",
+            MH_STATUS::MH_ERROR_MODULE_NOT_FOUND => "the module is not loaded",
+            MH_STATUS::MH_ERROR_FUNCTION_NOT_FOUND => "the function is not exported",
+        }
+    }
+}
+
+impl fmt::Display for MH_STATUS {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.description(), self.name())
+    }
+}
+
+impl std::error::Error for MH_STATUS {}
+
+impl From<MH_STATUS> for Error {
+    fn from(status: MH_STATUS) -> Self {
+        let code = match status {
+            // `MH_OK` isn't an error, and `S_OK` would make one that reads as success.
+            MH_STATUS::MH_OK => E_UNEXPECTED,
+            MH_STATUS::MH_ERROR_ALREADY_INITIALIZED => ERROR_ALREADY_INITIALIZED.to_hresult(),
+            MH_STATUS::MH_ERROR_ALREADY_CREATED => ERROR_ALREADY_EXISTS.to_hresult(),
+            MH_STATUS::MH_ERROR_NOT_CREATED => ERROR_NOT_FOUND.to_hresult(),
+            MH_STATUS::MH_ERROR_NOT_EXECUTABLE => E_INVALIDARG,
+            MH_STATUS::MH_ERROR_MEMORY_ALLOC => E_OUTOFMEMORY,
+            MH_STATUS::MH_ERROR_MEMORY_PROTECT => E_ACCESSDENIED,
+            MH_STATUS::MH_ERROR_MODULE_NOT_FOUND => ERROR_MOD_NOT_FOUND.to_hresult(),
+            MH_STATUS::MH_ERROR_FUNCTION_NOT_FOUND => ERROR_PROC_NOT_FOUND.to_hresult(),
+            _ => E_FAIL,
+        };
+        Error::from_hresult(code)
+    }
+}
+
+/// Initialize minhook, unless it already is.
+fn initialize() -> Result<(), MH_STATUS> {
+    match unsafe { MH_Initialize() } {
+        MH_STATUS::MH_OK | MH_STATUS::MH_ERROR_ALREADY_INITIALIZED => Ok(()),
+        status => {
+            error!("MH_Initialize: {status:?}");
+            Err(status)
+        },
+    }
 }
 
 /// Structure that holds original address, hook function address, and trampoline
 /// address for a given hook.
+#[derive(Clone)]
 pub struct MhHook {
     addr: *mut c_void,
     hook_impl: *mut c_void,
     trampoline: *mut c_void,
+    patch: Patch,
+}
+
+// How a hook is enabled.
+#[derive(Clone, Copy)]
+enum Patch {
+    // Minhook patches the code of the target.
+    Inline,
+    // A vtable or import address table slot points to the target.
+    Slot(*mut *mut c_void),
+    // An export address table slot holds the address of the target, relative
+    // to the module base.
+    Rva { slot: *mut u32, target: u32, hook_impl: u32 },
 }
 
 impl MhHook {
@@ -93,7 +205,76 @@ impl MhHook {
         let mut trampoline = null_mut();
         MH_CreateHook(addr, hook_impl, &mut trampoline).ok_context("MH_CreateHook")?;
 
-        Ok(Self { addr, hook_impl, trampoline })
+        Ok(Self { addr, hook_impl, trampoline, patch: Patch::Inline })
+    }
+
+    /// Create a hook replacing the function in a vtable or import address
+    /// table `slot`, instead of patching its code. The original function is
+    /// its own trampoline.
+    ///
+    /// Slot hooks are not queued: they are enabled and disabled right away.
+    ///
+    /// # Safety
+    ///
+    /// Most definitely undefined behavior.
+    pub unsafe fn vtable(slot: *mut *mut c_void, hook_impl: *mut c_void) -> Self {
+        let addr = *slot;
+        Self { addr, hook_impl, trampoline: addr, patch: Patch::Slot(slot) }
+    }
+
+    /// Create a hook replacing the relative address in an export address
+    /// table `slot` with `hook_impl`, which must be reachable from `base`.
+    /// The original function is its own trampoline.
+    ///
+    /// Export hooks are not queued: they are enabled and disabled right away.
+    ///
+    /// # Safety
+    ///
+    /// Most definitely undefined behavior.
+    pub unsafe fn export(base: *mut c_void, slot: *mut u32, hook_impl: *mut c_void) -> Self {
+        let target = *slot;
+        let addr = base.wrapping_byte_add(target as usize);
+        let patch = Patch::Rva {
+            slot,
+            target,
+            hook_impl: (hook_impl as usize).wrapping_sub(base as usize) as u32,
+        };
+        Self { addr, hook_impl, trampoline: addr, patch }
+    }
+
+    /// Create a hook of the function exported as `proc_name` by the loaded
+    /// module `module`.
+    ///
+    /// # Safety
+    ///
+    /// Most definitely undefined behavior.
+    pub unsafe fn from_api(
+        module: &str,
+        proc_name: &str,
+        hook_impl: *mut c_void,
+    ) -> Result<Self, MH_STATUS> {
+        let module = HSTRING::from(module);
+        let proc_name = CString::new(proc_name).map_err(|_| {
+            error!("Invalid function name: {proc_name:?}");
+            MH_STATUS::MH_ERROR_FUNCTION_NOT_FOUND
+        })?;
+
+        let mut addr = null_mut();
+        let mut trampoline = null_mut();
+        MH_CreateHookApiEx(
+            PCWSTR(module.as_ptr()),
+            PCSTR(proc_name.as_ptr() as _),
+            hook_impl,
+            &mut trampoline,
+            &mut addr,
+        )
+        .ok_context("MH_CreateHookApiEx")?;
+
+        Ok(Self { addr, hook_impl, trampoline, patch: Patch::Inline })
+    }
+
+    pub fn target(&self) -> *mut c_void {
+        self.addr
     }
 
     pub fn trampoline(&self) -> *mut c_void {
@@ -104,13 +285,418 @@ impl MhHook {
     ///
     /// Most definitely undefined behavior.
     pub unsafe fn queue_enable(&self) -> Result<(), MH_STATUS> {
-        MH_QueueEnableHook(self.addr).ok_context("MH_QueueEnableHook")
+        match self.patch {
+            Patch::Inline => MH_QueueEnableHook(self.addr).ok_context("MH_QueueEnableHook"),
+            Patch::Slot(slot) => write_slot(slot, self.hook_impl),
+            Patch::Rva { slot, hook_impl, .. } => write_rva(slot, hook_impl),
+        }
     }
 
     /// # Safety
     ///
     /// Most definitely undefined behavior.
     pub unsafe fn queue_disable(&self) -> Result<(), MH_STATUS> {
-        MH_QueueDisableHook(self.addr).ok_context("MH_QueueDisableHook")
+        match self.patch {
+            Patch::Inline => MH_QueueDisableHook(self.addr).ok_context("MH_QueueDisableHook"),
+            Patch::Slot(slot) => write_slot(slot, self.addr),
+            Patch::Rva { slot, target, .. } => write_rva(slot, target),
+        }
+    }
+}
+
+// Replace the function in a vtable or import address table slot.
+unsafe fn write_slot(slot: *mut *mut c_void, function: *mut c_void) -> Result<(), MH_STATUS> {
+    // Other threads may be reading the slot.
+    unprotected(slot, || AtomicPtr::from_ptr(slot).store(function, Ordering::SeqCst))
+}
+
+// Replace the relative address in an export address table slot.
+unsafe fn write_rva(slot: *mut u32, rva: u32) -> Result<(), MH_STATUS> {
+    unprotected(slot, || AtomicU32::from_ptr(slot).store(rva, Ordering::SeqCst))
+}
+
+// Run `f` while `slot`, which may be in read-only memory, is writable.
+unsafe fn unprotected<T>(slot: *mut T, f: impl FnOnce()) -> Result<(), MH_STATUS> {
+    let size = mem::size_of::<T>();
+    let mut old_protect = PAGE_PROTECTION_FLAGS::default();
+    VirtualProtect(slot as _, size, PAGE_READWRITE, &mut old_protect).map_err(|e| {
+        error!("Could not make the slot at {slot:p} writable: {e:?}");
+        MH_STATUS::MH_ERROR_MEMORY_PROTECT
+    })?;
+
+    f();
+
+    if let Err(e) = VirtualProtect(slot as _, size, old_protect, &mut old_protect) {
+        error!("Could not restore the protection of the slot at {slot:p}: {e:?}");
     }
+
+    Ok(())
+}
+
+/// Function pointer types that can be hooked with a [`TypedHook`] or a
+/// [`ClosureHook`].
+///
+/// It is implemented for `extern "C"` and `extern "system"` functions, safe or
+/// unsafe, of up to 12 arguments, and on x86 for `extern "thiscall"` and
+/// `extern "fastcall"` functions as well.
+///
+/// # Safety
+///
+/// Implementors must be function pointers, convertible to and from their
+/// address.
+pub unsafe trait HookFn: Copy + 'static {
+    /// The closures a hook of this function can be detoured to. They receive
+    /// the original function, followed by the arguments.
+    type Detour: ?Sized + Send + Sync;
+
+    /// The address of the function.
+    fn to_ptr(self) -> *mut c_void;
+
+    /// The function at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a function of this type.
+    unsafe fn from_ptr(ptr: *mut c_void) -> Self;
+
+    /// The function that thunks of [`ClosureHook`]s jump to.
+    #[doc(hidden)]
+    fn detour_entry() -> Self;
+}
+
+/// Closures that a hook of a function of type `F` can be detoured to.
+///
+/// It is implemented for closures taking the original function, followed by
+/// the arguments of `F`.
+pub trait DetourFn<F: HookFn>: Send + Sync + 'static {
+    /// Box the closure.
+    fn into_detour(self) -> Box<F::Detour>;
+}
+
+macro_rules! impl_hook_fn {
+    (@impl [$($unsafe:tt)?] $abi:tt [$($arg:ident: $ty:ident),*]) => {
+        unsafe impl<Ret: 'static, $($ty: 'static),*> HookFn
+            for $($unsafe)? extern $abi fn($($ty),*) -> Ret
+        {
+            type Detour = dyn Fn(Self, $($ty),*) -> Ret + Send + Sync;
+
+            fn to_ptr(self) -> *mut c_void {
+                self as *mut c_void
+            }
+
+            unsafe fn from_ptr(ptr: *mut c_void) -> Self {
+                std::mem::transmute(ptr)
+            }
+
+            fn detour_entry() -> Self {
+                #[allow(unused_unsafe)]
+                $($unsafe)? extern $abi fn entry<Ret: 'static, $($ty: 'static),*>(
+                    $($arg: $ty),*
+                ) -> Ret {
+                    let context = thunk::take_context()
+                        as *const DetourContext<$($unsafe)? extern $abi fn($($ty),*) -> Ret>;
+                    let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
+
+                    let context = unsafe { &*context };
+                    (context.detour)(context.original(), $($arg),*)
+                }
+
+                entry::<Ret, $($ty),*>
+            }
+        }
+
+        impl<Cl, Ret: 'static, $($ty: 'static),*>
+            DetourFn<$($unsafe)? extern $abi fn($($ty),*) -> Ret> for Cl
+        where
+            Cl: Fn($($unsafe)? extern $abi fn($($ty),*) -> Ret, $($ty),*) -> Ret
+                + Send
+                + Sync
+                + 'static,
+        {
+            fn into_detour(
+                self,
+            ) -> Box<<$($unsafe)? extern $abi fn($($ty),*) -> Ret as HookFn>::Detour> {
+                Box::new(self)
+            }
+        }
+    };
+    (@abi [$($arg:ident: $ty:ident),*] $($abi:tt)*) => {$(
+        impl_hook_fn!(@impl [] $abi [$($arg: $ty),*]);
+        impl_hook_fn!(@impl [unsafe] $abi [$($arg: $ty),*]);
+    )*};
+    ($($arg:ident: $ty:ident),*) => {
+        impl_hook_fn!(@abi [$($arg: $ty),*] "C" "system");
+        #[cfg(target_arch = "x86")]
+        impl_hook_fn!(@abi [$($arg: $ty),*] "thiscall" "fastcall");
+    };
+}
+
+impl_hook_fn!();
+impl_hook_fn!(a: A);
+impl_hook_fn!(a: A, b: B);
+impl_hook_fn!(a: A, b: B, c: C);
+impl_hook_fn!(a: A, b: B, c: C, d: D);
+impl_hook_fn!(a: A, b: B, c: C, d: D, e: E);
+impl_hook_fn!(a: A, b: B, c: C, d: D, e: E, f: F);
+impl_hook_fn!(a: A, b: B, c: C, d: D, e: E, f: F, g: G);
+impl_hook_fn!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H);
+impl_hook_fn!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H, i: I);
+impl_hook_fn!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H, i: I, j: J);
+impl_hook_fn!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H, i: I, j: J, k: K);
+impl_hook_fn!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H, i: I, j: J, k: K, l: L);
+
+/// A hook of a function of type `F`, which is disabled and removed when
+/// dropped.
+///
+/// Hooks are created disabled. Minhook is initialized if it wasn't already.
+///
+/// ```no_run
+/// # use std::sync::OnceLock;
+/// # use hudhook::mh::TypedHook;
+/// type Damage = unsafe extern "system" fn(entity: usize, amount: i32) -> i32;
+///
+/// static DAMAGE_HOOK: OnceLock<TypedHook<Damage>> = OnceLock::new();
+///
+/// unsafe extern "system" fn damage_impl(entity: usize, amount: i32) -> i32 {
+///     let original = DAMAGE_HOOK.get().unwrap().original();
+///     original(entity, amount / 2)
+/// }
+///
+/// # unsafe fn hook(damage_addr: usize) {
+/// let hook = TypedHook::<Damage>::new(std::mem::transmute(damage_addr), damage_impl).unwrap();
+/// let hook = DAMAGE_HOOK.get_or_init(|| hook);
+/// hook.enable().unwrap();
+/// # }
+/// ```
+pub struct TypedHook<F: HookFn> {
+    hook: MhHook,
+    _marker: PhantomData<F>,
+}
+
+unsafe impl<F: HookFn> Send for TypedHook<F> {}
+unsafe impl<F: HookFn> Sync for TypedHook<F> {}
+
+impl<F: HookFn> TypedHook<F> {
+    /// Create a hook of `target`, which calls `detour` instead once enabled.
+    ///
+    /// # Safety
+    ///
+    /// `target` must be a function of type `F` which can be patched.
+    pub unsafe fn new(target: F, detour: F) -> Result<Self, MH_STATUS> {
+        initialize()?;
+
+        let hook = MhHook::new(target.to_ptr(), detour.to_ptr())?;
+        Ok(Self { hook, _marker: PhantomData })
+    }
+
+    /// Create a hook of the function exported as `proc_name` by the loaded
+    /// module `module`, which calls `detour` instead once enabled.
+    ///
+    /// # Safety
+    ///
+    /// The exported function must be of type `F`.
+    pub unsafe fn from_api(module: &str, proc_name: &str, detour: F) -> Result<Self, MH_STATUS> {
+        initialize()?;
+
+        let hook = MhHook::from_api(module, proc_name, detour.to_ptr())?;
+        Ok(Self { hook, _marker: PhantomData })
+    }
+
+    /// The function calling the original implementation of the target.
+    pub fn original(&self) -> F {
+        unsafe { F::from_ptr(self.hook.trampoline()) }
+    }
+
+    /// The address of the hooked function.
+    pub fn target(&self) -> *mut c_void {
+        self.hook.target()
+    }
+
+    /// Enable the hook.
+    ///
+    /// # Safety
+    ///
+    /// The target is patched while other threads may be running it.
+    pub unsafe fn enable(&self) -> Result<(), MH_STATUS> {
+        MH_EnableHook(self.hook.target()).ok_context("MH_EnableHook")
+    }
+
+    /// Disable the hook.
+    ///
+    /// # Safety
+    ///
+    /// The target is patched while other threads may be running it.
+    pub unsafe fn disable(&self) -> Result<(), MH_STATUS> {
+        MH_DisableHook(self.hook.target()).ok_context("MH_DisableHook")
+    }
+}
+
+impl<F: HookFn> Drop for TypedHook<F> {
+    fn drop(&mut self) {
+        // Removing a hook disables it first. Minhook may have been
+        // uninitialized already, e.g. by `Hudhook::unapply`, which removed all
+        // the hooks.
+        match unsafe { MH_RemoveHook(self.hook.target()) } {
+            MH_STATUS::MH_OK
+            | MH_STATUS::MH_ERROR_NOT_INITIALIZED
+            | MH_STATUS::MH_ERROR_NOT_CREATED => {},
+            status => error!("MH_RemoveHook: {status:?}"),
+        }
+    }
+}
+
+// What the thunk of a closure hook passes to its entry function.
+struct DetourContext<F: HookFn> {
+    original: Option<F>,
+    detour: Box<F::Detour>,
+}
+
+impl<F: HookFn> DetourContext<F> {
+    fn original(&self) -> F {
+        self.original.expect("Closure hook trampoline uninitialized")
+    }
+}
+
+/// A hook of a function of type `F`, detoured to a closure which can capture
+/// state. It is disabled and removed when dropped.
+///
+/// Each hook gets a small executable thunk, which passes its closure to the
+/// detour. Calls of the closure hold an ejection guard, so that [`crate::eject`]
+/// waits for them to return. Other threads may still be running the thunk or
+/// the closure when the hook is dropped, so both are leaked rather than freed.
+///
+/// The types of the closure's parameters must be annotated.
+///
+/// ```no_run
+/// # use std::sync::atomic::{AtomicU32, Ordering};
+/// # use std::sync::Arc;
+/// # use hudhook::mh::hook_fn;
+/// type Damage = unsafe extern "system" fn(entity: usize, amount: i32) -> i32;
+///
+/// # unsafe fn hook(damage: Damage) {
+/// let hits = Arc::new(AtomicU32::new(0));
+/// let hook = hook_fn(damage, {
+///     let hits = Arc::clone(&hits);
+///     move |original: Damage, entity: usize, amount: i32| {
+///         hits.fetch_add(1, Ordering::Relaxed);
+///         original(entity, amount / 2)
+///     }
+/// })
+/// .unwrap();
+/// # }
+/// ```
+pub struct ClosureHook<F: HookFn> {
+    hook: TypedHook<F>,
+    // Threads which entered the detour before the hook was removed may still be
+    // running the thunk and the closure: they are leaked.
+    _thunk: ManuallyDrop<Thunk>,
+    _context: NonNull<DetourContext<F>>,
+}
+
+unsafe impl<F: HookFn> Send for ClosureHook<F> {}
+unsafe impl<F: HookFn> Sync for ClosureHook<F> {}
+
+impl<F: HookFn> ClosureHook<F> {
+    /// Create a hook of `target`, which calls `detour` instead once enabled.
+    ///
+    /// # Safety
+    ///
+    /// `target` must be a function of type `F` which can be patched.
+    pub unsafe fn new(target: F, detour: impl DetourFn<F>) -> Result<Self, MH_STATUS> {
+        let context = NonNull::from(Box::leak(Box::new(DetourContext {
+            original: None,
+            detour: detour.into_detour(),
+        })));
+
+        let hook = Thunk::new(context.as_ptr() as *const c_void, F::detour_entry().to_ptr())
+            .and_then(|thunk| Ok((TypedHook::new(target, F::from_ptr(thunk.as_ptr()))?, thunk)));
+
+        match hook {
+            Ok((hook, thunk)) => {
+                (*context.as_ptr()).original = Some(hook.original());
+                Ok(Self { hook, _thunk: ManuallyDrop::new(thunk), _context: context })
+            },
+            Err(status) => {
+                drop(Box::from_raw(context.as_ptr()));
+                Err(status)
+            },
+        }
+    }
+
+    /// The function calling the original implementation of the target.
+    pub fn original(&self) -> F {
+        self.hook.original()
+    }
+
+    /// The address of the hooked function.
+    pub fn target(&self) -> *mut c_void {
+        self.hook.target()
+    }
+
+    /// Enable the hook.
+    ///
+    /// # Safety
+    ///
+    /// The target is patched while other threads may be running it.
+    pub unsafe fn enable(&self) -> Result<(), MH_STATUS> {
+        self.hook.enable()
+    }
+
+    /// Disable the hook.
+    ///
+    /// # Safety
+    ///
+    /// The target is patched while other threads may be running it.
+    pub unsafe fn disable(&self) -> Result<(), MH_STATUS> {
+        self.hook.disable()
+    }
+}
+
+/// Hook `target` with a closure, and enable the hook. See [`ClosureHook`].
+///
+/// # Safety
+///
+/// `target` must be a function of type `F` which can be patched.
+pub unsafe fn hook_fn<F: HookFn>(
+    target: F,
+    detour: impl DetourFn<F>,
+) -> Result<ClosureHook<F>, MH_STATUS> {
+    let hook = ClosureHook::new(target, detour)?;
+    hook.enable()?;
+    Ok(hook)
+}
+
+/// Hook the function exported as `proc_name` by the loaded module `module`,
+/// and enable the hook.
+///
+/// ```no_run
+/// # use std::sync::OnceLock;
+/// # use hudhook::mh::{hook_api, TypedHook};
+/// # use hudhook::windows::Win32::Foundation::{BOOL, POINT};
+/// type GetCursorPos = unsafe extern "system" fn(*mut POINT) -> BOOL;
+///
+/// static GET_CURSOR_POS: OnceLock<TypedHook<GetCursorPos>> = OnceLock::new();
+///
+/// unsafe extern "system" fn get_cursor_pos_impl(point: *mut POINT) -> BOOL {
+///     GET_CURSOR_POS.get().unwrap().original()(point)
+/// }
+///
+/// # fn hook() -> hudhook::windows::core::Result<()> {
+/// let hook = unsafe { hook_api("user32.dll", "GetCursorPos", get_cursor_pos_impl as _) }?;
+/// GET_CURSOR_POS.get_or_init(|| hook);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Safety
+///
+/// The exported function must be of type `F`.
+pub unsafe fn hook_api<F: HookFn>(
+    module: &str,
+    proc_name: &str,
+    detour: F,
+) -> Result<TypedHook<F>, MH_STATUS> {
+    let hook = TypedHook::from_api(module, proc_name, detour)?;
+    hook.enable()?;
+    Ok(hook)
 }
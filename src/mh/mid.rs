@@ -0,0 +1,530 @@
+//! Hooks of arbitrary instructions, which pass the registers to a callback.
+//!
+//! Minhook patches the hooked instruction with a jump to a stub, and copies
+//! the instructions it overwrites to a trampoline. The stub saves the
+//! registers into a [`Context`] on a 16-byte aligned stack, calls the
+//! callback with it, restores the registers, and jumps to the trampoline,
+//! which runs the overwritten instructions and jumps back after them.
+//!
+//! The stub jumps through a thread local slot rather than returning, which
+//! shadow stacks (CET) would reject as no call pushed the return address.
+
+use std::array;
+use std::ffi::c_void;
+use std::mem::{offset_of, size_of, ManuallyDrop};
+use std::ptr::NonNull;
+use std::sync::OnceLock;
+
+use tracing::error;
+use windows::Win32::System::SystemServices::TLS_MINIMUM_AVAILABLE;
+use windows::Win32::System::Threading::{TlsAlloc, TlsFree, TLS_OUT_OF_INDEXES};
+
+use super::thunk::Thunk;
+use super::{initialize, MH_DisableHook, MH_EnableHook, MH_RemoveHook, MhHook, MH_STATUS};
+use crate::HOOK_EJECTION_BARRIER;
+
+/// An XMM register.
+#[repr(C, align(16))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Xmm(pub [u8; 16]);
+
+impl Xmm {
+    /// The register as four `f32`, the first one being the scalar value.
+    pub fn f32s(&self) -> [f32; 4] {
+        array::from_fn(|i| f32::from_le_bytes(self.0[i * 4..][..4].try_into().unwrap()))
+    }
+
+    /// Set the register to four `f32`.
+    pub fn set_f32s(&mut self, values: [f32; 4]) {
+        for (lane, value) in self.0.chunks_exact_mut(4).zip(values) {
+            lane.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    /// The register as two `f64`, the first one being the scalar value.
+    pub fn f64s(&self) -> [f64; 2] {
+        array::from_fn(|i| f64::from_le_bytes(self.0[i * 8..][..8].try_into().unwrap()))
+    }
+
+    /// Set the register to two `f64`.
+    pub fn set_f64s(&mut self, values: [f64; 2]) {
+        for (lane, value) in self.0.chunks_exact_mut(8).zip(values) {
+            lane.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// The registers at the hooked instruction, which are written back when the
+/// callback returns.
+///
+/// Changing the instruction pointer continues execution at another address,
+/// instead of the hooked instruction. Changes to the stack pointer are
+/// ignored. The x87 registers are not saved.
+// The general purpose registers are in the order of their encoding.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    pub rax: usize,
+    pub rcx: usize,
+    pub rdx: usize,
+    pub rbx: usize,
+    pub rsp: usize,
+    pub rbp: usize,
+    pub rsi: usize,
+    pub rdi: usize,
+    pub r8: usize,
+    pub r9: usize,
+    pub r10: usize,
+    pub r11: usize,
+    pub r12: usize,
+    pub r13: usize,
+    pub r14: usize,
+    pub r15: usize,
+    pub rip: usize,
+    pub rflags: usize,
+    pub xmm: [Xmm; 16],
+}
+
+/// The registers at the hooked instruction, which are written back when the
+/// callback returns.
+///
+/// Changing the instruction pointer continues execution at another address,
+/// instead of the hooked instruction. Changes to the stack pointer are
+/// ignored. The x87 registers are not saved.
+// The general purpose registers are in the order of their encoding.
+#[cfg(target_arch = "x86")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    pub eax: usize,
+    pub ecx: usize,
+    pub edx: usize,
+    pub ebx: usize,
+    pub esp: usize,
+    pub ebp: usize,
+    pub esi: usize,
+    pub edi: usize,
+    pub eip: usize,
+    pub eflags: usize,
+    pub xmm: [Xmm; 8],
+}
+
+impl Context {
+    #[cfg(target_arch = "x86_64")]
+    fn ip_mut(&mut self) -> &mut usize {
+        &mut self.rip
+    }
+
+    #[cfg(target_arch = "x86")]
+    fn ip_mut(&mut self) -> &mut usize {
+        &mut self.eip
+    }
+}
+
+type Callback = dyn Fn(&mut Context) + Send + Sync;
+
+// What the stub of a mid hook passes to its entry function.
+struct MidState {
+    target: usize,
+    trampoline: usize,
+    callback: Box<Callback>,
+}
+
+unsafe extern "C" fn entry(context: *mut Context, state: *const MidState) {
+    let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
+
+    let (context, state) = (&mut *context, &*state);
+    *context.ip_mut() = state.target;
+    (state.callback)(context);
+
+    if *context.ip_mut() == state.target {
+        *context.ip_mut() = state.trampoline;
+    }
+}
+
+const WORD: usize = size_of::<usize>();
+
+// The number of general purpose registers, which is also the number of XMM
+// registers.
+#[cfg(target_arch = "x86_64")]
+const REGISTERS: u8 = 16;
+#[cfg(target_arch = "x86")]
+const REGISTERS: u8 = 8;
+
+// Register encodings.
+const AX: u8 = 0;
+const CX: u8 = 1;
+const DX: u8 = 2;
+const SP: u8 = 4;
+
+// The REX.W prefix selecting 64-bit operands.
+#[cfg(target_arch = "x86_64")]
+const REX_W: &[u8] = &[0x48];
+#[cfg(target_arch = "x86")]
+const REX_W: &[u8] = &[];
+
+// The stack frame of the stub: the arguments of the entry function (or its
+// shadow space on x64), the stack pointer to pop the pushed registers from,
+// and the context.
+#[cfg(target_arch = "x86_64")]
+const ARGS: usize = 32;
+#[cfg(target_arch = "x86")]
+const ARGS: usize = 2 * WORD;
+const SAVED_SP: usize = ARGS;
+const CONTEXT: usize = (SAVED_SP + WORD).next_multiple_of(16);
+const FRAME: usize = CONTEXT + size_of::<Context>();
+
+#[cfg(target_arch = "x86_64")]
+const IP: usize = CONTEXT + offset_of!(Context, rip);
+#[cfg(target_arch = "x86_64")]
+const FLAGS: usize = CONTEXT + offset_of!(Context, rflags);
+#[cfg(target_arch = "x86")]
+const IP: usize = CONTEXT + offset_of!(Context, eip);
+#[cfg(target_arch = "x86")]
+const FLAGS: usize = CONTEXT + offset_of!(Context, eflags);
+const XMM: usize = CONTEXT + offset_of!(Context, xmm);
+
+fn gpr(reg: u8) -> usize {
+    CONTEXT + reg as usize * WORD
+}
+
+fn disp8(disp: isize) -> u8 {
+    disp as i8 as u8
+}
+
+// The REX prefix for a register operand, with 64-bit operands if `wide`.
+#[cfg(target_arch = "x86_64")]
+fn rex(wide: bool, reg: u8) -> Option<u8> {
+    let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2;
+    (rex != 0x40).then_some(rex)
+}
+
+#[cfg(target_arch = "x86")]
+fn rex(_: bool, _: u8) -> Option<u8> {
+    None
+}
+
+// `op reg, [rsp + disp]`, or `op [rsp + disp], reg`.
+fn rsp_relative(code: &mut Vec<u8>, rex: Option<u8>, opcode: &[u8], reg: u8, disp: usize) {
+    code.extend(rex);
+    code.extend_from_slice(opcode);
+    code.extend_from_slice(&[0x84 | (reg & 7) << 3, 0x24]);
+    code.extend_from_slice(&(disp as u32).to_le_bytes());
+}
+
+fn store(code: &mut Vec<u8>, reg: u8, disp: usize) {
+    rsp_relative(code, rex(true, reg), &[0x89], reg, disp);
+}
+
+fn load(code: &mut Vec<u8>, reg: u8, disp: usize) {
+    rsp_relative(code, rex(true, reg), &[0x8B], reg, disp);
+}
+
+fn store_xmm(code: &mut Vec<u8>, reg: u8) {
+    rsp_relative(code, rex(false, reg), &[0x0F, 0x29], reg, XMM + reg as usize * 16);
+}
+
+fn load_xmm(code: &mut Vec<u8>, reg: u8) {
+    rsp_relative(code, rex(false, reg), &[0x0F, 0x28], reg, XMM + reg as usize * 16);
+}
+
+// `op [teb + disp]`, or `op reg, [teb + disp]`, addressing the thread
+// environment block through its segment register.
+#[cfg(target_arch = "x86_64")]
+fn teb_relative(code: &mut Vec<u8>, rex: Option<u8>, opcode: &[u8], reg: u8, disp: u32) {
+    code.push(0x65); // gs
+    code.extend(rex);
+    code.extend_from_slice(opcode);
+    code.extend_from_slice(&[0x04 | (reg & 7) << 3, 0x25]);
+    code.extend_from_slice(&disp.to_le_bytes());
+}
+
+#[cfg(target_arch = "x86")]
+fn teb_relative(code: &mut Vec<u8>, _: Option<u8>, opcode: &[u8], reg: u8, disp: u32) {
+    code.push(0x64); // fs
+    code.extend_from_slice(opcode);
+    code.push(0x05 | (reg & 7) << 3);
+    code.extend_from_slice(&disp.to_le_bytes());
+}
+
+// The offset in the thread environment block of the TLS slot holding the
+// address the stubs continue at.
+fn continuation_slot() -> Result<u32, MH_STATUS> {
+    #[cfg(target_arch = "x86_64")]
+    const TLS_SLOTS: u32 = 0x1480;
+    #[cfg(target_arch = "x86")]
+    const TLS_SLOTS: u32 = 0xE10;
+
+    static INDEX: OnceLock<Option<u32>> = OnceLock::new();
+
+    // Only the first slots are in the thread environment block itself.
+    let index = *INDEX.get_or_init(|| match unsafe { TlsAlloc() } {
+        TLS_OUT_OF_INDEXES => None,
+        index if index >= TLS_MINIMUM_AVAILABLE => {
+            let _ = unsafe { TlsFree(index) };
+            None
+        },
+        index => Some(index),
+    });
+
+    match index {
+        Some(index) => Ok(TLS_SLOTS + index * WORD as u32),
+        None => {
+            error!("Could not allocate a TLS slot for mid hooks");
+            Err(MH_STATUS::MH_ERROR_MEMORY_ALLOC)
+        },
+    }
+}
+
+// The registers saved and restored from the context as they are, i.e. all
+// but the ones used as scratch registers and the stack pointer.
+fn saved_registers() -> impl Iterator<Item = u8> {
+    (0..REGISTERS).filter(|&reg| ![AX, CX, SP].contains(&reg))
+}
+
+// The stub calling `entry` with the context and `state`. Below the stack
+// pointer of the hooked instruction, it pushes the flags, rax and rcx, which
+// are popped last before jumping to the address to continue at, stored in the
+// TLS slot at `slot` in the thread environment block.
+fn assemble(state: usize, entry: usize, slot: u32) -> Vec<u8> {
+    let word = WORD as isize;
+    let mut code = vec![];
+
+    code.extend_from_slice(&[
+        0x9C, // pushf
+        0x50, // push rax
+        0x51, // push rcx
+        0xFC, // cld
+    ]);
+    code.extend_from_slice(REX_W);
+    code.extend_from_slice(&[0x89, 0xE0]); // mov rax, rsp
+    code.extend_from_slice(REX_W);
+    code.extend_from_slice(&[0x83, 0xE4, 0xF0]); // and rsp, -16
+    code.extend_from_slice(REX_W);
+    code.extend_from_slice(&[0x81, 0xEC]); // sub rsp, FRAME
+    code.extend_from_slice(&(FRAME as u32).to_le_bytes());
+
+    store(&mut code, AX, SAVED_SP);
+    for reg in saved_registers() {
+        store(&mut code, reg, gpr(reg));
+    }
+    for (offset, disp) in [(0, gpr(CX)), (word, gpr(AX)), (2 * word, FLAGS)] {
+        code.extend_from_slice(REX_W);
+        code.extend_from_slice(&[0x8B, 0x48, disp8(offset)]); // mov rcx, [rax + offset]
+        store(&mut code, CX, disp);
+    }
+    code.extend_from_slice(REX_W);
+    code.extend_from_slice(&[0x8D, 0x48, disp8(3 * word)]); // lea rcx, [rax + 3 * word]
+    store(&mut code, CX, gpr(SP));
+    for reg in 0..REGISTERS {
+        store_xmm(&mut code, reg);
+    }
+
+    rsp_relative(&mut code, rex(true, CX), &[0x8D], CX, CONTEXT); // lea rcx, [rsp + CONTEXT]
+    code.extend_from_slice(REX_W);
+    code.push(0xBA); // mov rdx, state
+    code.extend_from_slice(&state.to_le_bytes());
+    #[cfg(target_arch = "x86")]
+    {
+        store(&mut code, CX, 0);
+        store(&mut code, DX, WORD);
+    }
+    code.extend_from_slice(REX_W);
+    code.push(0xB8); // mov rax, entry
+    code.extend_from_slice(&entry.to_le_bytes());
+    code.extend_from_slice(&[0xFF, 0xD0]); // call rax
+
+    for reg in 0..REGISTERS {
+        load_xmm(&mut code, reg);
+    }
+    for reg in saved_registers() {
+        load(&mut code, reg, gpr(reg));
+    }
+    load(&mut code, CX, SAVED_SP);
+    for (index, disp) in [(2, FLAGS), (1, gpr(AX)), (0, gpr(CX))] {
+        load(&mut code, AX, disp);
+        code.extend_from_slice(REX_W);
+        code.extend_from_slice(&[0x89, 0x41, disp8(index * word)]); // mov [rcx + index * word], rax
+    }
+    load(&mut code, AX, IP);
+    teb_relative(&mut code, rex(true, AX), &[0x89], AX, slot); // mov [teb + slot], rax
+    code.extend_from_slice(REX_W);
+    code.extend_from_slice(&[0x89, 0xCC]); // mov rsp, rcx
+    code.extend_from_slice(&[
+        0x59, // pop rcx
+        0x58, // pop rax
+        0x9D, // popf
+    ]);
+    teb_relative(&mut code, None, &[0xFF], 4, slot); // jmp [teb + slot]
+    code
+}
+
+// The instruction decoded by hde. Only its length and flags are read.
+#[cfg(target_arch = "x86_64")]
+#[repr(C, packed)]
+#[derive(Default)]
+struct Instruction {
+    len: u8,
+    fields: [u8; 20],
+    imm: u64,
+    disp: u32,
+    flags: u32,
+}
+
+#[cfg(target_arch = "x86")]
+#[repr(C, packed)]
+#[derive(Default)]
+struct Instruction {
+    len: u8,
+    fields: [u8; 15],
+    imm: u32,
+    disp: u32,
+    flags: u32,
+}
+
+const F_ERROR: u32 = 0x1000;
+
+extern "C" {
+    #[cfg(target_arch = "x86_64")]
+    #[link_name = "hde64_disasm"]
+    fn hde_disasm(code: *const c_void, hs: *mut Instruction) -> u32;
+    #[cfg(target_arch = "x86")]
+    #[link_name = "hde32_disasm"]
+    fn hde_disasm(code: *const c_void, hs: *mut Instruction) -> u32;
+}
+
+// Check that the instructions overwritten by the jump to the stub can be
+// decoded, so that minhook can copy them to the trampoline.
+unsafe fn check_instructions(address: *mut c_void) -> Result<(), MH_STATUS> {
+    let mut len = 0;
+    while len < 5 {
+        let mut instruction = Instruction::default();
+        let size = hde_disasm(address.byte_add(len), &mut instruction);
+        if size == 0 || instruction.flags & F_ERROR != 0 {
+            error!("Could not decode the instruction at {:p}", address.byte_add(len));
+            return Err(MH_STATUS::MH_ERROR_UNSUPPORTED_FUNCTION);
+        }
+        len += size as usize;
+    }
+
+    Ok(())
+}
+
+/// A hook of an instruction anywhere in a function, which calls a callback
+/// with the registers before running it. It is disabled and removed when
+/// dropped.
+///
+/// The jump to the hook overwrites 5 bytes, which must not span the end of
+/// the function, nor hold an instruction that another one jumps to. Calls of
+/// the callback hold an ejection guard, so that [`crate::eject`] waits for
+/// them to return. Other threads may still be running the stub or the callback
+/// when the hook is dropped, so both are leaked rather than freed.
+///
+/// ```no_run
+/// # use hudhook::mh::mid_hook;
+/// # unsafe fn hook(address: *mut std::ffi::c_void) {
+/// // The game subtracts the damage in xmm1 from the health here.
+/// let hook = mid_hook(address, |context| {
+///     context.xmm[1].set_f32s([0.; 4]);
+/// })
+/// .unwrap();
+/// # }
+/// ```
+pub struct MidHook {
+    hook: MhHook,
+    // Threads which entered the stub before the hook was removed may still be
+    // running it and the callback: they are leaked.
+    _thunk: ManuallyDrop<Thunk>,
+    _state: NonNull<MidState>,
+}
+
+unsafe impl Send for MidHook {}
+unsafe impl Sync for MidHook {}
+
+impl MidHook {
+    /// Create a hook of the instruction at `address`, which calls `callback`
+    /// before running it once enabled.
+    ///
+    /// # Safety
+    ///
+    /// `address` must be the start of an instruction which can be patched.
+    pub unsafe fn new(
+        address: *mut c_void,
+        callback: impl Fn(&mut Context) + Send + Sync + 'static,
+    ) -> Result<Self, MH_STATUS> {
+        initialize()?;
+        check_instructions(address)?;
+        let slot = continuation_slot()?;
+
+        let state = NonNull::from(Box::leak(Box::new(MidState {
+            target: address as usize,
+            trampoline: 0,
+            callback: Box::new(callback),
+        })));
+
+        let code = assemble(state.as_ptr() as usize, entry as *const c_void as usize, slot);
+        let hook = Thunk::from_code(&code)
+            .and_then(|thunk| Ok((MhHook::new(address, thunk.as_ptr())?, thunk)));
+
+        match hook {
+            Ok((hook, thunk)) => {
+                (*state.as_ptr()).trampoline = hook.trampoline() as usize;
+                Ok(Self { hook, _thunk: ManuallyDrop::new(thunk), _state: state })
+            },
+            Err(status) => {
+                drop(Box::from_raw(state.as_ptr()));
+                Err(status)
+            },
+        }
+    }
+
+    /// The address of the hooked instruction.
+    pub fn target(&self) -> *mut c_void {
+        self.hook.target()
+    }
+
+    /// Enable the hook.
+    ///
+    /// # Safety
+    ///
+    /// The target is patched while other threads may be running it.
+    pub unsafe fn enable(&self) -> Result<(), MH_STATUS> {
+        MH_EnableHook(self.hook.target()).ok_context("MH_EnableHook")
+    }
+
+    /// Disable the hook.
+    ///
+    /// # Safety
+    ///
+    /// The target is patched while other threads may be running it.
+    pub unsafe fn disable(&self) -> Result<(), MH_STATUS> {
+        MH_DisableHook(self.hook.target()).ok_context("MH_DisableHook")
+    }
+}
+
+impl Drop for MidHook {
+    fn drop(&mut self) {
+        match unsafe { MH_RemoveHook(self.hook.target()) } {
+            MH_STATUS::MH_OK
+            | MH_STATUS::MH_ERROR_NOT_INITIALIZED
+            | MH_STATUS::MH_ERROR_NOT_CREATED => {},
+            status => error!("MH_RemoveHook: {status:?}"),
+        }
+    }
+}
+
+/// Hook the instruction at `address` with a callback, and enable the hook.
+/// See [`MidHook`].
+///
+/// # Safety
+///
+/// `address` must be the start of an instruction which can be patched.
+pub unsafe fn mid_hook(
+    address: *mut c_void,
+    callback: impl Fn(&mut Context) + Send + Sync + 'static,
+) -> Result<MidHook, MH_STATUS> {
+    let hook = MidHook::new(address, callback)?;
+    hook.enable()?;
+    Ok(hook)
+}
@@ -0,0 +1,144 @@
+//! Executable stubs which pass a context pointer to a function.
+//!
+//! A thunk saves the argument registers, stores its context pointer in a
+//! thread local slot, restores the registers and jumps to its function, which
+//! takes the context back with [`take_context`] before doing anything else.
+//! The function thus receives the arguments, stack and return address the
+//! thunk was called with.
+
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::ptr::null;
+
+use tracing::error;
+use windows::Win32::System::Diagnostics::Debug::FlushInstructionCache;
+use windows::Win32::System::Memory::{
+    VirtualAlloc, VirtualFree, VirtualProtect, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE,
+    PAGE_EXECUTE_READ, PAGE_PROTECTION_FLAGS, PAGE_READWRITE,
+};
+use windows::Win32::System::Threading::GetCurrentProcess;
+
+use super::MH_STATUS;
+
+thread_local! {
+    static CONTEXT: Cell<*const c_void> = const { Cell::new(null()) };
+}
+
+extern "C" fn set_context(context: *const c_void) {
+    CONTEXT.with(|c| c.set(context));
+}
+
+/// The context of the thunk which jumped to the calling function. It must be
+/// invoked before anything else can run a thunk on this thread.
+pub(super) fn take_context() -> *const c_void {
+    CONTEXT.with(|c| c.replace(null()))
+}
+
+#[cfg(target_arch = "x86_64")]
+fn assemble(context: usize, function: usize) -> Vec<u8> {
+    let mut code = vec![
+        0x51, // push rcx
+        0x52, // push rdx
+        0x41, 0x50, // push r8
+        0x41, 0x51, // push r9
+        0x48, 0x83, 0xEC, 0x48, // sub rsp, 0x48
+        0x66, 0x0F, 0xD6, 0x44, 0x24, 0x20, // movq [rsp+0x20], xmm0
+        0x66, 0x0F, 0xD6, 0x4C, 0x24, 0x28, // movq [rsp+0x28], xmm1
+        0x66, 0x0F, 0xD6, 0x54, 0x24, 0x30, // movq [rsp+0x30], xmm2
+        0x66, 0x0F, 0xD6, 0x5C, 0x24, 0x38, // movq [rsp+0x38], xmm3
+        0x48, 0xB9, // mov rcx, context
+    ];
+    code.extend_from_slice(&context.to_le_bytes());
+    code.extend_from_slice(&[0x48, 0xB8]); // mov rax, set_context
+    code.extend_from_slice(&(set_context as usize).to_le_bytes());
+    code.extend_from_slice(&[
+        0xFF, 0xD0, // call rax
+        0xF3, 0x0F, 0x7E, 0x44, 0x24, 0x20, // movq xmm0, [rsp+0x20]
+        0xF3, 0x0F, 0x7E, 0x4C, 0x24, 0x28, // movq xmm1, [rsp+0x28]
+        0xF3, 0x0F, 0x7E, 0x54, 0x24, 0x30, // movq xmm2, [rsp+0x30]
+        0xF3, 0x0F, 0x7E, 0x5C, 0x24, 0x38, // movq xmm3, [rsp+0x38]
+        0x48, 0x83, 0xC4, 0x48, // add rsp, 0x48
+        0x41, 0x59, // pop r9
+        0x41, 0x58, // pop r8
+        0x5A, // pop rdx
+        0x59, // pop rcx
+        0x48, 0xB8, // mov rax, function
+    ]);
+    code.extend_from_slice(&function.to_le_bytes());
+    code.extend_from_slice(&[0xFF, 0xE0]); // jmp rax
+    code
+}
+
+#[cfg(target_arch = "x86")]
+fn assemble(context: usize, function: usize) -> Vec<u8> {
+    let mut code = vec![
+        0x51, // push ecx
+        0x52, // push edx
+        0x68, // push context
+    ];
+    code.extend_from_slice(&context.to_le_bytes());
+    code.push(0xB8); // mov eax, set_context
+    code.extend_from_slice(&(set_context as usize).to_le_bytes());
+    code.extend_from_slice(&[
+        0xFF, 0xD0, // call eax
+        0x83, 0xC4, 0x04, // add esp, 4
+        0x5A, // pop edx
+        0x59, // pop ecx
+        0xB8, // mov eax, function
+    ]);
+    code.extend_from_slice(&function.to_le_bytes());
+    code.extend_from_slice(&[0xFF, 0xE0]); // jmp eax
+    code
+}
+
+/// An executable thunk, freed when dropped.
+pub(super) struct Thunk(*mut c_void);
+
+impl Thunk {
+    /// Write a thunk jumping to `function` with `context`.
+    pub(super) fn new(context: *const c_void, function: *const c_void) -> Result<Self, MH_STATUS> {
+        Self::from_code(&assemble(context as usize, function as usize))
+    }
+
+    /// Write executable `code`.
+    pub(super) fn from_code(code: &[u8]) -> Result<Self, MH_STATUS> {
+        let thunk =
+            unsafe { VirtualAlloc(None, code.len(), MEM_COMMIT | MEM_RESERVE, PAGE_READWRITE) };
+        if thunk.is_null() {
+            error!("Could not allocate a thunk: {:?}", windows::core::Error::from_win32());
+            return Err(MH_STATUS::MH_ERROR_MEMORY_ALLOC);
+        }
+        let thunk = Self(thunk);
+
+        unsafe {
+            (thunk.0 as *mut u8).copy_from_nonoverlapping(code.as_ptr(), code.len());
+
+            let mut old_protect = PAGE_PROTECTION_FLAGS::default();
+            VirtualProtect(thunk.0, code.len(), PAGE_EXECUTE_READ, &mut old_protect).map_err(
+                |e| {
+                    error!("Could not make a thunk executable: {e:?}");
+                    MH_STATUS::MH_ERROR_MEMORY_PROTECT
+                },
+            )?;
+
+            if let Err(e) = FlushInstructionCache(GetCurrentProcess(), Some(thunk.0), code.len()) {
+                error!("Could not flush the instruction cache: {e:?}");
+            }
+        }
+
+        Ok(thunk)
+    }
+
+    /// The address to call.
+    pub(super) fn as_ptr(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+impl Drop for Thunk {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { VirtualFree(self.0, 0, MEM_RELEASE) } {
+            error!("Could not free a thunk: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Animated textures, played back by uploading their frames in turn.
+
+use std::time::Duration;
+
+use imgui::TextureId;
+use tracing::error;
+use windows::core::{Error, Result, HRESULT};
+
+use crate::RenderContext;
+
+/// A frame of an [`Animation`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnimationFrame {
+    /// Tightly packed RGBA8 pixels, as many as in the animation.
+    pub data: Vec<u8>,
+    /// How long the frame is shown.
+    pub delay: Duration,
+}
+
+/// A sequence of frames of the same size.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Animation {
+    width: u32,
+    height: u32,
+    frames: Vec<AnimationFrame>,
+}
+
+impl Animation {
+    /// An animation of at least one frame, each of `width * height` pixels.
+    pub fn new(width: u32, height: u32, frames: Vec<AnimationFrame>) -> Result<Self> {
+        if frames.is_empty() {
+            error!("Animations need at least one frame");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let size = width as usize * height as usize * 4;
+        if let Some(index) = frames.iter().position(|frame| frame.data.len() != size) {
+            error!("Frame {index} of the animation is not {width}x{height} RGBA pixels");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        Ok(Self { width, height, frames })
+    }
+
+    /// Decode a GIF, APNG or animated WebP file. Still images are decoded as
+    /// a single frame.
+    #[cfg(feature = "animation")]
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        use std::io::Cursor;
+
+        use image::codecs::gif::GifDecoder;
+        use image::codecs::png::PngDecoder;
+        use image::codecs::webp::WebPDecoder;
+        use image::{AnimationDecoder, ImageFormat, ImageResult};
+
+        fn collect<'a>(decoder: impl AnimationDecoder<'a>) -> ImageResult<Vec<image::Frame>> {
+            decoder.into_frames().collect_frames()
+        }
+
+        let frames = match image::guess_format(bytes) {
+            Ok(ImageFormat::Gif) => GifDecoder::new(Cursor::new(bytes)).and_then(collect),
+            Ok(ImageFormat::Png) => PngDecoder::new(Cursor::new(bytes)).and_then(|decoder| {
+                if decoder.is_apng() {
+                    collect(decoder.apng())
+                } else {
+                    image::load_from_memory(bytes)
+                        .map(|image| vec![image::Frame::new(image.into_rgba8())])
+                }
+            }),
+            Ok(ImageFormat::WebP) => WebPDecoder::new(Cursor::new(bytes)).and_then(collect),
+            _ => image::load_from_memory(bytes)
+                .map(|image| vec![image::Frame::new(image.into_rgba8())]),
+        };
+
+        let frames = frames.map_err(|e| {
+            error!("Could not decode the animation: {e}");
+            Error::from_hresult(HRESULT(-1))
+        })?;
+
+        let (width, height) = frames.first().map_or((0, 0), |frame| frame.buffer().dimensions());
+        let frames = frames
+            .into_iter()
+            .map(|frame| AnimationFrame {
+                delay: frame.delay().into(),
+                data: frame.into_buffer().into_raw(),
+            })
+            .collect();
+
+        Self::new(width, height, frames)
+    }
+
+    /// Width of the frames, in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height of the frames, in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The frames, in the order they are played.
+    pub fn frames(&self) -> &[AnimationFrame] {
+        &self.frames
+    }
+}
+
+/// Which frame of an animation is shown, as time goes by.
+#[derive(Clone, Debug)]
+pub struct FrameSchedule {
+    delays: Vec<Duration>,
+    duration: Duration,
+    looping: bool,
+    current: usize,
+    // Time since the current frame was shown.
+    elapsed: Duration,
+}
+
+impl FrameSchedule {
+    /// Shorter delays are played as this one, so that an animation can't be
+    /// stuck on frames without delays.
+    pub const MIN_DELAY: Duration = Duration::from_millis(10);
+
+    /// A looping schedule of frames shown for `delays`, from the first one.
+    pub fn new(delays: impl IntoIterator<Item = Duration>) -> Self {
+        let delays = delays.into_iter().map(|delay| delay.max(Self::MIN_DELAY)).collect::<Vec<_>>();
+        let duration = delays.iter().sum();
+
+        Self { delays, duration, looping: true, current: 0, elapsed: Duration::ZERO }
+    }
+
+    /// Whether the animation starts over after its last frame, or stays on it.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// The index of the frame shown.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Whether the last frame of an animation which doesn't loop has been
+    /// shown for its whole delay.
+    pub fn is_finished(&self) -> bool {
+        !self.looping
+            && self.current + 1 == self.delays.len()
+            && self.elapsed >= self.delays[self.current]
+    }
+
+    /// Show the first frame again.
+    pub fn reset(&mut self) {
+        self.current = 0;
+        self.elapsed = Duration::ZERO;
+    }
+
+    /// Let `delta` go by, and return whether another frame is shown.
+    pub fn advance(&mut self, delta: Duration) -> bool {
+        let Some(&last_delay) = self.delays.last() else {
+            return false;
+        };
+        let previous = self.current;
+
+        self.elapsed += delta;
+        if self.looping && self.elapsed >= self.duration {
+            // Whole loops come back to the same frame.
+            let elapsed = self.elapsed.as_nanos() % self.duration.as_nanos();
+            self.elapsed = Duration::from_nanos(elapsed as u64);
+        }
+
+        while self.elapsed >= self.delays[self.current] {
+            if self.current + 1 == self.delays.len() {
+                if !self.looping {
+                    self.elapsed = last_delay;
+                    break;
+                }
+                self.elapsed -= last_delay;
+                self.current = 0;
+            } else {
+                self.elapsed -= self.delays[self.current];
+                self.current += 1;
+            }
+        }
+
+        self.current != previous
+    }
+}
+
+/// A texture playing an [`Animation`], by replacing its content with the
+/// frame shown.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use hudhook::*;
+/// struct MyRenderLoop(AnimatedTexture);
+///
+/// impl ImguiRenderLoop for MyRenderLoop {
+///     fn initialize<'a>(
+///         &'a mut self,
+///         _ctx: &mut imgui::Context,
+///         render_context: &'a mut dyn RenderContext,
+///     ) {
+///         self.0.load(render_context).unwrap();
+///     }
+///
+///     fn before_render<'a>(
+///         &'a mut self,
+///         ctx: &mut imgui::Context,
+///         render_context: &'a mut dyn RenderContext,
+///     ) {
+///         let delta = Duration::from_secs_f32(ctx.io().delta_time);
+///         self.0.update(render_context, delta).unwrap();
+///     }
+///
+///     fn render(&mut self, ui: &mut imgui::Ui) {
+///         if let Some(texture_id) = self.0.texture_id() {
+///             imgui::Image::new(texture_id, self.0.size()).build(ui);
+///         }
+///     }
+/// }
+/// ```
+pub struct AnimatedTexture {
+    animation: Animation,
+    schedule: FrameSchedule,
+    texture_id: Option<TextureId>,
+}
+
+impl AnimatedTexture {
+    /// A looping animated texture, to load before use.
+    pub fn new(animation: Animation) -> Self {
+        let schedule = FrameSchedule::new(animation.frames.iter().map(|frame| frame.delay));
+        Self { animation, schedule, texture_id: None }
+    }
+
+    /// Load the texture, with the frame shown. Invoke it in your
+    /// [`crate::ImguiRenderLoop::initialize`] method.
+    pub fn load(&mut self, render_context: &mut dyn RenderContext) -> Result<TextureId> {
+        let frame = &self.animation.frames[self.schedule.current()];
+        let texture_id = render_context.load_texture(
+            &frame.data,
+            self.animation.width,
+            self.animation.height,
+        )?;
+        self.texture_id = Some(texture_id);
+
+        Ok(texture_id)
+    }
+
+    /// Let `delta` go by, usually imgui's delta time, and upload the frame
+    /// shown if it changed. Invoke it in your
+    /// [`crate::ImguiRenderLoop::before_render`] method.
+    pub fn update(
+        &mut self,
+        render_context: &mut dyn RenderContext,
+        delta: Duration,
+    ) -> Result<()> {
+        if !self.schedule.advance(delta) {
+            return Ok(());
+        }
+
+        let Some(texture_id) = self.texture_id else {
+            return Ok(());
+        };
+
+        let frame = &self.animation.frames[self.schedule.current()];
+        render_context.replace_texture(
+            texture_id,
+            &frame.data,
+            self.animation.width,
+            self.animation.height,
+        )
+    }
+
+    /// The texture, once loaded.
+    pub fn texture_id(&self) -> Option<TextureId> {
+        self.texture_id
+    }
+
+    /// The size of the frames, e.g. for [`imgui::Image`].
+    pub fn size(&self) -> [f32; 2] {
+        [self.animation.width as f32, self.animation.height as f32]
+    }
+
+    /// The schedule of the frames, e.g. to change whether it loops.
+    pub fn schedule_mut(&mut self) -> &mut FrameSchedule {
+        &mut self.schedule
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_schedule_advance() {
+        let mut schedule = FrameSchedule::new([ms(100), ms(50), ms(200)]);
+
+        assert!(!schedule.advance(ms(99)));
+        assert_eq!(schedule.current(), 0);
+        assert!(schedule.advance(ms(1)));
+        assert_eq!(schedule.current(), 1);
+        assert!(schedule.advance(ms(100)));
+        assert_eq!(schedule.current(), 2);
+        assert!(schedule.advance(ms(150)));
+        assert_eq!(schedule.current(), 0);
+    }
+
+    #[test]
+    fn test_schedule_skips_whole_loops() {
+        let mut schedule = FrameSchedule::new([ms(100), ms(100)]);
+
+        assert!(schedule.advance(ms(10 * 200 + 150)));
+        assert_eq!(schedule.current(), 1);
+        assert!(!schedule.advance(ms(49)));
+        assert!(schedule.advance(ms(1)));
+        assert_eq!(schedule.current(), 0);
+    }
+
+    #[test]
+    fn test_schedule_without_looping() {
+        let mut schedule = FrameSchedule::new([ms(100), ms(100)]);
+        schedule.set_looping(false);
+
+        assert!(schedule.advance(ms(150)));
+        assert!(!schedule.is_finished());
+        assert!(!schedule.advance(ms(1000)));
+        assert_eq!(schedule.current(), 1);
+        assert!(schedule.is_finished());
+
+        schedule.reset();
+        assert_eq!(schedule.current(), 0);
+    }
+
+    #[test]
+    fn test_schedule_min_delay() {
+        let mut schedule = FrameSchedule::new([Duration::ZERO, Duration::ZERO]);
+
+        assert!(!schedule.advance(ms(5)));
+        assert!(schedule.advance(ms(5)));
+        assert_eq!(schedule.current(), 1);
+    }
+
+    #[test]
+    fn test_animation_frame_size() {
+        let frame = |len| AnimationFrame { data: vec![0; len], delay: ms(100) };
+
+        assert!(Animation::new(2, 2, vec![frame(16), frame(16)]).is_ok());
+        assert!(Animation::new(2, 2, vec![frame(16), frame(12)]).is_err());
+        assert!(Animation::new(2, 2, vec![]).is_err());
+    }
+
+    #[cfg(feature = "animation")]
+    #[test]
+    fn test_decode_gif() {
+        use image::codecs::gif::GifEncoder;
+        use image::{Delay, Frame, Rgba, RgbaImage};
+
+        let mut data = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut data);
+            let frames = [[255, 0, 0, 255], [0, 0, 255, 255]].map(|color| {
+                let image = RgbaImage::from_pixel(4, 2, Rgba(color));
+                Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(70, 1))
+            });
+            encoder.encode_frames(frames).unwrap();
+        }
+
+        let animation = Animation::decode(&data).unwrap();
+        assert_eq!((animation.width(), animation.height()), (4, 2));
+        assert_eq!(animation.frames().len(), 2);
+        assert_eq!(animation.frames()[0].delay, ms(70));
+        assert_eq!(animation.frames()[0].data[..4], [255, 0, 0, 255]);
+        assert_eq!(animation.frames()[1].data[..4], [0, 0, 255, 255]);
+    }
+}
@@ -1,30 +1,60 @@
 use std::ffi::c_void;
 use std::mem::offset_of;
+use std::time::Duration;
 use std::{mem, ptr, slice};
 
 use imgui::internal::{RawCast, RawWrapper};
 use imgui::{sys, BackendFlags, Context, DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
 use tracing::error;
-use windows::core::{s, Error, Result, HRESULT};
-use windows::Win32::Foundation::RECT;
+use windows::core::{s, Error, Interface, Result, HRESULT};
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Foundation::HWND;
+use windows::Win32::Foundation::{RECT, S_OK};
 use windows::Win32::Graphics::Direct3D::Fxc::D3DCompile;
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D11::*;
 use windows::Win32::Graphics::Dxgi::Common::*;
-
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Graphics::Dxgi::IDXGISwapChain1;
+
+use crate::renderer::callback::{self, DrawCallbackBackend};
+use crate::renderer::capture::{self, PixelFormat};
+use crate::renderer::color::{self, OutputConstants, OutputTransfer};
+use crate::renderer::shader::{self, PixelShaders};
+use crate::renderer::stats::{self, QueryRing};
 use crate::renderer::RenderEngine;
-use crate::{util, RenderContext};
+use crate::{util, CapturedImage, FrameStats, RenderContext, ShaderId, TextureDesc, TextureFormat};
 
 pub struct D3D11RenderEngine {
     device: ID3D11Device,
     device_context: ID3D11DeviceContext,
 
     shader_program: ShaderProgram,
+    pixel_shaders: PixelShaders<PixelShader>,
     texture_heap: TextureHeap,
 
     vertex_buffer: Buffer<DrawVert>,
     index_buffer: Buffer<DrawIdx>,
     projection_buffer: Buffer<[[f32; 4]; 4]>,
+    output_buffer: Buffer<OutputConstants>,
+
+    hdr10_output: bool,
+    paper_white: f32,
+
+    capture_source: Option<ID3D11Texture2D>,
+    capture_texture: Option<TextureId>,
+
+    frame_stats: FrameStats,
+    gpu_timer: QueryRing<GpuQueries>,
+
+    #[cfg(feature = "imgui-docking")]
+    viewports: Vec<ViewportWindow>,
+}
+
+#[cfg(feature = "imgui-docking")]
+struct ViewportWindow {
+    hwnd: HWND,
+    swap_chain: IDXGISwapChain1,
 }
 
 impl D3D11RenderEngine {
@@ -35,9 +65,15 @@ impl D3D11RenderEngine {
         let vertex_buffer = Buffer::new(&device, 5000, D3D11_BIND_VERTEX_BUFFER)?;
         let index_buffer = Buffer::new(&device, 10000, D3D11_BIND_INDEX_BUFFER)?;
         let projection_buffer = Buffer::new(&device, 1, D3D11_BIND_CONSTANT_BUFFER)?;
+        let output_buffer = Buffer::new(&device, 1, D3D11_BIND_CONSTANT_BUFFER)?;
 
         let shader_program = ShaderProgram::new(&device)?;
         let texture_heap = TextureHeap::new(&device, &device_context)?;
+        let gpu_timer = QueryRing::new(
+            (0..QueryRing::<GpuQueries>::LEN)
+                .map(|_| GpuQueries::new(&device))
+                .collect::<Result<_>>()?,
+        );
 
         ctx.set_ini_filename(None);
         ctx.io_mut().backend_flags |= BackendFlags::RENDERER_HAS_VTX_OFFSET;
@@ -47,17 +83,97 @@ impl D3D11RenderEngine {
             device,
             device_context,
             shader_program,
+            pixel_shaders: PixelShaders::new(),
             texture_heap,
             vertex_buffer,
             index_buffer,
             projection_buffer,
+            output_buffer,
+            hdr10_output: false,
+            paper_white: color::DEFAULT_PAPER_WHITE,
+            capture_source: None,
+            capture_texture: None,
+            frame_stats: FrameStats::default(),
+            gpu_timer,
+            #[cfg(feature = "imgui-docking")]
+            viewports: Vec::new(),
+        })
+    }
+
+    /// Encode colors for HDR10 on 10 bits render targets.
+    pub(crate) fn set_hdr10_output(&mut self, hdr10_output: bool) {
+        self.hdr10_output = hdr10_output;
+    }
+
+    // Create a view of the render target with a typed format, and return the
+    // transfer of the colors drawn to it.
+    unsafe fn create_render_target_view(
+        &self,
+        render_target: &ID3D11Texture2D,
+    ) -> Result<(ID3D11RenderTargetView, OutputTransfer)> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        render_target.GetDesc(&mut desc);
+
+        let view_desc = D3D11_RENDER_TARGET_VIEW_DESC {
+            Format: capture::typed_dxgi_format(desc.Format),
+            ViewDimension: if desc.SampleDesc.Count > 1 {
+                D3D11_RTV_DIMENSION_TEXTURE2DMS
+            } else {
+                D3D11_RTV_DIMENSION_TEXTURE2D
+            },
+            ..Default::default()
+        };
+        let view = util::try_out_ptr(|v| {
+            self.device.CreateRenderTargetView(render_target, Some(&view_desc), Some(v))
+        })?;
+
+        Ok((view, OutputTransfer::from_dxgi(desc.Format, self.hdr10_output)))
+    }
+
+    fn capture_source(&self) -> Result<(ID3D11Texture2D, D3D11_TEXTURE2D_DESC)> {
+        let Some(source) = self.capture_source.clone() else {
+            error!("The render target can only be captured before rendering");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { source.GetDesc(&mut desc) };
+
+        Ok((source, desc))
+    }
+
+    // Copy the capture source to a texture, resolving it if it is multisampled.
+    unsafe fn copy_capture_source(
+        &self,
+        destination: &ID3D11Texture2D,
+        source: &ID3D11Texture2D,
+        desc: &D3D11_TEXTURE2D_DESC,
+    ) {
+        if desc.SampleDesc.Count > 1 {
+            self.device_context.ResolveSubresource(
+                destination,
+                0,
+                source,
+                0,
+                capture::typed_dxgi_format(desc.Format),
+            );
+        } else {
+            self.device_context.CopyResource(destination, source);
+        }
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn viewport(&self, hwnd: HWND) -> Result<&ViewportWindow> {
+        self.viewports.iter().find(|viewport| viewport.hwnd == hwnd).ok_or_else(|| {
+            error!("Unknown viewport window {hwnd:?}");
+            Error::from_hresult(HRESULT(-1))
         })
     }
 }
 
 impl RenderContext for D3D11RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
-        unsafe { self.texture_heap.create_texture(data, width, height) }
+    fn load_texture_with_desc(&mut self, data: &[u8], desc: &TextureDesc) -> Result<TextureId> {
+        unsafe { self.texture_heap.create_texture_with_desc(data, desc) }
     }
 
     fn replace_texture(
@@ -69,6 +185,169 @@ impl RenderContext for D3D11RenderEngine {
     ) -> Result<()> {
         unsafe { self.texture_heap.update_texture(texture_id, data, width, height) }
     }
+
+    fn create_pixel_shader(&mut self, source: &str) -> Result<ShaderId> {
+        let blob = util::compile_shader(source, s!("ps_4_0"))?;
+        let shader = util::try_out_ptr(|v| unsafe {
+            let ptr = blob.GetBufferPointer();
+            let size = blob.GetBufferSize();
+            self.device.CreatePixelShader(slice::from_raw_parts(ptr as _, size), None, Some(v))
+        })?;
+
+        Ok(self.pixel_shaders.insert(PixelShader { shader, constant_buffer: None }))
+    }
+
+    fn set_shader_constants(&mut self, shader: ShaderId, data: &[u8]) -> Result<()> {
+        let constants = shader::constants_to_f32(data);
+        let byte_width = (constants.len() * mem::size_of::<f32>()) as u32;
+        let pixel_shader = self.pixel_shaders.get_mut(shader)?;
+
+        unsafe {
+            match &pixel_shader.constant_buffer {
+                Some(buffer) if buffer_byte_width(buffer) == byte_width => {
+                    self.device_context.UpdateSubresource(
+                        buffer,
+                        0,
+                        None,
+                        constants.as_ptr() as *const c_void,
+                        0,
+                        0,
+                    );
+                },
+                _ if byte_width == 0 => pixel_shader.constant_buffer = None,
+                _ => {
+                    pixel_shader.constant_buffer = Some(util::try_out_ptr(|v| {
+                        self.device.CreateBuffer(
+                            &D3D11_BUFFER_DESC {
+                                ByteWidth: byte_width,
+                                Usage: D3D11_USAGE_DEFAULT,
+                                BindFlags: D3D11_BIND_CONSTANT_BUFFER.0 as u32,
+                                ..Default::default()
+                            },
+                            Some(&D3D11_SUBRESOURCE_DATA {
+                                pSysMem: constants.as_ptr() as *const c_void,
+                                ..Default::default()
+                            }),
+                            Some(v),
+                        )
+                    })?);
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    fn bind_texture_shader(
+        &mut self,
+        texture_id: TextureId,
+        shader: Option<ShaderId>,
+    ) -> Result<()> {
+        self.pixel_shaders.bind_texture(texture_id, shader)
+    }
+
+    fn set_hdr_paper_white(&mut self, nits: f32) {
+        self.paper_white = nits;
+    }
+
+    fn capture_backbuffer(&mut self) -> Result<TextureId> {
+        let (source, desc) = self.capture_source()?;
+
+        unsafe {
+            let texture_id = self.texture_heap.create_capture_texture(self.capture_texture, &desc)?;
+            self.capture_texture = Some(texture_id);
+
+            let texture = &self.texture_heap.textures[texture_id.id()].resource;
+            self.copy_capture_source(texture, &source, &desc);
+
+            Ok(texture_id)
+        }
+    }
+
+    fn capture_backbuffer_rgba(&mut self) -> Result<CapturedImage> {
+        let (source, desc) = self.capture_source()?;
+        let Some(format) = PixelFormat::from_dxgi(desc.Format) else {
+            error!("Unsupported render target format {:?}", desc.Format);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        unsafe {
+            // Multisampled render targets are resolved to the capture texture,
+            // as staging textures can't be resolve destinations.
+            let source = if desc.SampleDesc.Count > 1 {
+                self.capture_backbuffer()?;
+                let texture_id = self.capture_texture.expect("capture texture");
+                self.texture_heap.textures[texture_id.id()].resource.clone()
+            } else {
+                source
+            };
+
+            let staging: ID3D11Texture2D = util::try_out_ptr(|v| {
+                self.device.CreateTexture2D(
+                    &D3D11_TEXTURE2D_DESC {
+                        Width: desc.Width,
+                        Height: desc.Height,
+                        MipLevels: 1,
+                        ArraySize: 1,
+                        Format: capture::typed_dxgi_format(desc.Format),
+                        SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                        Usage: D3D11_USAGE_STAGING,
+                        BindFlags: 0,
+                        CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+                        MiscFlags: 0,
+                    },
+                    None,
+                    Some(v),
+                )
+            })?;
+            self.device_context.CopyResource(&staging, &source);
+
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            self.device_context.Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+            let row_pitch = mapped.RowPitch as usize;
+            let data =
+                slice::from_raw_parts(mapped.pData as *const u8, row_pitch * desc.Height as usize);
+            let image = capture::convert_to_rgba(
+                format,
+                data,
+                row_pitch,
+                desc.Width,
+                desc.Height,
+                false,
+            );
+            self.device_context.Unmap(&staging, 0);
+
+            Ok(image)
+        }
+    }
+
+    fn create_render_target(&mut self, width: u32, height: u32) -> Result<TextureId> {
+        unsafe { self.texture_heap.create_render_target(width, height) }
+    }
+
+    fn render_to_target(&mut self, texture_id: TextureId, draw_data: &DrawData) -> Result<()> {
+        let render_target = self.texture_heap.textures[texture_id.id()].resource.clone();
+
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { render_target.GetDesc(&mut desc) };
+        if desc.BindFlags & D3D11_BIND_RENDER_TARGET.0 as u32 == 0 {
+            error!("Texture {texture_id:?} is not a render target");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        self.update_textures(draw_data)?;
+
+        unsafe {
+            let (render_target_view, _) = self.create_render_target_view(&render_target)?;
+            self.device_context.ClearRenderTargetView(&render_target_view, &[0.; 4]);
+        }
+
+        self.render_to(draw_data, &render_target)
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
 }
 
 impl RenderEngine for D3D11RenderEngine {
@@ -79,19 +358,42 @@ impl RenderEngine for D3D11RenderEngine {
         draw_data: &imgui::DrawData,
         render_target: Self::RenderTarget,
     ) -> Result<()> {
+        let queries = self.gpu_timer.current();
         unsafe {
-            let state_backup = StateBackup::backup(&self.device_context);
+            self.device_context.Begin(&queries.disjoint);
+            self.device_context.End(&queries.begin);
+        }
 
-            let render_target: ID3D11RenderTargetView = util::try_out_ptr(|v| {
-                self.device.CreateRenderTargetView(&render_target, None, Some(v))
-            })?;
+        let result = self.render_to(draw_data, &render_target);
 
-            self.device_context.OMSetRenderTargets(Some(&[Some(render_target)]), None);
-            self.render_draw_data(draw_data)?;
-            state_backup.restore(&self.device_context);
-        };
+        let queries = self.gpu_timer.current();
+        unsafe {
+            self.device_context.End(&queries.end);
+            self.device_context.End(&queries.disjoint);
+        }
 
-        Ok(())
+        if let Some(gpu_time) = self.read_gpu_timer() {
+            self.frame_stats.gpu_time = Some(gpu_time);
+        }
+
+        let textures = self.texture_heap.textures.iter().map(|t| t.size);
+        self.frame_stats.set_textures(textures);
+
+        result
+    }
+
+    fn render_target_size(&self, render_target: &Self::RenderTarget) -> Option<[u32; 2]> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { render_target.GetDesc(&mut desc) };
+        Some([desc.Width, desc.Height])
+    }
+
+    fn frame_stats_mut(&mut self) -> &mut FrameStats {
+        &mut self.frame_stats
+    }
+
+    fn set_capture_source(&mut self, render_target: Option<&Self::RenderTarget>) {
+        self.capture_source = render_target.cloned();
     }
 
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
@@ -256,13 +558,86 @@ impl RenderEngine for D3D11RenderEngine {
 
         Ok(())
     }
+
+    #[cfg(feature = "imgui-docking")]
+    fn create_viewport(&mut self, hwnd: HWND, width: u32, height: u32) -> Result<()> {
+        let swap_chain =
+            util::create_viewport_swap_chain(&self.device.cast()?, hwnd, width, height)?;
+        self.viewports.push(ViewportWindow { hwnd, swap_chain });
+        Ok(())
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn destroy_viewport(&mut self, hwnd: HWND) {
+        self.viewports.retain(|viewport| viewport.hwnd != hwnd);
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn resize_viewport(&mut self, hwnd: HWND, width: u32, height: u32) -> Result<()> {
+        let viewport = self.viewport(hwnd)?;
+        unsafe { viewport.swap_chain.ResizeBuffers(0, width, height, DXGI_FORMAT_UNKNOWN, 0) }
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn render_viewport(&mut self, hwnd: HWND, draw_data: &DrawData, clear: bool) -> Result<()> {
+        let back_buffer: ID3D11Texture2D = unsafe { self.viewport(hwnd)?.swap_chain.GetBuffer(0) }?;
+
+        if clear {
+            unsafe {
+                let (render_target, _) = self.create_render_target_view(&back_buffer)?;
+                self.device_context.ClearRenderTargetView(&render_target, &[0., 0., 0., 1.]);
+            }
+        }
+
+        self.render_to(draw_data, &back_buffer)
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn present_viewport(&mut self, hwnd: HWND) -> Result<()> {
+        unsafe { self.viewport(hwnd)?.swap_chain.Present(0, 0) }.ok()
+    }
 }
 
 impl D3D11RenderEngine {
-    unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
+    fn render_to(&mut self, draw_data: &DrawData, render_target: &ID3D11Texture2D) -> Result<()> {
+        unsafe {
+            let state_backup = StateBackup::backup(&self.device_context);
+
+            let (render_target, transfer) = self.create_render_target_view(render_target)?;
+
+            self.device_context.OMSetRenderTargets(Some(&[Some(render_target)]), None);
+            self.render_draw_data(draw_data, transfer)?;
+            state_backup.restore(&self.device_context);
+        };
+
+        Ok(())
+    }
+
+    // Read the timings of the oldest frame in flight, if they are there.
+    fn read_gpu_timer(&mut self) -> Option<Duration> {
+        let queries = self.gpu_timer.advance()?;
+
+        let disjoint: D3D11_QUERY_DATA_TIMESTAMP_DISJOINT =
+            query_data(&self.device_context, &queries.disjoint)?;
+        let begin: u64 = query_data(&self.device_context, &queries.begin)?;
+        let end: u64 = query_data(&self.device_context, &queries.end)?;
+
+        if disjoint.Disjoint.as_bool() {
+            return None;
+        }
+
+        stats::ticks_to_duration(begin, end, disjoint.Frequency)
+    }
+
+    unsafe fn render_draw_data(
+        &mut self,
+        draw_data: &DrawData,
+        transfer: OutputTransfer,
+    ) -> Result<()> {
         self.vertex_buffer.clear();
         self.index_buffer.clear();
         self.projection_buffer.clear();
+        self.output_buffer.clear();
 
         draw_data
             .draw_lists()
@@ -290,9 +665,12 @@ impl D3D11RenderEngine {
             ]]
         });
 
+        self.output_buffer.push(OutputConstants::new(transfer, self.paper_white));
+
         self.vertex_buffer.upload(&self.device, &self.device_context)?;
         self.index_buffer.upload(&self.device, &self.device_context)?;
         self.projection_buffer.upload(&self.device, &self.device_context)?;
+        self.output_buffer.upload(&self.device, &self.device_context)?;
 
         self.setup_render_state(draw_data);
 
@@ -300,16 +678,19 @@ impl D3D11RenderEngine {
         let mut idx_offset = 0usize;
 
         for cl in draw_data.draw_lists() {
+            self.pixel_shaders.set_current(None);
+
             for cmd in cl.commands() {
                 match cmd {
                     DrawCmd::Elements { count, cmd_params } => {
                         let [cx, cy, cw, ch] = cmd_params.clip_rect;
                         let [x, y] = draw_data.display_pos;
+                        let [sx, sy] = draw_data.framebuffer_scale;
                         let r = RECT {
-                            left: (cx - x) as i32,
-                            top: (cy - y) as i32,
-                            right: (cw - x) as i32,
-                            bottom: (ch - y) as i32,
+                            left: ((cx - x) * sx) as i32,
+                            top: ((cy - y) * sy) as i32,
+                            right: ((cw - x) * sx) as i32,
+                            bottom: ((ch - y) * sy) as i32,
                         };
 
                         if r.right > r.left && r.bottom > r.top {
@@ -317,6 +698,19 @@ impl D3D11RenderEngine {
                                 .shader_resource_view
                                 .clone();
                             unsafe {
+                                match self.pixel_shaders.resolve(cmd_params.texture_id) {
+                                    Some(PixelShader { shader, constant_buffer }) => {
+                                        self.device_context.PSSetShader(shader, Some(&[]));
+                                        self.device_context.PSSetConstantBuffers(
+                                            0,
+                                            Some(&[constant_buffer.clone()]),
+                                        );
+                                    },
+                                    None => self.device_context.PSSetShader(
+                                        &self.shader_program.pixel_shader,
+                                        Some(&[]),
+                                    ),
+                                }
                                 self.device_context.PSSetShaderResources(0, Some(&[Some(srv)]));
                                 self.device_context.RSSetScissorRects(Some(&[r]));
                                 self.device_context.DrawIndexed(
@@ -328,13 +722,23 @@ impl D3D11RenderEngine {
                         }
                     },
                     DrawCmd::ResetRenderState => {
-                        // Q: looking at the commands recorded in here, it
-                        // doesn't seem like this should have any effect
-                        // whatsoever. What am I doing wrong?
                         self.setup_render_state(draw_data);
                     },
-                    DrawCmd::RawCallback { callback, raw_cmd } => unsafe {
-                        callback(cl.raw(), raw_cmd)
+                    DrawCmd::RawCallback { callback, raw_cmd } => {
+                        if let Some(shader) = shader::pixel_shader_command(callback, raw_cmd) {
+                            self.pixel_shaders.set_current(shader);
+                        } else {
+                            callback::invoke_callback(
+                                callback,
+                                cl.raw(),
+                                raw_cmd,
+                                draw_data,
+                                DrawCallbackBackend::D3D11 {
+                                    device: &self.device,
+                                    device_context: &self.device_context,
+                                },
+                            );
+                        }
                     },
                 }
             }
@@ -349,8 +753,8 @@ impl D3D11RenderEngine {
         self.device_context.RSSetViewports(Some(&[D3D11_VIEWPORT {
             TopLeftX: 0f32,
             TopLeftY: 0f32,
-            Width: draw_data.display_size[0],
-            Height: draw_data.display_size[1],
+            Width: draw_data.display_size[0] * draw_data.framebuffer_scale[0],
+            Height: draw_data.display_size[1] * draw_data.framebuffer_scale[1],
             MinDepth: 0f32,
             MaxDepth: 1f32,
         }]));
@@ -376,6 +780,12 @@ impl D3D11RenderEngine {
         self.device_context
             .VSSetConstantBuffers(0, Some(&[Some(self.projection_buffer.resource.clone())]));
         self.device_context.PSSetShader(&self.shader_program.pixel_shader, Some(&[]));
+        self.device_context
+            .PSSetConstantBuffers(1, Some(&[Some(self.output_buffer.resource.clone())]));
+        self.device_context.GSSetShader(None, None);
+        self.device_context.HSSetShader(None, None);
+        self.device_context.DSSetShader(None, None);
+        self.device_context.CSSetShader(None, None);
         self.device_context
             .PSSetSamplers(0, Some(&[Some(self.shader_program.sampler_state.clone())]));
         self.device_context.OMSetBlendState(
@@ -388,6 +798,17 @@ impl D3D11RenderEngine {
     }
 }
 
+struct PixelShader {
+    shader: ID3D11PixelShader,
+    constant_buffer: Option<ID3D11Buffer>,
+}
+
+unsafe fn buffer_byte_width(buffer: &ID3D11Buffer) -> u32 {
+    let mut desc = D3D11_BUFFER_DESC::default();
+    buffer.GetDesc(&mut desc);
+    desc.ByteWidth
+}
+
 struct ShaderProgram {
     vertex_shader: ID3D11VertexShader,
     pixel_shader: ID3D11PixelShader,
@@ -438,7 +859,7 @@ impl ShaderProgram {
 
         float4 main(PS_INPUT input): SV_Target {
           float4 col = input.col * texture0.Sample(sampler0, input.uv);
-          return col;
+          return output_color(col);
         }
         ";
 
@@ -460,22 +881,10 @@ impl ShaderProgram {
         .map_err(util::print_error_blob("Compiling vertex shader"))
         .expect("D3DCompile");
 
-        let ps_blob = util::try_out_err_blob(|v, err_blob| unsafe {
-            D3DCompile(
-                PIXEL_SHADER_SRC.as_ptr() as _,
-                PIXEL_SHADER_SRC.len(),
-                None,
-                None,
-                None,
-                s!("main\0"),
-                s!("ps_4_0\0"),
-                0,
-                0,
-                v,
-                Some(err_blob),
-            )
-        })
-        .map_err(util::print_error_blob("Compiling pixel shader"))
+        let ps_blob = util::compile_shader(
+            &[color::HLSL_OUTPUT_COLOR, PIXEL_SHADER_SRC].concat(),
+            s!("ps_4_0"),
+        )
         .expect("D3DCompile");
 
         let vertex_shader = util::try_out_ptr(|v| unsafe {
@@ -725,6 +1134,8 @@ struct Texture {
     id: TextureId,
     width: u32,
     height: u32,
+    // Bytes of the texture, including its mip levels.
+    size: usize,
 }
 
 struct TextureHeap {
@@ -743,25 +1154,40 @@ impl TextureHeap {
     }
 
     unsafe fn create_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+        self.create_texture_with_desc(data, &TextureDesc::new(width, height, TextureFormat::Rgba8))
+    }
+
+    unsafe fn create_texture_with_desc(
+        &mut self,
+        data: &[u8],
+        desc: &TextureDesc,
+    ) -> Result<TextureId> {
+        let format = desc.format.to_dxgi();
+        let initial_data = desc
+            .mip_levels(data)?
+            .iter()
+            .map(|level| D3D11_SUBRESOURCE_DATA {
+                pSysMem: level.data.as_ptr() as *const c_void,
+                SysMemPitch: level.row_pitch as u32,
+                SysMemSlicePitch: 0,
+            })
+            .collect::<Vec<_>>();
+
         let resource: ID3D11Texture2D = util::try_out_ptr(|v| {
             self.device.CreateTexture2D(
                 &D3D11_TEXTURE2D_DESC {
-                    Width: width,
-                    Height: height,
-                    MipLevels: 1,
+                    Width: desc.width,
+                    Height: desc.height,
+                    MipLevels: desc.mip_levels,
                     ArraySize: 1,
-                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    Format: format,
                     SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
                     Usage: D3D11_USAGE_DEFAULT,
                     BindFlags: D3D11_BIND_SHADER_RESOURCE.0 as u32,
                     CPUAccessFlags: 0,
                     MiscFlags: 0,
                 },
-                Some(&D3D11_SUBRESOURCE_DATA {
-                    pSysMem: data.as_ptr() as *const c_void,
-                    SysMemPitch: width * 4,
-                    SysMemSlicePitch: 0,
-                }),
+                Some(initial_data.as_ptr()),
                 Some(v),
             )
         })?;
@@ -770,18 +1196,112 @@ impl TextureHeap {
             self.device.CreateShaderResourceView(
                 &resource,
                 Some(&D3D11_SHADER_RESOURCE_VIEW_DESC {
-                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    Format: format,
                     ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
                     Anonymous: D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
-                        Texture2D: D3D11_TEX2D_SRV { MostDetailedMip: 0, MipLevels: 1 },
+                        Texture2D: D3D11_TEX2D_SRV {
+                            MostDetailedMip: 0,
+                            MipLevels: desc.mip_levels,
+                        },
                     },
                 }),
                 Some(v),
             )
         })?;
 
+        let (width, height, size) = (desc.width, desc.height, desc.size());
         let id = TextureId::from(self.textures.len());
-        self.textures.push(Texture { resource, shader_resource_view, id, width, height });
+        self.textures.push(Texture { resource, shader_resource_view, id, width, height, size });
+
+        Ok(id)
+    }
+
+    unsafe fn create_render_target(&mut self, width: u32, height: u32) -> Result<TextureId> {
+        let resource: ID3D11Texture2D = util::try_out_ptr(|v| {
+            self.device.CreateTexture2D(
+                &D3D11_TEXTURE2D_DESC {
+                    Width: width,
+                    Height: height,
+                    MipLevels: 1,
+                    ArraySize: 1,
+                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Usage: D3D11_USAGE_DEFAULT,
+                    BindFlags: (D3D11_BIND_SHADER_RESOURCE.0 | D3D11_BIND_RENDER_TARGET.0) as u32,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                },
+                None,
+                Some(v),
+            )
+        })?;
+
+        let shader_resource_view = util::try_out_ptr(|v| {
+            self.device.CreateShaderResourceView(&resource, None, Some(v))
+        })?;
+
+        let size = width as usize * height as usize * 4;
+        let id = TextureId::from(self.textures.len());
+        self.textures.push(Texture { resource, shader_resource_view, id, width, height, size });
+
+        Ok(id)
+    }
+
+    // Create a texture to copy a render target to, unless the texture of the
+    // previous capture matches it already.
+    unsafe fn create_capture_texture(
+        &mut self,
+        texture_id: Option<TextureId>,
+        source: &D3D11_TEXTURE2D_DESC,
+    ) -> Result<TextureId> {
+        let format = capture::typed_dxgi_format(source.Format);
+
+        if let Some(texture_id) = texture_id {
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            self.textures[texture_id.id()].resource.GetDesc(&mut desc);
+            if (desc.Width, desc.Height, desc.Format) == (source.Width, source.Height, format) {
+                return Ok(texture_id);
+            }
+        }
+
+        let resource: ID3D11Texture2D = util::try_out_ptr(|v| {
+            self.device.CreateTexture2D(
+                &D3D11_TEXTURE2D_DESC {
+                    Width: source.Width,
+                    Height: source.Height,
+                    MipLevels: 1,
+                    ArraySize: 1,
+                    Format: format,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Usage: D3D11_USAGE_DEFAULT,
+                    BindFlags: D3D11_BIND_SHADER_RESOURCE.0 as u32,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                },
+                None,
+                Some(v),
+            )
+        })?;
+
+        let shader_resource_view = util::try_out_ptr(|v| {
+            self.device.CreateShaderResourceView(&resource, None, Some(v))
+        })?;
+
+        let bytes_per_pixel =
+            PixelFormat::from_dxgi(format).map_or(4, PixelFormat::bytes_per_pixel);
+        let id = texture_id.unwrap_or_else(|| TextureId::from(self.textures.len()));
+        let texture = Texture {
+            resource,
+            shader_resource_view,
+            id,
+            width: source.Width,
+            height: source.Height,
+            size: source.Width as usize * source.Height as usize * bytes_per_pixel,
+        };
+        match self.textures.get_mut(id.id()) {
+            Some(previous) => *previous = texture,
+            None => self.textures.push(texture),
+        }
 
         Ok(id)
     }
@@ -793,6 +1313,13 @@ impl TextureHeap {
         width: u32,
         height: u32,
     ) -> Result<()> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        self.textures[texture_id.id()].resource.GetDesc(&mut desc);
+        if (desc.Format, desc.MipLevels) != (DXGI_FORMAT_R8G8B8A8_UNORM, 1) {
+            error!("Only RGBA8 textures without mip levels can be replaced");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
         let src_pitch = (width as usize) * 4;
         self.update_texture_region(
             texture_id,
@@ -870,6 +1397,46 @@ impl TextureHeap {
     }
 }
 
+// Queries timing the overlay's draw commands on the GPU.
+struct GpuQueries {
+    disjoint: ID3D11Query,
+    begin: ID3D11Query,
+    end: ID3D11Query,
+}
+
+impl GpuQueries {
+    fn new(device: &ID3D11Device) -> Result<Self> {
+        let query = |query| {
+            util::try_out_ptr(|v| unsafe {
+                device.CreateQuery(&D3D11_QUERY_DESC { Query: query, MiscFlags: 0 }, Some(v))
+            })
+        };
+
+        Ok(Self {
+            disjoint: query(D3D11_QUERY_TIMESTAMP_DISJOINT)?,
+            begin: query(D3D11_QUERY_TIMESTAMP)?,
+            end: query(D3D11_QUERY_TIMESTAMP)?,
+        })
+    }
+}
+
+// The data of a query, if it is available. `GetData` returns `S_FALSE` while it
+// is not, which the `windows` wrapper reports as a success.
+fn query_data<T: Default>(device_context: &ID3D11DeviceContext, query: &ID3D11Query) -> Option<T> {
+    let mut data = T::default();
+    let hr = unsafe {
+        (Interface::vtable(device_context).GetData)(
+            device_context.as_raw(),
+            query.as_raw(),
+            &mut data as *mut T as *mut c_void,
+            mem::size_of::<T>() as u32,
+            D3D11_ASYNC_GETDATA_DONOTFLUSH.0 as u32,
+        )
+    };
+
+    (hr == S_OK).then_some(data)
+}
+
 const BACKUP_OBJECT_COUNT: usize = 16;
 
 struct StateBackup {
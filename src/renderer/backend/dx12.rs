@@ -1,7 +1,9 @@
 // NOTE: see this for ManuallyDrop instances https://github.com/microsoft/windows-rs/issues/2386
 
+use std::collections::HashMap;
 use std::ffi::c_void;
 use std::mem::{offset_of, ManuallyDrop};
+use std::time::Duration;
 use std::{mem, ptr, slice};
 
 use imgui::internal::{RawCast, RawWrapper};
@@ -13,10 +15,18 @@ use windows::Win32::Graphics::Direct3D::Fxc::*;
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D12::*;
 use windows::Win32::Graphics::Dxgi::Common::*;
-
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Graphics::Dxgi::{IDXGISwapChain1, IDXGISwapChain3};
+
+use crate::renderer::callback::{self, DrawCallbackBackend};
+use crate::renderer::capture::{self, PixelFormat};
+use crate::renderer::color::{self, OutputConstants, OutputTransfer};
+use crate::renderer::shader::{self, PixelShaders};
+use crate::renderer::stats;
+use crate::renderer::texture::MipLevel;
 use crate::renderer::RenderEngine;
 use crate::util::{self, Fence};
-use crate::RenderContext;
+use crate::{CapturedImage, FrameStats, RenderContext, ShaderId, TextureDesc};
 
 pub struct D3D12RenderEngine {
     device: ID3D12Device,
@@ -31,13 +41,34 @@ pub struct D3D12RenderEngine {
     texture_heap: TextureHeap,
 
     root_signature: ID3D12RootSignature,
-    pipeline_state: ID3D12PipelineState,
+    pipeline_states: PipelineStates,
+    pixel_shaders: PixelShaders<PixelShader>,
+    rtv_format: DXGI_FORMAT,
 
     vertex_buffer: Buffer<DrawVert>,
     index_buffer: Buffer<u16>,
     projection_buffer: [[f32; 4]; 4],
+    output_constants: OutputConstants,
+
+    hdr10_output: bool,
+    paper_white: f32,
 
     fence: Fence,
+
+    capture_source: Option<ID3D12Resource>,
+    capture_texture: Option<TextureId>,
+
+    frame_stats: FrameStats,
+    gpu_timer: Option<GpuTimer>,
+
+    #[cfg(feature = "imgui-docking")]
+    viewports: Vec<ViewportWindow>,
+}
+
+#[cfg(feature = "imgui-docking")]
+struct ViewportWindow {
+    hwnd: HWND,
+    swap_chain: IDXGISwapChain1,
 }
 
 impl D3D12RenderEngine {
@@ -48,13 +79,17 @@ impl D3D12RenderEngine {
         let (rtv_heap, texture_heap) = unsafe { create_heaps(&device) }?;
         let rtv_heap_start = unsafe { rtv_heap.GetCPUDescriptorHandleForHeapStart() };
 
-        let (root_signature, pipeline_state) = unsafe { create_shader_program(&device) }?;
+        let (root_signature, ps_blob) = unsafe { create_shader_program(&device) }?;
 
         let vertex_buffer = Buffer::new(&device, 5000)?;
         let index_buffer = Buffer::new(&device, 10000)?;
 
         let fence = Fence::new(&device)?;
 
+        let gpu_timer = unsafe { GpuTimer::new(&device, &command_queue) }
+            .map_err(|e| error!("Could not create the GPU timer: {e:?}"))
+            .ok();
+
         ctx.set_ini_filename(None);
         ctx.io_mut().backend_flags |= BackendFlags::RENDERER_HAS_VTX_OFFSET;
         ctx.set_renderer_name(String::from(concat!("hudhook-dx12@", env!("CARGO_PKG_VERSION"))));
@@ -68,20 +103,97 @@ impl D3D12RenderEngine {
             rtv_heap_start,
             texture_heap,
             root_signature,
-            pipeline_state,
+            pipeline_states: PipelineStates::new(ps_blob),
+            pixel_shaders: PixelShaders::new(),
+            rtv_format: DXGI_FORMAT_UNKNOWN,
             vertex_buffer,
             index_buffer,
             projection_buffer: Default::default(),
+            output_constants: OutputConstants::new(
+                OutputTransfer::Srgb,
+                color::DEFAULT_PAPER_WHITE,
+            ),
+            hdr10_output: false,
+            paper_white: color::DEFAULT_PAPER_WHITE,
             fence,
+            capture_source: None,
+            capture_texture: None,
+            frame_stats: FrameStats::default(),
+            gpu_timer,
+            #[cfg(feature = "imgui-docking")]
+            viewports: Vec::new(),
+        })
+    }
+
+    /// Encode colors for HDR10 on 10 bits render targets.
+    pub(crate) fn set_hdr10_output(&mut self, hdr10_output: bool) {
+        self.hdr10_output = hdr10_output;
+    }
+
+    // Create the pipeline states of the default and custom pixel shaders for
+    // the format of the render target, the first time it is rendered to.
+    unsafe fn prepare_pipeline_states(&mut self, format: DXGI_FORMAT) -> Result<()> {
+        self.pipeline_states.prepare(&self.device, &self.root_signature, format)?;
+        for pixel_shader in self.pixel_shaders.iter_mut() {
+            pixel_shader.pipeline_states.prepare(&self.device, &self.root_signature, format)?;
+        }
+        self.rtv_format = format;
+
+        Ok(())
+    }
+
+    fn capture_source(&self) -> Result<(ID3D12Resource, D3D12_RESOURCE_DESC)> {
+        let Some(source) = self.capture_source.clone() else {
+            error!("The render target can only be captured before rendering");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+        let desc = unsafe { source.GetDesc() };
+
+        Ok((source, desc))
+    }
+
+    // Record commands after the application's ones on its queue, and wait for
+    // them to complete.
+    unsafe fn execute_commands(
+        &mut self,
+        record: impl FnOnce(&ID3D12GraphicsCommandList),
+    ) -> Result<()> {
+        self.command_allocator.Reset()?;
+        self.command_list.Reset(&self.command_allocator, None)?;
+        record(&self.command_list);
+        self.command_list.Close()?;
+        self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
+        self.command_queue.Signal(self.fence.fence(), self.fence.value())?;
+        self.fence.wait()?;
+        self.fence.incr();
+
+        Ok(())
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn viewport(&self, hwnd: HWND) -> Result<&ViewportWindow> {
+        self.viewports.iter().find(|viewport| viewport.hwnd == hwnd).ok_or_else(|| {
+            error!("Unknown viewport window {hwnd:?}");
+            Error::from_hresult(HRESULT(-1))
         })
     }
 }
 
 impl RenderContext for D3D12RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    fn load_texture_with_desc(&mut self, data: &[u8], desc: &TextureDesc) -> Result<TextureId> {
         unsafe {
-            let texture_id = self.texture_heap.create_texture(width, height)?;
-            self.texture_heap.upload_texture(texture_id, data, width, height)?;
+            let levels = desc.mip_levels(data)?;
+            let texture_id = self.texture_heap.create_texture_with(
+                None,
+                desc.width,
+                desc.height,
+                desc.format.to_dxgi(),
+                desc.mip_levels,
+                desc.size(),
+                D3D12_RESOURCE_FLAG_NONE,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+            )?;
+            self.texture_heap.upload_mip_levels(texture_id, &levels)?;
             Ok(texture_id)
         }
     }
@@ -95,50 +207,260 @@ impl RenderContext for D3D12RenderEngine {
     ) -> Result<()> {
         unsafe { self.texture_heap.upload_texture(texture_id, data, width, height) }
     }
-}
 
-impl RenderEngine for D3D12RenderEngine {
-    type RenderTarget = ID3D12Resource;
+    fn create_pixel_shader(&mut self, source: &str) -> Result<ShaderId> {
+        let blob = util::compile_shader(source, s!("ps_5_0"))?;
+        let mut pipeline_states = PipelineStates::new(blob);
+        if self.rtv_format != DXGI_FORMAT_UNKNOWN {
+            unsafe {
+                pipeline_states.prepare(&self.device, &self.root_signature, self.rtv_format)
+            }?;
+        }
+
+        Ok(self.pixel_shaders.insert(PixelShader { pipeline_states, constant_buffer: None }))
+    }
+
+    fn set_shader_constants(&mut self, shader: ShaderId, data: &[u8]) -> Result<()> {
+        let constants = shader::constants_to_f32(data);
+        let pixel_shader = self.pixel_shaders.get_mut(shader)?;
+
+        if constants.is_empty() {
+            pixel_shader.constant_buffer = None;
+            return Ok(());
+        }
+
+        let constant_buffer = match &mut pixel_shader.constant_buffer {
+            Some(constant_buffer) => constant_buffer,
+            // Root constant buffer views are read in chunks of 256 bytes.
+            constant_buffer => constant_buffer
+                .insert(Buffer::new(&self.device, constants.len().next_multiple_of(64))?),
+        };
+        constant_buffer.clear();
+        constant_buffer.extend(constants);
+        constant_buffer.upload(&self.device)
+    }
+
+    fn bind_texture_shader(
+        &mut self,
+        texture_id: TextureId,
+        shader: Option<ShaderId>,
+    ) -> Result<()> {
+        self.pixel_shaders.bind_texture(texture_id, shader)
+    }
+
+    fn set_hdr_paper_white(&mut self, nits: f32) {
+        self.paper_white = nits;
+    }
+
+    fn capture_backbuffer(&mut self) -> Result<TextureId> {
+        let (source, desc) = self.capture_source()?;
 
-    fn render(&mut self, draw_data: &DrawData, render_target: Self::RenderTarget) -> Result<()> {
         unsafe {
-            self.device.CreateRenderTargetView(&render_target, None, self.rtv_heap_start);
+            let texture_id = self.texture_heap.create_capture_texture(self.capture_texture, &desc)?;
+            self.capture_texture = Some(texture_id);
+            let texture = self.texture_heap.textures[texture_id.id()].resource.clone();
+
+            let before_copy_barriers = [
+                util::create_barrier(
+                    &source,
+                    D3D12_RESOURCE_STATE_PRESENT,
+                    D3D12_RESOURCE_STATE_COPY_SOURCE,
+                ),
+                util::create_barrier(
+                    &texture,
+                    D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+                    D3D12_RESOURCE_STATE_COPY_DEST,
+                ),
+            ];
+            let after_copy_barriers = [
+                util::create_barrier(
+                    &source,
+                    D3D12_RESOURCE_STATE_COPY_SOURCE,
+                    D3D12_RESOURCE_STATE_PRESENT,
+                ),
+                util::create_barrier(
+                    &texture,
+                    D3D12_RESOURCE_STATE_COPY_DEST,
+                    D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+                ),
+            ];
 
-            self.command_allocator.Reset()?;
-            self.command_list.Reset(&self.command_allocator, None)?;
+            let result = self.execute_commands(|command_list| {
+                command_list.ResourceBarrier(&before_copy_barriers);
+                command_list.CopyResource(&texture, &source);
+                command_list.ResourceBarrier(&after_copy_barriers);
+            });
 
-            let present_to_rtv_barriers = [util::create_barrier(
-                &render_target,
+            before_copy_barriers.into_iter().for_each(util::drop_barrier);
+            after_copy_barriers.into_iter().for_each(util::drop_barrier);
+            result?;
+
+            Ok(texture_id)
+        }
+    }
+
+    fn capture_backbuffer_rgba(&mut self) -> Result<CapturedImage> {
+        let (source, desc) = self.capture_source()?;
+        let Some(format) = PixelFormat::from_dxgi(desc.Format) else {
+            error!("Unsupported render target format {:?}", desc.Format);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        unsafe {
+            let mut footprint = D3D12_PLACED_SUBRESOURCE_FOOTPRINT::default();
+            let mut readback_size = 0u64;
+            self.device.GetCopyableFootprints(
+                &desc,
+                0,
+                1,
+                0,
+                Some(&mut footprint),
+                None,
+                None,
+                Some(&mut readback_size),
+            );
+
+            let readback_buffer: ID3D12Resource = util::try_out_ptr(|v| {
+                self.device.CreateCommittedResource(
+                    &D3D12_HEAP_PROPERTIES {
+                        Type: D3D12_HEAP_TYPE_READBACK,
+                        CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                        MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+                        CreationNodeMask: Default::default(),
+                        VisibleNodeMask: Default::default(),
+                    },
+                    D3D12_HEAP_FLAG_NONE,
+                    &D3D12_RESOURCE_DESC {
+                        Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+                        Alignment: 0,
+                        Width: readback_size,
+                        Height: 1,
+                        DepthOrArraySize: 1,
+                        MipLevels: 1,
+                        Format: DXGI_FORMAT_UNKNOWN,
+                        SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                        Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+                        Flags: D3D12_RESOURCE_FLAG_NONE,
+                    },
+                    D3D12_RESOURCE_STATE_COPY_DEST,
+                    None,
+                    v,
+                )
+            })?;
+
+            let dst_location = D3D12_TEXTURE_COPY_LOCATION {
+                pResource: ManuallyDrop::new(Some(readback_buffer.clone())),
+                Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+                Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { PlacedFootprint: footprint },
+            };
+            let src_location = D3D12_TEXTURE_COPY_LOCATION {
+                pResource: ManuallyDrop::new(Some(source.clone())),
+                Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: 0 },
+            };
+
+            let before_copy_barriers = [util::create_barrier(
+                &source,
                 D3D12_RESOURCE_STATE_PRESENT,
-                D3D12_RESOURCE_STATE_RENDER_TARGET,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
             )];
-
-            let rtv_to_present_barriers = [util::create_barrier(
-                &render_target,
-                D3D12_RESOURCE_STATE_RENDER_TARGET,
-                D3D12_RESOURCE_STATE_COMMON,
+            let after_copy_barriers = [util::create_barrier(
+                &source,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+                D3D12_RESOURCE_STATE_PRESENT,
             )];
 
-            self.command_list.ResourceBarrier(&present_to_rtv_barriers);
-            self.command_list.OMSetRenderTargets(1, Some(&self.rtv_heap_start), false, None);
-            self.command_list.SetDescriptorHeaps(&[Some(self.texture_heap.srv_heap.clone())]);
+            let result = self.execute_commands(|command_list| {
+                command_list.ResourceBarrier(&before_copy_barriers);
+                command_list.CopyTextureRegion(&dst_location, 0, 0, 0, &src_location, None);
+                command_list.ResourceBarrier(&after_copy_barriers);
+            });
 
-            self.render_draw_data(draw_data)?;
+            before_copy_barriers.into_iter().for_each(util::drop_barrier);
+            after_copy_barriers.into_iter().for_each(util::drop_barrier);
+            // Release the references to the back buffer, or the swap chain
+            // can't be resized anymore.
+            let _ = ManuallyDrop::into_inner(dst_location.pResource);
+            let _ = ManuallyDrop::into_inner(src_location.pResource);
+            result?;
+
+            let mut readback_ptr = ptr::null_mut();
+            readback_buffer.Map(0, None, Some(&mut readback_ptr))?;
+            let data = slice::from_raw_parts(readback_ptr as *const u8, readback_size as usize);
+            let image = capture::convert_to_rgba(
+                format,
+                data,
+                footprint.Footprint.RowPitch as usize,
+                desc.Width as u32,
+                desc.Height,
+                false,
+            );
+            readback_buffer.Unmap(0, None);
 
-            self.command_list.ResourceBarrier(&rtv_to_present_barriers);
-            self.command_list.Close()?;
-            self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
-            self.command_queue.Signal(self.fence.fence(), self.fence.value())?;
-            self.fence.wait()?;
-            self.fence.incr();
+            Ok(image)
+        }
+    }
 
-            present_to_rtv_barriers.into_iter().for_each(util::drop_barrier);
-            rtv_to_present_barriers.into_iter().for_each(util::drop_barrier);
+    fn create_render_target(&mut self, width: u32, height: u32) -> Result<TextureId> {
+        // Render targets are left in the common state, from which they are
+        // implicitly promoted when sampled, as `render_to` expects.
+        unsafe {
+            self.texture_heap.create_texture_with(
+                None,
+                width,
+                height,
+                DXGI_FORMAT_R8G8B8A8_UNORM,
+                1,
+                width as usize * height as usize * 4,
+                D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+                D3D12_RESOURCE_STATE_COMMON,
+            )
         }
+    }
+
+    fn render_to_target(&mut self, texture_id: TextureId, draw_data: &DrawData) -> Result<()> {
+        let render_target = self.texture_heap.textures[texture_id.id()].resource.clone();
+
+        let desc = unsafe { render_target.GetDesc() };
+        if !desc.Flags.contains(D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET) {
+            error!("Texture {texture_id:?} is not a render target");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        self.update_textures(draw_data)?;
+        self.render_to(draw_data, &render_target, Some([0.; 4]), false)
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
+}
+
+impl RenderEngine for D3D12RenderEngine {
+    type RenderTarget = ID3D12Resource;
+
+    fn render(&mut self, draw_data: &DrawData, render_target: Self::RenderTarget) -> Result<()> {
+        self.render_to(draw_data, &render_target, None, true)?;
+
+        let textures = self.texture_heap.textures.iter().map(|t| t.size);
+        self.frame_stats.set_textures(textures);
 
         Ok(())
     }
 
+    fn render_target_size(&self, render_target: &Self::RenderTarget) -> Option<[u32; 2]> {
+        let desc = unsafe { render_target.GetDesc() };
+        Some([desc.Width as u32, desc.Height])
+    }
+
+    fn frame_stats_mut(&mut self) -> &mut FrameStats {
+        &mut self.frame_stats
+    }
+
+    fn set_capture_source(&mut self, render_target: Option<&Self::RenderTarget>) {
+        self.capture_source = render_target.cloned();
+    }
+
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
         let fonts_texture = fonts.build_rgba32_texture();
@@ -291,9 +613,118 @@ impl RenderEngine for D3D12RenderEngine {
 
         Ok(())
     }
+
+    #[cfg(feature = "imgui-docking")]
+    fn create_viewport(&mut self, hwnd: HWND, width: u32, height: u32) -> Result<()> {
+        let swap_chain =
+            util::create_viewport_swap_chain(&self.command_queue.cast()?, hwnd, width, height)?;
+        self.viewports.push(ViewportWindow { hwnd, swap_chain });
+        Ok(())
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn destroy_viewport(&mut self, hwnd: HWND) {
+        self.viewports.retain(|viewport| viewport.hwnd != hwnd);
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn resize_viewport(&mut self, hwnd: HWND, width: u32, height: u32) -> Result<()> {
+        let viewport = self.viewport(hwnd)?;
+        unsafe { viewport.swap_chain.ResizeBuffers(0, width, height, DXGI_FORMAT_UNKNOWN, 0) }
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn render_viewport(&mut self, hwnd: HWND, draw_data: &DrawData, clear: bool) -> Result<()> {
+        let back_buffer: ID3D12Resource = unsafe {
+            let swap_chain: IDXGISwapChain3 = self.viewport(hwnd)?.swap_chain.cast()?;
+            swap_chain.GetBuffer(swap_chain.GetCurrentBackBufferIndex())
+        }?;
+
+        let clear_color = clear.then_some([0., 0., 0., 1.]);
+        self.render_to(draw_data, &back_buffer, clear_color, false)
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn present_viewport(&mut self, hwnd: HWND) -> Result<()> {
+        unsafe { self.viewport(hwnd)?.swap_chain.Present(0, 0) }.ok()
+    }
 }
 
 impl D3D12RenderEngine {
+    fn render_to(
+        &mut self,
+        draw_data: &DrawData,
+        render_target: &ID3D12Resource,
+        clear_color: Option<[f32; 4]>,
+        timed: bool,
+    ) -> Result<()> {
+        unsafe {
+            let desc = render_target.GetDesc();
+            let format = capture::typed_dxgi_format(desc.Format);
+            self.prepare_pipeline_states(format)?;
+            self.output_constants = OutputConstants::new(
+                OutputTransfer::from_dxgi(desc.Format, self.hdr10_output),
+                self.paper_white,
+            );
+
+            self.device.CreateRenderTargetView(
+                render_target,
+                Some(&D3D12_RENDER_TARGET_VIEW_DESC {
+                    Format: format,
+                    ViewDimension: D3D12_RTV_DIMENSION_TEXTURE2D,
+                    ..Default::default()
+                }),
+                self.rtv_heap_start,
+            );
+
+            self.command_allocator.Reset()?;
+            self.command_list.Reset(&self.command_allocator, None)?;
+
+            let present_to_rtv_barriers = [util::create_barrier(
+                render_target,
+                D3D12_RESOURCE_STATE_PRESENT,
+                D3D12_RESOURCE_STATE_RENDER_TARGET,
+            )];
+
+            let rtv_to_present_barriers = [util::create_barrier(
+                render_target,
+                D3D12_RESOURCE_STATE_RENDER_TARGET,
+                D3D12_RESOURCE_STATE_COMMON,
+            )];
+
+            if let Some(gpu_timer) = self.gpu_timer.as_ref().filter(|_| timed) {
+                gpu_timer.begin(&self.command_list);
+            }
+            self.command_list.ResourceBarrier(&present_to_rtv_barriers);
+            if let Some(clear_color) = clear_color {
+                self.command_list.ClearRenderTargetView(self.rtv_heap_start, &clear_color, None);
+            }
+            self.command_list.OMSetRenderTargets(1, Some(&self.rtv_heap_start), false, None);
+
+            self.render_draw_data(draw_data)?;
+
+            self.command_list.ResourceBarrier(&rtv_to_present_barriers);
+            if let Some(gpu_timer) = self.gpu_timer.as_ref().filter(|_| timed) {
+                gpu_timer.end(&self.command_list);
+            }
+            self.command_list.Close()?;
+            self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
+            self.command_queue.Signal(self.fence.fence(), self.fence.value())?;
+            self.fence.wait()?;
+            self.fence.incr();
+
+            let gpu_timer = self.gpu_timer.as_ref().filter(|_| timed);
+            if let Some(gpu_time) = gpu_timer.and_then(|gpu_timer| gpu_timer.read()) {
+                self.frame_stats.gpu_time = Some(gpu_time);
+            }
+
+            present_to_rtv_barriers.into_iter().for_each(util::drop_barrier);
+            rtv_to_present_barriers.into_iter().for_each(util::drop_barrier);
+        }
+
+        Ok(())
+    }
+
     unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
         self.vertex_buffer.clear();
         self.index_buffer.clear();
@@ -333,21 +764,39 @@ impl D3D12RenderEngine {
         let mut idx_offset = 0usize;
 
         for cl in draw_data.draw_lists() {
+            self.pixel_shaders.set_current(None);
+
             for cmd in cl.commands() {
                 match cmd {
                     DrawCmd::Elements { count, cmd_params } => {
                         let [cx, cy, cw, ch] = cmd_params.clip_rect;
                         let [x, y] = draw_data.display_pos;
+                        let [sx, sy] = draw_data.framebuffer_scale;
                         let r = RECT {
-                            left: (cx - x) as i32,
-                            top: (cy - y) as i32,
-                            right: (cw - x) as i32,
-                            bottom: (ch - y) as i32,
+                            left: ((cx - x) * sx) as i32,
+                            top: ((cy - y) * sy) as i32,
+                            right: ((cw - x) * sx) as i32,
+                            bottom: ((ch - y) * sy) as i32,
                         };
 
                         if r.right > r.left && r.bottom > r.top {
                             let tex_handle =
                                 self.texture_heap.textures[cmd_params.texture_id.id()].gpu_desc;
+                            match self.pixel_shaders.resolve(cmd_params.texture_id) {
+                                Some(PixelShader { pipeline_states, constant_buffer }) => {
+                                    self.command_list
+                                        .SetPipelineState(pipeline_states.get(self.rtv_format));
+                                    if let Some(constant_buffer) = constant_buffer {
+                                        self.command_list.SetGraphicsRootConstantBufferView(
+                                            2,
+                                            constant_buffer.resource.GetGPUVirtualAddress(),
+                                        );
+                                    }
+                                },
+                                None => self
+                                    .command_list
+                                    .SetPipelineState(self.pipeline_states.get(self.rtv_format)),
+                            }
                             self.command_list.SetGraphicsRootDescriptorTable(1, tex_handle);
                             self.command_list.RSSetScissorRects(&[r]);
                             self.command_list.DrawIndexedInstanced(
@@ -360,12 +809,24 @@ impl D3D12RenderEngine {
                         }
                     },
                     DrawCmd::ResetRenderState => {
-                        // Q: looking at the commands recorded in here, it
-                        // doesn't seem like this should have any effect
-                        // whatsoever. What am I doing wrong?
                         self.setup_render_state(draw_data);
                     },
-                    DrawCmd::RawCallback { callback, raw_cmd } => callback(cl.raw(), raw_cmd),
+                    DrawCmd::RawCallback { callback, raw_cmd } => {
+                        if let Some(shader) = shader::pixel_shader_command(callback, raw_cmd) {
+                            self.pixel_shaders.set_current(shader);
+                        } else {
+                            callback::invoke_callback(
+                                callback,
+                                cl.raw(),
+                                raw_cmd,
+                                draw_data,
+                                DrawCallbackBackend::D3D12 {
+                                    device: &self.device,
+                                    command_list: &self.command_list,
+                                },
+                            );
+                        }
+                    },
                 }
             }
             idx_offset += cl.idx_buffer().len();
@@ -379,8 +840,8 @@ impl D3D12RenderEngine {
         self.command_list.RSSetViewports(&[D3D12_VIEWPORT {
             TopLeftX: 0f32,
             TopLeftY: 0f32,
-            Width: draw_data.display_size[0],
-            Height: draw_data.display_size[1],
+            Width: draw_data.display_size[0] * draw_data.framebuffer_scale[0],
+            Height: draw_data.display_size[1] * draw_data.framebuffer_scale[1],
             MinDepth: 0f32,
             MaxDepth: 1f32,
         }]);
@@ -404,14 +865,21 @@ impl D3D12RenderEngine {
             },
         }));
         self.command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
-        self.command_list.SetPipelineState(&self.pipeline_state);
+        self.command_list.SetPipelineState(self.pipeline_states.get(self.rtv_format));
         self.command_list.SetGraphicsRootSignature(&self.root_signature);
+        self.command_list.SetDescriptorHeaps(&[Some(self.texture_heap.srv_heap.clone())]);
         self.command_list.SetGraphicsRoot32BitConstants(
             0,
             16,
             self.projection_buffer.as_ptr() as *const c_void,
             0,
         );
+        self.command_list.SetGraphicsRoot32BitConstants(
+            3,
+            (mem::size_of::<OutputConstants>() / 4) as u32,
+            &self.output_constants as *const OutputConstants as *const c_void,
+            0,
+        );
         self.command_list.OMSetBlendFactor(Some(&[0f32; 4]));
     }
 }
@@ -457,9 +925,7 @@ unsafe fn create_heaps(device: &ID3D12Device) -> Result<(ID3D12DescriptorHeap, T
     Ok((rtv_heap, texture_heap))
 }
 
-unsafe fn create_shader_program(
-    device: &ID3D12Device,
-) -> Result<(ID3D12RootSignature, ID3D12PipelineState)> {
+unsafe fn create_shader_program(device: &ID3D12Device) -> Result<(ID3D12RootSignature, ID3DBlob)> {
     let parameters = [
         D3D12_ROOT_PARAMETER {
             ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
@@ -488,10 +954,28 @@ unsafe fn create_shader_program(
             },
             ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
         },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR { ShaderRegister: 0, RegisterSpace: 0 },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                    Num32BitValues: (mem::size_of::<OutputConstants>() / 4) as u32,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        },
     ];
 
     let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
-        NumParameters: 2,
+        NumParameters: parameters.len() as u32,
         pParameters: parameters.as_ptr(),
         NumStaticSamplers: 1,
         pStaticSamplers: &D3D12_STATIC_SAMPLER_DESC {
@@ -531,6 +1015,33 @@ unsafe fn create_shader_program(
         slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize()),
     )?;
 
+    const PS: &str = r#"
+    struct PS_INPUT {
+      float4 pos: SV_POSITION;
+      float4 col: COLOR0;
+      float2 uv: TEXCOORD0;
+    };
+
+    SamplerState sampler0: register(s0);
+    Texture2D texture0: register(t0);
+
+    float4 main(PS_INPUT input): SV_Target {
+      float4 out_col = input.col * texture0.Sample(sampler0, input.uv);
+      return output_color(out_col);
+    }"#;
+
+    let pix_shader = util::compile_shader(&[color::HLSL_OUTPUT_COLOR, PS].concat(), s!("ps_5_0"))
+        .expect("D3DCompile");
+
+    Ok((root_signature, pix_shader))
+}
+
+unsafe fn create_pipeline_state(
+    device: &ID3D12Device,
+    root_signature: &ID3D12RootSignature,
+    ps_blob: &ID3DBlob,
+    rtv_format: DXGI_FORMAT,
+) -> Result<ID3D12PipelineState> {
     const VS: &str = r#"
     cbuffer vertexBuffer : register(b0) {
       float4x4 ProjectionMatrix;
@@ -556,21 +1067,6 @@ unsafe fn create_shader_program(
       return output;
     }"#;
 
-    const PS: &str = r#"
-    struct PS_INPUT {
-      float4 pos: SV_POSITION;
-      float4 col: COLOR0;
-      float2 uv: TEXCOORD0;
-    };
-
-    SamplerState sampler0: register(s0);
-    Texture2D texture0: register(t0);
-
-    float4 main(PS_INPUT input): SV_Target {
-      float4 out_col = input.col * texture0.Sample(sampler0, input.uv);
-      return out_col;
-    }"#;
-
     let vtx_shader: ID3DBlob = util::try_out_err_blob(|v, err_blob| unsafe {
         D3DCompile(
             VS.as_ptr() as _,
@@ -589,24 +1085,6 @@ unsafe fn create_shader_program(
     .map_err(util::print_error_blob("Compiling vertex shader"))
     .expect("D3DCompile");
 
-    let pix_shader = util::try_out_err_blob(|v, err_blob| unsafe {
-        D3DCompile(
-            PS.as_ptr() as _,
-            PS.len(),
-            None,
-            None,
-            None::<&ID3DInclude>,
-            s!("main\0"),
-            s!("ps_5_0\0"),
-            0,
-            0,
-            v,
-            Some(err_blob),
-        )
-    })
-    .map_err(util::print_error_blob("Compiling pixel shader"))
-    .expect("D3DCompile");
-
     let input_elements = [
         D3D12_INPUT_ELEMENT_DESC {
             SemanticName: s!("POSITION"),
@@ -646,7 +1124,7 @@ unsafe fn create_shader_program(
         SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
         Flags: D3D12_PIPELINE_STATE_FLAG_NONE,
         RTVFormats: [
-            DXGI_FORMAT_B8G8R8A8_UNORM,
+            rtv_format,
             Default::default(),
             Default::default(),
             Default::default(),
@@ -661,8 +1139,8 @@ unsafe fn create_shader_program(
             BytecodeLength: unsafe { vtx_shader.GetBufferSize() },
         },
         PS: D3D12_SHADER_BYTECODE {
-            pShaderBytecode: unsafe { pix_shader.GetBufferPointer() },
-            BytecodeLength: unsafe { pix_shader.GetBufferSize() },
+            pShaderBytecode: unsafe { ps_blob.GetBufferPointer() },
+            BytecodeLength: unsafe { ps_blob.GetBufferSize() },
         },
         InputLayout: D3D12_INPUT_LAYOUT_DESC {
             pInputElementDescs: input_elements.as_ptr(),
@@ -712,7 +1190,44 @@ unsafe fn create_shader_program(
     let pipeline_state = unsafe { device.CreateGraphicsPipelineState(&pso_desc)? };
     let _ = ManuallyDrop::into_inner(pso_desc.pRootSignature);
 
-    Ok((root_signature, pipeline_state))
+    Ok(pipeline_state)
+}
+
+// The pipeline states of a pixel shader, one for each render target format
+// it has drawn to.
+struct PipelineStates {
+    ps_blob: ID3DBlob,
+    by_format: HashMap<i32, ID3D12PipelineState>,
+}
+
+impl PipelineStates {
+    fn new(ps_blob: ID3DBlob) -> Self {
+        Self { ps_blob, by_format: HashMap::new() }
+    }
+
+    unsafe fn prepare(
+        &mut self,
+        device: &ID3D12Device,
+        root_signature: &ID3D12RootSignature,
+        format: DXGI_FORMAT,
+    ) -> Result<()> {
+        if !self.by_format.contains_key(&format.0) {
+            let pipeline_state =
+                create_pipeline_state(device, root_signature, &self.ps_blob, format)?;
+            self.by_format.insert(format.0, pipeline_state);
+        }
+        Ok(())
+    }
+
+    // Only valid for formats prepared beforehand.
+    fn get(&self, format: DXGI_FORMAT) -> &ID3D12PipelineState {
+        &self.by_format[&format.0]
+    }
+}
+
+struct PixelShader {
+    pipeline_states: PipelineStates,
+    constant_buffer: Option<Buffer<f32>>,
 }
 
 struct Buffer<T: Sized> {
@@ -792,6 +1307,8 @@ struct Texture {
     gpu_desc: D3D12_GPU_DESCRIPTOR_HANDLE,
     width: u32,
     height: u32,
+    // Bytes of the texture, including its mip levels.
+    size: usize,
 }
 
 struct TextureHeap {
@@ -896,7 +1413,64 @@ impl TextureHeap {
     }
 
     unsafe fn create_texture(&mut self, width: u32, height: u32) -> Result<TextureId> {
-        self.resize_heap()?;
+        self.create_texture_with(
+            None,
+            width,
+            height,
+            DXGI_FORMAT_R8G8B8A8_UNORM,
+            1,
+            width as usize * height as usize * 4,
+            D3D12_RESOURCE_FLAG_NONE,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+        )
+    }
+
+    // Create a texture to copy a render target to, unless the texture of the
+    // previous capture matches it already.
+    unsafe fn create_capture_texture(
+        &mut self,
+        texture_id: Option<TextureId>,
+        source: &D3D12_RESOURCE_DESC,
+    ) -> Result<TextureId> {
+        let format = capture::typed_dxgi_format(source.Format);
+
+        if let Some(texture_id) = texture_id {
+            let desc = self.textures[texture_id.id()].resource.GetDesc();
+            if (desc.Width, desc.Height, desc.Format) == (source.Width, source.Height, format) {
+                return Ok(texture_id);
+            }
+        }
+
+        let bytes_per_pixel =
+            PixelFormat::from_dxgi(format).map_or(4, PixelFormat::bytes_per_pixel);
+        self.create_texture_with(
+            texture_id,
+            source.Width as u32,
+            source.Height,
+            format,
+            1,
+            source.Width as usize * source.Height as usize * bytes_per_pixel,
+            D3D12_RESOURCE_FLAG_NONE,
+            D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+        )
+    }
+
+    // Create a texture of `size` bytes, replacing the one of `texture_id` if
+    // any.
+    unsafe fn create_texture_with(
+        &mut self,
+        texture_id: Option<TextureId>,
+        width: u32,
+        height: u32,
+        format: DXGI_FORMAT,
+        mip_levels: u32,
+        size: usize,
+        flags: D3D12_RESOURCE_FLAGS,
+        initial_state: D3D12_RESOURCE_STATES,
+    ) -> Result<TextureId> {
+        if texture_id.is_none() {
+            self.resize_heap()?;
+        }
 
         let cpu_heap_stg_start = self.srv_staging_heap.GetCPUDescriptorHandleForHeapStart();
         let cpu_heap_start = self.srv_heap.GetCPUDescriptorHandleForHeapStart();
@@ -904,7 +1478,7 @@ impl TextureHeap {
         let heap_inc_size =
             self.device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV);
 
-        let texture_index = self.textures.len() as u32;
+        let texture_index = texture_id.map_or(self.textures.len(), |id| id.id()) as u32;
 
         let cpu_desc_stg = D3D12_CPU_DESCRIPTOR_HANDLE {
             ptr: cpu_heap_stg_start.ptr + (texture_index * heap_inc_size) as usize,
@@ -934,13 +1508,13 @@ impl TextureHeap {
                     Width: width as _,
                     Height: height as _,
                     DepthOrArraySize: 1,
-                    MipLevels: 1,
-                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    MipLevels: mip_levels as u16,
+                    Format: format,
                     SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
                     Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
-                    Flags: D3D12_RESOURCE_FLAG_NONE,
+                    Flags: flags,
                 },
-                D3D12_RESOURCE_STATE_COPY_DEST,
+                initial_state,
                 None,
                 v,
             )
@@ -949,13 +1523,13 @@ impl TextureHeap {
         self.device.CreateShaderResourceView(
             &texture,
             Some(&D3D12_SHADER_RESOURCE_VIEW_DESC {
-                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                Format: format,
                 ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
                 Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
                 Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
                     Texture2D: D3D12_TEX2D_SRV {
                         MostDetailedMip: 0,
-                        MipLevels: 1,
+                        MipLevels: mip_levels,
                         PlaneSlice: Default::default(),
                         ResourceMinLODClamp: Default::default(),
                     },
@@ -971,12 +1545,129 @@ impl TextureHeap {
             D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
         );
 
-        let id = TextureId::from(self.textures.len());
-        self.textures.push(Texture { resource: texture.clone(), gpu_desc, width, height });
+        let id = TextureId::from(texture_index as usize);
+        let texture = Texture { resource: texture.clone(), gpu_desc, width, height, size };
+        match self.textures.get_mut(id.id()) {
+            Some(previous) => *previous = texture,
+            None => self.textures.push(texture),
+        }
 
         Ok(id)
     }
 
+    unsafe fn create_upload_buffer(&self, size: u64) -> Result<ID3D12Resource> {
+        util::try_out_ptr(|v| unsafe {
+            self.device.CreateCommittedResource(
+                &D3D12_HEAP_PROPERTIES {
+                    Type: D3D12_HEAP_TYPE_UPLOAD,
+                    CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                    MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+                    CreationNodeMask: Default::default(),
+                    VisibleNodeMask: Default::default(),
+                },
+                D3D12_HEAP_FLAG_NONE,
+                &D3D12_RESOURCE_DESC {
+                    Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+                    Alignment: 0,
+                    Width: size,
+                    Height: 1,
+                    DepthOrArraySize: 1,
+                    MipLevels: 1,
+                    Format: DXGI_FORMAT_UNKNOWN,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+                    Flags: D3D12_RESOURCE_FLAG_NONE,
+                },
+                D3D12_RESOURCE_STATE_GENERIC_READ,
+                None,
+                v,
+            )
+        })
+    }
+
+    // Upload the mip levels of a texture created in the COPY_DEST state.
+    unsafe fn upload_mip_levels(
+        &mut self,
+        texture_id: TextureId,
+        levels: &[MipLevel<'_>],
+    ) -> Result<()> {
+        let texture = &self.textures[texture_id.id()];
+        let desc = texture.resource.GetDesc();
+
+        let mut footprints = vec![D3D12_PLACED_SUBRESOURCE_FOOTPRINT::default(); levels.len()];
+        let mut upload_size = 0u64;
+        self.device.GetCopyableFootprints(
+            &desc,
+            0,
+            levels.len() as u32,
+            0,
+            Some(footprints.as_mut_ptr()),
+            None,
+            None,
+            Some(&mut upload_size),
+        );
+
+        let upload_buffer = self.create_upload_buffer(upload_size)?;
+
+        let mut upload_buffer_ptr = ptr::null_mut();
+        upload_buffer.Map(0, None, Some(&mut upload_buffer_ptr))?;
+        for (level, footprint) in levels.iter().zip(&footprints) {
+            let level_ptr = (upload_buffer_ptr as *mut u8).add(footprint.Offset as usize);
+            let dst_pitch = footprint.Footprint.RowPitch as usize;
+            for (row_index, row) in level.rows().enumerate() {
+                let dst = level_ptr.add(row_index * dst_pitch);
+                ptr::copy_nonoverlapping(row.as_ptr(), dst, row.len());
+            }
+        }
+        upload_buffer.Unmap(0, None);
+
+        self.command_allocator.Reset()?;
+        self.command_list.Reset(&self.command_allocator, None)?;
+
+        let locations = footprints
+            .iter()
+            .enumerate()
+            .map(|(index, footprint)| {
+                let dst_location = D3D12_TEXTURE_COPY_LOCATION {
+                    pResource: ManuallyDrop::new(Some(texture.resource.clone())),
+                    Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                    Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: index as u32 },
+                };
+                let src_location = D3D12_TEXTURE_COPY_LOCATION {
+                    pResource: ManuallyDrop::new(Some(upload_buffer.clone())),
+                    Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+                    Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { PlacedFootprint: *footprint },
+                };
+                (dst_location, src_location)
+            })
+            .collect::<Vec<_>>();
+
+        for (dst_location, src_location) in &locations {
+            self.command_list.CopyTextureRegion(dst_location, 0, 0, 0, src_location, None);
+        }
+
+        let barriers = [util::create_barrier(
+            &texture.resource,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+            D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+        )];
+
+        self.command_list.ResourceBarrier(&barriers);
+        self.command_list.Close()?;
+        self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
+        self.command_queue.Signal(self.fence.fence(), self.fence.value())?;
+        self.fence.wait()?;
+        self.fence.incr();
+
+        barriers.into_iter().for_each(util::drop_barrier);
+        for (dst_location, src_location) in locations {
+            let _ = ManuallyDrop::into_inner(dst_location.pResource);
+            let _ = ManuallyDrop::into_inner(src_location.pResource);
+        }
+
+        Ok(())
+    }
+
     unsafe fn upload_texture(
         &mut self,
         texture_id: TextureId,
@@ -984,6 +1675,12 @@ impl TextureHeap {
         width: u32,
         height: u32,
     ) -> Result<()> {
+        let desc = self.textures[texture_id.id()].resource.GetDesc();
+        if (desc.Format, desc.MipLevels) != (DXGI_FORMAT_R8G8B8A8_UNORM, 1) {
+            error!("Only RGBA8 textures without mip levels can be replaced");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
         let src_pitch = (width as usize) * 4;
         self.upload_texture_region(
             texture_id,
@@ -1026,33 +1723,7 @@ impl TextureHeap {
         let upload_pitch = upload_row_size.div_ceil(align) * align; // 256 bytes aligned
         let upload_size = h * upload_pitch;
 
-        let upload_buffer: ID3D12Resource = util::try_out_ptr(|v| unsafe {
-            self.device.CreateCommittedResource(
-                &D3D12_HEAP_PROPERTIES {
-                    Type: D3D12_HEAP_TYPE_UPLOAD,
-                    CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
-                    MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
-                    CreationNodeMask: Default::default(),
-                    VisibleNodeMask: Default::default(),
-                },
-                D3D12_HEAP_FLAG_NONE,
-                &D3D12_RESOURCE_DESC {
-                    Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
-                    Alignment: 0,
-                    Width: upload_size as _,
-                    Height: 1,
-                    DepthOrArraySize: 1,
-                    MipLevels: 1,
-                    Format: DXGI_FORMAT_UNKNOWN,
-                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
-                    Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
-                    Flags: D3D12_RESOURCE_FLAG_NONE,
-                },
-                D3D12_RESOURCE_STATE_GENERIC_READ,
-                None,
-                v,
-            )
-        })?;
+        let upload_buffer = self.create_upload_buffer(upload_size as u64)?;
 
         let mut upload_buffer_ptr = ptr::null_mut();
         upload_buffer.Map(0, None, Some(&mut upload_buffer_ptr))?;
@@ -1130,3 +1801,83 @@ impl TextureHeap {
         Ok(())
     }
 }
+
+// Timestamps before and after the overlay's draw commands, read back once the
+// commands have completed.
+struct GpuTimer {
+    query_heap: ID3D12QueryHeap,
+    readback_buffer: ID3D12Resource,
+    frequency: u64,
+}
+
+impl GpuTimer {
+    unsafe fn new(device: &ID3D12Device, command_queue: &ID3D12CommandQueue) -> Result<Self> {
+        let query_heap: ID3D12QueryHeap = util::try_out_ptr(|v| {
+            device.CreateQueryHeap(
+                &D3D12_QUERY_HEAP_DESC {
+                    Type: D3D12_QUERY_HEAP_TYPE_TIMESTAMP,
+                    Count: 2,
+                    NodeMask: 0,
+                },
+                v,
+            )
+        })?;
+
+        let readback_buffer: ID3D12Resource = util::try_out_ptr(|v| {
+            device.CreateCommittedResource(
+                &D3D12_HEAP_PROPERTIES {
+                    Type: D3D12_HEAP_TYPE_READBACK,
+                    CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                    MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+                    CreationNodeMask: Default::default(),
+                    VisibleNodeMask: Default::default(),
+                },
+                D3D12_HEAP_FLAG_NONE,
+                &D3D12_RESOURCE_DESC {
+                    Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+                    Alignment: 0,
+                    Width: mem::size_of::<[u64; 2]>() as u64,
+                    Height: 1,
+                    DepthOrArraySize: 1,
+                    MipLevels: 1,
+                    Format: DXGI_FORMAT_UNKNOWN,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+                    Flags: D3D12_RESOURCE_FLAG_NONE,
+                },
+                D3D12_RESOURCE_STATE_COPY_DEST,
+                None,
+                v,
+            )
+        })?;
+
+        let frequency = command_queue.GetTimestampFrequency()?;
+
+        Ok(Self { query_heap, readback_buffer, frequency })
+    }
+
+    unsafe fn begin(&self, command_list: &ID3D12GraphicsCommandList) {
+        command_list.EndQuery(&self.query_heap, D3D12_QUERY_TYPE_TIMESTAMP, 0);
+    }
+
+    unsafe fn end(&self, command_list: &ID3D12GraphicsCommandList) {
+        command_list.EndQuery(&self.query_heap, D3D12_QUERY_TYPE_TIMESTAMP, 1);
+        command_list.ResolveQueryData(
+            &self.query_heap,
+            D3D12_QUERY_TYPE_TIMESTAMP,
+            0,
+            2,
+            &self.readback_buffer,
+            0,
+        );
+    }
+
+    unsafe fn read(&self) -> Option<Duration> {
+        let mut readback_ptr = ptr::null_mut();
+        self.readback_buffer.Map(0, None, Some(&mut readback_ptr)).ok()?;
+        let [begin, end] = *(readback_ptr as *const [u64; 2]);
+        self.readback_buffer.Unmap(0, None);
+
+        stats::ticks_to_duration(begin, end, self.frequency)
+    }
+}
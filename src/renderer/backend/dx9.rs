@@ -1,17 +1,22 @@
 // Based on https://github.com/Veykril/imgui-dx9-renderer
 
-use std::{mem, ptr};
+use std::{mem, ptr, slice};
 
 use imgui::internal::{RawCast, RawWrapper};
 use imgui::{sys, BackendFlags, Context, DrawCmd, DrawData, DrawIdx, TextureId};
 use tracing::error;
-use windows::core::{Error, Result, HRESULT};
+use windows::core::{s, Error, Result, HRESULT};
 use windows::Foundation::Numerics::Matrix4x4;
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Foundation::{HWND, TRUE};
 use windows::Win32::Foundation::RECT;
 use windows::Win32::Graphics::Direct3D9::*;
 
+use crate::renderer::callback::{self, DrawCallbackBackend};
+use crate::renderer::capture::{self, PixelFormat};
+use crate::renderer::shader::{self, PixelShaders};
 use crate::renderer::RenderEngine;
-use crate::{util, RenderContext};
+use crate::{util, CapturedImage, FrameStats, RenderContext, ShaderId, TextureDesc, TextureFormat};
 
 const D3DFVF_CUSTOMVERTEX: u32 = D3DFVF_XYZ | D3DFVF_DIFFUSE | D3DFVF_TEX1;
 const MAT_IDENTITY: Matrix4x4 = Matrix4x4 {
@@ -44,10 +49,30 @@ pub struct D3D9RenderEngine {
     device: IDirect3DDevice9,
 
     texture_heap: TextureHeap,
+    pixel_shaders: PixelShaders<PixelShader>,
 
     vertex_buffer: Buffer<IDirect3DVertexBuffer9, CustomVertex>,
     index_buffer: Buffer<IDirect3DIndexBuffer9, DrawIdx>,
     projection_buffer: Matrix4x4,
+
+    capture_source: Option<IDirect3DSurface9>,
+    capture_texture: Option<TextureId>,
+
+    frame_stats: FrameStats,
+
+    #[cfg(feature = "imgui-docking")]
+    viewports: Vec<ViewportWindow>,
+}
+
+struct PixelShader {
+    shader: IDirect3DPixelShader9,
+    constants: Vec<f32>,
+}
+
+#[cfg(feature = "imgui-docking")]
+struct ViewportWindow {
+    hwnd: HWND,
+    swap_chain: IDirect3DSwapChain9,
 }
 
 impl D3D9RenderEngine {
@@ -64,18 +89,67 @@ impl D3D9RenderEngine {
         ctx.io_mut().backend_flags |= BackendFlags::RENDERER_HAS_VTX_OFFSET;
         ctx.set_renderer_name(String::from(concat!("hudhook-dx9@", env!("CARGO_PKG_VERSION"))));
 
-        Ok(Self { device, texture_heap, vertex_buffer, index_buffer, projection_buffer })
+        Ok(Self {
+            device,
+            texture_heap,
+            pixel_shaders: PixelShaders::new(),
+            vertex_buffer,
+            index_buffer,
+            projection_buffer,
+            capture_source: None,
+            capture_texture: None,
+            frame_stats: FrameStats::default(),
+            #[cfg(feature = "imgui-docking")]
+            viewports: Vec::new(),
+        })
     }
-}
 
-impl RenderContext for D3D9RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    fn capture_source(&self) -> Result<(IDirect3DSurface9, D3DSURFACE_DESC)> {
+        let Some(source) = self.capture_source.clone() else {
+            error!("The render target can only be captured before rendering");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+        let desc = util::try_out_param(|v| unsafe { source.GetDesc(v) })?;
+
+        Ok((source, desc))
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn viewport(&self, hwnd: HWND) -> Result<&ViewportWindow> {
+        self.viewports.iter().find(|viewport| viewport.hwnd == hwnd).ok_or_else(|| {
+            error!("Unknown viewport window {hwnd:?}");
+            Error::from_hresult(HRESULT(-1))
+        })
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn create_swap_chain(
+        &self,
+        hwnd: HWND,
+        width: u32,
+        height: u32,
+    ) -> Result<IDirect3DSwapChain9> {
+        let mut present_params = D3DPRESENT_PARAMETERS {
+            BackBufferWidth: width,
+            BackBufferHeight: height,
+            BackBufferFormat: D3DFMT_UNKNOWN,
+            SwapEffect: D3DSWAPEFFECT_DISCARD,
+            hDeviceWindow: hwnd,
+            Windowed: TRUE,
+            PresentationInterval: D3DPRESENT_INTERVAL_IMMEDIATE as u32,
+            ..Default::default()
+        };
+
         unsafe {
-            let texture_id = self.texture_heap.create_texture(width, height)?;
-            self.texture_heap.upload_texture(texture_id, data, width, height)?;
-            Ok(texture_id)
+            util::try_out_ptr(|v| self.device.CreateAdditionalSwapChain(&mut present_params, v))
         }
     }
+}
+
+impl RenderContext for D3D9RenderEngine {
+    fn load_texture_with_desc(&mut self, data: &[u8], desc: &TextureDesc) -> Result<TextureId> {
+        unsafe { self.texture_heap.create_texture_with_desc(data, desc) }
+    }
 
     fn replace_texture(
         &mut self,
@@ -86,6 +160,123 @@ impl RenderContext for D3D9RenderEngine {
     ) -> Result<()> {
         unsafe { self.texture_heap.upload_texture(texture_id, data, width, height) }
     }
+
+    fn create_pixel_shader(&mut self, source: &str) -> Result<ShaderId> {
+        let blob = util::compile_shader(source, s!("ps_2_0"))?;
+        let shader =
+            unsafe { self.device.CreatePixelShader(blob.GetBufferPointer() as *const u32) }?;
+
+        Ok(self.pixel_shaders.insert(PixelShader { shader, constants: Vec::new() }))
+    }
+
+    fn set_shader_constants(&mut self, shader: ShaderId, data: &[u8]) -> Result<()> {
+        self.pixel_shaders.get_mut(shader)?.constants = shader::constants_to_f32(data);
+        Ok(())
+    }
+
+    fn bind_texture_shader(
+        &mut self,
+        texture_id: TextureId,
+        shader: Option<ShaderId>,
+    ) -> Result<()> {
+        self.pixel_shaders.bind_texture(texture_id, shader)
+    }
+
+    fn set_hdr_paper_white(&mut self, _nits: f32) {}
+
+    fn capture_backbuffer(&mut self) -> Result<TextureId> {
+        let (source, desc) = self.capture_source()?;
+
+        unsafe {
+            let texture_id = self.texture_heap.create_capture_texture(self.capture_texture, &desc)?;
+            self.capture_texture = Some(texture_id);
+
+            let surface = self.texture_heap.get(texture_id).GetSurfaceLevel(0)?;
+            self.device.StretchRect(&source, ptr::null(), &surface, ptr::null(), D3DTEXF_NONE)?;
+
+            Ok(texture_id)
+        }
+    }
+
+    fn capture_backbuffer_rgba(&mut self) -> Result<CapturedImage> {
+        let (source, desc) = self.capture_source()?;
+        let Some(format) = PixelFormat::from_d3d9(desc.Format) else {
+            error!("Unsupported render target format {:?}", desc.Format);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        unsafe {
+            // Multisampled render targets can't be read back: resolve them to
+            // the capture texture first.
+            let source = if desc.MultiSampleType != D3DMULTISAMPLE_NONE {
+                let texture_id = self.capture_backbuffer()?;
+                self.texture_heap.get(texture_id).GetSurfaceLevel(0)?
+            } else {
+                source
+            };
+
+            let surface = util::try_out_ptr(|v| {
+                self.device.CreateOffscreenPlainSurface(
+                    desc.Width,
+                    desc.Height,
+                    desc.Format,
+                    D3DPOOL_SYSTEMMEM,
+                    v,
+                    ptr::null_mut(),
+                )
+            })?;
+            self.device.GetRenderTargetData(&source, &surface)?;
+
+            let mut locked_rect = D3DLOCKED_RECT::default();
+            surface.LockRect(&mut locked_rect, ptr::null(), D3DLOCK_READONLY as u32)?;
+            let row_pitch = locked_rect.Pitch as usize;
+            let data = std::slice::from_raw_parts(
+                locked_rect.pBits as *const u8,
+                row_pitch * desc.Height as usize,
+            );
+            let image = capture::convert_to_rgba(
+                format,
+                data,
+                row_pitch,
+                desc.Width,
+                desc.Height,
+                false,
+            );
+            surface.UnlockRect()?;
+
+            Ok(image)
+        }
+    }
+
+    fn create_render_target(&mut self, width: u32, height: u32) -> Result<TextureId> {
+        unsafe {
+            let desc = TextureDesc::new(width, height, TextureFormat::Bgra8);
+            self.texture_heap.create_texture_with(
+                &desc,
+                D3DUSAGE_RENDERTARGET as u32,
+                D3DFMT_A8R8G8B8,
+            )
+        }
+    }
+
+    fn render_to_target(&mut self, texture_id: TextureId, draw_data: &DrawData) -> Result<()> {
+        let texture = self.texture_heap.get(texture_id).clone();
+
+        let desc = util::try_out_param(|v| unsafe { texture.GetLevelDesc(0, v) })?;
+        if desc.Usage & D3DUSAGE_RENDERTARGET as u32 == 0 {
+            error!("Texture {texture_id:?} is not a render target");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        self.update_textures(draw_data)?;
+
+        let render_target = unsafe { texture.GetSurfaceLevel(0) }?;
+        self.render_to(draw_data, &render_target, Some(0))
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
 }
 
 impl RenderEngine for D3D9RenderEngine {
@@ -96,15 +287,27 @@ impl RenderEngine for D3D9RenderEngine {
         draw_data: &imgui::DrawData,
         render_target: Self::RenderTarget,
     ) -> Result<()> {
-        unsafe {
-            let state_backup = StateBackup::backup(&self.device)?;
-            self.device.SetRenderTarget(0, &render_target)?;
-            self.render_draw_data(draw_data)?;
-            state_backup.restore(&self.device)?;
-        }
+        self.render_to(draw_data, &render_target, None)?;
+
+        let textures = self.texture_heap.textures.iter().map(|t| t.size);
+        self.frame_stats.set_textures(textures);
+
         Ok(())
     }
 
+    fn render_target_size(&self, render_target: &Self::RenderTarget) -> Option<[u32; 2]> {
+        let desc = util::try_out_param(|v| unsafe { render_target.GetDesc(v) }).ok()?;
+        Some([desc.Width, desc.Height])
+    }
+
+    fn frame_stats_mut(&mut self) -> &mut FrameStats {
+        &mut self.frame_stats
+    }
+
+    fn set_capture_source(&mut self, render_target: Option<&Self::RenderTarget>) {
+        self.capture_source = render_target.cloned();
+    }
+
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
         let fonts_texture = fonts.build_rgba32_texture();
@@ -257,9 +460,73 @@ impl RenderEngine for D3D9RenderEngine {
 
         Ok(())
     }
+
+    #[cfg(feature = "imgui-docking")]
+    fn create_viewport(&mut self, hwnd: HWND, width: u32, height: u32) -> Result<()> {
+        let swap_chain = self.create_swap_chain(hwnd, width, height)?;
+        self.viewports.push(ViewportWindow { hwnd, swap_chain });
+        Ok(())
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn destroy_viewport(&mut self, hwnd: HWND) {
+        self.viewports.retain(|viewport| viewport.hwnd != hwnd);
+    }
+
+    // Additional swap chains can't be resized, so they are recreated instead.
+    #[cfg(feature = "imgui-docking")]
+    fn resize_viewport(&mut self, hwnd: HWND, width: u32, height: u32) -> Result<()> {
+        let swap_chain = self.create_swap_chain(hwnd, width, height)?;
+        self.viewports
+            .iter_mut()
+            .filter(|viewport| viewport.hwnd == hwnd)
+            .for_each(|viewport| viewport.swap_chain = swap_chain.clone());
+        Ok(())
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn render_viewport(&mut self, hwnd: HWND, draw_data: &DrawData, clear: bool) -> Result<()> {
+        let back_buffer =
+            unsafe { self.viewport(hwnd)?.swap_chain.GetBackBuffer(0, D3DBACKBUFFER_TYPE_MONO) }?;
+
+        self.render_to(draw_data, &back_buffer, clear.then_some(0xff000000))
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn present_viewport(&mut self, hwnd: HWND) -> Result<()> {
+        unsafe {
+            self.viewport(hwnd)?.swap_chain.Present(
+                ptr::null(),
+                ptr::null(),
+                HWND(0),
+                ptr::null(),
+                0,
+            )
+        }
+    }
 }
 
 impl D3D9RenderEngine {
+    fn render_to(
+        &mut self,
+        draw_data: &DrawData,
+        render_target: &IDirect3DSurface9,
+        clear_color: Option<u32>,
+    ) -> Result<()> {
+        unsafe {
+            let state_backup = StateBackup::backup(&self.device)?;
+            self.device.SetRenderTarget(0, render_target)?;
+            if let Some(clear_color) = clear_color {
+                self.device.Clear(0, ptr::null(), D3DCLEAR_TARGET as u32, clear_color, 1.0, 0)?;
+            }
+            self.device.BeginScene()?;
+            let result = self.render_draw_data(draw_data);
+            self.device.EndScene()?;
+            state_backup.restore(&self.device)?;
+            result
+        }
+    }
+
     unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
         self.vertex_buffer.clear();
         self.index_buffer.clear();
@@ -283,11 +550,13 @@ impl D3D9RenderEngine {
         self.index_buffer.upload(&self.device)?;
 
         self.projection_buffer = {
+            // Half a pixel of the render target, in imgui coordinates.
+            let [ox, oy] = draw_data.framebuffer_scale.map(|scale| 0.5 / scale);
             let [l, t, r, b] = [
-                draw_data.display_pos[0] + 0.5,
-                draw_data.display_pos[1] + 0.5,
-                draw_data.display_pos[0] + draw_data.display_size[0] + 0.5,
-                draw_data.display_pos[1] + draw_data.display_size[1] + 0.5,
+                draw_data.display_pos[0] + ox,
+                draw_data.display_pos[1] + oy,
+                draw_data.display_pos[0] + draw_data.display_size[0] + ox,
+                draw_data.display_pos[1] + draw_data.display_size[1] + oy,
             ];
 
             Matrix4x4 {
@@ -309,16 +578,19 @@ impl D3D9RenderEngine {
         let mut last_texture = None;
 
         for cl in draw_data.draw_lists() {
+            self.pixel_shaders.set_current(None);
+
             for cmd in cl.commands() {
                 match cmd {
                     DrawCmd::Elements { count, cmd_params } => {
                         let [cx, cy, cw, ch] = cmd_params.clip_rect;
                         let [x, y] = draw_data.display_pos;
+                        let [sx, sy] = draw_data.framebuffer_scale;
                         let r = RECT {
-                            left: (cx - x) as i32,
-                            top: (cy - y) as i32,
-                            right: (cw - x) as i32,
-                            bottom: (ch - y) as i32,
+                            left: ((cx - x) * sx) as i32,
+                            top: ((cy - y) * sy) as i32,
+                            right: ((cw - x) * sx) as i32,
+                            bottom: ((ch - y) * sy) as i32,
                         };
 
                         last_texture = match last_texture {
@@ -331,6 +603,17 @@ impl D3D9RenderEngine {
                         };
 
                         if r.right > r.left && r.bottom > r.top {
+                            match self.pixel_shaders.resolve(cmd_params.texture_id) {
+                                Some(PixelShader { shader, constants }) => {
+                                    self.device.SetPixelShader(shader)?;
+                                    self.device.SetPixelShaderConstantF(
+                                        0,
+                                        constants.as_ptr(),
+                                        (constants.len() / 4) as u32,
+                                    )?;
+                                },
+                                None => self.device.SetPixelShader(None)?,
+                            }
                             self.device.SetScissorRect(&r)?;
                             self.device.DrawIndexedPrimitive(
                                 D3DPT_TRIANGLELIST,
@@ -344,8 +627,24 @@ impl D3D9RenderEngine {
                     },
                     DrawCmd::ResetRenderState => {
                         self.setup_render_state(draw_data)?;
+                        last_texture = None;
+                    },
+                    DrawCmd::RawCallback { callback, raw_cmd } => {
+                        if let Some(shader) = shader::pixel_shader_command(callback, raw_cmd) {
+                            self.pixel_shaders.set_current(shader);
+                            continue;
+                        }
+
+                        callback::invoke_callback(
+                            callback,
+                            cl.raw(),
+                            raw_cmd,
+                            draw_data,
+                            DrawCallbackBackend::D3D9 { device: &self.device },
+                        );
+                        // The callback may have bound another texture.
+                        last_texture = None;
                     },
-                    DrawCmd::RawCallback { callback, raw_cmd } => callback(cl.raw(), raw_cmd),
                 }
             }
             idx_offset += cl.idx_buffer().len();
@@ -359,8 +658,8 @@ impl D3D9RenderEngine {
         self.device.SetViewport(&D3DVIEWPORT9 {
             X: 0,
             Y: 0,
-            Width: draw_data.display_size[0] as u32,
-            Height: draw_data.display_size[1] as u32,
+            Width: (draw_data.display_size[0] * draw_data.framebuffer_scale[0]) as u32,
+            Height: (draw_data.display_size[1] * draw_data.framebuffer_scale[1]) as u32,
             MinZ: 0.0,
             MaxZ: 1.0,
         })?;
@@ -377,6 +676,8 @@ impl D3D9RenderEngine {
         self.device.SetRenderState(D3DRS_SCISSORTESTENABLE, true.into())?;
         self.device.SetRenderState(D3DRS_SHADEMODE, D3DSHADE_GOURAUD.0 as u32)?;
         self.device.SetRenderState(D3DRS_FOGENABLE, false.into())?;
+        // imgui's colors are already sRGB encoded.
+        self.device.SetRenderState(D3DRS_SRGBWRITEENABLE, false.into())?;
         self.device.SetTextureStageState(0, D3DTSS_COLOROP, D3DTOP_MODULATE.0 as u32)?;
         self.device.SetTextureStageState(0, D3DTSS_COLORARG1, D3DTA_TEXTURE)?;
         self.device.SetTextureStageState(0, D3DTSS_COLORARG2, D3DTA_DIFFUSE)?;
@@ -385,6 +686,7 @@ impl D3D9RenderEngine {
         self.device.SetTextureStageState(0, D3DTSS_ALPHAARG2, D3DTA_DIFFUSE)?;
         self.device.SetSamplerState(0, D3DSAMP_MINFILTER, D3DTEXF_LINEAR.0 as u32)?;
         self.device.SetSamplerState(0, D3DSAMP_MAGFILTER, D3DTEXF_LINEAR.0 as u32)?;
+        self.device.SetSamplerState(0, D3DSAMP_MIPFILTER, D3DTEXF_LINEAR.0 as u32)?;
         self.device.SetTransform(D3DTRANSFORMSTATETYPE(256), &MAT_IDENTITY)?;
         self.device.SetTransform(D3DTS_VIEW, &MAT_IDENTITY)?;
         self.device.SetTransform(D3DTS_PROJECTION, &self.projection_buffer)?;
@@ -512,6 +814,8 @@ struct Texture {
     id: TextureId,
     width: u32,
     height: u32,
+    // Bytes of the texture, including its mip levels.
+    size: usize,
 }
 
 struct TextureHeap {
@@ -529,13 +833,26 @@ impl TextureHeap {
     }
 
     unsafe fn create_texture(&mut self, width: u32, height: u32) -> Result<TextureId> {
+        let desc = TextureDesc::new(width, height, TextureFormat::Bgra8);
+        self.create_texture_with(&desc, D3DUSAGE_DYNAMIC as u32, D3DFMT_A8R8G8B8)
+    }
+
+    // Create a texture of the size of `desc`, stored in `format`.
+    unsafe fn create_texture_with(
+        &mut self,
+        desc: &TextureDesc,
+        usage: u32,
+        format: D3DFORMAT,
+    ) -> Result<TextureId> {
+        let (width, height, size) = (desc.width, desc.height, desc.size());
+
         let resource = util::try_out_ptr(|v| {
             self.device.CreateTexture(
                 width,
                 height,
-                1,
-                D3DUSAGE_DYNAMIC as u32,
-                D3DFMT_A8R8G8B8,
+                desc.mip_levels,
+                usage,
+                format,
                 D3DPOOL_DEFAULT,
                 v,
                 ptr::null_mut(),
@@ -543,7 +860,112 @@ impl TextureHeap {
         })?;
 
         let id = TextureId::from(self.textures.len());
-        self.textures.push(Texture { resource, id, width, height });
+        self.textures.push(Texture { resource, id, width, height, size });
+
+        Ok(id)
+    }
+
+    // Formats without a D3D9 equivalent are converted to BGRA8 while they are
+    // copied, which compressed formats can't be.
+    unsafe fn create_texture_with_desc(
+        &mut self,
+        data: &[u8],
+        desc: &TextureDesc,
+    ) -> Result<TextureId> {
+        let levels = desc.mip_levels(data)?;
+        let native_format = desc.format.to_d3d9();
+        if native_format.is_none() && desc.format.is_compressed() {
+            error!("{:?} textures are not supported by DirectX 9", desc.format);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        // Converted textures take 4 bytes per pixel.
+        let stored_desc = match native_format {
+            Some(_) => *desc,
+            None => TextureDesc { format: TextureFormat::Bgra8, ..*desc },
+        };
+        let texture_id = self.create_texture_with(
+            &stored_desc,
+            D3DUSAGE_DYNAMIC as u32,
+            native_format.unwrap_or(D3DFMT_A8R8G8B8),
+        )?;
+        let texture = self.get(texture_id);
+
+        for (index, level) in levels.iter().enumerate() {
+            let mut locked_rect = D3DLOCKED_RECT::default();
+            texture.LockRect(index as u32, &mut locked_rect, ptr::null(), 0)?;
+
+            let bits = locked_rect.pBits as *mut u8;
+            let dst_pitch = locked_rect.Pitch as usize;
+            let dst_row_size = match native_format {
+                Some(_) => level.row_size,
+                None => level.width as usize * 4,
+            };
+
+            for (row_index, row) in level.rows().enumerate() {
+                let dst = slice::from_raw_parts_mut(bits.add(row_index * dst_pitch), dst_row_size);
+                if native_format.is_some() {
+                    dst.copy_from_slice(row);
+                    continue;
+                }
+
+                let src_pixels = row.chunks_exact(desc.format.bytes_per_block());
+                for (src, dst) in src_pixels.zip(dst.chunks_exact_mut(4)) {
+                    if let Some(pixel) = desc.format.to_bgra8(src) {
+                        dst.copy_from_slice(&pixel);
+                    }
+                }
+            }
+
+            texture.UnlockRect(index as u32)?;
+        }
+
+        Ok(texture_id)
+    }
+
+    // Create a texture to copy a render target to, unless the texture of the
+    // previous capture matches it already.
+    unsafe fn create_capture_texture(
+        &mut self,
+        texture_id: Option<TextureId>,
+        source: &D3DSURFACE_DESC,
+    ) -> Result<TextureId> {
+        if let Some(texture_id) = texture_id {
+            let desc = util::try_out_param(|v| self.get(texture_id).GetLevelDesc(0, v))?;
+            let matches = (desc.Width, desc.Height, desc.Format)
+                == (source.Width, source.Height, source.Format);
+            if matches {
+                return Ok(texture_id);
+            }
+        }
+
+        let resource = util::try_out_ptr(|v| {
+            self.device.CreateTexture(
+                source.Width,
+                source.Height,
+                1,
+                D3DUSAGE_RENDERTARGET as u32,
+                source.Format,
+                D3DPOOL_DEFAULT,
+                v,
+                ptr::null_mut(),
+            )
+        })?;
+
+        let id = texture_id.unwrap_or_else(|| TextureId::from(self.textures.len()));
+        let bytes_per_pixel =
+            PixelFormat::from_d3d9(source.Format).map_or(4, PixelFormat::bytes_per_pixel);
+        let texture = Texture {
+            resource,
+            id,
+            width: source.Width,
+            height: source.Height,
+            size: source.Width as usize * source.Height as usize * bytes_per_pixel,
+        };
+        match self.textures.get_mut(id.id()) {
+            Some(previous) => *previous = texture,
+            None => self.textures.push(texture),
+        }
 
         Ok(id)
     }
@@ -555,6 +977,13 @@ impl TextureHeap {
         width: u32,
         height: u32,
     ) -> Result<()> {
+        let texture = self.get(texture_id);
+        let desc = util::try_out_param(|v| texture.GetLevelDesc(0, v))?;
+        if (desc.Format, texture.GetLevelCount()) != (D3DFMT_A8R8G8B8, 1) {
+            error!("Only RGBA8 textures without mip levels can be replaced");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
         let src_pitch = (width as usize) * 4;
         self.upload_texture_region(
             texture_id,
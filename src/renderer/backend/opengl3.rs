@@ -2,6 +2,8 @@
 
 use std::ffi::{c_void, CString};
 use std::mem::{self, offset_of};
+use std::ptr;
+use std::time::Duration;
 
 use gl::types::*;
 use imgui::internal::{RawCast, RawWrapper};
@@ -9,15 +11,27 @@ use imgui::{sys, Context, DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
 use once_cell::sync::OnceCell;
 use tracing::error;
 use windows::core::{s, Error, Result, HRESULT, PCSTR};
-use windows::Win32::Foundation::{FARPROC, HINSTANCE};
+use windows::Win32::Foundation::{FARPROC, HINSTANCE, RECT};
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Foundation::HWND;
+use windows::Win32::Graphics::Gdi::WindowFromDC;
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Graphics::Gdi::{GetDC, ReleaseDC, HDC};
 use windows::Win32::Graphics::OpenGL::*;
 use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryA};
+use windows::Win32::UI::WindowsAndMessaging::GetClientRect;
 
+use crate::renderer::callback::{self, DrawCallbackBackend};
+use crate::renderer::capture::{self, PixelFormat};
+use crate::renderer::shader::{self, PixelShaders};
+use crate::renderer::stats::QueryRing;
 use crate::renderer::RenderEngine;
-use crate::{util, RenderContext};
+use crate::{util, CapturedImage, FrameStats, RenderContext, ShaderId, TextureDesc, TextureFormat};
 
-mod gl {
+/// OpenGL bindings, as used by the renderer and passed to draw callbacks.
+pub mod gl {
     #![allow(
+        missing_docs,
         clippy::unreadable_literal,
         clippy::too_many_arguments,
         clippy::unused_unit,
@@ -63,6 +77,34 @@ pub struct OpenGl3RenderEngine {
     projection_buffer: [[f32; 4]; 4],
 
     texture_heap: TextureHeap,
+    pixel_shaders: PixelShaders<PixelShader>,
+
+    // The back buffer of the current context can be captured.
+    capture_enabled: bool,
+    capture_texture: Option<TextureId>,
+    capture_framebuffer: GLuint,
+    render_target_framebuffer: GLuint,
+
+    frame_stats: FrameStats,
+    // Timestamps before and after drawing into the main window.
+    gpu_timer: QueryRing<[GLuint; 2]>,
+
+    #[cfg(feature = "imgui-docking")]
+    viewports: Vec<ViewportWindow>,
+}
+
+struct PixelShader {
+    program: GLuint,
+    projection_loc: GLint,
+    texture_loc: GLint,
+    constants_loc: GLint,
+    constants: Vec<f32>,
+}
+
+#[cfg(feature = "imgui-docking")]
+struct ViewportWindow {
+    hwnd: HWND,
+    dc: HDC,
 }
 
 impl OpenGl3RenderEngine {
@@ -80,6 +122,12 @@ impl OpenGl3RenderEngine {
 
         let texture_heap = TextureHeap::new();
 
+        let mut queries = vec![[0; 2]; QueryRing::<[GLuint; 2]>::LEN];
+        unsafe {
+            gl.GenQueries((queries.len() * 2) as GLsizei, queries.as_mut_ptr() as *mut GLuint)
+        };
+        let gpu_timer = QueryRing::new(queries);
+
         ctx.set_ini_filename(None);
         ctx.set_renderer_name(String::from(concat!("hudhook-opengl3@", env!("CARGO_PKG_VERSION"))));
 
@@ -96,13 +144,58 @@ impl OpenGl3RenderEngine {
             index_buffer,
             projection_buffer,
             texture_heap,
+            pixel_shaders: PixelShaders::new(),
+            capture_enabled: false,
+            capture_texture: None,
+            capture_framebuffer: 0,
+            render_target_framebuffer: 0,
+            frame_stats: FrameStats::default(),
+            gpu_timer,
+            #[cfg(feature = "imgui-docking")]
+            viewports: Vec::new(),
         })
     }
+
+    // The size of the back buffer is the one of the window being rendered to.
+    fn capture_size(&self) -> Result<(u32, u32)> {
+        if !self.capture_enabled {
+            error!("The render target can only be captured before rendering");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let mut rect = RECT::default();
+        unsafe { GetClientRect(WindowFromDC(wglGetCurrentDC()), &mut rect) }?;
+
+        Ok(((rect.right - rect.left) as u32, (rect.bottom - rect.top) as u32))
+    }
+
+    // Viewports share the current GL context, which is made current on the
+    // viewport's device context for the duration of `f`.
+    #[cfg(feature = "imgui-docking")]
+    fn with_viewport_dc<R>(
+        &mut self,
+        hwnd: HWND,
+        f: impl FnOnce(&mut Self, HDC) -> Result<R>,
+    ) -> Result<R> {
+        let Some(dc) = self.viewports.iter().find(|viewport| viewport.hwnd == hwnd).map(|v| v.dc)
+        else {
+            error!("Unknown viewport window {hwnd:?}");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        unsafe {
+            let (prev_dc, glrc) = (wglGetCurrentDC(), wglGetCurrentContext());
+            wglMakeCurrent(dc, glrc)?;
+            let result = f(self, dc);
+            wglMakeCurrent(prev_dc, glrc)?;
+            result
+        }
+    }
 }
 
 impl RenderContext for OpenGl3RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
-        unsafe { self.texture_heap.create_texture(&self.gl, data, width, height) }
+    fn load_texture_with_desc(&mut self, data: &[u8], desc: &TextureDesc) -> Result<TextureId> {
+        unsafe { self.texture_heap.create_texture_with_desc(&self.gl, data, desc) }
     }
 
     fn replace_texture(
@@ -114,6 +207,212 @@ impl RenderContext for OpenGl3RenderEngine {
     ) -> Result<()> {
         unsafe { self.texture_heap.update_texture(&self.gl, texture_id, data, width, height) }
     }
+
+    fn create_pixel_shader(&mut self, source: &str) -> Result<ShaderId> {
+        let gl = &self.gl;
+
+        unsafe {
+            let vertex_shader = compile_shader(gl, gl::VERTEX_SHADER, VS)?;
+            let fragment_shader =
+                match compile_shader(gl, gl::FRAGMENT_SHADER, source.as_bytes()) {
+                    Ok(fragment_shader) => fragment_shader,
+                    Err(e) => {
+                        gl.DeleteShader(vertex_shader);
+                        return Err(e);
+                    },
+                };
+
+            // Share the attribute locations of the default program, so that
+            // the vertex array can be used with any pixel shader.
+            let program = gl.CreateProgram();
+            gl.BindAttribLocation(program, self.position_loc, c"position".as_ptr() as _);
+            gl.BindAttribLocation(program, self.color_loc, c"color".as_ptr() as _);
+            gl.BindAttribLocation(program, self.uv_loc, c"uv".as_ptr() as _);
+            gl.AttachShader(program, vertex_shader);
+            gl.AttachShader(program, fragment_shader);
+            gl.LinkProgram(program);
+            gl.DeleteShader(vertex_shader);
+            gl.DeleteShader(fragment_shader);
+
+            let status = util::out_param(|x| gl.GetProgramiv(program, gl::LINK_STATUS, x));
+            if status != gl::TRUE as GLint {
+                let log_len =
+                    util::out_param(|x| gl.GetProgramiv(program, gl::INFO_LOG_LENGTH, x));
+                let mut log = vec![0u8; log_len.max(1) as usize];
+                gl.GetProgramInfoLog(program, log_len, ptr::null_mut(), log.as_mut_ptr() as _);
+                error!("Linking pixel shader: {}", String::from_utf8_lossy(&log));
+                gl.DeleteProgram(program);
+                return Err(Error::from_hresult(HRESULT(-1)));
+            }
+
+            Ok(self.pixel_shaders.insert(PixelShader {
+                program,
+                projection_loc: gl
+                    .GetUniformLocation(program, c"projection_matrix".as_ptr() as _),
+                texture_loc: gl.GetUniformLocation(program, c"tex".as_ptr() as _),
+                constants_loc: gl.GetUniformLocation(program, c"constants".as_ptr() as _),
+                constants: Vec::new(),
+            }))
+        }
+    }
+
+    fn set_shader_constants(&mut self, shader: ShaderId, data: &[u8]) -> Result<()> {
+        self.pixel_shaders.get_mut(shader)?.constants = shader::constants_to_f32(data);
+        Ok(())
+    }
+
+    fn bind_texture_shader(
+        &mut self,
+        texture_id: TextureId,
+        shader: Option<ShaderId>,
+    ) -> Result<()> {
+        self.pixel_shaders.bind_texture(texture_id, shader)
+    }
+
+    fn set_hdr_paper_white(&mut self, _nits: f32) {}
+
+    fn capture_backbuffer(&mut self) -> Result<TextureId> {
+        let (width, height) = self.capture_size()?;
+        let gl = &self.gl;
+
+        unsafe {
+            let texture_id = self.texture_heap.create_capture_texture(
+                gl,
+                self.capture_texture,
+                width,
+                height,
+            )?;
+            self.capture_texture = Some(texture_id);
+
+            if self.capture_framebuffer == 0 {
+                self.capture_framebuffer = util::out_param(|x| gl.GenFramebuffers(1, x));
+            }
+
+            let last_read_framebuffer =
+                util::out_param(|x| gl.GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, x));
+            let last_draw_framebuffer =
+                util::out_param(|x| gl.GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, x));
+            let last_read_buffer = util::out_param(|x| gl.GetIntegerv(gl::READ_BUFFER, x));
+            let last_scissor_test = gl.IsEnabled(gl::SCISSOR_TEST);
+
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.capture_framebuffer);
+            gl.FramebufferTexture2D(
+                gl::DRAW_FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                self.texture_heap.get(texture_id).gl_texture,
+                0,
+            );
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            gl.ReadBuffer(gl::BACK);
+            gl.Disable(gl::SCISSOR_TEST);
+
+            // Flip the image, as the first row of the texture is its top.
+            gl.BlitFramebuffer(
+                0,
+                0,
+                width as GLint,
+                height as GLint,
+                0,
+                height as GLint,
+                width as GLint,
+                0,
+                gl::COLOR_BUFFER_BIT,
+                gl::NEAREST,
+            );
+
+            if last_scissor_test == gl::TRUE {
+                gl.Enable(gl::SCISSOR_TEST);
+            }
+            gl.ReadBuffer(last_read_buffer as _);
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, last_read_framebuffer as _);
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, last_draw_framebuffer as _);
+
+            Ok(texture_id)
+        }
+    }
+
+    fn capture_backbuffer_rgba(&mut self) -> Result<CapturedImage> {
+        let (width, height) = self.capture_size()?;
+        let gl = &self.gl;
+        let mut data = vec![0u8; width as usize * height as usize * 4];
+
+        unsafe {
+            let last_read_framebuffer =
+                util::out_param(|x| gl.GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, x));
+            let last_read_buffer = util::out_param(|x| gl.GetIntegerv(gl::READ_BUFFER, x));
+            let last_pack_buffer =
+                util::out_param(|x| gl.GetIntegerv(gl::PIXEL_PACK_BUFFER_BINDING, x));
+            let last_pack_alignment = util::out_param(|x| gl.GetIntegerv(gl::PACK_ALIGNMENT, x));
+
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            gl.ReadBuffer(gl::BACK);
+            gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl.ReadPixels(
+                0,
+                0,
+                width as GLint,
+                height as GLint,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                data.as_mut_ptr() as *mut c_void,
+            );
+
+            gl.PixelStorei(gl::PACK_ALIGNMENT, last_pack_alignment);
+            gl.BindBuffer(gl::PIXEL_PACK_BUFFER, last_pack_buffer as _);
+            gl.ReadBuffer(last_read_buffer as _);
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, last_read_framebuffer as _);
+        }
+
+        // Rows are read from the bottom of the back buffer.
+        let row_pitch = width as usize * 4;
+        Ok(capture::convert_to_rgba(PixelFormat::Rgba8, &data, row_pitch, width, height, true))
+    }
+
+    fn create_render_target(&mut self, width: u32, height: u32) -> Result<TextureId> {
+        let data = vec![0u8; width as usize * height as usize * 4];
+        unsafe { self.texture_heap.create_texture(&self.gl, &data, width, height) }
+    }
+
+    fn render_to_target(&mut self, texture_id: TextureId, draw_data: &DrawData) -> Result<()> {
+        self.update_textures(draw_data)?;
+
+        unsafe {
+            if self.render_target_framebuffer == 0 {
+                self.render_target_framebuffer =
+                    util::out_param(|x| self.gl.GenFramebuffers(1, x));
+            }
+
+            let last_draw_framebuffer =
+                util::out_param(|x| self.gl.GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, x));
+
+            self.gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.render_target_framebuffer);
+            self.gl.FramebufferTexture2D(
+                gl::DRAW_FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                self.texture_heap.get(texture_id).gl_texture,
+                0,
+            );
+
+            let status = self.gl.CheckFramebufferStatus(gl::DRAW_FRAMEBUFFER);
+            let result = if status == gl::FRAMEBUFFER_COMPLETE {
+                self.clear([0.; 4]);
+                self.render_with_backup(draw_data, true)
+            } else {
+                error!("Texture {texture_id:?} can't be rendered to");
+                Err(Error::from_hresult(HRESULT(-1)))
+            };
+
+            self.gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, last_draw_framebuffer as _);
+            result
+        }
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
 }
 
 impl RenderEngine for OpenGl3RenderEngine {
@@ -121,13 +420,37 @@ impl RenderEngine for OpenGl3RenderEngine {
 
     fn render(&mut self, draw_data: &DrawData, _render_target: Self::RenderTarget) -> Result<()> {
         unsafe {
-            let state_backup = StateBackup::backup(&self.gl);
-            self.render_draw_data(draw_data)?;
-            state_backup.restore(&self.gl);
+            self.gl.QueryCounter(self.gpu_timer.current()[0], gl::TIMESTAMP);
+            self.render_with_backup(draw_data, false)?;
+            self.gl.QueryCounter(self.gpu_timer.current()[1], gl::TIMESTAMP);
+
+            if let Some(gpu_time) = self.read_gpu_timer() {
+                self.frame_stats.gpu_time = Some(gpu_time);
+            }
         }
+
+        let textures = self.texture_heap.textures.iter().map(|t| {
+            let desc = TextureDesc::new(t.width, t.height, t.format);
+            TextureDesc { mip_levels: t.mip_levels, ..desc }.size()
+        });
+        self.frame_stats.set_textures(textures);
+
         Ok(())
     }
 
+    // The default framebuffer always matches the window.
+    fn render_target_size(&self, _render_target: &Self::RenderTarget) -> Option<[u32; 2]> {
+        None
+    }
+
+    fn frame_stats_mut(&mut self) -> &mut FrameStats {
+        &mut self.frame_stats
+    }
+
+    fn set_capture_source(&mut self, render_target: Option<&Self::RenderTarget>) {
+        self.capture_enabled = render_target.is_some();
+    }
+
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
         let fonts_texture = fonts.build_rgba32_texture();
@@ -294,21 +617,131 @@ impl RenderEngine for OpenGl3RenderEngine {
 
         Ok(())
     }
+
+    #[cfg(feature = "imgui-docking")]
+    fn create_viewport(&mut self, hwnd: HWND, _width: u32, _height: u32) -> Result<()> {
+        unsafe {
+            let dc = GetDC(hwnd);
+
+            // The context can only be made current on a device context with the
+            // same pixel format as the one it was created for.
+            let current_dc = wglGetCurrentDC();
+            let format = GetPixelFormat(current_dc);
+            let mut pfd = PIXELFORMATDESCRIPTOR::default();
+            DescribePixelFormat(
+                current_dc,
+                format,
+                mem::size_of::<PIXELFORMATDESCRIPTOR>() as u32,
+                Some(&mut pfd),
+            );
+
+            if let Err(e) = SetPixelFormat(dc, format, &pfd) {
+                ReleaseDC(hwnd, dc);
+                return Err(e);
+            }
+
+            self.viewports.push(ViewportWindow { hwnd, dc });
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn destroy_viewport(&mut self, hwnd: HWND) {
+        self.viewports.retain(|viewport| {
+            if viewport.hwnd == hwnd {
+                unsafe { ReleaseDC(viewport.hwnd, viewport.dc) };
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    // The default framebuffer follows the window size.
+    #[cfg(feature = "imgui-docking")]
+    fn resize_viewport(&mut self, _hwnd: HWND, _width: u32, _height: u32) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn render_viewport(&mut self, hwnd: HWND, draw_data: &DrawData, clear: bool) -> Result<()> {
+        self.with_viewport_dc(hwnd, |engine, _| {
+            unsafe {
+                if clear {
+                    engine.clear([0., 0., 0., 1.]);
+                }
+                engine.render_with_backup(draw_data, false)
+            }
+        })
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    fn present_viewport(&mut self, hwnd: HWND) -> Result<()> {
+        self.with_viewport_dc(hwnd, |_, dc| unsafe { SwapBuffers(dc) })
+    }
 }
 
 impl OpenGl3RenderEngine {
-    unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
+    unsafe fn render_with_backup(&mut self, draw_data: &DrawData, flip_y: bool) -> Result<()> {
+        let state_backup = StateBackup::backup(&self.gl);
+        self.render_draw_data(draw_data, flip_y)?;
+        state_backup.restore(&self.gl);
+        Ok(())
+    }
+
+    // Clear the bound framebuffer. The clear state belongs to the
+    // application's context.
+    unsafe fn clear(&self, color: [f32; 4]) {
+        let mut clear_color = [0f32; 4];
+        self.gl.GetFloatv(gl::COLOR_CLEAR_VALUE, clear_color.as_mut_ptr());
+        let scissor_test = self.gl.IsEnabled(gl::SCISSOR_TEST) == gl::TRUE;
+
+        self.gl.Disable(gl::SCISSOR_TEST);
+        let [r, g, b, a] = color;
+        self.gl.ClearColor(r, g, b, a);
+        self.gl.Clear(gl::COLOR_BUFFER_BIT);
+
+        let [r, g, b, a] = clear_color;
+        self.gl.ClearColor(r, g, b, a);
+        if scissor_test {
+            self.gl.Enable(gl::SCISSOR_TEST);
+        }
+    }
+
+    // Read the timestamps of the oldest frame in flight, if they are there.
+    unsafe fn read_gpu_timer(&mut self) -> Option<Duration> {
+        let &[begin, end] = self.gpu_timer.advance()?;
+
+        let available: GLint =
+            util::out_param(|x| self.gl.GetQueryObjectiv(end, gl::QUERY_RESULT_AVAILABLE, x));
+        if available == 0 {
+            return None;
+        }
+
+        let begin: GLuint64 =
+            util::out_param(|x| self.gl.GetQueryObjectui64v(begin, gl::QUERY_RESULT, x));
+        let end: GLuint64 =
+            util::out_param(|x| self.gl.GetQueryObjectui64v(end, gl::QUERY_RESULT, x));
+        Some(Duration::from_nanos(end.saturating_sub(begin)))
+    }
+
+    // Textures are sampled from their first row, which is at the bottom of
+    // framebuffers, so they are rendered to with `flip_y`.
+    unsafe fn render_draw_data(&mut self, draw_data: &DrawData, flip_y: bool) -> Result<()> {
         let [clip_offset_x, clip_offset_y] = draw_data.display_pos;
         let [clip_scale_w, clip_scale_h] = draw_data.framebuffer_scale;
         let fb_height = clip_scale_h * draw_data.display_size[1];
 
         self.projection_buffer = {
-            let [l, t, r, b] = [
+            let [l, mut t, r, mut b] = [
                 draw_data.display_pos[0],
                 draw_data.display_pos[1],
                 draw_data.display_pos[0] + draw_data.display_size[0],
                 draw_data.display_pos[1] + draw_data.display_size[1],
             ];
+            if flip_y {
+                mem::swap(&mut t, &mut b);
+            }
 
             [[2. / (r - l), 0., 0., 0.], [0., 2. / (t - b), 0., 0.], [0., 0., 0.5, 0.], [
                 (r + l) / (l - r),
@@ -321,6 +754,8 @@ impl OpenGl3RenderEngine {
         self.setup_render_state(draw_data);
 
         for cl in draw_data.draw_lists() {
+            self.pixel_shaders.set_current(None);
+
             for cmd in cl.commands() {
                 match cmd {
                     DrawCmd::Elements { count, cmd_params } => {
@@ -335,12 +770,33 @@ impl OpenGl3RenderEngine {
                             continue;
                         }
 
+                        let scissor_y = if flip_y { clip_min_y } else { fb_height - clip_max_y };
                         self.gl.Scissor(
                             clip_min_x as i32,
-                            (fb_height - clip_max_y) as i32,
+                            scissor_y as i32,
                             (clip_max_x - clip_min_x) as i32,
                             (clip_max_y - clip_min_y) as i32,
                         );
+                        match self.pixel_shaders.resolve(cmd_params.texture_id) {
+                            Some(pixel_shader) => {
+                                self.gl.UseProgram(pixel_shader.program);
+                                self.gl.Uniform1i(pixel_shader.texture_loc, 0);
+                                self.gl.UniformMatrix4fv(
+                                    pixel_shader.projection_loc,
+                                    1,
+                                    gl::FALSE,
+                                    self.projection_buffer.as_ptr() as *const f32,
+                                );
+                                if !pixel_shader.constants.is_empty() {
+                                    self.gl.Uniform4fv(
+                                        pixel_shader.constants_loc,
+                                        (pixel_shader.constants.len() / 4) as GLint,
+                                        pixel_shader.constants.as_ptr(),
+                                    );
+                                }
+                            },
+                            None => self.gl.UseProgram(self.program),
+                        }
                         self.gl.ActiveTexture(gl::TEXTURE0);
                         self.gl.BindTexture(
                             gl::TEXTURE_2D,
@@ -374,7 +830,19 @@ impl OpenGl3RenderEngine {
                     DrawCmd::ResetRenderState => {
                         self.setup_render_state(draw_data);
                     },
-                    DrawCmd::RawCallback { callback, raw_cmd } => callback(cl.raw(), raw_cmd),
+                    DrawCmd::RawCallback { callback, raw_cmd } => {
+                        if let Some(shader) = shader::pixel_shader_command(callback, raw_cmd) {
+                            self.pixel_shaders.set_current(shader);
+                        } else {
+                            callback::invoke_callback(
+                                callback,
+                                cl.raw(),
+                                raw_cmd,
+                                draw_data,
+                                DrawCallbackBackend::OpenGl3 { gl: &self.gl },
+                            );
+                        }
+                    },
                 }
             }
         }
@@ -395,6 +863,8 @@ impl OpenGl3RenderEngine {
         self.gl.Disable(gl::DEPTH_TEST);
         self.gl.Disable(gl::STENCIL_TEST);
         self.gl.Enable(gl::SCISSOR_TEST);
+        // imgui's colors are already sRGB encoded.
+        self.gl.Disable(gl::FRAMEBUFFER_SRGB);
         self.gl.PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
 
         self.gl.Viewport(
@@ -449,25 +919,25 @@ impl OpenGl3RenderEngine {
     }
 }
 
-unsafe fn create_shader_program(gl: &gl::Gl) -> (GLuint, GLuint, GLuint, GLuint, GLuint, GLuint) {
-    const VS: &[u8] = b"
-    #version 130
+const VS: &[u8] = b"
+#version 130
 
-    uniform mat4 projection_matrix;
-    in vec2 position;
-    in vec4 color;
-    in vec2 uv;
+uniform mat4 projection_matrix;
+in vec2 position;
+in vec4 color;
+in vec2 uv;
 
-    out vec2 frag_uv;
-    out vec4 frag_color;
+out vec2 frag_uv;
+out vec4 frag_color;
 
-    void main() {
-        frag_uv = uv;
-        frag_color = color;
-        gl_Position = projection_matrix * vec4(position.xy, 0.0, 1.0);
-    }
-    \0";
+void main() {
+    frag_uv = uv;
+    frag_color = color;
+    gl_Position = projection_matrix * vec4(position.xy, 0.0, 1.0);
+}
+\0";
 
+unsafe fn create_shader_program(gl: &gl::Gl) -> (GLuint, GLuint, GLuint, GLuint, GLuint, GLuint) {
     const FS: &[u8] = b"
     #version 130
 
@@ -508,6 +978,24 @@ unsafe fn create_shader_program(gl: &gl::Gl) -> (GLuint, GLuint, GLuint, GLuint,
     (program, projection_loc, position_loc, color_loc, uv_loc, texture_loc)
 }
 
+unsafe fn compile_shader(gl: &gl::Gl, kind: GLenum, source: &[u8]) -> Result<GLuint> {
+    let shader = gl.CreateShader(kind);
+    gl.ShaderSource(shader, 1, &(source.as_ptr() as *const GLchar), &(source.len() as GLint));
+    gl.CompileShader(shader);
+
+    let status = util::out_param(|x| gl.GetShaderiv(shader, gl::COMPILE_STATUS, x));
+    if status != gl::TRUE as GLint {
+        let log_len = util::out_param(|x| gl.GetShaderiv(shader, gl::INFO_LOG_LENGTH, x));
+        let mut log = vec![0u8; log_len.max(1) as usize];
+        gl.GetShaderInfoLog(shader, log_len, ptr::null_mut(), log.as_mut_ptr() as _);
+        error!("Compiling shader: {}", String::from_utf8_lossy(&log));
+        gl.DeleteShader(shader);
+        return Err(Error::from_hresult(HRESULT(-1)));
+    }
+
+    Ok(shader)
+}
+
 struct TextureHeap {
     textures: Vec<Texture>,
 }
@@ -515,6 +1003,35 @@ struct Texture {
     gl_texture: GLuint,
     width: u32,
     height: u32,
+    format: TextureFormat,
+    mip_levels: u32,
+}
+
+// Compressed formats of the EXT_texture_compression_s3tc and
+// ARB_texture_compression_bptc extensions, which the core bindings lack.
+const COMPRESSED_RGBA_S3TC_DXT1_EXT: GLenum = 0x83f1;
+const COMPRESSED_RGBA_S3TC_DXT3_EXT: GLenum = 0x83f2;
+const COMPRESSED_RGBA_S3TC_DXT5_EXT: GLenum = 0x83f3;
+const COMPRESSED_RGBA_BPTC_UNORM: GLenum = 0x8e8c;
+const COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT: GLenum = 0x8e8f;
+
+// The internal format of a texture, and the format and type of its data unless
+// it is compressed.
+fn gl_texture_format(format: TextureFormat) -> (GLenum, Option<(GLenum, GLenum)>) {
+    match format {
+        TextureFormat::Rgba8 => (gl::RGBA8, Some((gl::RGBA, gl::UNSIGNED_BYTE))),
+        TextureFormat::Bgra8 => (gl::RGBA8, Some((gl::BGRA, gl::UNSIGNED_BYTE))),
+        TextureFormat::R8 => (gl::R8, Some((gl::RED, gl::UNSIGNED_BYTE))),
+        TextureFormat::Rg8 => (gl::RG8, Some((gl::RG, gl::UNSIGNED_BYTE))),
+        TextureFormat::Rgba16Float => (gl::RGBA16F, Some((gl::RGBA, gl::HALF_FLOAT))),
+        TextureFormat::Bc1 => (COMPRESSED_RGBA_S3TC_DXT1_EXT, None),
+        TextureFormat::Bc2 => (COMPRESSED_RGBA_S3TC_DXT3_EXT, None),
+        TextureFormat::Bc3 => (COMPRESSED_RGBA_S3TC_DXT5_EXT, None),
+        TextureFormat::Bc4 => (gl::COMPRESSED_RED_RGTC1, None),
+        TextureFormat::Bc5 => (gl::COMPRESSED_RG_RGTC2, None),
+        TextureFormat::Bc6h => (COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT, None),
+        TextureFormat::Bc7 => (COMPRESSED_RGBA_BPTC_UNORM, None),
+    }
 }
 
 impl TextureHeap {
@@ -533,35 +1050,125 @@ impl TextureHeap {
         width: u32,
         height: u32,
     ) -> Result<TextureId> {
+        let desc = TextureDesc::new(width, height, TextureFormat::Rgba8);
+        self.create_texture_with_desc(gl, data, &desc)
+    }
+
+    unsafe fn create_texture_with_desc(
+        &mut self,
+        gl: &gl::Gl,
+        data: &[u8],
+        desc: &TextureDesc,
+    ) -> Result<TextureId> {
+        let levels = desc.mip_levels(data)?;
+        let (internal_format, data_format) = gl_texture_format(desc.format);
+
         let texture = util::out_param(|x| gl.GenTextures(1, x));
 
         let mut bound_texture = 0;
         gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut bound_texture);
+        let mut unpack_alignment = 0;
+        gl.GetIntegerv(gl::UNPACK_ALIGNMENT, &mut unpack_alignment);
 
+        let min_filter = if desc.mip_levels > 1 { gl::LINEAR_MIPMAP_LINEAR } else { gl::LINEAR };
         gl.ActiveTexture(gl::TEXTURE0);
         gl.BindTexture(gl::TEXTURE_2D, texture);
-        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as _);
         gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, desc.mip_levels as GLint - 1);
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+
+        for (index, level) in levels.iter().enumerate() {
+            let data = level.tightly_packed();
+            match data_format {
+                Some((format, ty)) => gl.TexImage2D(
+                    gl::TEXTURE_2D,
+                    index as GLint,
+                    internal_format as GLint,
+                    level.width as GLint,
+                    level.height as GLint,
+                    0,
+                    format,
+                    ty,
+                    data.as_ptr() as *const c_void,
+                ),
+                None => gl.CompressedTexImage2D(
+                    gl::TEXTURE_2D,
+                    index as GLint,
+                    internal_format,
+                    level.width as GLint,
+                    level.height as GLint,
+                    0,
+                    data.len() as GLsizei,
+                    data.as_ptr() as *const c_void,
+                ),
+            }
+        }
 
-        gl.TexImage2D(
-            gl::TEXTURE_2D,
-            0,
-            gl::RGBA as GLint,
-            width as GLint,
-            height as GLint,
-            0,
-            gl::RGBA,
-            gl::UNSIGNED_BYTE,
-            data.as_ptr() as *const c_void,
-        );
+        // Compressed formats depend on the driver's extensions.
+        let error = if data_format.is_none() { gl.GetError() } else { gl::NO_ERROR };
+
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, unpack_alignment);
         gl.BindTexture(gl::TEXTURE_2D, bound_texture as _);
 
+        if error != gl::NO_ERROR {
+            gl.DeleteTextures(1, &texture);
+            error!("Could not load a {:?} texture: GL error {error:#x}", desc.format);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
         let id = TextureId::from(self.textures.len());
-        self.textures.push(Texture { gl_texture: texture, width, height });
+        self.textures.push(Texture {
+            gl_texture: texture,
+            width: desc.width,
+            height: desc.height,
+            format: desc.format,
+            mip_levels: desc.mip_levels,
+        });
 
         Ok(id)
     }
 
+    // Create a texture to copy the back buffer to, or resize the texture of
+    // the previous capture.
+    unsafe fn create_capture_texture(
+        &mut self,
+        gl: &gl::Gl,
+        texture_id: Option<TextureId>,
+        width: u32,
+        height: u32,
+    ) -> Result<TextureId> {
+        let Some(texture_id) = texture_id else {
+            let data = vec![0u8; width as usize * height as usize * 4];
+            return self.create_texture(gl, &data, width, height);
+        };
+
+        let texture = &mut self.textures[texture_id.id()];
+        if (texture.width, texture.height) != (width, height) {
+            let mut bound_texture = 0;
+            gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut bound_texture);
+
+            gl.BindTexture(gl::TEXTURE_2D, texture.gl_texture);
+            gl.TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA as GLint,
+                width as GLint,
+                height as GLint,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                ptr::null(),
+            );
+            gl.BindTexture(gl::TEXTURE_2D, bound_texture as _);
+
+            texture.width = width;
+            texture.height = height;
+        }
+
+        Ok(texture_id)
+    }
+
     unsafe fn update_texture(
         &mut self,
         gl: &gl::Gl,
@@ -570,6 +1177,12 @@ impl TextureHeap {
         width: u32,
         height: u32,
     ) -> Result<()> {
+        let texture_info = self.get(texture);
+        if (texture_info.format, texture_info.mip_levels) != (TextureFormat::Rgba8, 1) {
+            error!("Only RGBA8 textures without mip levels can be replaced");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
         let src_pitch = (width as usize) * 4;
         self.update_texture_region(
             gl,
@@ -668,6 +1281,7 @@ struct StateBackup {
     last_enable_cull_face: bool,
     last_enable_depth_test: bool,
     last_enable_scissor_test: bool,
+    last_enable_framebuffer_srgb: bool,
 }
 
 impl StateBackup {
@@ -703,6 +1317,7 @@ impl StateBackup {
         let last_enable_cull_face = gl.IsEnabled(gl::CULL_FACE) == gl::TRUE;
         let last_enable_depth_test = gl.IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
         let last_enable_scissor_test = gl.IsEnabled(gl::SCISSOR_TEST) == gl::TRUE;
+        let last_enable_framebuffer_srgb = gl.IsEnabled(gl::FRAMEBUFFER_SRGB) == gl::TRUE;
 
         StateBackup {
             last_active_texture,
@@ -725,6 +1340,7 @@ impl StateBackup {
             last_enable_cull_face,
             last_enable_depth_test,
             last_enable_scissor_test,
+            last_enable_framebuffer_srgb,
         }
     }
 
@@ -750,6 +1366,7 @@ impl StateBackup {
             last_enable_cull_face,
             last_enable_depth_test,
             last_enable_scissor_test,
+            last_enable_framebuffer_srgb,
         } = self;
 
         gl.UseProgram(last_program as _);
@@ -788,6 +1405,11 @@ impl StateBackup {
         } else {
             gl.Disable(gl::SCISSOR_TEST)
         };
+        if last_enable_framebuffer_srgb {
+            gl.Enable(gl::FRAMEBUFFER_SRGB)
+        } else {
+            gl.Disable(gl::FRAMEBUFFER_SRGB)
+        };
         gl.PolygonMode(gl::FRONT_AND_BACK, last_polygon_mode[0] as _);
         gl.Viewport(
             last_viewport[0] as _,
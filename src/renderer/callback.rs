@@ -0,0 +1,159 @@
+//! Draw callbacks, to run custom rendering code in the middle of imgui's draw
+//! lists.
+
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::{mem, ptr};
+
+use imgui::{sys, Ui};
+#[cfg(feature = "dx11")]
+use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11DeviceContext};
+#[cfg(feature = "dx12")]
+use windows::Win32::Graphics::Direct3D12::{ID3D12Device, ID3D12GraphicsCommandList};
+#[cfg(feature = "dx9")]
+use windows::Win32::Graphics::Direct3D9::IDirect3DDevice9;
+
+#[cfg(feature = "opengl3")]
+use super::gl::Gl;
+
+// Value of imgui's `ImDrawCallback_ResetRenderState`.
+const RESET_RENDER_STATE: isize = -8;
+
+pub(crate) type DrawCallback = Box<dyn FnMut(&DrawCallbackContext<'_>)>;
+type RawDrawCallback = unsafe extern "C" fn(*const sys::ImDrawList, *const sys::ImDrawCmd);
+
+thread_local! {
+    // Callbacks registered while building the current frame.
+    static PENDING_CALLBACKS: RefCell<Vec<Box<DrawCallback>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The objects of the render engine a draw callback is invoked from.
+pub enum DrawCallbackBackend<'a> {
+    /// DirectX 9.
+    #[cfg(feature = "dx9")]
+    D3D9 {
+        /// The device rendering the overlay.
+        device: &'a IDirect3DDevice9,
+    },
+    /// DirectX 11.
+    #[cfg(feature = "dx11")]
+    D3D11 {
+        /// The device rendering the overlay.
+        device: &'a ID3D11Device,
+        /// The immediate context recording the draw calls.
+        device_context: &'a ID3D11DeviceContext,
+    },
+    /// DirectX 12.
+    #[cfg(feature = "dx12")]
+    D3D12 {
+        /// The device rendering the overlay.
+        device: &'a ID3D12Device,
+        /// The command list recording the draw calls.
+        command_list: &'a ID3D12GraphicsCommandList,
+    },
+    /// OpenGL 3.
+    #[cfg(feature = "opengl3")]
+    OpenGl3 {
+        /// The OpenGL functions loaded by the renderer.
+        gl: &'a Gl,
+    },
+}
+
+/// The context a draw callback is invoked with.
+pub struct DrawCallbackContext<'a> {
+    /// The clipping rectangle of the callback command, as `[x1, y1, x2, y2]`
+    /// in imgui coordinates.
+    pub clip_rect: [f32; 4],
+    /// The imgui coordinates of the top left corner of the render target.
+    pub display_pos: [f32; 2],
+    /// The size of the render target, in imgui coordinates.
+    pub display_size: [f32; 2],
+    /// The number of render target pixels in an imgui unit, on each axis.
+    pub framebuffer_scale: [f32; 2],
+    /// The objects of the render engine.
+    pub backend: DrawCallbackBackend<'a>,
+}
+
+/// Add a callback to the draw list of the current window.
+///
+/// The callback is invoked by the render engine when the draw list is
+/// rendered, with the render state set up for imgui. Call
+/// [`add_reset_render_state`] afterwards if the callback alters that state.
+///
+/// The callback is kept until the next frame is rendered, and may be invoked
+/// more than once, e.g. for each viewport.
+pub fn add_draw_callback<F>(_ui: &Ui, callback: F)
+where
+    F: FnMut(&DrawCallbackContext<'_>) + 'static,
+{
+    let mut callback: Box<DrawCallback> = Box::new(Box::new(callback));
+    let user_data = &mut *callback as *mut DrawCallback as *mut c_void;
+
+    PENDING_CALLBACKS.with_borrow_mut(|callbacks| callbacks.push(callback));
+
+    unsafe {
+        sys::ImDrawList_AddCallback(
+            sys::igGetWindowDrawList(),
+            Some(draw_callback_trampoline),
+            user_data,
+            0,
+        )
+    };
+}
+
+/// Request the render engine to set up its render state again, typically
+/// after a draw callback has modified it.
+pub fn add_reset_render_state(_ui: &Ui) {
+    unsafe {
+        sys::ImDrawList_AddCallback(
+            sys::igGetWindowDrawList(),
+            mem::transmute::<isize, sys::ImDrawCallback>(RESET_RENDER_STATE),
+            ptr::null_mut(),
+            0,
+        )
+    };
+}
+
+// Only used to identify hudhook's callbacks: they are dispatched by
+// `invoke_callback` with the render engine's context.
+unsafe extern "C" fn draw_callback_trampoline(
+    _draw_list: *const sys::ImDrawList,
+    _cmd: *const sys::ImDrawCmd,
+) {
+}
+
+/// Take the callbacks registered for the frame that has just been built. They
+/// must be kept alive until the frame's draw data is no longer rendered.
+pub(crate) fn take_pending_callbacks() -> Vec<Box<DrawCallback>> {
+    PENDING_CALLBACKS.with_borrow_mut(mem::take)
+}
+
+/// Invoke a `DrawCmd::RawCallback` command.
+///
+/// # Safety
+///
+/// `draw_list` and `raw_cmd` must come from draw data whose callbacks are
+/// still alive.
+pub(crate) unsafe fn invoke_callback(
+    callback: RawDrawCallback,
+    draw_list: *const sys::ImDrawList,
+    raw_cmd: *const sys::ImDrawCmd,
+    draw_data: &imgui::DrawData,
+    backend: DrawCallbackBackend<'_>,
+) {
+    if callback as usize != draw_callback_trampoline as usize {
+        callback(draw_list, raw_cmd);
+        return;
+    }
+
+    let callback = &mut *((*raw_cmd).UserCallbackData as *mut DrawCallback);
+    let sys::ImVec4 { x, y, z, w } = (*raw_cmd).ClipRect;
+
+    callback(&DrawCallbackContext {
+        clip_rect: [x, y, z, w],
+        display_pos: draw_data.display_pos,
+        display_size: draw_data.display_size,
+        framebuffer_scale: draw_data.framebuffer_scale,
+        backend,
+    });
+}
@@ -0,0 +1,243 @@
+//! Captures of the render target, and conversion of their pixels to RGBA.
+
+#[cfg(feature = "dx9")]
+use windows::Win32::Graphics::Direct3D9::*;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+/// An image read back from the render target with
+/// [`RenderContext::capture_backbuffer_rgba`](crate::RenderContext::capture_backbuffer_rgba).
+///
+/// Pixels are tightly packed, top to bottom, with 8 bits per channel in RGBA
+/// order and sRGB encoded colors, e.g. ready to be saved as a PNG file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedImage {
+    /// Width of the image, in pixels.
+    pub width: u32,
+    /// Height of the image, in pixels.
+    pub height: u32,
+    /// `width * height` RGBA pixels.
+    pub data: Vec<u8>,
+}
+
+/// Pixel layouts of the render targets that can be read back.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum PixelFormat {
+    /// 8 bits per channel, RGBA order. sRGB render targets also use it, as
+    /// their values are already encoded.
+    Rgba8,
+    /// 8 bits per channel, BGRA order.
+    Bgra8,
+    /// 8 bits per channel, BGR order, followed by an unused byte.
+    Bgrx8,
+    /// 10 bits per color channel and 2 bits of alpha, red in the low bits.
+    Rgb10A2,
+    /// 10 bits per color channel and 2 bits of alpha, blue in the low bits.
+    Bgr10A2,
+    /// Linear 16 bits floats per channel, RGBA order, as used by scRGB.
+    Rgba16Float,
+}
+
+impl PixelFormat {
+    pub(crate) fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba16Float => 8,
+            _ => 4,
+        }
+    }
+
+    #[cfg(any(feature = "dx11", feature = "dx12"))]
+    pub(crate) fn from_dxgi(format: DXGI_FORMAT) -> Option<Self> {
+        match format {
+            DXGI_FORMAT_R8G8B8A8_UNORM
+            | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+            | DXGI_FORMAT_R8G8B8A8_TYPELESS => Some(PixelFormat::Rgba8),
+            DXGI_FORMAT_B8G8R8A8_UNORM
+            | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+            | DXGI_FORMAT_B8G8R8A8_TYPELESS => Some(PixelFormat::Bgra8),
+            DXGI_FORMAT_B8G8R8X8_UNORM
+            | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB
+            | DXGI_FORMAT_B8G8R8X8_TYPELESS => Some(PixelFormat::Bgrx8),
+            DXGI_FORMAT_R10G10B10A2_UNORM | DXGI_FORMAT_R10G10B10A2_TYPELESS => {
+                Some(PixelFormat::Rgb10A2)
+            },
+            DXGI_FORMAT_R16G16B16A16_FLOAT | DXGI_FORMAT_R16G16B16A16_TYPELESS => {
+                Some(PixelFormat::Rgba16Float)
+            },
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "dx9")]
+    pub(crate) fn from_d3d9(format: D3DFORMAT) -> Option<Self> {
+        match format {
+            D3DFMT_A8R8G8B8 => Some(PixelFormat::Bgra8),
+            D3DFMT_X8R8G8B8 => Some(PixelFormat::Bgrx8),
+            D3DFMT_A8B8G8R8 => Some(PixelFormat::Rgba8),
+            D3DFMT_A2R10G10B10 => Some(PixelFormat::Bgr10A2),
+            D3DFMT_A2B10G10R10 => Some(PixelFormat::Rgb10A2),
+            D3DFMT_A16B16G16R16F => Some(PixelFormat::Rgba16Float),
+            _ => None,
+        }
+    }
+
+    fn to_rgba8(self, pixel: &[u8]) -> [u8; 4] {
+        match self {
+            PixelFormat::Rgba8 => [pixel[0], pixel[1], pixel[2], pixel[3]],
+            PixelFormat::Bgra8 => [pixel[2], pixel[1], pixel[0], pixel[3]],
+            PixelFormat::Bgrx8 => [pixel[2], pixel[1], pixel[0], 0xff],
+            PixelFormat::Rgb10A2 | PixelFormat::Bgr10A2 => {
+                let value = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                let channel = |shift: u32| ((value >> shift & 0x3ff) * 255 + 511) / 1023;
+                let (r, b) = match self {
+                    PixelFormat::Rgb10A2 => (channel(0), channel(20)),
+                    _ => (channel(20), channel(0)),
+                };
+                [r as u8, channel(10) as u8, b as u8, ((value >> 30) * 85) as u8]
+            },
+            PixelFormat::Rgba16Float => {
+                let channel = |i: usize| f16_to_f32(u16::from_le_bytes([pixel[i], pixel[i + 1]]));
+                [
+                    linear_to_srgb(channel(0)),
+                    linear_to_srgb(channel(2)),
+                    linear_to_srgb(channel(4)),
+                    (channel(6).clamp(0.0, 1.0) * 255.0).round() as u8,
+                ]
+            },
+        }
+    }
+}
+
+/// The format to view a render target with, which can't be typeless.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) fn typed_dxgi_format(format: DXGI_FORMAT) -> DXGI_FORMAT {
+    match format {
+        DXGI_FORMAT_R8G8B8A8_TYPELESS => DXGI_FORMAT_R8G8B8A8_UNORM,
+        DXGI_FORMAT_B8G8R8A8_TYPELESS => DXGI_FORMAT_B8G8R8A8_UNORM,
+        DXGI_FORMAT_B8G8R8X8_TYPELESS => DXGI_FORMAT_B8G8R8X8_UNORM,
+        DXGI_FORMAT_R10G10B10A2_TYPELESS => DXGI_FORMAT_R10G10B10A2_UNORM,
+        DXGI_FORMAT_R16G16B16A16_TYPELESS => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        format => format,
+    }
+}
+
+/// Convert the rows of a render target read back to memory, `row_pitch`
+/// bytes apart, to an RGBA image. `bottom_up` rows are stored from the bottom
+/// of the image, as OpenGL does.
+pub(crate) fn convert_to_rgba(
+    format: PixelFormat,
+    data: &[u8],
+    row_pitch: usize,
+    width: u32,
+    height: u32,
+    bottom_up: bool,
+) -> CapturedImage {
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let row_size = width as usize * bytes_per_pixel;
+
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height as usize {
+        let row = if bottom_up { height as usize - 1 - y } else { y };
+        let row = &data[row * row_pitch..row * row_pitch + row_size];
+        for pixel in row.chunks_exact(bytes_per_pixel) {
+            rgba.extend_from_slice(&format.to_rgba8(pixel));
+        }
+    }
+
+    CapturedImage { width, height, data: rgba }
+}
+
+/// Encode a linear color channel with the sRGB transfer function.
+pub(crate) fn linear_to_srgb(value: f32) -> u8 {
+    let value = if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) };
+    let encoded =
+        if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (encoded * 255.0).round() as u8
+}
+
+/// Decode an IEEE 754 half precision float.
+pub(crate) fn f16_to_f32(value: u16) -> f32 {
+    let sign = if value & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((value >> 10) & 0x1f) as i32;
+    let mantissa = (value & 0x3ff) as f32;
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_bgra() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        let image = convert_to_rgba(PixelFormat::Bgra8, &data, 8, 2, 1, false);
+        assert_eq!(image.data, [3, 2, 1, 4, 7, 6, 5, 8]);
+
+        let image = convert_to_rgba(PixelFormat::Bgrx8, &data, 8, 2, 1, false);
+        assert_eq!(image.data, [3, 2, 1, 255, 7, 6, 5, 255]);
+    }
+
+    #[test]
+    fn test_convert_rgb10a2() {
+        let pixel =
+            |r: u32, g: u32, b: u32, a: u32| (r | g << 10 | b << 20 | a << 30).to_le_bytes();
+
+        let data = [pixel(1023, 512, 0, 3), pixel(0, 0, 1023, 1)].concat();
+        let image = convert_to_rgba(PixelFormat::Rgb10A2, &data, 8, 2, 1, false);
+        assert_eq!(image.data, [255, 128, 0, 255, 0, 0, 255, 85]);
+
+        let image = convert_to_rgba(PixelFormat::Bgr10A2, &data, 8, 2, 1, false);
+        assert_eq!(image.data, [0, 128, 255, 255, 255, 0, 0, 85]);
+    }
+
+    #[test]
+    fn test_convert_rgba16_float() {
+        // 1.0, 0.5, 0.0, 1.0
+        let data = [0x00, 0x3c, 0x00, 0x38, 0x00, 0x00, 0x00, 0x3c];
+        let image = convert_to_rgba(PixelFormat::Rgba16Float, &data, 8, 1, 1, false);
+        assert_eq!(image.data, [255, 188, 0, 255]);
+    }
+
+    #[test]
+    fn test_convert_row_pitch_and_bottom_up() {
+        // Two rows of one pixel, padded to 8 bytes.
+        let data = [1, 1, 1, 1, 0, 0, 0, 0, 2, 2, 2, 2, 0, 0, 0, 0];
+
+        let image = convert_to_rgba(PixelFormat::Rgba8, &data, 8, 1, 2, false);
+        assert_eq!(
+            image,
+            CapturedImage { width: 1, height: 2, data: vec![1, 1, 1, 1, 2, 2, 2, 2] }
+        );
+
+        let image = convert_to_rgba(PixelFormat::Rgba8, &data, 8, 1, 2, true);
+        assert_eq!(image.data, [2, 2, 2, 2, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_linear_to_srgb() {
+        assert_eq!(linear_to_srgb(0.0), 0);
+        assert_eq!(linear_to_srgb(0.002), 7);
+        assert_eq!(linear_to_srgb(0.5), 188);
+        assert_eq!(linear_to_srgb(1.0), 255);
+        assert_eq!(linear_to_srgb(4.0), 255);
+        assert_eq!(linear_to_srgb(-1.0), 0);
+        assert_eq!(linear_to_srgb(f32::NAN), 0);
+    }
+
+    #[test]
+    fn test_f16_to_f32() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3555), 0.33325195);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+}
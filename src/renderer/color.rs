@@ -0,0 +1,139 @@
+//! Encoding of imgui's sRGB colors for the format of the render target.
+
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::core::Interface;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Dxgi::Common::*;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Dxgi::{IDXGIOutput6, IDXGISwapChain};
+
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use crate::util;
+
+/// Brightness of imgui's white on HDR render targets, in nits, until the
+/// render loop picks another one.
+pub(crate) const DEFAULT_PAPER_WHITE: f32 = 203.0;
+
+/// How the default pixel shaders encode colors for the render target.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum OutputTransfer {
+    /// UNORM render targets: imgui's sRGB colors are written as they are.
+    Srgb = 0,
+    /// sRGB render targets: colors are linearized, and encoded again on write.
+    Linear = 1,
+    /// scRGB render targets: linear Rec. 709 colors, where 1.0 is 80 nits.
+    Scrgb = 2,
+    /// HDR10 render targets: Rec. 2020 colors encoded with the PQ curve.
+    Pq = 3,
+}
+
+impl OutputTransfer {
+    /// The transfer for a DXGI render target format. `hdr10` tells whether
+    /// 10 bits render targets are presented with the HDR10 color space.
+    #[cfg(any(feature = "dx11", feature = "dx12"))]
+    pub(crate) fn from_dxgi(format: DXGI_FORMAT, hdr10: bool) -> Self {
+        match format {
+            DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+            | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+            | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => OutputTransfer::Linear,
+            DXGI_FORMAT_R16G16B16A16_FLOAT | DXGI_FORMAT_R16G16B16A16_TYPELESS => {
+                OutputTransfer::Scrgb
+            },
+            DXGI_FORMAT_R10G10B10A2_UNORM | DXGI_FORMAT_R10G10B10A2_TYPELESS if hdr10 => {
+                OutputTransfer::Pq
+            },
+            _ => OutputTransfer::Srgb,
+        }
+    }
+}
+
+/// Constants of the default DirectX pixel shaders, bound to `register(b1)`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct OutputConstants {
+    pub(crate) transfer: u32,
+    /// Multiplier of the linear colors, relative to the transfer's white.
+    pub(crate) scale: f32,
+    _padding: [f32; 2],
+}
+
+impl OutputConstants {
+    pub(crate) fn new(transfer: OutputTransfer, paper_white: f32) -> Self {
+        let scale = match transfer {
+            OutputTransfer::Scrgb => paper_white / 80.0,
+            OutputTransfer::Pq => paper_white / 10000.0,
+            _ => 1.0,
+        };
+
+        Self { transfer: transfer as u32, scale, _padding: [0.0; 2] }
+    }
+}
+
+/// HLSL declarations shared by the default DirectX pixel shaders, which
+/// return `output_color(col)` instead of `col`.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) const HLSL_OUTPUT_COLOR: &str = r"
+cbuffer output_constants: register(b1) {
+  uint output_transfer;
+  float output_scale;
+};
+
+float3 srgb_to_linear(float3 c) {
+  return c <= 0.04045 ? c / 12.92 : pow((c + 0.055) / 1.055, 2.4);
+}
+
+float3 linear_to_pq(float3 c) {
+  float3 p = pow(max(c, 0.0), 0.1593017578125);
+  return pow((0.8359375 + 18.8515625 * p) / (1.0 + 18.6875 * p), 78.84375);
+}
+
+float4 output_color(float4 col) {
+  if (output_transfer == 0) {
+    return col;
+  }
+
+  float3 rgb = srgb_to_linear(col.rgb);
+  if (output_transfer == 2) {
+    rgb *= output_scale;
+  } else if (output_transfer == 3) {
+    const float3x3 bt709_to_bt2020 = {
+      0.6274040, 0.3292820, 0.0433136,
+      0.0690970, 0.9195400, 0.0113612,
+      0.0163916, 0.0880132, 0.8955950
+    };
+    rgb = linear_to_pq(mul(bt709_to_bt2020, rgb) * output_scale);
+  }
+
+  return float4(rgb, col.a);
+}
+";
+
+/// Whether a swap chain is presented on an output using the HDR10 color space.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) fn is_hdr10_output(swap_chain: &IDXGISwapChain) -> bool {
+    unsafe {
+        swap_chain
+            .GetContainingOutput()
+            .and_then(|output| output.cast::<IDXGIOutput6>())
+            .and_then(|output| util::try_out_param(|v| output.GetDesc1(v)))
+            .map(|desc| desc.ColorSpace == DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_constants_scale() {
+        let constants = |transfer| OutputConstants::new(transfer, 203.0);
+
+        assert_eq!(constants(OutputTransfer::Srgb).scale, 1.0);
+        assert_eq!(constants(OutputTransfer::Linear).scale, 1.0);
+        assert_eq!(constants(OutputTransfer::Scrgb).scale, 203.0 / 80.0);
+        assert_eq!(constants(OutputTransfer::Pq).scale, 0.0203);
+        assert_eq!(constants(OutputTransfer::Pq).transfer, 3);
+    }
+}
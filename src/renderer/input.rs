@@ -3,8 +3,14 @@
 use std::ffi::c_void;
 use std::mem::size_of;
 
+#[cfg(feature = "imgui-docking")]
+use imgui::ConfigFlags;
 use imgui::{Io, Key, MouseButton};
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Foundation::POINT;
 use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Graphics::Gdi::ClientToScreen;
 use windows::Win32::UI::Input::KeyboardAndMouse::*;
 use windows::Win32::UI::Input::{
     GetRawInputData, HRAWINPUT, MOUSE_MOVE_ABSOLUTE, RAWINPUT, RAWINPUTHEADER, RAWKEYBOARD,
@@ -270,6 +276,14 @@ fn handle_input(io: &mut Io, state: u32, WPARAM(wparam): WPARAM, LPARAM(lparam):
 // Window procedure
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Whether a window message is user input, or changes the window's size.
+pub(crate) fn is_input_message(umsg: u32) -> bool {
+    matches!(
+        umsg,
+        WM_KEYFIRST..=WM_KEYLAST | WM_MOUSEFIRST..=WM_MOUSELAST | WM_INPUT | WM_SIZE
+    )
+}
+
 pub fn imgui_wnd_proc_impl<T: RenderEngine>(
     hwnd: HWND,
     umsg: u32,
@@ -277,6 +291,7 @@ pub fn imgui_wnd_proc_impl<T: RenderEngine>(
     LPARAM(lparam): LPARAM,
     pipeline: &mut Pipeline<T>,
 ) {
+    let ui_scale = pipeline.ui_scale();
     let io = pipeline.context().io_mut();
 
     match umsg {
@@ -331,16 +346,32 @@ pub fn imgui_wnd_proc_impl<T: RenderEngine>(
             io.add_mouse_wheel_event([(wheel_delta_wparam as i16 as f32) / wheel_delta, 0.0]);
         },
         WM_MOUSEMOVE => {
-            let x = lowordi(lparam as u32) as f32;
-            let y = hiwordi(lparam as u32) as f32;
-            io.add_mouse_pos_event([x, y]);
+            let x = lowordi(lparam as u32) as i32;
+            let y = hiwordi(lparam as u32) as i32;
+
+            // With multiple viewports, imgui expects the mouse position in screen
+            // coordinates.
+            #[cfg(feature = "imgui-docking")]
+            let (x, y) = if io.config_flags.contains(ConfigFlags::VIEWPORTS_ENABLE) {
+                let mut pos = POINT { x, y };
+                unsafe { ClientToScreen(hwnd, &mut pos) };
+                (pos.x, pos.y)
+            } else {
+                (x, y)
+            };
+
+            io.add_mouse_pos_event([x as f32 / ui_scale, y as f32 / ui_scale]);
         },
         WM_CHAR => io.add_input_character(char::from_u32(wparam as u32).unwrap()),
-        WM_SIZE => {
+        // Viewport windows are resized through imgui.
+        WM_SIZE if hwnd == pipeline.hwnd() => {
             pipeline.resize(loword(lparam as u32) as u32, hiword(lparam as u32) as u32);
         },
         _ => {},
     };
 
+    #[cfg(feature = "imgui-docking")]
+    pipeline.handle_viewport_message(hwnd, umsg);
+
     pipeline.render_loop().on_wnd_proc(hwnd, umsg, WPARAM(wparam), LPARAM(lparam));
 }
@@ -1,27 +1,61 @@
 //! The [`hudhook`](crate) overlay rendering engine.
+pub(crate) mod animation;
 mod backend;
+pub(crate) mod callback;
+pub(crate) mod capture;
+pub(crate) mod color;
 mod input;
 mod keys;
 pub(crate) mod msg_filter;
 mod pipeline;
+pub(crate) mod shader;
+pub(crate) mod stats;
+pub(crate) mod texture;
+#[cfg(feature = "imgui-docking")]
+mod viewports;
 
 use imgui::internal::RawCast;
 use imgui::{sys, Context, DrawData, TextureId};
 use windows::core::Result;
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Foundation::HWND;
 
-use crate::RenderContext;
+use crate::{FrameStats, RenderContext};
 
 pub(crate) trait RenderEngine: RenderContext {
     type RenderTarget;
 
     fn render(&mut self, draw_data: &DrawData, render_target: Self::RenderTarget) -> Result<()>;
+    /// The size of the render target in pixels, or `None` if it matches the
+    /// window's client area.
+    fn render_target_size(&self, render_target: &Self::RenderTarget) -> Option<[u32; 2]>;
+    /// Set the render target read by back buffer captures, while the render
+    /// loop prepares the frame.
+    fn set_capture_source(&mut self, render_target: Option<&Self::RenderTarget>);
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()>;
+    /// The stats returned by [`RenderContext::frame_stats`], completed by the
+    /// pipeline after each frame.
+    fn frame_stats_mut(&mut self) -> &mut FrameStats;
     fn update_textures(&mut self, draw_data: &DrawData) -> Result<()>
     where
         Self: Sized,
     {
         update_textures(self, draw_data)
     }
+
+    /// Create the resources needed to render to an additional viewport window.
+    #[cfg(feature = "imgui-docking")]
+    fn create_viewport(&mut self, hwnd: HWND, width: u32, height: u32) -> Result<()>;
+    /// Release the resources of a viewport window.
+    #[cfg(feature = "imgui-docking")]
+    fn destroy_viewport(&mut self, hwnd: HWND);
+    #[cfg(feature = "imgui-docking")]
+    fn resize_viewport(&mut self, hwnd: HWND, width: u32, height: u32) -> Result<()>;
+    /// Render a viewport's draw data to its window, without presenting it.
+    #[cfg(feature = "imgui-docking")]
+    fn render_viewport(&mut self, hwnd: HWND, draw_data: &DrawData, clear: bool) -> Result<()>;
+    #[cfg(feature = "imgui-docking")]
+    fn present_viewport(&mut self, hwnd: HWND) -> Result<()>;
 }
 
 fn update_textures(render_context: &mut dyn RenderContext, draw_data: &DrawData) -> Result<()> {
@@ -105,5 +139,9 @@ pub(crate) use backend::dx12::D3D12RenderEngine;
 #[cfg(feature = "dx9")]
 pub(crate) use backend::dx9::D3D9RenderEngine;
 #[cfg(feature = "opengl3")]
+pub use backend::opengl3::gl;
+#[cfg(feature = "opengl3")]
 pub(crate) use backend::opengl3::OpenGl3RenderEngine;
-pub(crate) use pipeline::Pipeline;
+pub(crate) use pipeline::{
+    current_frame, Pipeline, Pipelines, RenderLoop, RenderLoopFactory, RenderLoops,
+};
@@ -1,11 +1,13 @@
+use std::cell::Cell;
 use std::collections::HashMap;
-use std::mem;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use std::{mem, ptr};
 
-use imgui::Context;
+use imgui::internal::RawCast;
+use imgui::{sys, Context, DrawData};
 use once_cell::sync::{Lazy, OnceCell};
 use parking_lot::Mutex;
 use tracing::error;
@@ -15,13 +17,42 @@ use windows::Win32::UI::WindowsAndMessaging::{
     CallWindowProcW, DefWindowProcW, SetWindowLongPtrW, GWLP_WNDPROC,
 };
 
-use crate::renderer::input::{imgui_wnd_proc_impl, WndProcType};
+use crate::hooks::{filter_swap_chain, SwapChainInfo};
+use crate::renderer::callback::{self, DrawCallback};
+use crate::renderer::input::{imgui_wnd_proc_impl, is_input_message, WndProcType};
+#[cfg(feature = "imgui-docking")]
+use crate::renderer::viewports::Viewports;
 use crate::renderer::RenderEngine;
 use crate::{util, ImguiRenderLoop, MessageFilter};
 
-type RenderLoop = Box<dyn ImguiRenderLoop + Send + Sync>;
+pub(crate) type RenderLoop = Box<dyn ImguiRenderLoop + Send + Sync>;
+pub(crate) type RenderLoopFactory = dyn Fn(&SwapChainInfo) -> Option<RenderLoop> + Send + Sync;
 
-static PIPELINE_STATES: Lazy<Mutex<HashMap<isize, Arc<PipelineSharedState>>>> =
+// Frames built by any pipeline.
+static FRAME_COUNT: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // The number of the frame the render loop is building on this thread.
+    static CURRENT_FRAME: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// The number of the frame the render loop is building on the calling thread,
+/// if any. Frames are numbered across all the pipelines.
+pub(crate) fn current_frame() -> Option<u64> {
+    CURRENT_FRAME.get()
+}
+
+// The draw data of the current context's last frame, which is valid until the
+// next frame starts.
+fn last_draw_data<'a>() -> Option<&'a DrawData> {
+    let draw_data = unsafe { sys::igGetDrawData() };
+    if draw_data.is_null() || !unsafe { (*draw_data).Valid } {
+        return None;
+    }
+    Some(unsafe { DrawData::from_raw(&*draw_data) })
+}
+
+pub(super) static PIPELINE_STATES: Lazy<Mutex<HashMap<isize, Arc<PipelineSharedState>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
 #[derive(Debug)]
@@ -41,12 +72,21 @@ pub(crate) struct PipelineSharedState {
 pub(crate) struct Pipeline<T: RenderEngine> {
     hwnd: HWND,
     ctx: Context,
+    raw_ctx: *mut sys::ImGuiContext,
     engine: T,
     render_loop: RenderLoop,
     rx: Receiver<PipelineMessage>,
     shared_state: Arc<PipelineSharedState>,
     queue_buffer: OnceCell<Vec<PipelineMessage>>,
     start_of_first_frame: OnceCell<Instant>,
+    last_frame_build: Option<Instant>,
+    rebuild_frame: bool,
+    prepare_time: Duration,
+    window_size: [f32; 2],
+    ui_scale: f32,
+    draw_callbacks: Vec<Box<DrawCallback>>,
+    #[cfg(feature = "imgui-docking")]
+    viewports: Viewports,
 }
 
 impl<T: RenderEngine> Pipeline<T> {
@@ -56,16 +96,31 @@ impl<T: RenderEngine> Pipeline<T> {
         mut engine: T,
         mut render_loop: RenderLoop,
     ) -> std::result::Result<Self, (Error, RenderLoop)> {
+        // The context has just been created, so it is the current one.
+        let raw_ctx = unsafe { sys::igGetCurrentContext() };
+
         let (width, height) = util::win_size(hwnd);
+        let window_size = [width as f32, height as f32];
 
-        ctx.io_mut().display_size = [width as f32, height as f32];
+        ctx.io_mut().display_size = window_size;
 
         render_loop.initialize(&mut ctx, &mut engine);
 
+        #[cfg(feature = "imgui-docking")]
+        let viewports = Viewports::new(&mut ctx, hwnd);
+
         if let Err(e) = engine.setup_fonts(&mut ctx) {
             return Err((e, render_loop));
         }
 
+        // The window is subclassed once, by its only pipeline: a second one
+        // would take the first one's window procedure for the original.
+        let mut pipeline_states = PIPELINE_STATES.lock();
+        if pipeline_states.contains_key(&hwnd.0) {
+            error!("Window {hwnd:?} already has a pipeline");
+            return Err((Error::from_hresult(HRESULT(-1)), render_loop));
+        }
+
         let wnd_proc = unsafe {
             #[cfg(target_arch = "x86")]
             type SwlpRet = i32;
@@ -86,27 +141,51 @@ impl<T: RenderEngine> Pipeline<T> {
             tx,
         });
 
-        PIPELINE_STATES.lock().insert(hwnd.0, Arc::clone(&shared_state));
+        pipeline_states.insert(hwnd.0, Arc::clone(&shared_state));
+        drop(pipeline_states);
 
         let queue_buffer = OnceCell::from(Vec::new());
 
         Ok(Self {
             hwnd,
             ctx,
+            raw_ctx,
             engine,
             render_loop,
             rx,
             shared_state: Arc::clone(&shared_state),
             queue_buffer,
             start_of_first_frame: OnceCell::new(),
+            last_frame_build: None,
+            rebuild_frame: true,
+            prepare_time: Duration::ZERO,
+            window_size,
+            ui_scale: 1.0,
+            draw_callbacks: Vec::new(),
+            #[cfg(feature = "imgui-docking")]
+            viewports,
         })
     }
 
-    pub(crate) fn prepare_render(&mut self) -> Result<()> {
+    // Several pipelines can coexist, each with its own imgui context: make this
+    // pipeline's context the current one before touching it.
+    fn make_current(&self) {
+        unsafe { sys::igSetCurrentContext(self.raw_ctx) };
+    }
+
+    pub(crate) fn prepare_render(&mut self, render_target: &T::RenderTarget) -> Result<()> {
+        let start = Instant::now();
+        self.make_current();
+
+        #[cfg(feature = "imgui-docking")]
+        self.viewports.dispatch_messages(&self.ctx);
+
         let mut queue_buffer = self.queue_buffer.take().unwrap();
         queue_buffer.clear();
         queue_buffer.extend(self.rx.try_iter());
+        let mut input_received = false;
         queue_buffer.drain(..).for_each(|PipelineMessage(hwnd, umsg, wparam, lparam)| {
+            input_received |= is_input_message(umsg);
             imgui_wnd_proc_impl(hwnd, umsg, wparam, lparam, self);
         });
         self.queue_buffer.set(queue_buffer).expect("OnceCell should be empty");
@@ -115,17 +194,108 @@ impl<T: RenderEngine> Pipeline<T> {
 
         self.shared_state.message_filter.store(message_filter.bits(), Ordering::SeqCst);
 
+        // Input and resizing are handled by the next frame as soon as possible;
+        // otherwise the last frame is rendered again until the frame rate limit
+        // allows a new one.
+        let resized = self.update_display_size(render_target);
+        let frame_rate_limit = self.render_loop.frame_rate_limit(self.ctx.io());
+        self.rebuild_frame = input_received
+            || resized
+            || self.frame_due(frame_rate_limit)
+            || last_draw_data().is_none();
+        if !self.rebuild_frame {
+            self.prepare_time = start.elapsed();
+            return Ok(());
+        }
+
         let io = self.ctx.io_mut();
 
         io.nav_active = true;
         io.nav_visible = true;
 
+        // Don't hold on to the render target past this point: swap chains can't
+        // be resized while their buffers are referenced.
+        self.engine.set_capture_source(Some(render_target));
         self.render_loop.before_render(&mut self.ctx, &mut self.engine);
+        self.engine.set_capture_source(None);
+
+        self.prepare_time = start.elapsed();
 
         Ok(())
     }
 
+    fn frame_due(&self, frame_rate_limit: Option<f32>) -> bool {
+        match (self.last_frame_build, frame_rate_limit) {
+            (Some(last_frame_build), Some(limit)) if limit > 0.0 => {
+                last_frame_build.elapsed().as_secs_f32() >= limit.recip()
+            },
+            _ => true,
+        }
+    }
+
+    // Map the window's client area to imgui's coordinates, scaled by the
+    // render loop, and imgui's coordinates to the pixels of the render target.
+    // Returns whether the mapping changed.
+    fn update_display_size(&mut self, render_target: &T::RenderTarget) -> bool {
+        let mut ui_scale = self.render_loop.ui_scale(self.ctx.io());
+        #[allow(unused_mut)]
+        let mut render_target_size = self.engine.render_target_size(render_target);
+
+        // Additional viewports are laid out in screen pixels, and their swap
+        // chains match their windows.
+        #[cfg(feature = "imgui-docking")]
+        if Viewports::enabled(&self.ctx) {
+            ui_scale = 1.0;
+            render_target_size = None;
+        }
+
+        if !(ui_scale.is_finite() && ui_scale > 0.0) {
+            error!("Invalid UI scale: {ui_scale}");
+            ui_scale = 1.0;
+        }
+
+        let [width, height] = self.window_size;
+        let display_size = [width / ui_scale, height / ui_scale];
+        let framebuffer_scale = match render_target_size {
+            Some([rt_width, rt_height]) if width > 0.0 && height > 0.0 => {
+                [rt_width as f32 / display_size[0], rt_height as f32 / display_size[1]]
+            },
+            _ => [ui_scale, ui_scale],
+        };
+
+        let io = self.ctx.io_mut();
+        let changed = self.ui_scale != ui_scale
+            || io.display_size != display_size
+            || io.display_framebuffer_scale != framebuffer_scale;
+
+        self.ui_scale = ui_scale;
+        io.display_size = display_size;
+        io.display_framebuffer_scale = framebuffer_scale;
+        changed
+    }
+
     pub(crate) fn render(&mut self, render_target: T::RenderTarget) -> Result<()> {
+        let start = Instant::now();
+
+        if !self.rebuild_frame {
+            let Some(draw_data) = last_draw_data() else {
+                error!("No frame to render again");
+                return Err(Error::from_hresult(HRESULT(-1)));
+            };
+            let counts = [draw_data.total_vtx_count, draw_data.total_idx_count];
+            let engine_start = Instant::now();
+            self.engine.render(draw_data, render_target)?;
+            let engine_time = engine_start.elapsed();
+
+            #[cfg(feature = "imgui-docking")]
+            if Viewports::enabled(&self.ctx) {
+                self.viewports.render_windows(&self.ctx, &mut self.engine);
+            }
+
+            self.record_stats(start, engine_time, counts);
+            return Ok(());
+        }
+
         let delta_time = Instant::now()
             .checked_duration_since(*self.start_of_first_frame.get_or_init(Instant::now))
             .unwrap_or(Duration::ZERO)
@@ -142,16 +312,48 @@ impl<T: RenderEngine> Pipeline<T> {
             return Err(Error::from_hresult(HRESULT(-1)));
         }
 
+        CURRENT_FRAME.set(Some(FRAME_COUNT.fetch_add(1, Ordering::Relaxed) + 1));
         let ui = self.ctx.frame();
         self.render_loop.render(ui);
         let draw_data = self.ctx.render();
+        CURRENT_FRAME.set(None);
+        self.last_frame_build = Some(Instant::now());
+
+        // The callbacks of the previous frame are no longer referenced.
+        self.draw_callbacks = callback::take_pending_callbacks();
 
+        let counts = [draw_data.total_vtx_count, draw_data.total_idx_count];
         self.engine.update_textures(draw_data)?;
+        let engine_start = Instant::now();
         self.engine.render(draw_data, render_target)?;
+        let engine_time = engine_start.elapsed();
+
+        #[cfg(feature = "imgui-docking")]
+        if Viewports::enabled(&self.ctx) {
+            self.viewports.render(&mut self.ctx, &mut self.engine);
+        }
+
+        self.record_stats(start, engine_time, counts);
 
         Ok(())
     }
 
+    // Complete the stats of the frame, after the engine has filled in its own.
+    fn record_stats(&mut self, start: Instant, engine_time: Duration, counts: [i32; 2]) {
+        let [vertex_count, index_count] = counts;
+        let stats = self.engine.frame_stats_mut();
+        stats.prepare_time = self.prepare_time;
+        stats.render_time = start.elapsed();
+        stats.engine_time = engine_time;
+        stats.rebuilt = self.rebuild_frame;
+        stats.vertex_count = vertex_count as usize;
+        stats.index_count = index_count as usize;
+    }
+
+    pub(crate) fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
     pub(crate) fn context(&mut self) -> &mut Context {
         &mut self.ctx
     }
@@ -160,14 +362,28 @@ impl<T: RenderEngine> Pipeline<T> {
         &mut self.render_loop
     }
 
+    /// The number of window pixels in an imgui unit.
+    pub(crate) fn ui_scale(&self) -> f32 {
+        self.ui_scale
+    }
+
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
-        self.ctx.io_mut().display_size = [width as f32, height as f32];
+        self.window_size = [width as f32, height as f32];
+    }
+
+    #[cfg(feature = "imgui-docking")]
+    pub(crate) fn handle_viewport_message(&mut self, hwnd: HWND, msg: u32) {
+        self.viewports.handle_message(&mut self.ctx, hwnd, msg);
     }
 
     pub(crate) fn cleanup(&mut self) {
+        self.make_current();
+        #[cfg(feature = "imgui-docking")]
+        self.viewports.cleanup(&mut self.engine);
         unsafe {
             SetWindowLongPtrW(self.hwnd, GWLP_WNDPROC, self.shared_state.wnd_proc as usize as _)
         };
+        PIPELINE_STATES.lock().remove(&self.hwnd.0);
     }
 
     pub(crate) fn take(mut self) -> RenderLoop {
@@ -176,6 +392,150 @@ impl<T: RenderEngine> Pipeline<T> {
     }
 }
 
+/// Source of the render loops for the pipelines of a hook module.
+pub(crate) enum RenderLoops {
+    /// A single render loop, owned by at most one pipeline at a time.
+    Single(Option<RenderLoop>),
+    /// A factory creating a render loop for each swap chain.
+    PerWindow(Arc<RenderLoopFactory>),
+}
+
+impl RenderLoops {
+    pub(crate) fn single<T>(t: T) -> Self
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        RenderLoops::Single(Some(Box::new(t)))
+    }
+
+    // Whether a render loop could be acquired without knowing the swap chain.
+    fn is_available(&self) -> bool {
+        match self {
+            RenderLoops::Single(render_loop) => render_loop.is_some(),
+            RenderLoops::PerWindow(_) => true,
+        }
+    }
+
+    fn acquire(&mut self, info: &SwapChainInfo) -> Option<RenderLoop> {
+        match self {
+            RenderLoops::Single(render_loop) => render_loop.take(),
+            RenderLoops::PerWindow(factory) => factory(info),
+        }
+    }
+
+    fn release(&mut self, render_loop: RenderLoop) {
+        // Per-window render loops belong to their swap chain and are dropped.
+        if let RenderLoops::Single(slot) = self {
+            *slot = Some(render_loop);
+        }
+    }
+}
+
+/// The pipelines of a hook module, one for each swap chain being rendered to.
+///
+/// Swap chains are identified by a key `K`, e.g. the address of the swap
+/// chain. A window has at most one pipeline, which subclasses it.
+pub(crate) struct Pipelines<K, T: RenderEngine> {
+    render_loops: Option<RenderLoops>,
+    pipelines: Vec<(K, Pipeline<T>)>,
+}
+
+impl<K: PartialEq, T: RenderEngine> Pipelines<K, T> {
+    pub(crate) const fn new() -> Self {
+        Self { render_loops: None, pipelines: Vec::new() }
+    }
+
+    pub(crate) fn set_render_loops(&mut self, render_loops: RenderLoops) {
+        self.render_loops = Some(render_loops);
+    }
+
+    /// Retrieve the pipeline for the swap chain identified by `key`. If there
+    /// is none, create it if the swap chain described by `info` is accepted by
+    /// the filter and a render loop is available for it.
+    pub(crate) fn get_or_try_init<I, F>(
+        &mut self,
+        key: &K,
+        info: I,
+        init: F,
+    ) -> Result<Option<&mut Pipeline<T>>>
+    where
+        K: Clone,
+        I: FnOnce() -> Result<SwapChainInfo>,
+        F: FnOnce(&mut Context) -> Result<T>,
+    {
+        if let Some(idx) = self.pipelines.iter().position(|(k, _)| k == key) {
+            return Ok(Some(&mut self.pipelines[idx].1));
+        }
+
+        let Some(render_loops) = self.render_loops.as_mut() else {
+            error!("Render loop not yet initialized");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        if !render_loops.is_available() {
+            return Ok(None);
+        }
+
+        let info = info()?;
+        if !filter_swap_chain(&info) {
+            return Ok(None);
+        }
+
+        // Another swap chain of the window has a pipeline already, until it is
+        // torn down.
+        if PIPELINE_STATES.lock().contains_key(&info.hwnd.0) {
+            return Ok(None);
+        }
+
+        let Some(render_loop) = render_loops.acquire(&info) else {
+            return Ok(None);
+        };
+
+        // Detach the current context, if any, as imgui refuses to create a new
+        // one otherwise.
+        unsafe { sys::igSetCurrentContext(ptr::null_mut()) };
+        let mut ctx = Context::create();
+        let engine = match init(&mut ctx) {
+            Ok(engine) => engine,
+            Err(e) => {
+                render_loops.release(render_loop);
+                return Err(e);
+            },
+        };
+
+        let pipeline =
+            Pipeline::new(info.hwnd, ctx, engine, render_loop).map_err(|(e, render_loop)| {
+                render_loops.release(render_loop);
+                e
+            })?;
+
+        self.pipelines.push((key.clone(), pipeline));
+
+        Ok(self.pipelines.last_mut().map(|(_, pipeline)| pipeline))
+    }
+
+    /// Tear down the pipelines of the swap chains not satisfying the
+    /// predicate.
+    pub(crate) fn retain<F: FnMut(&K) -> bool>(&mut self, mut f: F) {
+        let (retained, removed): (Vec<_>, Vec<_>) =
+            mem::take(&mut self.pipelines).into_iter().partition(|(k, _)| f(k));
+        self.pipelines = retained;
+
+        for (_, pipeline) in removed {
+            let render_loop = pipeline.take();
+            if let Some(render_loops) = self.render_loops.as_mut() {
+                render_loops.release(render_loop);
+            }
+        }
+    }
+
+    /// Tear down all the pipelines and drop the render loops.
+    pub(crate) fn clear(&mut self) {
+        self.retain(|_| false);
+        self.render_loops = None;
+    }
+}
+
 unsafe extern "system" fn pipeline_wnd_proc(
     hwnd: HWND,
     msg: u32,
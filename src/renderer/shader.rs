@@ -0,0 +1,180 @@
+//! Custom pixel shaders, to draw textures and imgui windows with effects.
+
+use std::collections::HashMap;
+use std::ptr;
+
+use imgui::{sys, TextureId, Ui};
+use tracing::error;
+use windows::core::{Error, Result, HRESULT};
+
+/// Identifier of a pixel shader created with
+/// [`RenderContext::create_pixel_shader`](crate::RenderContext::create_pixel_shader).
+///
+/// Pixel shaders replace the one hudhook uses to draw imgui's vertices, and
+/// must declare the same inputs.
+///
+/// For DirectX, the source is HLSL with a `main` entry point, compiled with
+/// the `ps_2_0` profile for DirectX 9, `ps_4_0` for DirectX 11 and `ps_5_0`
+/// for DirectX 12:
+///
+/// ```hlsl
+/// struct PS_INPUT {
+///   float4 pos: SV_POSITION; // Not available in DirectX 9.
+///   float4 col: COLOR0;
+///   float2 uv: TEXCOORD0;
+/// };
+///
+/// cbuffer constants: register(b0) { float4 tint; }; // `register(c0)` in DirectX 9.
+/// Texture2D texture0: register(t0);
+/// SamplerState sampler0: register(s0);
+/// ```
+///
+/// For OpenGL, the source is a GLSL 1.30 fragment shader:
+///
+/// ```glsl
+/// #version 130
+///
+/// uniform sampler2D tex;
+/// uniform vec4 constants[1];
+/// in vec2 frag_uv;
+/// in vec4 frag_color;
+/// out vec4 out_color;
+/// ```
+///
+/// The shader constants are set with
+/// [`RenderContext::set_shader_constants`](crate::RenderContext::set_shader_constants),
+/// and are laid out as an array of `float4`.
+///
+/// Colors are written to the render target as the shader returns them, even
+/// if it uses an sRGB or HDR format.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderId(usize);
+
+impl ShaderId {
+    /// The shader's index in the render engine.
+    pub fn id(self) -> usize {
+        self.0
+    }
+}
+
+/// Draw the following items of the current window with a pixel shader, or
+/// with the default one if `shader` is `None`.
+///
+/// This takes precedence over the shaders bound to textures with
+/// [`RenderContext::bind_texture_shader`](crate::RenderContext::bind_texture_shader),
+/// until the end of the window's draw list.
+pub fn set_pixel_shader(_ui: &Ui, shader: Option<ShaderId>) {
+    // The shader is encoded in the callback data, offset by one so that the
+    // default shader is a null pointer.
+    let user_data = shader.map_or(ptr::null_mut(), |ShaderId(id)| (id + 1) as *mut _);
+
+    unsafe {
+        sys::ImDrawList_AddCallback(
+            sys::igGetWindowDrawList(),
+            Some(pixel_shader_marker),
+            user_data,
+            0,
+        )
+    };
+}
+
+// Only used to identify pixel shader commands, which are interpreted by the
+// render engines.
+unsafe extern "C" fn pixel_shader_marker(
+    _draw_list: *const sys::ImDrawList,
+    _cmd: *const sys::ImDrawCmd,
+) {
+}
+
+/// If the `DrawCmd::RawCallback` command was added by [`set_pixel_shader`],
+/// return its shader.
+///
+/// # Safety
+///
+/// `raw_cmd` must point to a valid draw command.
+pub(crate) unsafe fn pixel_shader_command(
+    callback: unsafe extern "C" fn(*const sys::ImDrawList, *const sys::ImDrawCmd),
+    raw_cmd: *const sys::ImDrawCmd,
+) -> Option<Option<ShaderId>> {
+    if callback as usize != pixel_shader_marker as usize {
+        return None;
+    }
+
+    match (*raw_cmd).UserCallbackData as usize {
+        0 => Some(None),
+        id => Some(Some(ShaderId(id - 1))),
+    }
+}
+
+/// The pixel shaders of a render engine, and the textures bound to them.
+pub(crate) struct PixelShaders<S> {
+    shaders: Vec<S>,
+    texture_shaders: HashMap<usize, ShaderId>,
+    current: Option<ShaderId>,
+}
+
+impl<S> PixelShaders<S> {
+    pub(crate) fn new() -> Self {
+        Self { shaders: Vec::new(), texture_shaders: HashMap::new(), current: None }
+    }
+
+    pub(crate) fn insert(&mut self, shader: S) -> ShaderId {
+        self.shaders.push(shader);
+        ShaderId(self.shaders.len() - 1)
+    }
+
+    pub(crate) fn get_mut(&mut self, shader: ShaderId) -> Result<&mut S> {
+        self.shaders.get_mut(shader.0).ok_or_else(|| {
+            error!("Unknown pixel shader {shader:?}");
+            Error::from_hresult(HRESULT(-1))
+        })
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut S> {
+        self.shaders.iter_mut()
+    }
+
+    pub(crate) fn bind_texture(
+        &mut self,
+        texture_id: TextureId,
+        shader: Option<ShaderId>,
+    ) -> Result<()> {
+        match shader {
+            Some(shader) => {
+                self.get_mut(shader)?;
+                self.texture_shaders.insert(texture_id.id(), shader);
+            },
+            None => {
+                self.texture_shaders.remove(&texture_id.id());
+            },
+        }
+        Ok(())
+    }
+
+    /// Set the shader selected by [`set_pixel_shader`] in the draw list being
+    /// rendered. Reset it to `None` at the start of each draw list.
+    pub(crate) fn set_current(&mut self, shader: Option<ShaderId>) {
+        self.current = shader;
+    }
+
+    /// The shader to draw `texture_id` with, or `None` for the default one.
+    pub(crate) fn resolve(&self, texture_id: TextureId) -> Option<&S> {
+        self.current
+            .or_else(|| self.texture_shaders.get(&texture_id.id()).copied())
+            .and_then(|ShaderId(id)| self.shaders.get(id))
+    }
+}
+
+/// Lay out shader constants as `float4` values, padding with zeroes.
+pub(crate) fn constants_to_f32(data: &[u8]) -> Vec<f32> {
+    let mut constants = data
+        .chunks(4)
+        .map(|chunk| {
+            let mut bytes = [0u8; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            f32::from_ne_bytes(bytes)
+        })
+        .collect::<Vec<_>>();
+    constants.resize(constants.len().next_multiple_of(4), 0.0);
+    constants
+}
@@ -0,0 +1,203 @@
+//! Statistics of the overlay's cost, and a window graphing them.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use imgui::Ui;
+
+/// The cost of the overlay's last frame, retrieved with
+/// [`RenderContext::frame_stats`](crate::RenderContext::frame_stats).
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// CPU time spent handling window messages and in
+    /// [`ImguiRenderLoop::before_render`](crate::ImguiRenderLoop::before_render).
+    pub prepare_time: Duration,
+    /// CPU time spent building and rendering the frame, including
+    /// `engine_time`.
+    pub render_time: Duration,
+    /// CPU time spent by the render engine.
+    pub engine_time: Duration,
+    /// GPU time spent drawing the overlay into the main window. It is
+    /// measured with timestamp queries on DirectX 11, DirectX 12 and OpenGL,
+    /// and can lag a few frames behind.
+    pub gpu_time: Option<Duration>,
+    /// Whether a new frame was built, rather than the last one rendered again
+    /// because of the [frame rate limit](crate::ImguiRenderLoop::frame_rate_limit).
+    pub rebuilt: bool,
+    /// Vertices of the draw data rendered.
+    pub vertex_count: usize,
+    /// Indices of the draw data rendered.
+    pub index_count: usize,
+    /// Textures loaded by the render engine, including imgui's.
+    pub texture_count: usize,
+    /// Memory used by the textures and their mip levels, in bytes.
+    pub texture_memory: usize,
+}
+
+impl FrameStats {
+    pub(crate) fn set_textures(&mut self, sizes: impl Iterator<Item = usize>) {
+        self.texture_count = 0;
+        self.texture_memory = 0;
+        for size in sizes {
+            self.texture_count += 1;
+            self.texture_memory += size;
+        }
+    }
+}
+
+/// Timestamp queries of the recent frames, reused in turn. Their results are
+/// read right before the queries are reused, so as not to stall the GPU.
+#[cfg(any(feature = "dx11", feature = "opengl3"))]
+pub(crate) struct QueryRing<Q> {
+    queries: Vec<Q>,
+    next: usize,
+    issued: usize,
+}
+
+#[cfg(any(feature = "dx11", feature = "opengl3"))]
+impl<Q> QueryRing<Q> {
+    pub(crate) const LEN: usize = 4;
+
+    pub(crate) fn new(queries: Vec<Q>) -> Self {
+        Self { queries, next: 0, issued: 0 }
+    }
+
+    /// The queries of the frame being rendered.
+    pub(crate) fn current(&self) -> &Q {
+        &self.queries[self.next]
+    }
+
+    /// Move on to the next frame, and return its queries if they were issued
+    /// before, so that their results can be read.
+    pub(crate) fn advance(&mut self) -> Option<&Q> {
+        self.next = (self.next + 1) % self.queries.len();
+        self.issued = (self.issued + 1).min(self.queries.len());
+        (self.issued == self.queries.len()).then(|| &self.queries[self.next])
+    }
+}
+
+/// Convert an interval between GPU timestamps to a duration.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) fn ticks_to_duration(begin: u64, end: u64, frequency: u64) -> Option<Duration> {
+    (frequency > 0 && end >= begin)
+        .then(|| Duration::from_secs_f64((end - begin) as f64 / frequency as f64))
+}
+
+/// An imgui window graphing the [`FrameStats`] of the recent frames.
+///
+/// ```no_run
+/// # use hudhook::*;
+/// struct MyRenderLoop(FrameStatsWindow);
+///
+/// impl ImguiRenderLoop for MyRenderLoop {
+///     fn before_render<'a>(
+///         &'a mut self,
+///         _ctx: &mut imgui::Context,
+///         render_context: &'a mut dyn RenderContext,
+///     ) {
+///         self.0.push(render_context.frame_stats());
+///     }
+///
+///     fn render(&mut self, ui: &mut imgui::Ui) {
+///         self.0.draw(ui);
+///     }
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct FrameStatsWindow {
+    history: VecDeque<FrameStats>,
+}
+
+impl FrameStatsWindow {
+    const HISTORY_LEN: usize = 120;
+
+    /// A window without any frame recorded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the stats of a frame.
+    pub fn push(&mut self, stats: FrameStats) {
+        if self.history.len() == Self::HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(stats);
+    }
+
+    /// Draw the window, with the stats recorded so far.
+    pub fn draw(&self, ui: &Ui) {
+        ui.window("Overlay stats").always_auto_resize(true).build(|| {
+            let Some(last) = self.history.back() else {
+                ui.text("No frame recorded yet");
+                return;
+            };
+
+            let plot = |label: &str, time: fn(&FrameStats) -> Option<Duration>| {
+                let values = self
+                    .history
+                    .iter()
+                    .map(|stats| time(stats).map_or(0.0, |time| time.as_secs_f32() * 1000.0))
+                    .collect::<Vec<_>>();
+                let overlay = match time(last) {
+                    Some(time) => format!("{:.3} ms", time.as_secs_f64() * 1000.0),
+                    None => String::from("n/a"),
+                };
+                ui.plot_lines(label, &values)
+                    .overlay_text(overlay)
+                    .scale_min(0.0)
+                    .graph_size([240.0, 40.0])
+                    .build();
+            };
+
+            plot("Prepare (CPU)", |stats| Some(stats.prepare_time));
+            plot("Render (CPU)", |stats| Some(stats.render_time));
+            plot("Engine (CPU)", |stats| Some(stats.engine_time));
+            plot("Render (GPU)", |stats| stats.gpu_time);
+
+            ui.separator();
+            ui.text(format!("Vertices: {}", last.vertex_count));
+            ui.text(format!("Indices: {}", last.index_count));
+            ui.text(format!(
+                "Textures: {} ({:.1} MiB)",
+                last.texture_count,
+                last.texture_memory as f64 / (1024.0 * 1024.0)
+            ));
+            ui.text(if last.rebuilt { "Frame rebuilt" } else { "Frame replayed" });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_textures() {
+        let mut stats = FrameStats::default();
+        stats.set_textures([1024, 65536].into_iter());
+
+        assert_eq!(stats.texture_count, 2);
+        assert_eq!(stats.texture_memory, 1024 + 65536);
+    }
+
+    #[cfg(any(feature = "dx11", feature = "opengl3"))]
+    #[test]
+    fn test_query_ring() {
+        let mut ring = QueryRing::new(vec![0, 1, 2]);
+
+        assert_eq!(*ring.current(), 0);
+        assert_eq!(ring.advance(), None);
+        assert_eq!(ring.advance(), None);
+        assert_eq!(ring.advance(), Some(&0));
+        assert_eq!(*ring.current(), 0);
+        assert_eq!(ring.advance(), Some(&1));
+    }
+
+    #[cfg(any(feature = "dx11", feature = "dx12"))]
+    #[test]
+    fn test_ticks_to_duration() {
+        assert_eq!(ticks_to_duration(1000, 3000, 1_000_000), Some(Duration::from_millis(2)));
+        assert_eq!(ticks_to_duration(3000, 1000, 1_000_000), None);
+        assert_eq!(ticks_to_duration(1000, 3000, 0), None);
+    }
+}
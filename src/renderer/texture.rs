@@ -0,0 +1,328 @@
+//! Formats and layouts of the textures loaded by the render loop.
+
+#[cfg(feature = "opengl3")]
+use std::borrow::Cow;
+
+use tracing::error;
+use windows::core::{Error, Result, HRESULT};
+#[cfg(feature = "dx9")]
+use windows::Win32::Graphics::Direct3D9::*;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+/// Pixel formats of the textures loaded with
+/// [`RenderContext::load_texture_with_desc`](crate::RenderContext::load_texture_with_desc).
+///
+/// Single and two channels formats are sampled as red and green, as in
+/// DirectX. Block compressed formats are supported natively by DirectX 11 and
+/// 12, by DirectX 9 up to BC3, and by OpenGL depending on the driver's
+/// extensions. Other formats are converted on the CPU where needed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    /// 8 bits per channel, RGBA order.
+    Rgba8,
+    /// 8 bits per channel, BGRA order, as in most screenshots.
+    Bgra8,
+    /// A single 8 bits channel.
+    R8,
+    /// Two 8 bits channels.
+    Rg8,
+    /// 16 bits floats per channel, RGBA order.
+    Rgba16Float,
+    /// Also known as DXT1.
+    Bc1,
+    /// Also known as DXT3.
+    Bc2,
+    /// Also known as DXT5.
+    Bc3,
+    /// A single channel, also known as ATI1.
+    Bc4,
+    /// Two channels, also known as ATI2.
+    Bc5,
+    /// Unsigned HDR colors.
+    Bc6h,
+    /// High quality RGBA colors.
+    Bc7,
+}
+
+impl TextureFormat {
+    /// Whether pixels are stored in blocks of 4x4.
+    pub fn is_compressed(self) -> bool {
+        !matches!(
+            self,
+            TextureFormat::Rgba8
+                | TextureFormat::Bgra8
+                | TextureFormat::R8
+                | TextureFormat::Rg8
+                | TextureFormat::Rgba16Float
+        )
+    }
+
+    /// Size of a pixel, or of a block of pixels for compressed formats.
+    pub(crate) fn bytes_per_block(self) -> usize {
+        match self {
+            TextureFormat::R8 => 1,
+            TextureFormat::Rg8 => 2,
+            TextureFormat::Rgba8 | TextureFormat::Bgra8 => 4,
+            TextureFormat::Rgba16Float | TextureFormat::Bc1 | TextureFormat::Bc4 => 8,
+            _ => 16,
+        }
+    }
+
+    fn block_dim(self) -> u32 {
+        if self.is_compressed() {
+            4
+        } else {
+            1
+        }
+    }
+
+    #[cfg(any(feature = "dx11", feature = "dx12"))]
+    pub(crate) fn to_dxgi(self) -> DXGI_FORMAT {
+        match self {
+            TextureFormat::Rgba8 => DXGI_FORMAT_R8G8B8A8_UNORM,
+            TextureFormat::Bgra8 => DXGI_FORMAT_B8G8R8A8_UNORM,
+            TextureFormat::R8 => DXGI_FORMAT_R8_UNORM,
+            TextureFormat::Rg8 => DXGI_FORMAT_R8G8_UNORM,
+            TextureFormat::Rgba16Float => DXGI_FORMAT_R16G16B16A16_FLOAT,
+            TextureFormat::Bc1 => DXGI_FORMAT_BC1_UNORM,
+            TextureFormat::Bc2 => DXGI_FORMAT_BC2_UNORM,
+            TextureFormat::Bc3 => DXGI_FORMAT_BC3_UNORM,
+            TextureFormat::Bc4 => DXGI_FORMAT_BC4_UNORM,
+            TextureFormat::Bc5 => DXGI_FORMAT_BC5_UNORM,
+            TextureFormat::Bc6h => DXGI_FORMAT_BC6H_UF16,
+            TextureFormat::Bc7 => DXGI_FORMAT_BC7_UNORM,
+        }
+    }
+
+    /// The D3D9 format storing the texture as it is, if any.
+    #[cfg(feature = "dx9")]
+    pub(crate) fn to_d3d9(self) -> Option<D3DFORMAT> {
+        match self {
+            TextureFormat::Bgra8 => Some(D3DFMT_A8R8G8B8),
+            TextureFormat::Rgba16Float => Some(D3DFMT_A16B16G16R16F),
+            TextureFormat::Bc1 => Some(D3DFMT_DXT1),
+            TextureFormat::Bc2 => Some(D3DFMT_DXT3),
+            TextureFormat::Bc3 => Some(D3DFMT_DXT5),
+            _ => None,
+        }
+    }
+
+    /// Convert a pixel of an uncompressed 8 bits format to BGRA, for backends
+    /// which can't store the format as it is.
+    #[cfg(feature = "dx9")]
+    pub(crate) fn to_bgra8(self, pixel: &[u8]) -> Option<[u8; 4]> {
+        match self {
+            TextureFormat::Rgba8 => Some([pixel[2], pixel[1], pixel[0], pixel[3]]),
+            TextureFormat::Bgra8 => Some([pixel[0], pixel[1], pixel[2], pixel[3]]),
+            TextureFormat::R8 => Some([0, 0, pixel[0], 0xff]),
+            TextureFormat::Rg8 => Some([0, pixel[1], pixel[0], 0xff]),
+            _ => None,
+        }
+    }
+}
+
+/// The layout of the data of a texture.
+///
+/// Mip levels follow one another in the data, from the largest one. Rows of
+/// pixels, or of blocks of 4x4 pixels for compressed formats, are `row_pitch`
+/// bytes apart in the largest level, and tightly packed in the smaller ones, as
+/// in DDS files.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TextureDesc {
+    /// Width of the largest level, in pixels.
+    pub width: u32,
+    /// Height of the largest level, in pixels.
+    pub height: u32,
+    /// Format of the pixels.
+    pub format: TextureFormat,
+    /// Bytes between the rows of the largest level, or `None` if they are
+    /// tightly packed.
+    pub row_pitch: Option<u32>,
+    /// Number of mip levels in the data, each half the size of the previous
+    /// one.
+    pub mip_levels: u32,
+}
+
+impl TextureDesc {
+    /// A texture with tightly packed rows and a single mip level.
+    pub fn new(width: u32, height: u32, format: TextureFormat) -> Self {
+        Self { width, height, format, row_pitch: None, mip_levels: 1 }
+    }
+
+    /// Split `data` into mip levels, after checking that it matches the
+    /// description.
+    pub(crate) fn mip_levels<'a>(&self, data: &'a [u8]) -> Result<Vec<MipLevel<'a>>> {
+        let block_dim = self.format.block_dim();
+        let max_mip_levels = u32::BITS - self.width.max(self.height).leading_zeros();
+
+        if self.width == 0 || self.height == 0 {
+            error!("Texture size {}x{} is empty", self.width, self.height);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+        if self.width % block_dim != 0 || self.height % block_dim != 0 {
+            error!(
+                "Texture size {}x{} is not a multiple of {block_dim} for {:?}",
+                self.width, self.height, self.format
+            );
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+        if self.mip_levels == 0 || self.mip_levels > max_mip_levels {
+            error!("Texture can't have {} mip levels", self.mip_levels);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let mut levels = Vec::with_capacity(self.mip_levels as usize);
+        let mut offset = 0;
+        for level in 0..self.mip_levels {
+            let width = (self.width >> level).max(1);
+            let height = (self.height >> level).max(1);
+            let row_size = width.div_ceil(block_dim) as usize * self.format.bytes_per_block();
+            let rows = height.div_ceil(block_dim) as usize;
+            let row_pitch = match self.row_pitch {
+                Some(row_pitch) if level == 0 => row_pitch as usize,
+                _ => row_size,
+            };
+
+            if row_pitch < row_size {
+                error!("Texture row pitch {row_pitch} is smaller than its rows of {row_size}");
+                return Err(Error::from_hresult(HRESULT(-1)));
+            }
+
+            let size = (rows - 1) * row_pitch + row_size;
+            let Some(level_data) = data.get(offset..offset + size) else {
+                error!(
+                    "Texture data of {} bytes is too small for its mip level {level}",
+                    data.len()
+                );
+                return Err(Error::from_hresult(HRESULT(-1)));
+            };
+
+            levels.push(MipLevel { width, height, row_pitch, rows, row_size, data: level_data });
+            offset += rows * row_pitch;
+        }
+
+        Ok(levels)
+    }
+
+    /// Bytes taken by the mip levels with tightly packed rows.
+    pub(crate) fn size(&self) -> usize {
+        let block_dim = self.format.block_dim();
+        (0..self.mip_levels)
+            .map(|level| {
+                let width = (self.width >> level).max(1).div_ceil(block_dim) as usize;
+                let height = (self.height >> level).max(1).div_ceil(block_dim) as usize;
+                width * height * self.format.bytes_per_block()
+            })
+            .sum()
+    }
+}
+
+/// A mip level of a texture's data.
+pub(crate) struct MipLevel<'a> {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// Bytes between the rows of pixels, or of blocks.
+    pub(crate) row_pitch: usize,
+    /// Number of rows of pixels, or of blocks.
+    pub(crate) rows: usize,
+    /// Bytes of pixels, or of blocks, in a row.
+    pub(crate) row_size: usize,
+    pub(crate) data: &'a [u8],
+}
+
+impl<'a> MipLevel<'a> {
+    pub(crate) fn rows(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        (0..self.rows).map(|row| &self.data[row * self.row_pitch..][..self.row_size])
+    }
+
+    /// The data without padding between rows.
+    #[cfg(feature = "opengl3")]
+    pub(crate) fn tightly_packed(&self) -> Cow<'a, [u8]> {
+        if self.row_pitch == self.row_size {
+            Cow::Borrowed(self.data)
+        } else {
+            Cow::Owned(self.rows().flatten().copied().collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mip_levels() {
+        let desc = TextureDesc { mip_levels: 3, ..TextureDesc::new(4, 2, TextureFormat::Rgba8) };
+        let data = (0..(4 * 2 + 2 + 1) * 4).map(|i| i as u8).collect::<Vec<_>>();
+        let levels = desc.mip_levels(&data).unwrap();
+
+        let sizes = levels.iter().map(|l| (l.width, l.height, l.rows, l.row_size));
+        assert_eq!(sizes.collect::<Vec<_>>(), [(4, 2, 2, 16), (2, 1, 1, 8), (1, 1, 1, 4)]);
+        assert_eq!(levels[1].data, &data[32..40]);
+        assert_eq!(levels[2].data, &data[40..44]);
+    }
+
+    #[cfg(feature = "opengl3")]
+    #[test]
+    fn test_mip_levels_row_pitch() {
+        let desc = TextureDesc { row_pitch: Some(4), ..TextureDesc::new(3, 2, TextureFormat::R8) };
+        let data = [1, 2, 3, 0, 4, 5, 6];
+        let levels = desc.mip_levels(&data).unwrap();
+
+        assert_eq!(levels[0].rows().collect::<Vec<_>>(), [[1, 2, 3], [4, 5, 6]]);
+        assert_eq!(&*levels[0].tightly_packed(), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_mip_levels_compressed() {
+        let desc = TextureDesc { mip_levels: 4, ..TextureDesc::new(8, 8, TextureFormat::Bc1) };
+        let data = vec![0; (4 + 1 + 1 + 1) * 8];
+        let levels = desc.mip_levels(&data).unwrap();
+
+        let sizes = levels.iter().map(|l| (l.width, l.rows, l.row_size));
+        assert_eq!(sizes.collect::<Vec<_>>(), [(8, 2, 16), (4, 1, 8), (2, 1, 8), (1, 1, 8)]);
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(TextureDesc::new(4, 2, TextureFormat::Rgba8).size(), 32);
+        assert_eq!(TextureDesc::new(4, 2, TextureFormat::R8).size(), 8);
+        assert_eq!(TextureDesc::new(4, 2, TextureFormat::Rgba16Float).size(), 64);
+
+        let desc = TextureDesc { mip_levels: 3, ..TextureDesc::new(4, 2, TextureFormat::Rg8) };
+        assert_eq!(desc.size(), (8 + 2 + 1) * 2);
+        let desc = TextureDesc { mip_levels: 4, ..TextureDesc::new(8, 8, TextureFormat::Bc1) };
+        assert_eq!(desc.size(), (4 + 1 + 1 + 1) * 8);
+        let desc =
+            TextureDesc { row_pitch: Some(64), ..TextureDesc::new(8, 8, TextureFormat::Bc7) };
+        assert_eq!(desc.size(), 4 * 16);
+    }
+
+    #[test]
+    fn test_mip_levels_invalid() {
+        let data = vec![0; 1024];
+
+        let desc = TextureDesc::new(6, 4, TextureFormat::Bc7);
+        assert!(desc.mip_levels(&data).is_err());
+
+        let desc = TextureDesc { mip_levels: 4, ..TextureDesc::new(4, 4, TextureFormat::Rgba8) };
+        assert!(desc.mip_levels(&data).is_err());
+
+        let desc =
+            TextureDesc { row_pitch: Some(12), ..TextureDesc::new(4, 4, TextureFormat::Rgba8) };
+        assert!(desc.mip_levels(&data).is_err());
+
+        let desc = TextureDesc::new(32, 32, TextureFormat::Rgba8);
+        assert!(desc.mip_levels(&data).is_err());
+    }
+
+    #[cfg(feature = "dx9")]
+    #[test]
+    fn test_to_bgra8() {
+        assert_eq!(TextureFormat::Rgba8.to_bgra8(&[1, 2, 3, 4]), Some([3, 2, 1, 4]));
+        assert_eq!(TextureFormat::R8.to_bgra8(&[7]), Some([0, 0, 7, 0xff]));
+        assert_eq!(TextureFormat::Rg8.to_bgra8(&[7, 8]), Some([0, 8, 7, 0xff]));
+        assert_eq!(TextureFormat::Bc1.to_bgra8(&[0; 8]), None);
+    }
+}
@@ -0,0 +1,523 @@
+//! Support for imgui's multiple viewports, enabled by the `imgui-docking`
+//! feature.
+//!
+//! Viewports other than the main one are backed by Win32 windows owned by the
+//! main window. The platform callbacks manage those windows directly, while
+//! the renderer callbacks only queue events: the pipeline forwards them to its
+//! [`RenderEngine`] after imgui is done updating the platform windows, and then
+//! renders each viewport.
+
+use std::mem;
+use std::sync::Arc;
+
+use imgui::{
+    sys, BackendFlags, ConfigFlags, Context, PlatformMonitor, PlatformViewportBackend,
+    RendererViewportBackend, Viewport, ViewportFlags,
+};
+use parking_lot::Mutex;
+use tracing::{debug, error};
+use windows::core::{w, Error, HSTRING, PCWSTR};
+use windows::Win32::Foundation::{
+    BOOL, COLORREF, FALSE, HINSTANCE, HWND, LPARAM, LRESULT, POINT, RECT, TRUE, WPARAM,
+};
+use windows::Win32::Graphics::Gdi::{
+    ClientToScreen, EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Input::KeyboardAndMouse::{GetCapture, ReleaseCapture, SetFocus};
+use windows::Win32::UI::WindowsAndMessaging::*;
+
+use super::pipeline::{PipelineMessage, PIPELINE_STATES};
+use crate::renderer::RenderEngine;
+
+const VIEWPORT_CLASS: PCWSTR = w!("HUDHOOK_VIEWPORT");
+
+enum ViewportEvent {
+    Create(HWND, [f32; 2]),
+    Resize(HWND, [f32; 2]),
+    Destroy(HWND),
+    DestroyWindow(HWND),
+}
+
+type ViewportEvents = Arc<Mutex<Vec<ViewportEvent>>>;
+
+fn viewport_hwnd(viewport: &Viewport) -> HWND {
+    HWND(viewport.platform_handle as isize)
+}
+
+fn module_handle() -> HINSTANCE {
+    unsafe { GetModuleHandleW(None) }.map(Into::into).unwrap_or_default()
+}
+
+fn window_style(flags: ViewportFlags) -> (WINDOW_STYLE, WINDOW_EX_STYLE) {
+    let style =
+        if flags.contains(ViewportFlags::NO_DECORATION) { WS_POPUP } else { WS_OVERLAPPEDWINDOW };
+
+    let mut ex_style = if flags.contains(ViewportFlags::NO_TASK_BAR_ICON) {
+        WS_EX_TOOLWINDOW
+    } else {
+        WS_EX_APPWINDOW
+    };
+
+    if flags.contains(ViewportFlags::TOP_MOST) {
+        ex_style |= WS_EX_TOPMOST;
+    }
+
+    (style, ex_style)
+}
+
+// Compute the window rectangle for a client area at `pos` of size `size`.
+fn window_rect(flags: ViewportFlags, pos: [f32; 2], size: [f32; 2]) -> RECT {
+    let (style, ex_style) = window_style(flags);
+    let mut rect = RECT {
+        left: pos[0] as i32,
+        top: pos[1] as i32,
+        right: (pos[0] + size[0]) as i32,
+        bottom: (pos[1] + size[1]) as i32,
+    };
+
+    if let Err(e) = unsafe { AdjustWindowRectEx(&mut rect, style, FALSE, ex_style) } {
+        error!("AdjustWindowRectEx: {e:?}");
+    }
+
+    rect
+}
+
+// Platform callbacks, implemented with Win32 windows.
+struct Win32Platform {
+    main_hwnd: HWND,
+    events: ViewportEvents,
+}
+
+impl PlatformViewportBackend for Win32Platform {
+    fn create_window(&mut self, viewport: &mut Viewport) {
+        let (style, ex_style) = window_style(viewport.flags);
+        let rect = window_rect(viewport.flags, viewport.pos, viewport.size);
+
+        let hwnd = unsafe {
+            CreateWindowExW(
+                ex_style,
+                VIEWPORT_CLASS,
+                w!("hudhook"),
+                style,
+                rect.left,
+                rect.top,
+                rect.right - rect.left,
+                rect.bottom - rect.top,
+                self.main_hwnd,
+                None,
+                module_handle(),
+                None,
+            )
+        };
+
+        if hwnd.0 == 0 {
+            error!("Could not create viewport window: {:?}", Error::from_win32());
+            return;
+        }
+
+        // Messages received by the viewport window are forwarded to the pipeline of
+        // the main window.
+        unsafe { SetWindowLongPtrW(hwnd, GWLP_USERDATA, self.main_hwnd.0) };
+
+        viewport.platform_handle = hwnd.0 as _;
+        viewport.platform_handle_raw = hwnd.0 as _;
+    }
+
+    fn destroy_window(&mut self, viewport: &mut Viewport) {
+        let hwnd = viewport_hwnd(viewport);
+
+        if hwnd.0 != 0 && hwnd != self.main_hwnd {
+            unsafe {
+                if GetCapture() == hwnd {
+                    ReleaseCapture().ok();
+                }
+            }
+
+            // The window is destroyed after the render engine has released its
+            // resources.
+            self.events.lock().push(ViewportEvent::DestroyWindow(hwnd));
+        }
+
+        viewport.platform_handle = std::ptr::null_mut();
+        viewport.platform_handle_raw = std::ptr::null_mut();
+    }
+
+    fn show_window(&mut self, viewport: &mut Viewport) {
+        let cmd = if viewport.flags.contains(ViewportFlags::NO_FOCUS_ON_APPEARING) {
+            SW_SHOWNA
+        } else {
+            SW_SHOW
+        };
+        unsafe { ShowWindow(viewport_hwnd(viewport), cmd) };
+    }
+
+    fn set_window_pos(&mut self, viewport: &mut Viewport, pos: [f32; 2]) {
+        let rect = window_rect(viewport.flags, pos, [0.0, 0.0]);
+        if let Err(e) = unsafe {
+            SetWindowPos(
+                viewport_hwnd(viewport),
+                None,
+                rect.left,
+                rect.top,
+                0,
+                0,
+                SWP_NOZORDER | SWP_NOSIZE | SWP_NOACTIVATE,
+            )
+        } {
+            error!("SetWindowPos: {e:?}");
+        }
+    }
+
+    fn get_window_pos(&mut self, viewport: &mut Viewport) -> [f32; 2] {
+        let mut pos = POINT::default();
+        unsafe { ClientToScreen(viewport_hwnd(viewport), &mut pos) };
+        [pos.x as f32, pos.y as f32]
+    }
+
+    fn set_window_size(&mut self, viewport: &mut Viewport, size: [f32; 2]) {
+        let rect = window_rect(viewport.flags, [0.0, 0.0], size);
+        if let Err(e) = unsafe {
+            SetWindowPos(
+                viewport_hwnd(viewport),
+                None,
+                0,
+                0,
+                rect.right - rect.left,
+                rect.bottom - rect.top,
+                SWP_NOZORDER | SWP_NOMOVE | SWP_NOACTIVATE,
+            )
+        } {
+            error!("SetWindowPos: {e:?}");
+        }
+    }
+
+    fn get_window_size(&mut self, viewport: &mut Viewport) -> [f32; 2] {
+        let mut rect = RECT::default();
+        if let Err(e) = unsafe { GetClientRect(viewport_hwnd(viewport), &mut rect) } {
+            error!("GetClientRect: {e:?}");
+        }
+        [(rect.right - rect.left) as f32, (rect.bottom - rect.top) as f32]
+    }
+
+    fn set_window_focus(&mut self, viewport: &mut Viewport) {
+        let hwnd = viewport_hwnd(viewport);
+        unsafe {
+            BringWindowToTop(hwnd).ok();
+            SetForegroundWindow(hwnd);
+            SetFocus(hwnd);
+        }
+    }
+
+    fn get_window_focus(&mut self, viewport: &mut Viewport) -> bool {
+        unsafe { GetForegroundWindow() == viewport_hwnd(viewport) }
+    }
+
+    fn get_window_minimized(&mut self, viewport: &mut Viewport) -> bool {
+        unsafe { IsIconic(viewport_hwnd(viewport)) }.as_bool()
+    }
+
+    fn set_window_title(&mut self, viewport: &mut Viewport, title: &str) {
+        if let Err(e) = unsafe { SetWindowTextW(viewport_hwnd(viewport), &HSTRING::from(title)) } {
+            error!("SetWindowTextW: {e:?}");
+        }
+    }
+
+    fn set_window_alpha(&mut self, viewport: &mut Viewport, alpha: f32) {
+        let hwnd = viewport_hwnd(viewport);
+        unsafe {
+            let ex_style = GetWindowLongPtrW(hwnd, GWL_EXSTYLE);
+            if alpha < 1.0 {
+                SetWindowLongPtrW(hwnd, GWL_EXSTYLE, ex_style | WS_EX_LAYERED.0 as isize);
+                if let Err(e) =
+                    SetLayeredWindowAttributes(hwnd, COLORREF(0), (255.0 * alpha) as u8, LWA_ALPHA)
+                {
+                    error!("SetLayeredWindowAttributes: {e:?}");
+                }
+            } else {
+                SetWindowLongPtrW(hwnd, GWL_EXSTYLE, ex_style & !(WS_EX_LAYERED.0 as isize));
+            }
+        }
+    }
+
+    fn update_window(&mut self, _viewport: &mut Viewport) {}
+
+    // Rendering is driven by the pipeline, see `Viewports::render`.
+    fn render_window(&mut self, _viewport: &mut Viewport) {}
+
+    fn swap_buffers(&mut self, _viewport: &mut Viewport) {}
+
+    fn create_vk_surface(
+        &mut self,
+        _viewport: &mut Viewport,
+        _instance: u64,
+        _out_surface: &mut u64,
+    ) -> i32 {
+        -1
+    }
+}
+
+// Renderer callbacks. The render engine belongs to the pipeline, so the
+// callbacks are queued up and processed in `Viewports::render`.
+struct QueuedRenderer {
+    main_hwnd: HWND,
+    events: ViewportEvents,
+}
+
+impl QueuedRenderer {
+    fn push(&self, viewport: &Viewport, event: impl FnOnce(HWND) -> ViewportEvent) {
+        let hwnd = viewport_hwnd(viewport);
+        if hwnd.0 != 0 && hwnd != self.main_hwnd {
+            self.events.lock().push(event(hwnd));
+        }
+    }
+}
+
+impl RendererViewportBackend for QueuedRenderer {
+    fn create_window(&mut self, viewport: &mut Viewport) {
+        self.push(viewport, |hwnd| ViewportEvent::Create(hwnd, viewport.size));
+    }
+
+    fn destroy_window(&mut self, viewport: &mut Viewport) {
+        self.push(viewport, ViewportEvent::Destroy);
+    }
+
+    fn set_window_size(&mut self, viewport: &mut Viewport, size: [f32; 2]) {
+        self.push(viewport, |hwnd| ViewportEvent::Resize(hwnd, size));
+    }
+
+    fn render_window(&mut self, _viewport: &mut Viewport) {}
+
+    fn swap_buffers(&mut self, _viewport: &mut Viewport) {}
+}
+
+unsafe extern "system" fn viewport_wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    let main_hwnd = GetWindowLongPtrW(hwnd, GWLP_USERDATA);
+
+    let shared_state =
+        PIPELINE_STATES.try_lock().and_then(|states| states.get(&main_hwnd).cloned());
+    if let Some(shared_state) = shared_state {
+        if let Err(e) = shared_state.tx.send(PipelineMessage(hwnd, msg, wparam, lparam)) {
+            error!("Could not send window message through pipeline: {e:?}");
+        }
+    }
+
+    match msg {
+        // Closing is requested to imgui, which then destroys the window.
+        WM_CLOSE => LRESULT(0),
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
+
+unsafe extern "system" fn monitor_enum_proc(
+    monitor: HMONITOR,
+    _: HDC,
+    _: *mut RECT,
+    lparam: LPARAM,
+) -> BOOL {
+    let monitors = &mut *(lparam.0 as *mut Vec<PlatformMonitor>);
+
+    let mut info =
+        MONITORINFO { cbSize: mem::size_of::<MONITORINFO>() as u32, ..Default::default() };
+    if !GetMonitorInfoW(monitor, &mut info).as_bool() {
+        return TRUE;
+    }
+
+    let rect_pos = |rect: &RECT| [rect.left as f32, rect.top as f32];
+    let rect_size =
+        |rect: &RECT| [(rect.right - rect.left) as f32, (rect.bottom - rect.top) as f32];
+
+    let platform_monitor = PlatformMonitor {
+        main_pos: rect_pos(&info.rcMonitor),
+        main_size: rect_size(&info.rcMonitor),
+        work_pos: rect_pos(&info.rcWork),
+        work_size: rect_size(&info.rcWork),
+        dpi_scale: 1.0,
+    };
+
+    // imgui expects the primary monitor to come first.
+    if info.dwFlags & MONITORINFOF_PRIMARY != 0 {
+        monitors.insert(0, platform_monitor);
+    } else {
+        monitors.push(platform_monitor);
+    }
+
+    TRUE
+}
+
+fn update_monitors(ctx: &mut Context) {
+    let mut monitors: Vec<PlatformMonitor> = Vec::new();
+    unsafe {
+        EnumDisplayMonitors(
+            None,
+            None,
+            Some(monitor_enum_proc),
+            LPARAM(&mut monitors as *mut _ as isize),
+        )
+    };
+    ctx.platform_io_mut().monitors.replace_from_slice(&monitors);
+}
+
+/// The additional viewports of a pipeline.
+pub(crate) struct Viewports {
+    main_hwnd: HWND,
+    events: ViewportEvents,
+    want_update_monitors: bool,
+}
+
+impl Viewports {
+    pub(crate) fn new(ctx: &mut Context, main_hwnd: HWND) -> Self {
+        let wndclass = WNDCLASSEXW {
+            cbSize: mem::size_of::<WNDCLASSEXW>() as u32,
+            style: CS_HREDRAW | CS_VREDRAW,
+            lpfnWndProc: Some(viewport_wnd_proc),
+            hInstance: module_handle(),
+            lpszClassName: VIEWPORT_CLASS,
+            ..Default::default()
+        };
+        // Fails harmlessly if another pipeline has already registered the class.
+        unsafe { RegisterClassExW(&wndclass) };
+
+        let events = ViewportEvents::default();
+
+        ctx.set_platform_backend(Win32Platform { main_hwnd, events: Arc::clone(&events) });
+        ctx.set_renderer_backend(QueuedRenderer { main_hwnd, events: Arc::clone(&events) });
+        ctx.io_mut().backend_flags |=
+            BackendFlags::PLATFORM_HAS_VIEWPORTS | BackendFlags::RENDERER_HAS_VIEWPORTS;
+
+        let main_viewport = ctx.main_viewport_mut();
+        main_viewport.platform_handle = main_hwnd.0 as _;
+        main_viewport.platform_handle_raw = main_hwnd.0 as _;
+
+        update_monitors(ctx);
+
+        Self { main_hwnd, events, want_update_monitors: false }
+    }
+
+    /// Whether the render loop has enabled multiple viewports.
+    pub(crate) fn enabled(ctx: &Context) -> bool {
+        ctx.io().config_flags.contains(ConfigFlags::VIEWPORTS_ENABLE)
+    }
+
+    /// Dispatch the pending messages of the viewport windows, which are
+    /// forwarded to the pipeline's message queue.
+    ///
+    /// The windows are created on the render thread, which is not guaranteed
+    /// to run a message loop.
+    pub(crate) fn dispatch_messages(&self, ctx: &Context) {
+        for hwnd in ctx.viewports().map(viewport_hwnd) {
+            if hwnd.0 == 0 || hwnd == self.main_hwnd {
+                continue;
+            }
+
+            let mut msg = MSG::default();
+            unsafe {
+                while PeekMessageW(&mut msg, hwnd, 0, 0, PM_REMOVE).as_bool() {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+        }
+    }
+
+    /// Handle a message received by the main window or by a viewport window.
+    pub(crate) fn handle_message(&mut self, ctx: &mut Context, hwnd: HWND, msg: u32) {
+        match msg {
+            WM_DISPLAYCHANGE => self.want_update_monitors = true,
+            WM_CLOSE | WM_MOVE | WM_SIZE if hwnd != self.main_hwnd => {
+                let Some(viewport) = ctx.viewports_mut().find(|v| viewport_hwnd(v) == hwnd) else {
+                    return;
+                };
+
+                match msg {
+                    WM_CLOSE => viewport.platform_request_close = true,
+                    WM_MOVE => viewport.platform_request_move = true,
+                    _ => viewport.platform_request_resize = true,
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Update the platform windows and render the additional viewports. Must
+    /// be called after the main viewport has been rendered.
+    pub(crate) fn render<T: RenderEngine>(&mut self, ctx: &mut Context, engine: &mut T) {
+        if mem::take(&mut self.want_update_monitors) {
+            update_monitors(ctx);
+        }
+
+        ctx.update_platform_windows();
+        self.process_events(engine);
+        self.render_windows(ctx, engine);
+    }
+
+    /// Render and present the additional viewports of the last frame, which is
+    /// done again when the frame is replayed.
+    pub(crate) fn render_windows<T: RenderEngine>(&self, ctx: &Context, engine: &mut T) {
+        let viewports = ctx
+            .viewports()
+            .filter(|viewport| {
+                let hwnd = viewport_hwnd(viewport);
+                hwnd.0 != 0 && hwnd != self.main_hwnd && !unsafe { IsIconic(hwnd) }.as_bool()
+            })
+            .collect::<Vec<_>>();
+
+        for viewport in &viewports {
+            let clear = !viewport.flags.contains(ViewportFlags::NO_RENDERER_CLEAR);
+            if let Err(e) =
+                engine.render_viewport(viewport_hwnd(viewport), viewport.draw_data(), clear)
+            {
+                error!("Could not render viewport: {e:?}");
+            }
+        }
+
+        for viewport in &viewports {
+            if let Err(e) = engine.present_viewport(viewport_hwnd(viewport)) {
+                error!("Could not present viewport: {e:?}");
+            }
+        }
+    }
+
+    /// Destroy all the viewport windows and their render engine resources.
+    pub(crate) fn cleanup<T: RenderEngine>(&mut self, engine: &mut T) {
+        unsafe { sys::igDestroyPlatformWindows() };
+        self.process_events(engine);
+
+        // Fails if another pipeline still has viewport windows.
+        if unsafe { UnregisterClassW(VIEWPORT_CLASS, module_handle()) }.is_err() {
+            debug!("Viewport window class still in use");
+        }
+    }
+
+    fn process_events<T: RenderEngine>(&self, engine: &mut T) {
+        for event in self.events.lock().drain(..) {
+            match event {
+                ViewportEvent::Create(hwnd, [width, height]) => {
+                    if let Err(e) = engine.create_viewport(hwnd, width as u32, height as u32) {
+                        error!("Could not create viewport: {e:?}");
+                    }
+                },
+                ViewportEvent::Resize(hwnd, [width, height]) => {
+                    if let Err(e) = engine.resize_viewport(hwnd, width as u32, height as u32) {
+                        error!("Could not resize viewport: {e:?}");
+                    }
+                },
+                ViewportEvent::Destroy(hwnd) => engine.destroy_viewport(hwnd),
+                ViewportEvent::DestroyWindow(hwnd) => unsafe {
+                    if let Err(e) = DestroyWindow(hwnd) {
+                        // The window belongs to another thread, e.g. when ejecting. Detach it
+                        // from our window procedure and let its thread close it.
+                        debug!("DestroyWindow: {e:?}");
+                        SetWindowLongPtrW(hwnd, GWLP_WNDPROC, DefWindowProcW as usize as _);
+                        PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0)).ok();
+                    }
+                },
+            }
+        }
+    }
+}
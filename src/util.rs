@@ -1,6 +1,11 @@
 //! General-purpose utilities. These are used across the [`crate`] but have
 //! proven useful in client code as well.
 
+pub mod patch;
+pub mod pe;
+pub mod pointer;
+pub mod scan;
+
 use std::ffi::{c_void, OsString};
 use std::fmt::Display;
 use std::mem::{size_of, ManuallyDrop};
@@ -10,9 +15,12 @@ use std::sync::atomic::{AtomicU64, Ordering};
 
 use parking_lot::{RwLock, RwLockReadGuard};
 use tracing::{debug, error};
-use windows::core::s;
+#[cfg(feature = "imgui-docking")]
+use windows::core::IUnknown;
+use windows::core::{s, IUnknown_Vtbl, Interface, PCSTR};
 use windows::Win32::Foundation::{HANDLE, HMODULE, HWND, MAX_PATH, RECT};
-use windows::Win32::Graphics::Direct3D::ID3DBlob;
+use windows::Win32::Graphics::Direct3D::Fxc::D3DCompile;
+use windows::Win32::Graphics::Direct3D::{ID3DBlob, ID3DInclude};
 use windows::Win32::Graphics::Direct3D12::{
     D3D12GetDebugInterface, ID3D12Debug, ID3D12Device, ID3D12Fence, ID3D12Resource,
     D3D12_FENCE_FLAG_NONE, D3D12_RESOURCE_BARRIER, D3D12_RESOURCE_BARRIER_0,
@@ -20,16 +28,25 @@ use windows::Win32::Graphics::Direct3D12::{
     D3D12_RESOURCE_BARRIER_TYPE_TRANSITION, D3D12_RESOURCE_STATES,
     D3D12_RESOURCE_TRANSITION_BARRIER,
 };
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_ALPHA_MODE_UNSPECIFIED, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_SAMPLE_DESC,
+};
 use windows::Win32::Graphics::Dxgi::{
     DXGIGetDebugInterface1, IDXGIInfoQueue, DXGI_DEBUG_ALL, DXGI_INFO_QUEUE_MESSAGE,
 };
+#[cfg(feature = "imgui-docking")]
+use windows::Win32::Graphics::Dxgi::{
+    CreateDXGIFactory1, IDXGIFactory2, IDXGISwapChain1, DXGI_SCALING_STRETCH,
+    DXGI_SWAP_CHAIN_DESC1, DXGI_SWAP_EFFECT_FLIP_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
+};
 use windows::Win32::System::LibraryLoader::{
     GetModuleFileNameW, GetModuleHandleExA, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
     GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
 };
 use windows::Win32::System::Memory::{
     VirtualQuery, MEMORY_BASIC_INFORMATION, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE,
-    PAGE_PROTECTION_FLAGS, PAGE_READONLY, PAGE_READWRITE,
+    PAGE_EXECUTE_WRITECOPY, PAGE_PROTECTION_FLAGS, PAGE_READONLY, PAGE_READWRITE, PAGE_WRITECOPY,
 };
 use windows::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
 use windows::Win32::System::Threading::{CreateEventExW, WaitForSingleObjectEx, CREATE_EVENT};
@@ -131,6 +148,42 @@ pub fn print_error_blob<D: Display, E>(msg: D) -> impl Fn((E, ID3DBlob)) -> E {
     }
 }
 
+/// Compile a HLSL shader with a `main` entry point for the `target` profile,
+/// logging the compiler's messages on failure.
+pub(crate) fn compile_shader(source: &str, target: PCSTR) -> windows::core::Result<ID3DBlob> {
+    let mut blob: Option<ID3DBlob> = None;
+    let mut err_blob: Option<ID3DBlob> = None;
+
+    let result = unsafe {
+        D3DCompile(
+            source.as_ptr() as _,
+            source.len(),
+            None,
+            None,
+            None::<&ID3DInclude>,
+            s!("main"),
+            target,
+            0,
+            0,
+            &mut blob,
+            Some(&mut err_blob),
+        )
+    };
+
+    if let Some(err_blob) = err_blob {
+        let messages = unsafe {
+            std::slice::from_raw_parts(
+                err_blob.GetBufferPointer() as *const u8,
+                err_blob.GetBufferSize(),
+            )
+        };
+        error!("Compiling shader: {}", String::from_utf8_lossy(messages));
+    }
+
+    result?;
+    Ok(blob.unwrap())
+}
+
 /// Enables the Direct3D12 debug interface.
 ///
 /// It will not panic if the interface is not available. Call this from your
@@ -183,6 +236,52 @@ pub fn win_size(hwnd: HWND) -> (i32, i32) {
     (rect.right - rect.left, rect.bottom - rect.top)
 }
 
+/// Return the current reference count of a COM object.
+///
+/// The count is only indicative, as other threads may acquire or release
+/// references at any time.
+pub(crate) fn ref_count<T: Interface>(obj: &T) -> u32 {
+    unsafe {
+        let raw = obj.as_raw();
+        let vtable = *(raw as *const *const IUnknown_Vtbl);
+        ((*vtable).AddRef)(raw);
+        ((*vtable).Release)(raw)
+    }
+}
+
+/// Create a swap chain presenting to an additional imgui viewport window.
+///
+/// `device` is the DirectX 11 device or the DirectX 12 command queue.
+#[cfg(feature = "imgui-docking")]
+pub(crate) fn create_viewport_swap_chain(
+    device: &IUnknown,
+    hwnd: HWND,
+    width: u32,
+    height: u32,
+) -> windows::core::Result<IDXGISwapChain1> {
+    unsafe {
+        let factory: IDXGIFactory2 = CreateDXGIFactory1()?;
+        factory.CreateSwapChainForHwnd(
+            device,
+            hwnd,
+            &DXGI_SWAP_CHAIN_DESC1 {
+                Width: width,
+                Height: height,
+                Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+                SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+                BufferCount: 2,
+                Scaling: DXGI_SCALING_STRETCH,
+                SwapEffect: DXGI_SWAP_EFFECT_FLIP_DISCARD,
+                AlphaMode: DXGI_ALPHA_MODE_UNSPECIFIED,
+                ..Default::default()
+            },
+            None,
+            None,
+        )
+    }
+}
+
 /// Returns the path of the current module.
 pub fn get_dll_path() -> Option<PathBuf> {
     let mut hmodule = HMODULE(0);
@@ -287,6 +386,21 @@ impl Fence {
     }
 }
 
+// If the page protection has any of these flags set, we can read from it
+const PAGE_READABLE: PAGE_PROTECTION_FLAGS = PAGE_PROTECTION_FLAGS(
+    PAGE_READONLY.0
+        | PAGE_READWRITE.0
+        | PAGE_WRITECOPY.0
+        | PAGE_EXECUTE_READ.0
+        | PAGE_EXECUTE_READWRITE.0
+        | PAGE_EXECUTE_WRITECOPY.0,
+);
+
+// If the page protection has any of these flags set, we can write to it
+const PAGE_WRITABLE: PAGE_PROTECTION_FLAGS = PAGE_PROTECTION_FLAGS(
+    PAGE_READWRITE.0 | PAGE_WRITECOPY.0 | PAGE_EXECUTE_READWRITE.0 | PAGE_EXECUTE_WRITECOPY.0,
+);
+
 /// Returns a slice of **up to** `limit` elements of type `T` starting at `ptr`.
 ///
 /// If the memory protection of some pages in this region prevents reading from
@@ -298,20 +412,49 @@ impl Fence {
 /// - Ignoring memory protection, the memory at `ptr` must be valid for at least
 ///   `limit` elements of type `T` (see [`std::slice::from_raw_parts`]).
 pub unsafe fn readable_region<T>(ptr: *const T, limit: usize) -> &'static [T] {
-    /// Check if the page pointed to by `ptr` is readable.
-    unsafe fn is_readable(
+    let len = accessible_len(ptr as _, limit * size_of::<T>(), PAGE_READABLE) / size_of::<T>();
+
+    // SAFETY:
+    // - `ptr` is a valid pointer to `limit` elements of type `T` and is properly
+    //   aligned
+    // - `len` is always less than or equal to `limit`
+    std::slice::from_raw_parts(ptr, len)
+}
+
+/// Returns a mutable slice of **up to** `limit` elements of type `T` starting
+/// at `ptr`, truncated like [`readable_region`] to the first `N` consecutive
+/// writable elements.
+///
+/// # Safety
+///
+/// See [`readable_region`]. The memory must not be otherwise referenced while
+/// the slice is used.
+pub unsafe fn writable_region<T>(ptr: *mut T, limit: usize) -> &'static mut [T] {
+    let len = accessible_len(ptr as _, limit * size_of::<T>(), PAGE_WRITABLE) / size_of::<T>();
+    std::slice::from_raw_parts_mut(ptr, len)
+}
+
+/// The number of consecutive bytes, up to `limit`, from `ptr` in pages with
+/// any of the `protection` flags.
+unsafe fn accessible_len(
+    ptr: *const c_void,
+    limit: usize,
+    protection: PAGE_PROTECTION_FLAGS,
+) -> usize {
+    if limit == 0 {
+        return 0;
+    }
+
+    /// Check if the page pointed to by `ptr` has any of the `protection` flags.
+    unsafe fn is_accessible(
         ptr: *const c_void,
+        protection: PAGE_PROTECTION_FLAGS,
         memory_basic_info: &mut MEMORY_BASIC_INFORMATION,
     ) -> bool {
-        // If the page protection has any of these flags set, we can read from it
-        const PAGE_READABLE: PAGE_PROTECTION_FLAGS = PAGE_PROTECTION_FLAGS(
-            PAGE_READONLY.0 | PAGE_READWRITE.0 | PAGE_EXECUTE_READ.0 | PAGE_EXECUTE_READWRITE.0,
-        );
-
         (unsafe {
             VirtualQuery(Some(ptr), memory_basic_info, size_of::<MEMORY_BASIC_INFORMATION>())
         } != 0)
-            && (memory_basic_info.Protect & PAGE_READABLE).0 != 0
+            && (memory_basic_info.Protect & protection).0 != 0
     }
 
     // This is probably 0x1000 (4096) bytes
@@ -323,32 +466,28 @@ pub unsafe fn readable_region<T>(ptr: *const T, limit: usize) -> &'static [T] {
     let page_align_mask = page_size_bytes - 1;
 
     // Calculate the starting address of the first and last pages that need to be
-    // readable in order to read `limit` elements of type `T` from `ptr`
+    // accessible in order to access `limit` bytes from `ptr`
     let first_page_addr = (ptr as usize) & !page_align_mask;
-    let last_page_addr = (ptr as usize + (limit * size_of::<T>()) - 1) & !page_align_mask;
+    let Some(last_addr) = (ptr as usize).checked_add(limit - 1) else {
+        // The region wraps around the address space.
+        return 0;
+    };
+    let last_page_addr = last_addr & !page_align_mask;
 
     let mut memory_basic_info = MEMORY_BASIC_INFORMATION::default();
     for page_addr in (first_page_addr..=last_page_addr).step_by(page_size_bytes) {
-        if unsafe { is_readable(page_addr as _, &mut memory_basic_info) } {
+        if unsafe { is_accessible(page_addr as _, protection, &mut memory_basic_info) } {
             continue;
         }
 
-        // If this page is not readable, we can read from `ptr`
+        // If this page is not accessible, we can access from `ptr`
         // up to (not including) the start of this page
         //
         // Note: `page_addr` can be less than `ptr` if `ptr` is not page-aligned
-        let num_readable = page_addr.saturating_sub(ptr as usize) / size_of::<T>();
-
-        // SAFETY:
-        // - `ptr` is a valid pointer to `limit` elements of type `T`
-        // - `num_readable` is always less than or equal to `limit`
-        return std::slice::from_raw_parts(ptr, num_readable);
+        return page_addr.saturating_sub(ptr as usize);
     }
 
-    // SAFETY:
-    // - `ptr` is a valid pointer to `limit` elements of type `T` and is properly
-    //   aligned
-    std::slice::from_raw_parts(ptr, limit)
+    limit
 }
 
 /// Implements a barrier to coordinate ejection of hooks
@@ -434,6 +573,45 @@ mod tests {
         let slice = unsafe { readable_region::<u8>((region as usize + PAGE_SIZE - 1) as _, 2) };
         assert_eq!(slice.len(), 1);
 
+        Ok(())
+    }
+    #[test]
+    fn test_writable_region() -> windows::core::Result<()> {
+        const PAGE_SIZE: usize = 0x1000;
+
+        let region = unsafe { VirtualAlloc(None, 2 * PAGE_SIZE, MEM_COMMIT, PAGE_READWRITE) };
+        if region.is_null() {
+            return Err(windows::core::Error::from_win32());
+        }
+
+        // Make the second page read-only
+        let mut old_protect = PAGE_PROTECTION_FLAGS::default();
+        unsafe {
+            VirtualProtect(
+                (region as usize + PAGE_SIZE) as _,
+                PAGE_SIZE,
+                PAGE_READONLY,
+                &mut old_protect,
+            )
+        }?;
+
+        let len = 2 * PAGE_SIZE / size_of::<u32>();
+        let slice = unsafe { writable_region::<u32>(region as _, len) };
+        assert_eq!(slice.len(), len / 2);
+
+        let slice = unsafe { readable_region::<u32>(region as _, len) };
+        assert_eq!(slice.len(), len);
+
+        let second_page = (region as usize + PAGE_SIZE) as *mut u8;
+        let slice = unsafe { writable_region::<u8>(second_page, 1) };
+        assert!(slice.is_empty());
+
+        let slice = unsafe { readable_region::<u8>(second_page, PAGE_SIZE) };
+        assert_eq!(slice.len(), PAGE_SIZE);
+
+        let slice = unsafe { writable_region::<u8>(second_page.wrapping_sub(1), 2) };
+        assert_eq!(slice.len(), 1);
+
         Ok(())
     }
 }
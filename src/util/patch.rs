@@ -0,0 +1,249 @@
+//! Patches of code or data, which are reverted when the library is ejected.
+//!
+//! ```no_run
+//! # use hudhook::util::patch::Patch;
+//! # unsafe fn patch(address: *mut std::ffi::c_void) -> hudhook::windows::core::Result<()> {
+//! // Skip a check by replacing it with NOPs.
+//! let patch = Patch::nop(address, 6)?;
+//! patch.enable()?;
+//! // ...
+//! patch.disable()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ffi::c_void;
+use std::sync::{Arc, Weak};
+
+use parking_lot::Mutex;
+use tracing::error;
+use windows::core::{Error, Result, HRESULT};
+use windows::Win32::System::Diagnostics::Debug::FlushInstructionCache;
+use windows::Win32::System::Memory::{
+    VirtualProtect, PAGE_EXECUTE_READWRITE, PAGE_PROTECTION_FLAGS,
+};
+use windows::Win32::System::Threading::GetCurrentProcess;
+
+use super::readable_region;
+
+// Patches to revert on ejection, in the order they were created.
+static PATCHES: Mutex<Vec<Weak<Mutex<PatchState>>>> = Mutex::new(Vec::new());
+
+struct PatchState {
+    address: usize,
+    original: Vec<u8>,
+    patched: Vec<u8>,
+    enabled: bool,
+}
+
+impl PatchState {
+    unsafe fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+        if self.enabled != enabled {
+            let bytes = if enabled { &self.patched } else { &self.original };
+            write(self.address as _, bytes)?;
+            self.enabled = enabled;
+        }
+        Ok(())
+    }
+}
+
+/// Bytes replacing the original ones at an address while enabled. The
+/// original bytes are restored when the patch is dropped, or when the library
+/// is ejected.
+pub struct Patch(Arc<Mutex<PatchState>>);
+
+impl Patch {
+    /// A disabled patch writing `bytes` at `address`. Fails if the bytes
+    /// overlap another patch, whose original bytes would be restored out of
+    /// order.
+    ///
+    /// # Safety
+    ///
+    /// `address` must be valid for `bytes.len()` bytes, and the memory must
+    /// stay valid while the patch is enabled.
+    pub unsafe fn new(address: *mut c_void, bytes: &[u8]) -> Result<Self> {
+        let original = readable_region(address as *const u8, bytes.len());
+        if bytes.is_empty() || original.len() != bytes.len() {
+            error!("Could not read {} bytes at {address:p}", bytes.len());
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let mut patches = PATCHES.lock();
+        patches.retain(|patch| patch.strong_count() > 0);
+
+        let range = address as usize..address as usize + bytes.len();
+        let overlaps = patches.iter().filter_map(Weak::upgrade).any(|patch| {
+            let patch = patch.lock();
+            patch.address < range.end && range.start < patch.address + patch.original.len()
+        });
+        if overlaps {
+            error!("Could not patch {address:p}: the bytes overlap another patch");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let state = Arc::new(Mutex::new(PatchState {
+            address: address as usize,
+            original: original.to_vec(),
+            patched: bytes.to_vec(),
+            enabled: false,
+        }));
+        patches.push(Arc::downgrade(&state));
+
+        Ok(Self(state))
+    }
+
+    /// A disabled patch writing `len` NOP instructions at `address`.
+    ///
+    /// # Safety
+    ///
+    /// See [`Patch::new`].
+    pub unsafe fn nop(address: *mut c_void, len: usize) -> Result<Self> {
+        Self::new(address, &vec![0x90; len])
+    }
+
+    /// Write the patched bytes.
+    ///
+    /// # Safety
+    ///
+    /// Other threads may be running or reading the patched memory.
+    pub unsafe fn enable(&self) -> Result<()> {
+        self.0.lock().set_enabled(true)
+    }
+
+    /// Restore the original bytes.
+    ///
+    /// # Safety
+    ///
+    /// Other threads may be running or reading the patched memory.
+    pub unsafe fn disable(&self) -> Result<()> {
+        self.0.lock().set_enabled(false)
+    }
+
+    /// Enable the patch if it is disabled and vice versa, and return whether
+    /// it is enabled.
+    ///
+    /// # Safety
+    ///
+    /// Other threads may be running or reading the patched memory.
+    pub unsafe fn toggle(&self) -> Result<bool> {
+        let mut state = self.0.lock();
+        let enabled = !state.enabled;
+        state.set_enabled(enabled)?;
+        Ok(enabled)
+    }
+
+    /// Whether the patched bytes are written.
+    pub fn is_enabled(&self) -> bool {
+        self.0.lock().enabled
+    }
+
+    /// The patched address.
+    pub fn address(&self) -> *mut c_void {
+        self.0.lock().address as _
+    }
+
+    /// The bytes at the address before the patch was created.
+    pub fn original(&self) -> Vec<u8> {
+        self.0.lock().original.clone()
+    }
+}
+
+impl Drop for Patch {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { self.disable() } {
+            error!("Could not revert the patch at {:p}: {e:?}", self.address());
+        }
+    }
+}
+
+/// Revert all the patches, last created first. This is done before the
+/// library is ejected.
+///
+/// # Safety
+///
+/// Other threads may be running or reading the patched memory.
+pub unsafe fn revert_all() {
+    let patches = PATCHES.lock().iter().filter_map(Weak::upgrade).collect::<Vec<_>>();
+
+    for patch in patches.iter().rev() {
+        let mut patch = patch.lock();
+        if let Err(e) = patch.set_enabled(false) {
+            error!("Could not revert the patch at {:#x}: {e:?}", patch.address);
+        }
+    }
+}
+
+// Write `bytes` at `address`, which may be in read-only or executable memory.
+unsafe fn write(address: *mut c_void, bytes: &[u8]) -> Result<()> {
+    let mut old_protect = PAGE_PROTECTION_FLAGS::default();
+    VirtualProtect(address, bytes.len(), PAGE_EXECUTE_READWRITE, &mut old_protect).map_err(
+        |e| {
+            error!("Could not make {address:p} writable: {e:?}");
+            e
+        },
+    )?;
+
+    (address as *mut u8).copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+
+    if let Err(e) = VirtualProtect(address, bytes.len(), old_protect, &mut old_protect) {
+        error!("Could not restore the protection of {address:p}: {e:?}");
+    }
+    if let Err(e) = FlushInstructionCache(GetCurrentProcess(), Some(address), bytes.len()) {
+        error!("Could not flush the instruction cache: {e:?}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::System::Memory::{VirtualAlloc, MEM_COMMIT, PAGE_EXECUTE_READ};
+
+    use super::*;
+
+    // Patches are reverted all at once, so they are tested together.
+    #[test]
+    fn test_patch() {
+        let page = unsafe { VirtualAlloc(None, 0x1000, MEM_COMMIT, PAGE_EXECUTE_READ) };
+        assert!(!page.is_null());
+        let bytes = |offset, len| unsafe {
+            std::slice::from_raw_parts((page as *const u8).add(offset), len).to_vec()
+        };
+
+        let patch = unsafe { Patch::new(page, &[0xC3, 0xCC]) }.unwrap();
+        assert_eq!(patch.original(), [0, 0]);
+        assert!(!patch.is_enabled());
+        assert_eq!(bytes(0, 2), [0, 0]);
+
+        unsafe { patch.enable() }.unwrap();
+        assert!(patch.is_enabled());
+        assert_eq!(bytes(0, 2), [0xC3, 0xCC]);
+
+        assert!(!unsafe { patch.toggle() }.unwrap());
+        assert_eq!(bytes(0, 2), [0, 0]);
+        assert!(unsafe { patch.toggle() }.unwrap());
+        assert_eq!(bytes(0, 2), [0xC3, 0xCC]);
+
+        drop(patch);
+        assert_eq!(bytes(0, 2), [0, 0]);
+
+        // Overlapping patches are rejected, adjacent ones are not.
+        let nops = unsafe { Patch::nop(page.byte_add(8), 4) }.unwrap();
+        assert!(unsafe { Patch::new(page.byte_add(10), &[0xC3]) }.is_err());
+        assert!(unsafe { Patch::new(page.byte_add(6), &[0xCC; 4]) }.is_err());
+        let ret = unsafe { Patch::new(page.byte_add(12), &[0xC3]) }.unwrap();
+        unsafe { nops.enable() }.unwrap();
+        unsafe { ret.enable() }.unwrap();
+        assert_eq!(bytes(8, 5), [0x90, 0x90, 0x90, 0x90, 0xC3]);
+
+        unsafe { revert_all() };
+        assert!(!nops.is_enabled());
+        assert!(!ret.is_enabled());
+        assert_eq!(bytes(8, 5), [0, 0, 0, 0, 0]);
+
+        drop(nops);
+        drop(unsafe { Patch::new(page.byte_add(10), &[0xC3]) }.unwrap());
+
+        assert!(unsafe { Patch::new(page, &[]) }.is_err());
+    }
+}
@@ -0,0 +1,620 @@
+//! Parser of PE32 and PE32+ images, either loaded as modules or read from
+//! files.
+//!
+//! ```no_run
+//! # use hudhook::util::pe::PeImage;
+//! # fn parse() -> hudhook::windows::core::Result<()> {
+//! let data = std::fs::read("hook.dll").unwrap();
+//! let image = PeImage::parse(&data)?;
+//! println!("{:?}", image.machine());
+//! for export in image.exports()? {
+//!     println!("{:?}", export.name);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ffi::c_void;
+use std::slice;
+
+use tracing::error;
+use windows::core::{Error, Result, HRESULT, HSTRING};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+
+const DIRECTORY_EXPORT: usize = 0;
+const DIRECTORY_IMPORT: usize = 1;
+const DIRECTORY_TLS: usize = 9;
+
+const SECTION_EXECUTE: u32 = 0x2000_0000;
+const FILE_DLL: u16 = 0x2000;
+
+/// The architecture an image is built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Machine {
+    /// 32 bits x86.
+    X86,
+    /// x86-64.
+    X64,
+    /// 64 bits ARM.
+    Arm64,
+    /// Another architecture, by its `IMAGE_FILE_MACHINE_*` value.
+    Other(u16),
+}
+
+impl Machine {
+    /// The architecture of this crate.
+    pub fn host() -> Self {
+        if cfg!(target_arch = "x86") {
+            Self::X86
+        } else if cfg!(target_arch = "x86_64") {
+            Self::X64
+        } else if cfg!(target_arch = "aarch64") {
+            Self::Arm64
+        } else {
+            Self::Other(0)
+        }
+    }
+}
+
+impl From<u16> for Machine {
+    fn from(machine: u16) -> Self {
+        match machine {
+            0x014C => Self::X86,
+            0x8664 => Self::X64,
+            0xAA64 => Self::Arm64,
+            machine => Self::Other(machine),
+        }
+    }
+}
+
+/// A section of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    /// The name, padded with zeros.
+    pub name: [u8; 8],
+    /// The size once loaded.
+    pub virtual_size: u32,
+    /// The address once loaded, relative to the image base.
+    pub virtual_address: u32,
+    /// The size in the file.
+    pub size_of_raw_data: u32,
+    /// The offset in the file.
+    pub pointer_to_raw_data: u32,
+    /// The `IMAGE_SCN_*` flags.
+    pub characteristics: u32,
+}
+
+impl Section {
+    /// The name, without padding.
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(self.name.len());
+        std::str::from_utf8(&self.name[..len]).unwrap_or_default()
+    }
+
+    /// Whether the section contains code.
+    pub fn is_executable(&self) -> bool {
+        self.characteristics & SECTION_EXECUTE != 0
+    }
+
+    /// Whether `rva` is in the section once loaded.
+    pub fn contains(&self, rva: u32) -> bool {
+        let size = self.virtual_size.max(self.size_of_raw_data);
+        rva.checked_sub(self.virtual_address).is_some_and(|offset| offset < size)
+    }
+}
+
+/// How a function is imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportName<'a> {
+    /// By name, with the index in the export names of the DLL where it is
+    /// likely to be found.
+    Name {
+        /// The likely index of the name.
+        hint: u16,
+        /// The name.
+        name: &'a str,
+    },
+    /// By ordinal.
+    Ordinal(u16),
+}
+
+/// A function imported by an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportedFunction<'a> {
+    /// How the function is imported.
+    pub name: ImportName<'a>,
+    /// The address of its slot in the import address table, relative to the
+    /// image base.
+    pub slot: u32,
+}
+
+/// The functions an image imports from a DLL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import<'a> {
+    /// The name of the DLL.
+    pub dll: &'a str,
+    /// The imported functions.
+    pub functions: Vec<ImportedFunction<'a>>,
+}
+
+/// What an export resolves to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportTarget<'a> {
+    /// An address relative to the image base.
+    Rva(u32),
+    /// An export of another DLL, as `dll.name` or `dll.#ordinal`.
+    Forwarder(&'a str),
+}
+
+/// A function or variable exported by an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Export<'a> {
+    /// The ordinal.
+    pub ordinal: u16,
+    /// The name, unless the export is only exported by ordinal.
+    pub name: Option<&'a str>,
+    /// What the export resolves to.
+    pub target: ExportTarget<'a>,
+    /// The address of its slot in the export address table, relative to the
+    /// image base.
+    pub slot: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    // Sections are at their raw data offsets.
+    File,
+    // Sections are at their virtual addresses.
+    Image,
+}
+
+/// A parsed PE image.
+#[derive(Debug, Clone)]
+pub struct PeImage<'a> {
+    data: &'a [u8],
+    layout: Layout,
+    is_64: bool,
+    machine: Machine,
+    characteristics: u16,
+    image_base: u64,
+    entry_point: u32,
+    size_of_image: u32,
+    size_of_headers: u32,
+    directories: Vec<(u32, u32)>,
+    sections: Vec<Section>,
+}
+
+fn invalid(what: &str) -> Error {
+    error!("Invalid PE image: {what}");
+    Error::from_hresult(HRESULT(-1))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+// The address of the entry `index` of a table of `size` bytes entries at `rva`,
+// unless it is out of the address space.
+fn entry_rva(rva: u32, index: u32, size: u32) -> Option<u32> {
+    rva.checked_add(index.checked_mul(size)?)
+}
+
+impl<'a> PeImage<'a> {
+    /// Parse the image in `data`, e.g. read from a file.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        Self::with_layout(data, Layout::File)
+    }
+
+    /// Parse the image loaded at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the base address of a module, which must stay loaded
+    /// while the image is used.
+    pub unsafe fn from_base(base: *const c_void) -> Result<PeImage<'static>> {
+        // Read the headers to find out how large the image is.
+        let headers = slice::from_raw_parts(base as *const u8, 0x40);
+        if headers.get(..2) != Some(b"MZ") {
+            return Err(invalid("no DOS header"));
+        }
+        let nt_headers = u32_at(headers, 0x3C).ok_or_else(|| invalid("no DOS header"))? as usize;
+        let headers = slice::from_raw_parts(base as *const u8, nt_headers + 0x60);
+        if headers.get(nt_headers..nt_headers + 4) != Some(b"PE\0\0") {
+            return Err(invalid("no NT headers"));
+        }
+        let size_of_image = u32_at(headers, nt_headers + 24 + 56)
+            .ok_or_else(|| invalid("truncated optional header"))?;
+
+        let data = slice::from_raw_parts(base as *const u8, size_of_image as usize);
+        PeImage::with_layout(data, Layout::Image)
+    }
+
+    /// Parse the loaded module `module`, or the executable.
+    ///
+    /// # Safety
+    ///
+    /// The module must stay loaded while the image is used.
+    pub unsafe fn from_module(module: Option<&str>) -> Result<PeImage<'static>> {
+        let module = match module {
+            Some(module) => GetModuleHandleW(&HSTRING::from(module)),
+            None => GetModuleHandleW(None),
+        }
+        .map_err(|e| {
+            error!("Could not find the module {module:?}: {e:?}");
+            e
+        })?;
+
+        Self::from_base(module.0 as _)
+    }
+
+    fn with_layout(data: &'a [u8], layout: Layout) -> Result<Self> {
+        if data.get(..2) != Some(b"MZ") {
+            return Err(invalid("no DOS header"));
+        }
+        let nt_headers = u32_at(data, 0x3C).ok_or_else(|| invalid("no DOS header"))? as usize;
+        if nt_headers.checked_add(4).and_then(|end| data.get(nt_headers..end)) != Some(b"PE\0\0") {
+            return Err(invalid("no NT headers"));
+        }
+
+        let file_header = nt_headers + 4;
+        let optional_header = file_header + 20;
+        let header = |offset| u16_at(data, file_header + offset);
+        let (Some(machine), Some(section_count), Some(optional_header_size), Some(flags)) =
+            (header(0), header(2), header(16), header(18))
+        else {
+            return Err(invalid("truncated file header"));
+        };
+
+        let is_64 = match u16_at(data, optional_header) {
+            Some(0x10B) => false,
+            Some(0x20B) => true,
+            _ => return Err(invalid("unknown optional header")),
+        };
+
+        let (image_base, directory_count, directories) = if is_64 {
+            (u64_at(data, optional_header + 24), optional_header + 108, optional_header + 112)
+        } else {
+            (
+                u32_at(data, optional_header + 28).map(u64::from),
+                optional_header + 92,
+                optional_header + 96,
+            )
+        };
+        let (
+            Some(image_base),
+            Some(entry_point),
+            Some(size_of_image),
+            Some(size_of_headers),
+            Some(directory_count),
+        ) = (
+            image_base,
+            u32_at(data, optional_header + 16),
+            u32_at(data, optional_header + 56),
+            u32_at(data, optional_header + 60),
+            u32_at(data, directory_count),
+        )
+        else {
+            return Err(invalid("truncated optional header"));
+        };
+
+        // Addresses are relative to where a module is loaded.
+        let image_base = match layout {
+            Layout::File => image_base,
+            Layout::Image => data.as_ptr() as u64,
+        };
+
+        let directories = (0..directory_count.min(16) as usize)
+            .map(|index| {
+                let directory = directories + index * 8;
+                Some((u32_at(data, directory)?, u32_at(data, directory + 4)?))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("truncated data directories"))?;
+
+        let section_table = optional_header + optional_header_size as usize;
+        let sections = (0..section_count as usize)
+            .map(|index| {
+                let section = section_table + index * 40;
+                Some(Section {
+                    name: data.get(section..section + 8)?.try_into().ok()?,
+                    virtual_size: u32_at(data, section + 8)?,
+                    virtual_address: u32_at(data, section + 12)?,
+                    size_of_raw_data: u32_at(data, section + 16)?,
+                    pointer_to_raw_data: u32_at(data, section + 20)?,
+                    characteristics: u32_at(data, section + 36)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("truncated section table"))?;
+
+        Ok(Self {
+            data,
+            layout,
+            is_64,
+            machine: machine.into(),
+            characteristics: flags,
+            image_base,
+            entry_point,
+            size_of_image,
+            size_of_headers,
+            directories,
+            sections,
+        })
+    }
+
+    /// The architecture the image is built for.
+    pub fn machine(&self) -> Machine {
+        self.machine
+    }
+
+    /// Whether the image is PE32+, rather than PE32.
+    pub fn is_64(&self) -> bool {
+        self.is_64
+    }
+
+    /// Whether the image is a DLL.
+    pub fn is_dll(&self) -> bool {
+        self.characteristics & FILE_DLL != 0
+    }
+
+    /// The address the image is loaded at, or preferably loaded at if it was
+    /// read from a file.
+    pub fn image_base(&self) -> u64 {
+        self.image_base
+    }
+
+    /// The address of the entry point relative to the image base, or 0.
+    pub fn entry_point(&self) -> u32 {
+        self.entry_point
+    }
+
+    /// The size of the image once loaded.
+    pub fn size_of_image(&self) -> u32 {
+        self.size_of_image
+    }
+
+    /// The sections, in order.
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    /// The section named `name`.
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name() == name)
+    }
+
+    /// The contents of `section`, as far as they are in the image.
+    pub fn section_data(&self, section: &Section) -> &'a [u8] {
+        let (offset, size) = match self.layout {
+            Layout::File => (section.pointer_to_raw_data, section.size_of_raw_data),
+            Layout::Image => (section.virtual_address, section.virtual_size),
+        };
+        let start = (offset as usize).min(self.data.len());
+        let end = (offset as usize).saturating_add(size as usize).min(self.data.len());
+        &self.data[start..end]
+    }
+
+    /// The offset in the image data of the address `rva`, relative to the
+    /// image base.
+    fn offset(&self, rva: u32) -> Option<usize> {
+        let offset = match self.layout {
+            Layout::Image => rva,
+            Layout::File => match self.sections.iter().find(|section| section.contains(rva)) {
+                Some(section) => {
+                    let offset = rva - section.virtual_address;
+                    if offset >= section.size_of_raw_data {
+                        return None;
+                    }
+                    section.pointer_to_raw_data.checked_add(offset)?
+                },
+                // Headers are at the same place in the file.
+                None if rva < self.size_of_headers => rva,
+                None => return None,
+            },
+        };
+
+        let offset = offset as usize;
+        (offset < self.data.len()).then_some(offset)
+    }
+
+    /// The `len` bytes at the address `rva`, relative to the image base.
+    pub fn data_at(&self, rva: u32, len: usize) -> Option<&'a [u8]> {
+        let offset = self.offset(rva)?;
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+
+    fn u16_at(&self, rva: u32) -> Option<u16> {
+        u16_at(self.data, self.offset(rva)?)
+    }
+
+    fn u32_at(&self, rva: u32) -> Option<u32> {
+        u32_at(self.data, self.offset(rva)?)
+    }
+
+    // A pointer sized value.
+    fn usize_at(&self, rva: u32) -> Option<u64> {
+        if self.is_64 {
+            u64_at(self.data, self.offset(rva)?)
+        } else {
+            self.u32_at(rva).map(u64::from)
+        }
+    }
+
+    fn str_at(&self, rva: u32) -> Option<&'a str> {
+        let data = &self.data[self.offset(rva)?..];
+        let len = data.iter().position(|&c| c == 0)?;
+        std::str::from_utf8(&data[..len]).ok()
+    }
+
+    fn directory(&self, index: usize) -> Option<(u32, u32)> {
+        self.directories.get(index).copied().filter(|&(rva, size)| rva != 0 && size != 0)
+    }
+
+    /// The functions the image imports, by DLL.
+    ///
+    /// Loaded modules whose import lookup tables were left out by the linker
+    /// only keep the addresses of their imports: their functions aren't
+    /// listed.
+    pub fn imports(&self) -> Result<Vec<Import<'a>>> {
+        let Some((directory, _)) = self.directory(DIRECTORY_IMPORT) else {
+            return Ok(Vec::new());
+        };
+        let pointer_size = if self.is_64 { 8 } else { 4 };
+        let ordinal_flag = if self.is_64 { 1 << 63 } else { 1 << 31 };
+
+        let mut imports = Vec::new();
+        for descriptor in 0.. {
+            let truncated = || invalid("truncated import descriptor");
+            let field =
+                |offset| self.u32_at(entry_rva(directory, descriptor, 20)?.checked_add(offset)?);
+            let lookup = field(0).ok_or_else(truncated)?;
+            let name = field(12).ok_or_else(truncated)?;
+            let address_table = field(16).ok_or_else(truncated)?;
+            if name == 0 {
+                break;
+            }
+
+            let dll = self.str_at(name).ok_or_else(|| invalid("truncated DLL name"))?;
+
+            // The address table holds the names until the imports are bound.
+            let lookup = match (lookup, self.layout) {
+                (0, Layout::File) => address_table,
+                (0, Layout::Image) => {
+                    imports.push(Import { dll, functions: Vec::new() });
+                    continue;
+                },
+                (lookup, _) => lookup,
+            };
+
+            let mut functions = Vec::new();
+            for index in 0.. {
+                let entry = entry_rva(lookup, index, pointer_size)
+                    .and_then(|entry| self.usize_at(entry))
+                    .ok_or_else(|| invalid("truncated import lookup table"))?;
+                if entry == 0 {
+                    break;
+                }
+
+                let name = if entry & ordinal_flag != 0 {
+                    ImportName::Ordinal(entry as u16)
+                } else {
+                    let hint = self.u16_at(entry as u32);
+                    let name = (entry as u32).checked_add(2).and_then(|name| self.str_at(name));
+                    let (Some(hint), Some(name)) = (hint, name) else {
+                        return Err(invalid("truncated import name"));
+                    };
+                    ImportName::Name { hint, name }
+                };
+
+                let slot = entry_rva(address_table, index, pointer_size)
+                    .ok_or_else(|| invalid("import address table out of range"))?;
+                functions.push(ImportedFunction { name, slot });
+            }
+
+            imports.push(Import { dll, functions });
+        }
+
+        Ok(imports)
+    }
+
+    /// The functions and variables the image exports, by ordinal.
+    pub fn exports(&self) -> Result<Vec<Export<'a>>> {
+        let Some((directory, size)) = self.directory(DIRECTORY_EXPORT) else {
+            return Ok(Vec::new());
+        };
+
+        let field = |offset| self.u32_at(directory.checked_add(offset)?);
+        let (
+            Some(base),
+            Some(function_count),
+            Some(name_count),
+            Some(functions),
+            Some(names),
+            Some(name_ordinals),
+        ) = (field(16), field(20), field(24), field(28), field(32), field(36))
+        else {
+            return Err(invalid("truncated export directory"));
+        };
+        let directory_end =
+            directory.checked_add(size).ok_or_else(|| invalid("export directory out of range"))?;
+
+        let mut exports = (0..function_count)
+            .map(|index| {
+                let slot = entry_rva(functions, index, 4)?;
+                let rva = self.u32_at(slot)?;
+                // Forwarders are strings in the export directory.
+                let target = if (directory..directory_end).contains(&rva) {
+                    ExportTarget::Forwarder(self.str_at(rva)?)
+                } else {
+                    ExportTarget::Rva(rva)
+                };
+                let ordinal = base.checked_add(index)? as u16;
+                Some(Export { ordinal, name: None, target, slot })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("truncated export address table"))?;
+
+        for index in 0..name_count {
+            let name = entry_rva(names, index, 4)
+                .and_then(|name| self.u32_at(name))
+                .and_then(|name| self.str_at(name));
+            let function =
+                entry_rva(name_ordinals, index, 2).and_then(|ordinal| self.u16_at(ordinal));
+            let (Some(name), Some(function)) = (name, function) else {
+                return Err(invalid("truncated export name table"));
+            };
+            let export = exports
+                .get_mut(function as usize)
+                .ok_or_else(|| invalid("export name of a missing function"))?;
+            export.name = Some(name);
+        }
+
+        // Unused ordinals have no address.
+        exports.retain(|export| export.target != ExportTarget::Rva(0));
+        Ok(exports)
+    }
+
+    /// The export named `name`.
+    pub fn export(&self, name: &str) -> Result<Option<Export<'a>>> {
+        Ok(self.exports()?.into_iter().find(|export| export.name == Some(name)))
+    }
+
+    /// The addresses of the TLS callbacks, relative to the image base, in the
+    /// order they are invoked.
+    pub fn tls_callbacks(&self) -> Result<Vec<u32>> {
+        let Some((directory, _)) = self.directory(DIRECTORY_TLS) else {
+            return Ok(Vec::new());
+        };
+        let pointer_size = if self.is_64 { 8 } else { 4 };
+        let rva = |address: u64| address.checked_sub(self.image_base).map(|rva| rva as u32);
+
+        let callbacks = entry_rva(directory, 3, pointer_size)
+            .and_then(|callbacks| self.usize_at(callbacks))
+            .ok_or_else(|| invalid("truncated TLS directory"))?;
+        if callbacks == 0 {
+            return Ok(Vec::new());
+        }
+        let callbacks = rva(callbacks).ok_or_else(|| invalid("TLS callbacks out of the image"))?;
+
+        let mut addresses = Vec::new();
+        for index in 0.. {
+            let callback = entry_rva(callbacks, index, pointer_size)
+                .and_then(|callback| self.usize_at(callback))
+                .ok_or_else(|| invalid("truncated TLS callbacks"))?;
+            if callback == 0 {
+                break;
+            }
+            addresses.push(rva(callback).ok_or_else(|| invalid("TLS callback out of the image"))?);
+        }
+
+        Ok(addresses)
+    }
+}
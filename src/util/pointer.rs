@@ -0,0 +1,182 @@
+//! Multi-level pointers, resolved and accessed without faulting.
+
+use std::ffi::c_void;
+use std::mem::size_of;
+
+use parking_lot::Mutex;
+use tracing::error;
+use windows::core::{Error, Result, HRESULT, HSTRING};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+
+use super::{readable_region, writable_region};
+use crate::renderer::current_frame;
+
+/// A chain of pointers and offsets, like `[[base + 0x10] + 0x48] + 0x8`,
+/// which is `PointerChain::new(base, &[0x10, 0x48, 0x8])`.
+///
+/// Each pointer of the chain is only read if its memory is readable, and the
+/// resolved address is cached while the render loop builds a frame.
+///
+/// ```no_run
+/// # use hudhook::util::pointer::PointerChain;
+/// # fn chain() -> hudhook::windows::core::Result<()> {
+/// let health = PointerChain::from_module(Some("game.exe"), &[0x1F2A30, 0x48, 0x10])?;
+/// if let Some(health) = unsafe { health.read::<f32>() } {
+///     println!("{health}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PointerChain {
+    base: usize,
+    offsets: Vec<isize>,
+    // The frame the address was resolved in, and the address.
+    cache: Mutex<Option<(u64, Option<usize>)>>,
+}
+
+impl PointerChain {
+    /// A chain starting at `base`. The first offset is added to `base`, and
+    /// each next one to the pointer read at the address so far.
+    pub fn new(base: usize, offsets: &[isize]) -> Self {
+        Self { base, offsets: offsets.to_vec(), cache: Mutex::new(None) }
+    }
+
+    /// A chain starting at the base of the loaded module `module`, or of the
+    /// executable.
+    pub fn from_module(module: Option<&str>, offsets: &[isize]) -> Result<Self> {
+        let module = match module {
+            Some(module) => unsafe { GetModuleHandleW(&HSTRING::from(module)) },
+            None => unsafe { GetModuleHandleW(None) },
+        }
+        .map_err(|e| {
+            error!("Could not find the module {module:?}: {e:?}");
+            e
+        })?;
+
+        Ok(Self::new(module.0 as usize, offsets))
+    }
+
+    /// Resolve the address the chain points to, or `None` if a pointer of the
+    /// chain can't be read.
+    pub fn resolve(&self) -> Option<*mut c_void> {
+        let mut address = self.base;
+        for (index, &offset) in self.offsets.iter().enumerate() {
+            if index > 0 {
+                address = unsafe { read_at::<usize>(address) }?;
+                if address == 0 {
+                    return None;
+                }
+            }
+            address = address.wrapping_add_signed(offset);
+        }
+
+        Some(address as _)
+    }
+
+    /// The address the chain points to, resolved at most once per frame
+    /// while the render loop builds it. Elsewhere, e.g. in hooks of game
+    /// functions, it is resolved every time.
+    pub fn address(&self) -> Option<*mut c_void> {
+        let Some(frame) = current_frame() else {
+            return self.resolve();
+        };
+        let mut cache = self.cache.lock();
+
+        match *cache {
+            Some((cached_frame, address)) if cached_frame == frame => {
+                address.map(|address| address as _)
+            },
+            _ => {
+                let address = self.resolve();
+                *cache = Some((frame, address.map(|address| address as usize)));
+                address
+            },
+        }
+    }
+
+    /// Forget the cached address, e.g. after the game reallocated an object
+    /// of the chain.
+    pub fn invalidate(&self) {
+        *self.cache.lock() = None;
+    }
+
+    /// Read a `T` at the address the chain points to, or `None` if it can't
+    /// be read.
+    ///
+    /// # Safety
+    ///
+    /// The bytes at the address must be a valid `T`.
+    pub unsafe fn read<T: Copy>(&self) -> Option<T> {
+        read_at(self.address()? as usize)
+    }
+
+    /// Write `value` at the address the chain points to.
+    ///
+    /// # Safety
+    ///
+    /// The address must hold a `T`, which nothing else references.
+    pub unsafe fn write<T: Copy>(&self, value: T) -> Result<()> {
+        let Some(address) = self.address() else {
+            error!("Could not resolve the pointer chain {self:?}");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        let region = writable_region(address as *mut u8, size_of::<T>());
+        if region.len() != size_of::<T>() {
+            error!("Could not write {} bytes at {address:p}", size_of::<T>());
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        (address as *mut T).write_unaligned(value);
+        Ok(())
+    }
+}
+
+// Read the bytes of a `T` at `address` if they are readable.
+unsafe fn read_at<T: Copy>(address: usize) -> Option<T> {
+    if address == 0 {
+        return None;
+    }
+
+    let region = readable_region(address as *const u8, size_of::<T>());
+    (region.len() == size_of::<T>()).then(|| (address as *const T).read_unaligned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let value = Box::into_raw(Box::new(42u32));
+        let inner = Box::new([0usize, 0, value as usize - 4]);
+        let outer = Box::new(inner.as_ptr() as usize);
+        let base = &*outer as *const usize as usize;
+
+        // [[outer + 0] + &inner[2]] + 4
+        let chain = PointerChain::new(base, &[0, 2 * size_of::<usize>() as isize, 4]);
+        assert_eq!(chain.resolve(), Some(value as *mut c_void));
+        assert_eq!(unsafe { chain.read::<u32>() }, Some(42));
+
+        unsafe { chain.write(7u32) }.unwrap();
+        assert_eq!(unsafe { *value }, 7);
+
+        // The null pointer at [outer + 0] + 0 isn't followed.
+        let chain = PointerChain::new(base, &[0, 0, 4]);
+        assert_eq!(chain.resolve(), None);
+        assert_eq!(unsafe { chain.read::<u32>() }, None);
+        assert!(unsafe { chain.write(7u32) }.is_err());
+
+        // Nor is an unreadable pointer.
+        let chain = PointerChain::new(usize::MAX - 0x1000, &[0, 0]);
+        assert_eq!(chain.resolve(), None);
+
+        // Nor one whose value would wrap around the address space.
+        let chain = PointerChain::new(usize::MAX - 2, &[0, 0]);
+        assert_eq!(chain.resolve(), None);
+        assert_eq!(unsafe { chain.read::<u32>() }, None);
+
+        drop(unsafe { Box::from_raw(value) });
+    }
+}
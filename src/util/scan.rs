@@ -0,0 +1,363 @@
+//! Byte pattern (AOB) scanning, e.g. to find functions of a game.
+//!
+//! Patterns are written IDA-style, as hex bytes separated by spaces, where
+//! `??` (or `?`) matches any byte and `4?` or `?8` match any byte with the
+//! given high or low nibble.
+//!
+//! ```no_run
+//! # use hudhook::util::scan::{self, Pattern};
+//! # unsafe fn find() -> hudhook::windows::core::Result<()> {
+//! // call <function>
+//! let pattern: Pattern = "48 8B 0D ?? ?? ?? ?? E8 ?? ?? ?? ?? 84 C0".parse()?;
+//! if let Some(address) = scan::find_in_module(None, &pattern)? {
+//!     let function = scan::resolve_rip_relative(address.add(7), 1, 5);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use tracing::error;
+use windows::core::{Error, Result, HRESULT};
+
+use super::pe::PeImage;
+use super::readable_region;
+
+/// A byte pattern with wildcards.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pattern {
+    bytes: Vec<u8>,
+    // Bits of each byte which must match.
+    masks: Vec<u8>,
+    // A byte without wildcards, searched for before matching the rest.
+    anchor: Option<usize>,
+}
+
+impl Pattern {
+    /// A pattern of `bytes`, where only the bits set in the corresponding
+    /// `masks` must match.
+    pub fn from_masked(bytes: &[u8], masks: &[u8]) -> Result<Self> {
+        if bytes.is_empty() || bytes.len() != masks.len() {
+            error!("Patterns need as many masks as bytes, and at least one of them");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let bytes = bytes.iter().zip(masks).map(|(byte, mask)| byte & mask).collect();
+        let anchor = masks.iter().position(|&mask| mask == 0xFF);
+        Ok(Self { bytes, masks: masks.to_vec(), anchor })
+    }
+
+    /// The length of the matched bytes.
+    // Patterns are never empty.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether the pattern matches the start of `data`.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() >= self.len()
+            && self
+                .bytes
+                .iter()
+                .zip(&self.masks)
+                .zip(data)
+                .all(|((byte, mask), data)| data & mask == *byte)
+    }
+
+    /// The offset of the first match in `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        self.find_from(haystack, 0)
+    }
+
+    /// The offsets of the matches in `haystack`, which may overlap.
+    pub fn find_iter<'a>(&'a self, haystack: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            let offset = self.find_from(haystack, start)?;
+            start = offset + 1;
+            Some(offset)
+        })
+    }
+
+    fn find_from(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        let last = haystack.len().checked_sub(self.len())?;
+        if start > last {
+            return None;
+        }
+
+        let Some(anchor) = self.anchor else {
+            return (start..=last).find(|&offset| self.matches(&haystack[offset..]));
+        };
+
+        // Look for the anchor among the bytes where it can be part of a match.
+        let candidates = &haystack[start + anchor..=last + anchor];
+        let byte = self.bytes[anchor];
+        let mut position = 0;
+        while let Some(found) = find_byte(&candidates[position..], byte) {
+            let offset = start + position + found;
+            if self.matches(&haystack[offset..]) {
+                return Some(offset);
+            }
+            position += found + 1;
+        }
+
+        None
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(pattern: &str) -> Result<Self> {
+        fn nibble(c: char) -> Option<(u8, u8)> {
+            match c {
+                '?' => Some((0, 0)),
+                c => c.to_digit(16).map(|digit| (digit as u8, 0xF)),
+            }
+        }
+
+        let (bytes, masks): (Vec<u8>, Vec<u8>) = pattern
+            .split_whitespace()
+            .map(|token| {
+                let mut chars = token.chars();
+                let (byte, mask) = match (chars.next(), chars.next(), chars.next()) {
+                    (Some('?'), None, None) => Some((0, 0)),
+                    (Some(high), Some(low), None) => {
+                        nibble(high).zip(nibble(low)).map(|((high, high_mask), (low, low_mask))| {
+                            (high << 4 | low, high_mask << 4 | low_mask)
+                        })
+                    },
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    error!("Invalid byte {token:?} in the pattern {pattern:?}");
+                    Error::from_hresult(HRESULT(-1))
+                })?;
+                Ok((byte, mask))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+
+        Self::from_masked(&bytes, &masks)
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pattern(\"{self}\")")
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (byte, mask)) in self.bytes.iter().zip(&self.masks).enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            for shift in [4, 0] {
+                match (mask >> shift) & 0xF {
+                    0 => f.write_str("?")?,
+                    _ => write!(f, "{:X}", (byte >> shift) & 0xF)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The offset of the first `byte` in `haystack`, compared 16 bytes at a time.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn find_byte(haystack: &[u8], byte: u8) -> Option<usize> {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn find_byte_sse2(haystack: &[u8], byte: u8) -> Option<usize> {
+        let needle = _mm_set1_epi8(byte as i8);
+        let chunks = haystack.chunks_exact(16);
+        let remainder = chunks.remainder();
+
+        for (index, chunk) in chunks.enumerate() {
+            let chunk = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            let found = _mm_movemask_epi8(_mm_cmpeq_epi8(chunk, needle));
+            if found != 0 {
+                return Some(index * 16 + found.trailing_zeros() as usize);
+            }
+        }
+
+        let offset = haystack.len() - remainder.len();
+        remainder.iter().position(|&b| b == byte).map(|position| offset + position)
+    }
+
+    if is_x86_feature_detected!("sse2") {
+        unsafe { find_byte_sse2(haystack, byte) }
+    } else {
+        haystack.iter().position(|&b| b == byte)
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn find_byte(haystack: &[u8], byte: u8) -> Option<usize> {
+    haystack.iter().position(|&b| b == byte)
+}
+
+/// The offset, from the start of `code`, of the target of a RIP-relative
+/// operand: its 32-bit displacement at `displacement_offset` is relative to
+/// the next instruction, `instruction_len` bytes from the start of `code`.
+pub fn rip_relative(
+    code: &[u8],
+    displacement_offset: usize,
+    instruction_len: usize,
+) -> Option<isize> {
+    let displacement = code.get(displacement_offset..displacement_offset + 4)?;
+    let displacement = i32::from_le_bytes(displacement.try_into().ok()?);
+    Some(instruction_len as isize + displacement as isize)
+}
+
+/// The target of a RIP-relative operand of the instruction at `address`. See
+/// [`rip_relative`].
+///
+/// # Safety
+///
+/// The instruction at `address` must be readable.
+pub unsafe fn resolve_rip_relative(
+    address: *const u8,
+    displacement_offset: usize,
+    instruction_len: usize,
+) -> *const u8 {
+    let displacement = address.add(displacement_offset).cast::<i32>().read_unaligned();
+    address.wrapping_add(instruction_len).wrapping_offset(displacement as isize)
+}
+
+/// The readable bytes of the executable sections of the loaded module
+/// `module`, or of the executable.
+///
+/// # Safety
+///
+/// The module must not be unloaded while the sections are used.
+pub unsafe fn executable_sections(module: Option<&str>) -> Result<Vec<&'static [u8]>> {
+    let image = PeImage::from_module(module)?;
+
+    Ok(image
+        .sections()
+        .iter()
+        .filter(|section| section.is_executable())
+        .map(|section| {
+            let data = image.section_data(section);
+            readable_region(data.as_ptr(), data.len())
+        })
+        .collect())
+}
+
+/// The address of the first match of `pattern` in the executable sections of
+/// the loaded module `module`, or of the executable.
+///
+/// # Safety
+///
+/// See [`executable_sections`].
+pub unsafe fn find_in_module(module: Option<&str>, pattern: &Pattern) -> Result<Option<*const u8>> {
+    Ok(executable_sections(module)?
+        .into_iter()
+        .find_map(|section| pattern.find(section).map(|offset| section.as_ptr().add(offset))))
+}
+
+/// The addresses of the matches of `pattern` in the executable sections of
+/// the loaded module `module`, or of the executable.
+///
+/// # Safety
+///
+/// See [`executable_sections`].
+pub unsafe fn find_all_in_module(
+    module: Option<&str>,
+    pattern: &Pattern,
+) -> Result<Vec<*const u8>> {
+    Ok(executable_sections(module)?
+        .into_iter()
+        .flat_map(|section| pattern.find_iter(section).map(|offset| section.as_ptr().add(offset)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(pattern: &str) -> Pattern {
+        pattern.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let p = pattern("48 8b ?? ? E8 4? ?c");
+        assert_eq!(p.len(), 7);
+        assert_eq!(p.bytes, [0x48, 0x8B, 0, 0, 0xE8, 0x40, 0x0C]);
+        assert_eq!(p.masks, [0xFF, 0xFF, 0, 0, 0xFF, 0xF0, 0x0F]);
+        assert_eq!(p.to_string(), "48 8B ?? ?? E8 4? ?C");
+
+        assert!("".parse::<Pattern>().is_err());
+        assert!("48 8".parse::<Pattern>().is_err());
+        assert!("48 8B0".parse::<Pattern>().is_err());
+        assert!("48 GG".parse::<Pattern>().is_err());
+        assert!(Pattern::from_masked(&[1, 2], &[0xFF]).is_err());
+    }
+
+    #[test]
+    fn test_find() {
+        let haystack = [0x90, 0x48, 0x8B, 0x05, 0x11, 0x22, 0xE8, 0x48, 0x8B, 0x0D, 0x33, 0xE8];
+
+        assert_eq!(pattern("48 8B ?? ?? ?? E8").find(&haystack), Some(1));
+        assert_eq!(pattern("48 8B ?D").find(&haystack), Some(7));
+        assert_eq!(pattern("48 8B 0? ?? ?? E8").find(&haystack), Some(1));
+        assert_eq!(pattern("?? E8").find(&haystack), Some(5));
+        assert_eq!(pattern("33 E8").find(&haystack), Some(10));
+        assert_eq!(pattern("E8 ??").find(&haystack), Some(6));
+        assert_eq!(pattern("E8 ?? ??").find(&haystack), Some(6));
+        assert_eq!(pattern("E8 ?? ?? ?? ?? ?? ??").find(&haystack), None);
+        assert_eq!(pattern("48 8B 1?").find(&haystack), None);
+        assert_eq!(pattern("?? ??").find(&[0x90]), None);
+        assert_eq!(pattern("90").find(&[]), None);
+    }
+
+    #[test]
+    fn test_find_iter() {
+        let haystack = [0xCC, 0xCC, 0xCC, 0x90, 0xCC, 0xCC];
+
+        assert_eq!(pattern("CC CC").find_iter(&haystack).collect::<Vec<_>>(), [0, 1, 4]);
+        assert_eq!(pattern("?? CC").find_iter(&haystack).collect::<Vec<_>>(), [0, 1, 3, 4]);
+        assert_eq!(pattern("CC ?? CC").find_iter(&haystack).collect::<Vec<_>>(), [0, 2]);
+    }
+
+    #[test]
+    fn test_find_long_haystack() {
+        // Matches across and at the end of the 16 bytes compared at once.
+        let mut haystack = vec![0x00; 100];
+        haystack[15..18].copy_from_slice(&[0x12, 0x34, 0x56]);
+        haystack[97..].copy_from_slice(&[0x12, 0x34, 0x57]);
+
+        let p = pattern("12 34 5?");
+        assert_eq!(p.find_iter(&haystack).collect::<Vec<_>>(), [15, 97]);
+        assert_eq!(find_byte(&haystack[18..], 0x12), Some(79));
+        assert_eq!(find_byte(&haystack, 0xFF), None);
+    }
+
+    #[test]
+    fn test_rip_relative() {
+        // lea rcx, [rip + 0x10]
+        let code = [0x48, 0x8D, 0x0D, 0x10, 0x00, 0x00, 0x00];
+        assert_eq!(rip_relative(&code, 3, 7), Some(0x17));
+
+        // call rip - 0x20
+        let code = [0xE8, 0xE0, 0xFF, 0xFF, 0xFF];
+        assert_eq!(rip_relative(&code, 1, 5), Some(-0x1B));
+        assert_eq!(unsafe { resolve_rip_relative(code.as_ptr(), 1, 5) }, {
+            code.as_ptr().wrapping_offset(-0x1B)
+        });
+
+        assert_eq!(rip_relative(&code, 2, 5), None);
+    }
+}
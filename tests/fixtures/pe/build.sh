@@ -0,0 +1,22 @@
+#!/bin/sh
+# Build the fixture DLLs with GNU binutils, which can link PE images from ELF
+# objects on any host.
+set -e
+
+cd "$(dirname "$0")"
+out=$(mktemp -d)
+trap 'rm -rf "$out"' EXIT
+
+link() {
+    # link <emulation> <as flag> <suffix>
+    as "$2" "dep$3.s" -o "$out/dep$3.o"
+    ld -m "$1" --dll --entry=0 --no-insert-timestamp "$out/dep$3.o" dep.def \
+        -o "$out/dep$3.dll" --out-implib "$out/libdep$3.a"
+
+    as "$2" "fixture$3.s" -o "$out/fixture$3.o"
+    ld -m "$1" --dll --entry=0 --no-insert-timestamp -s "$out/fixture$3.o" fixture.def \
+        "$out/libdep$3.a" -o "fixture$3.dll"
+}
+
+link i386pep --64 64
+link i386pe --32 32
@@ -0,0 +1,9 @@
+    .text
+    .globl _dep_one
+_dep_one:
+    mov $1, %eax
+    ret
+    .globl _dep_two
+_dep_two:
+    mov $2, %eax
+    ret
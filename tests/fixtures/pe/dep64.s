@@ -0,0 +1,9 @@
+    .text
+    .globl dep_one
+dep_one:
+    mov $1, %eax
+    ret
+    .globl dep_two
+dep_two:
+    mov $2, %eax
+    ret
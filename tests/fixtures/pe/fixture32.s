@@ -0,0 +1,36 @@
+    .text
+    .globl _add
+_add:
+    mov 4(%esp), %eax
+    add 8(%esp), %eax
+    ret
+    .globl _call_deps
+_call_deps:
+    call *__imp__DepOne
+    call *__imp__DepTwo
+    ret
+    .globl _hidden
+_hidden:
+    xor %eax, %eax
+    ret
+tls_callback:
+    ret $12
+
+    .data
+    .globl _answer
+_answer:
+    .long 42
+tls_index:
+    .long 0
+
+    .section .tls$,"aw"
+tls_start:
+    .long 0
+tls_end:
+
+    .section .rdata,"a"
+    .globl __tls_used
+__tls_used:
+    .long tls_start, tls_end, tls_index, tls_callbacks, 0, 0
+tls_callbacks:
+    .long tls_callback, 0
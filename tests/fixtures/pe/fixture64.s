@@ -0,0 +1,38 @@
+    .text
+    .globl add
+add:
+    lea (%rcx,%rdx), %rax
+    ret
+    .globl call_deps
+call_deps:
+    sub $40, %rsp
+    call *__imp_DepOne(%rip)
+    call *__imp_DepTwo(%rip)
+    add $40, %rsp
+    ret
+    .globl hidden
+hidden:
+    xor %eax, %eax
+    ret
+tls_callback:
+    ret
+
+    .data
+    .globl answer
+answer:
+    .long 42
+tls_index:
+    .long 0
+
+    .section .tls$,"aw"
+tls_start:
+    .quad 0
+tls_end:
+
+    .section .rdata,"a"
+    .globl _tls_used
+_tls_used:
+    .quad tls_start, tls_end, tls_index, tls_callbacks
+    .long 0, 0
+tls_callbacks:
+    .quad tls_callback, 0
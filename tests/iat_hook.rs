@@ -0,0 +1,102 @@
+use std::ffi::c_void;
+
+use hudhook::hooks::iat::{find_export, find_import, IatHook, Symbol};
+use hudhook::mh::MH_STATUS;
+use hudhook::windows::core::s;
+use hudhook::windows::Win32::System::LibraryLoader::{GetModuleHandleW, GetProcAddress};
+use hudhook::windows::Win32::System::SystemInformation::GetTickCount;
+
+type GetTickCountFn = extern "system" fn() -> u32;
+
+extern "system" fn get_tick_count_impl() -> u32 {
+    42
+}
+
+fn resolve() -> GetTickCountFn {
+    unsafe {
+        let kernel32 = GetModuleHandleW(hudhook::windows::core::w!("kernel32.dll")).unwrap();
+        std::mem::transmute(GetProcAddress(kernel32, s!("GetTickCount")).unwrap())
+    }
+}
+
+#[test]
+fn test_export_hook() {
+    let original = resolve();
+
+    let hook = unsafe {
+        IatHook::<GetTickCountFn>::export(
+            "kernel32.dll",
+            Symbol::Name("GetTickCount"),
+            get_tick_count_impl,
+        )
+    }
+    .unwrap();
+    assert_eq!(hook.original() as *const c_void, original as *const c_void);
+    assert_eq!(resolve() as *const c_void, original as *const c_void);
+
+    unsafe { hook.enable() }.unwrap();
+    assert_eq!(resolve()(), 42);
+
+    drop(hook);
+    assert_eq!(resolve() as *const c_void, original as *const c_void);
+}
+
+// The slot through which this executable calls `GetTickCount`.
+fn import_slot() -> *mut *mut c_void {
+    unsafe {
+        let base = GetModuleHandleW(None).unwrap().0 as *mut c_void;
+        find_import(base, "kernel32.dll", Symbol::Name("GetTickCount")).unwrap()
+    }
+}
+
+#[test]
+fn test_import_hook() {
+    let slot = import_slot();
+    let original = unsafe { slot.read_volatile() };
+
+    let hook = unsafe {
+        IatHook::<GetTickCountFn>::import(
+            None,
+            "kernel32.dll",
+            Symbol::Name("GetTickCount"),
+            get_tick_count_impl,
+        )
+    }
+    .unwrap();
+    assert_eq!(hook.original() as *const c_void, original as *const c_void);
+    assert_eq!(unsafe { slot.read_volatile() }, original);
+
+    unsafe { hook.enable() }.unwrap();
+    assert_eq!(unsafe { slot.read_volatile() }, get_tick_count_impl as *mut c_void);
+    assert_eq!(unsafe { GetTickCount() }, 42);
+
+    drop(hook);
+    assert_eq!(unsafe { slot.read_volatile() }, original);
+}
+
+#[test]
+fn test_find_export() {
+    unsafe {
+        let kernel32 = GetModuleHandleW(hudhook::windows::core::w!("kernel32.dll")).unwrap();
+        let base = kernel32.0 as *mut c_void;
+
+        assert!(find_export(base, Symbol::Name("GetTickCount")).is_some());
+        assert!(find_export(base, Symbol::Name("NoSuchFunction")).is_none());
+        assert!(find_export(base, Symbol::Ordinal(u16::MAX)).is_none());
+    }
+}
+
+#[test]
+fn test_import_not_found() {
+    let status = unsafe {
+        IatHook::<GetTickCountFn>::import(
+            None,
+            "nosuchmodule.dll",
+            Symbol::Name("GetTickCount"),
+            get_tick_count_impl,
+        )
+    }
+    .err()
+    .unwrap();
+    assert_eq!(status, MH_STATUS::MH_ERROR_FUNCTION_NOT_FOUND);
+}
@@ -0,0 +1,129 @@
+use std::ffi::c_void;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use hudhook::mh::{mid_hook, Context, MidHook};
+use hudhook::windows::Win32::System::Memory::{VirtualAlloc, MEM_COMMIT, PAGE_EXECUTE_READWRITE};
+
+type Function = extern "C" fn(u32) -> u32;
+
+// Load the argument into eax, or xmm0 with `movd`.
+#[cfg(target_arch = "x86_64")]
+const LOAD_EAX: &[u8] = &[0x89, 0xC8]; // mov eax, ecx
+#[cfg(target_arch = "x86_64")]
+const LOAD_XMM0: &[u8] = &[0x66, 0x0F, 0x6E, 0xC1]; // movd xmm0, ecx
+#[cfg(target_arch = "x86")]
+const LOAD_EAX: &[u8] = &[0x8B, 0x44, 0x24, 0x04]; // mov eax, [esp + 4]
+#[cfg(target_arch = "x86")]
+const LOAD_XMM0: &[u8] = &[0x66, 0x0F, 0x6E, 0x44, 0x24, 0x04]; // movd xmm0, [esp + 4]
+
+#[cfg(target_arch = "x86_64")]
+fn ax(context: &mut Context) -> &mut usize {
+    &mut context.rax
+}
+
+#[cfg(target_arch = "x86")]
+fn ax(context: &mut Context) -> &mut usize {
+    &mut context.eax
+}
+
+#[cfg(target_arch = "x86_64")]
+fn ip(context: &mut Context) -> &mut usize {
+    &mut context.rip
+}
+
+#[cfg(target_arch = "x86")]
+fn ip(context: &mut Context) -> &mut usize {
+    &mut context.eip
+}
+
+// Write a function made of `load`, then `body`, which is hooked, and return
+// it with the address of `body`.
+unsafe fn function(page: *mut u8, load: &[u8], body: &[u8]) -> (Function, *mut c_void) {
+    page.copy_from_nonoverlapping(load.as_ptr(), load.len());
+    let hooked = page.add(load.len());
+    hooked.copy_from_nonoverlapping(body.as_ptr(), body.len());
+    (std::mem::transmute(page), hooked as _)
+}
+
+fn page() -> *mut u8 {
+    let page = unsafe { VirtualAlloc(None, 0x1000, MEM_COMMIT, PAGE_EXECUTE_READWRITE) };
+    assert!(!page.is_null());
+    page as _
+}
+
+#[test]
+fn test_mid_hook_registers() {
+    let body = [
+        0x83, 0xC0, 0x01, // add eax, 1
+        0x6B, 0xC0, 0x03, // imul eax, eax, 3
+        0xC3, // ret
+    ];
+    let (f, hooked) = unsafe { function(page(), LOAD_EAX, &body) };
+    let call = |value| black_box(f)(value);
+    assert_eq!(call(2), 9);
+
+    let seen = Arc::new(AtomicUsize::new(0));
+    let hook = unsafe {
+        MidHook::new(hooked, {
+            let seen = Arc::clone(&seen);
+            move |context: &mut Context| {
+                seen.store(*ax(context), Ordering::SeqCst);
+                *ax(context) += 7;
+            }
+        })
+    }
+    .unwrap();
+    assert_eq!(hook.target(), hooked);
+    assert_eq!(call(2), 9);
+
+    unsafe { hook.enable() }.unwrap();
+    assert_eq!(call(2), 30);
+    assert_eq!(seen.load(Ordering::SeqCst), 2);
+
+    unsafe { hook.disable() }.unwrap();
+    assert_eq!(call(2), 9);
+
+    unsafe { hook.enable() }.unwrap();
+    drop(hook);
+    assert_eq!(call(2), 9);
+}
+
+#[test]
+fn test_mid_hook_xmm() {
+    let body = [
+        0xF3, 0x0F, 0x58, 0xC0, // addss xmm0, xmm0
+        0x66, 0x0F, 0x7E, 0xC0, // movd eax, xmm0
+        0xC3, // ret
+    ];
+    let (f, hooked) = unsafe { function(page(), LOAD_XMM0, &body) };
+    let call = |value: f32| f32::from_bits(black_box(f)(value.to_bits()));
+    assert_eq!(call(1.5), 3.);
+
+    let hook = unsafe {
+        mid_hook(hooked, |context: &mut Context| {
+            let [value, ..] = context.xmm[0].f32s();
+            context.xmm[0].set_f32s([value + 10., 0., 0., 0.]);
+        })
+    }
+    .unwrap();
+    assert_eq!(call(1.5), 23.);
+
+    drop(hook);
+    assert_eq!(call(1.5), 3.);
+}
+
+#[test]
+fn test_mid_hook_redirect() {
+    let body = [
+        0x83, 0xC0, 0x01, // add eax, 1
+        0x6B, 0xC0, 0x03, // imul eax, eax, 3
+        0xC3, // ret
+    ];
+    let (f, hooked) = unsafe { function(page(), LOAD_EAX, &body) };
+
+    // Skip to the ret.
+    let _hook = unsafe { mid_hook(hooked, |context: &mut Context| *ip(context) += 6) }.unwrap();
+    assert_eq!(black_box(f)(2), 2);
+}
@@ -0,0 +1,201 @@
+// The fixtures are built by `tests/fixtures/pe/build.sh`.
+
+use hudhook::util::pe::{
+    Export, ExportTarget, Import, ImportName, ImportedFunction, Machine, PeImage,
+};
+use hudhook::windows::core::w;
+use hudhook::windows::Win32::Foundation::FreeLibrary;
+use hudhook::windows::Win32::System::LibraryLoader::{LoadLibraryExW, DONT_RESOLVE_DLL_REFERENCES};
+
+const FIXTURE64: &[u8] = include_bytes!("fixtures/pe/fixture64.dll");
+const FIXTURE32: &[u8] = include_bytes!("fixtures/pe/fixture32.dll");
+
+struct Expected {
+    machine: Machine,
+    image_base: u64,
+    text: &'static [u8],
+    call_deps: u32,
+    hidden: u32,
+    import_slots: [u32; 2],
+    tls_callback: u32,
+}
+
+const EXPECTED64: Expected = Expected {
+    machine: Machine::X64,
+    image_base: 0x1_8000_0000,
+    // lea rax, [rcx + rdx]
+    text: &[0x48, 0x8D, 0x04, 0x11, 0xC3],
+    call_deps: 0x1005,
+    hidden: 0x101A,
+    import_slots: [0x5040, 0x5048],
+    tls_callback: 0x101D,
+};
+
+const EXPECTED32: Expected = Expected {
+    machine: Machine::X86,
+    image_base: 0x1000_0000,
+    // mov eax, [esp + 4]
+    text: &[0x8B, 0x44, 0x24, 0x04],
+    call_deps: 0x1009,
+    hidden: 0x1016,
+    import_slots: [0x5034, 0x5038],
+    tls_callback: 0x1019,
+};
+
+fn exports(expected: &Expected) -> Vec<Export<'static>> {
+    let export = |ordinal, name, target| Export {
+        ordinal,
+        name,
+        target,
+        slot: 0x4028 + (ordinal as u32 - 1) * 4,
+    };
+
+    vec![
+        export(1, Some("Add"), ExportTarget::Rva(0x1000)),
+        export(2, Some("Answer"), ExportTarget::Rva(0x2000)),
+        export(3, Some("CallDeps"), ExportTarget::Rva(expected.call_deps)),
+        export(4, Some("Forwarded"), ExportTarget::Forwarder("dep.DepOne")),
+        export(5, None, ExportTarget::Rva(expected.hidden)),
+    ]
+}
+
+fn imports(expected: &Expected) -> Vec<Import<'static>> {
+    vec![Import {
+        dll: "dep.dll",
+        functions: vec![
+            ImportedFunction {
+                name: ImportName::Name { hint: 6, name: "DepOne" },
+                slot: expected.import_slots[0],
+            },
+            ImportedFunction { name: ImportName::Ordinal(7), slot: expected.import_slots[1] },
+        ],
+    }]
+}
+
+fn check_fixture(data: &[u8], expected: &Expected) {
+    let image = PeImage::parse(data).unwrap();
+
+    assert_eq!(image.machine(), expected.machine);
+    assert_eq!(image.is_64(), expected.machine == Machine::X64);
+    assert!(image.is_dll());
+    assert_eq!(image.image_base(), expected.image_base);
+    assert_eq!(image.entry_point(), 0);
+    assert_eq!(image.size_of_image(), 0x8000);
+
+    let sections = image.sections().iter().map(|section| section.name()).collect::<Vec<_>>();
+    assert_eq!(sections, [".text", ".data", ".rdata", ".edata", ".idata", ".tls", ".reloc"]);
+
+    let text = image.section(".text").unwrap();
+    assert!(text.is_executable());
+    assert!(image.section_data(text).starts_with(expected.text));
+    assert!(!image.section(".data").unwrap().is_executable());
+    assert_eq!(image.data_at(0x2000, 4), Some(&42u32.to_le_bytes()[..]));
+
+    assert_eq!(image.exports().unwrap(), exports(expected));
+    assert_eq!(image.export("Answer").unwrap().map(|export| export.ordinal), Some(2));
+    assert_eq!(image.export("Missing").unwrap(), None);
+    assert_eq!(image.imports().unwrap(), imports(expected));
+    assert_eq!(image.tls_callbacks().unwrap(), [expected.tls_callback]);
+}
+
+#[test]
+fn test_parse_pe32_plus() {
+    check_fixture(FIXTURE64, &EXPECTED64);
+}
+
+#[test]
+fn test_parse_pe32() {
+    check_fixture(FIXTURE32, &EXPECTED32);
+}
+
+// The 64 bits fixture with `bytes` written at `offset`.
+fn patched(offset: usize, bytes: &[u8]) -> Vec<u8> {
+    let mut data = FIXTURE64.to_vec();
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    data
+}
+
+// The offset in the 64 bits fixture of the address `rva`.
+fn file_offset(rva: u32) -> usize {
+    let image = PeImage::parse(FIXTURE64).unwrap();
+    let section = image.sections().iter().find(|section| section.contains(rva)).unwrap();
+    (section.pointer_to_raw_data + rva - section.virtual_address) as usize
+}
+
+#[test]
+fn test_parse_invalid() {
+    assert!(PeImage::parse(&[]).is_err());
+    assert!(PeImage::parse(b"MZ").is_err());
+    assert!(PeImage::parse(&FIXTURE64[..0x100]).is_err());
+
+    let mut data = FIXTURE64.to_vec();
+    data[0x3C] = 0xFF;
+    assert!(PeImage::parse(&data).is_err());
+
+    let nt_headers = u32::from_le_bytes(FIXTURE64[0x3C..0x40].try_into().unwrap()) as usize;
+    let optional_header_size =
+        u16::from_le_bytes([FIXTURE64[nt_headers + 20], FIXTURE64[nt_headers + 21]]);
+    let export_directory = nt_headers + 24 + 112;
+    let rdata_section = nt_headers + 24 + optional_header_size as usize + 2 * 40;
+
+    // Addresses and sizes reaching past the end of the address space.
+    let max = |offset: u32| (u32::MAX - offset).to_le_bytes();
+
+    let data = patched(rdata_section + 20, &max(0));
+    assert_eq!(PeImage::parse(&data).unwrap().data_at(0x3008, 4), None);
+
+    let data = patched(export_directory + 4, &max(0));
+    assert!(PeImage::parse(&data).unwrap().exports().is_err());
+    for field in [0x10, 0x1C, 0x20, 0x24] {
+        let data = patched(file_offset(0x4000 + field), &max(2));
+        assert!(PeImage::parse(&data).unwrap().exports().is_err());
+    }
+
+    for field in [0, 12, 16] {
+        let data = patched(file_offset(0x5000 + field), &max(6));
+        assert!(PeImage::parse(&data).unwrap().imports().is_err());
+    }
+
+    let callbacks = EXPECTED64.image_base + u64::from(u32::MAX - 4);
+    let data = patched(file_offset(0x3000 + 24), &callbacks.to_le_bytes());
+    assert!(PeImage::parse(&data).unwrap().tls_callbacks().is_err());
+}
+
+#[test]
+fn test_loaded_module() {
+    let image = unsafe { PeImage::from_module(Some("kernel32.dll")) }.unwrap();
+    assert_eq!(image.machine(), Machine::host());
+    assert!(image.sections().iter().any(|section| section.is_executable()));
+
+    let exports = image.exports().unwrap();
+    let get_tick_count = exports.iter().find(|export| export.name == Some("GetTickCount"));
+    assert!(matches!(get_tick_count.unwrap().target, ExportTarget::Rva(_)));
+    assert!(exports.iter().any(|export| matches!(export.target, ExportTarget::Forwarder(_))));
+
+    let image = unsafe { PeImage::from_module(None) }.unwrap();
+    assert!(!image.is_dll());
+    assert!(!image.imports().unwrap().is_empty());
+}
+
+#[test]
+fn test_mapped_fixture() {
+    let (data, expected, path) = match Machine::host() {
+        Machine::X64 => (FIXTURE64, &EXPECTED64, w!("tests/fixtures/pe/fixture64.dll")),
+        Machine::X86 => (FIXTURE32, &EXPECTED32, w!("tests/fixtures/pe/fixture32.dll")),
+        _ => return,
+    };
+
+    // Map the fixture without loading dep.dll or running any code.
+    let module = unsafe { LoadLibraryExW(path, None, DONT_RESOLVE_DLL_REFERENCES) }.unwrap();
+    let image = unsafe { PeImage::from_base(module.0 as _) }.unwrap();
+    let file = PeImage::parse(data).unwrap();
+
+    assert_eq!(image.image_base(), module.0 as u64);
+    assert_eq!(image.sections(), file.sections());
+    assert_eq!(image.data_at(0x2000, 4), file.data_at(0x2000, 4));
+    assert_eq!(image.exports().unwrap(), exports(expected));
+    assert_eq!(image.imports().unwrap(), imports(expected));
+    assert_eq!(image.tls_callbacks().unwrap(), [expected.tls_callback]);
+
+    unsafe { FreeLibrary(module) }.unwrap();
+}
@@ -0,0 +1,115 @@
+use std::hint::black_box;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use hudhook::mh::{hook_api, hook_fn, TypedHook, MH_STATUS};
+
+type Sum = extern "system" fn(*const u32, usize) -> u32;
+type Product = extern "C" fn(u64, u64) -> u64;
+
+static SUM_HOOK: OnceLock<TypedHook<Sum>> = OnceLock::new();
+
+#[inline(never)]
+extern "system" fn sum(values: *const u32, len: usize) -> u32 {
+    let values = unsafe { std::slice::from_raw_parts(values, len) };
+    values.iter().fold(0u32, |acc, &value| acc.wrapping_add(black_box(value)))
+}
+
+extern "system" fn sum_impl(values: *const u32, len: usize) -> u32 {
+    SUM_HOOK.get().unwrap().original()(values, len) + 1000
+}
+
+#[test]
+fn test_typed_hook() {
+    let values = [1, 2, 3];
+    let call = || black_box(sum as Sum)(values.as_ptr(), values.len());
+
+    let hook = unsafe { TypedHook::<Sum>::new(sum, sum_impl) }.unwrap();
+    let hook = SUM_HOOK.get_or_init(|| hook);
+    assert_eq!(call(), 6);
+
+    unsafe { hook.enable() }.unwrap();
+    assert_eq!(call(), 1006);
+    assert_eq!(hook.original()(values.as_ptr(), values.len()), 6);
+
+    unsafe { hook.disable() }.unwrap();
+    assert_eq!(call(), 6);
+}
+
+#[test]
+fn test_typed_hook_drop() {
+    #[inline(never)]
+    extern "C" fn product(a: u64, b: u64) -> u64 {
+        (0..black_box(b)).fold(0, |acc, _| acc + black_box(a))
+    }
+
+    extern "C" fn product_impl(_: u64, _: u64) -> u64 {
+        0
+    }
+
+    let call = || black_box(product as Product)(6, 7);
+
+    let hook = unsafe { TypedHook::<Product>::new(product, product_impl) }.unwrap();
+    unsafe { hook.enable() }.unwrap();
+    assert_eq!(call(), 0);
+
+    drop(hook);
+    assert_eq!(call(), 42);
+}
+
+#[test]
+fn test_closure_hook() {
+    type Scale = extern "system" fn(f64, u32) -> f64;
+
+    #[inline(never)]
+    extern "system" fn scale(value: f64, times: u32) -> f64 {
+        (0..black_box(times)).fold(0., |acc, _| acc + black_box(value))
+    }
+
+    let call = || black_box(scale as Scale)(1.5, 4);
+
+    let calls = Arc::new(AtomicU32::new(0));
+    let hook = unsafe {
+        hook_fn(scale as Scale, {
+            let calls = Arc::clone(&calls);
+            move |original: Scale, value: f64, times: u32| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                original(value, times) + 0.25
+            }
+        })
+    }
+    .unwrap();
+
+    assert_eq!(call(), 6.25);
+    assert_eq!(call(), 6.25);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(hook.original()(1.5, 4), 6.);
+
+    drop(hook);
+    assert_eq!(call(), 6.);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_hook_api_not_found() {
+    type GetTickCount = extern "system" fn() -> u32;
+
+    extern "system" fn get_tick_count_impl() -> u32 {
+        0
+    }
+
+    let status =
+        unsafe { hook_api::<GetTickCount>("kernel32.dll", "NoSuchFunction", get_tick_count_impl) }
+            .err()
+            .unwrap();
+    assert_eq!(status, MH_STATUS::MH_ERROR_FUNCTION_NOT_FOUND);
+    assert_eq!(status.name(), "MH_ERROR_FUNCTION_NOT_FOUND");
+    assert!(status.to_string().contains("MH_ERROR_FUNCTION_NOT_FOUND"));
+
+    let status = unsafe {
+        hook_api::<GetTickCount>("nosuchmodule.dll", "GetTickCount", get_tick_count_impl)
+    }
+    .err()
+    .unwrap();
+    assert_eq!(status, MH_STATUS::MH_ERROR_MODULE_NOT_FOUND);
+}